@@ -0,0 +1,79 @@
+// Run a CP/M program on the console, with drive A: on a host directory, the current one by default.
+//
+//   cpm [--drive DIR] [--record FILE] PROGRAM [ARGS]...
+//   cpm --replay FILE [--verify]
+//
+// --record saves the inputs of the run: the console input and what the files gave the program. --replay runs a
+// recording again without the files, checking the state hashes it holds with --verify.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use i8080::bdos::{Bdos, Stdio};
+use i8080::replay::{Journal, Log};
+use i8080::{loader, Cpu, Linear};
+
+fn usage() -> ! {
+    eprintln!("usage: cpm [--drive DIR] [--record FILE] PROGRAM [ARGS]...");
+    eprintln!("       cpm --replay FILE [--verify]");
+    std::process::exit(2);
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut drive = String::from(".");
+    let mut record = None;
+    let mut replay = None;
+    let mut verify = false;
+    let mut program = None;
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--drive" => drive = args.next().unwrap_or_else(|| usage()),
+            "--record" => record = Some(args.next().unwrap_or_else(|| usage())),
+            "--replay" => replay = Some(args.next().unwrap_or_else(|| usage())),
+            "--verify" => verify = true,
+            _ if a.starts_with("--") => usage(),
+            _ => {
+                program = Some(a);
+                break;
+            }
+        }
+    }
+    let tail: Vec<String> = args.collect();
+
+    let mem = Rc::new(RefCell::new(Linear::new()));
+    let mut cpu = Cpu::power_up(mem.clone());
+    let mut bdos = Bdos::new(Rc::new(RefCell::new(Stdio::new())));
+    bdos.mount(0, &drive);
+    bdos.install(&mut *mem.borrow_mut());
+    match (&replay, &program) {
+        (Some(path), None) if record.is_none() => {
+            let log = Log::load(&mut std::io::BufReader::new(std::fs::File::open(path)?))?;
+            cpu.journal = Some(Journal::replay(&mut cpu, log, verify));
+        }
+        (None, Some(program)) => {
+            loader::load_file(&mut cpu, program, loader::COM_BASE)?;
+            bdos.command_tail(&mut *mem.borrow_mut(), &tail.join(" "));
+            if record.is_some() {
+                cpu.journal = Some(Journal::record(&cpu, 1_000_000));
+            }
+        }
+        _ => usage(),
+    }
+    loop {
+        if cpu.halted || bdos.trap(&mut cpu) || cpu.journal.as_ref().is_some_and(|j| j.done(cpu.cycles)) {
+            break;
+        }
+        cpu.run_block();
+    }
+
+    let Some(j) = cpu.journal.take() else { return Ok(()) };
+    if !j.replaying() {
+        let log = j.finish(cpu.cycles);
+        return log.save(&mut std::io::BufWriter::new(std::fs::File::create(record.unwrap())?));
+    }
+    match &j.desync {
+        Some(d) => eprintln!("replay desynchronized at cycle {}, event {}: {}", d.cycles, d.index, d.reason),
+        None => eprintln!("replayed {} events over {} cycles", j.log.events.len(), cpu.cycles),
+    }
+    Ok(())
+}
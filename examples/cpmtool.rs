@@ -0,0 +1,78 @@
+// List, extract and insert files of CP/M disk images.
+//
+//   cpmtool new IMAGE [raw|imd|altair|hdsk]
+//   cpmtool ls IMAGE
+//   cpmtool get IMAGE [USER:]NAME [FILE]
+//   cpmtool put IMAGE FILE [[USER:]NAME]
+//   cpmtool rm IMAGE [USER:]NAME
+use std::fs;
+use std::path::Path;
+
+use i8080::bdos;
+use i8080::diskimg::{self, Cpm, Format};
+
+fn usage() -> ! {
+    eprintln!("usage: cpmtool new|ls|get|put|rm IMAGE [ARGS]");
+    std::process::exit(2);
+}
+
+fn format(path: &Path) -> Format {
+    let size = fs::metadata(path).map(|e| e.len() as usize).unwrap_or(0);
+    Format::detect(path, size)
+}
+
+// Parse a CP/M file name with an optional user number prefix.
+fn name(s: &str) -> (u8, bdos::Name) {
+    let (user, s) = match s.split_once(':') {
+        Some((u, n)) => (u.parse().unwrap_or_else(|_| usage()), n),
+        None => (0, s),
+    };
+    (user, bdos::to_name(s).unwrap_or_else(|| usage()))
+}
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        usage();
+    }
+    let path = Path::new(&args[1]);
+    if args[0] == "new" {
+        let f = match args.get(2).map(|e| e.as_str()) {
+            None => format(path),
+            Some("raw") => Format::Raw,
+            Some("imd") => Format::Imd,
+            Some("altair") => Format::Altair,
+            Some("hdsk") => Format::Hdsk,
+            Some(_) => usage(),
+        };
+        return diskimg::create(path, f);
+    }
+    let mut disk = diskimg::open(path, format(path))?;
+    let mut cpm = Cpm::new(&mut *disk);
+    match (args[0].as_str(), &args[2..]) {
+        ("ls", []) => {
+            for e in cpm.list()? {
+                let attr = format!("{}{}", if e.readonly { "R" } else { "-" }, if e.system { "S" } else { "-" });
+                println!("{:>2}: {:<12} {} {:>8}", e.user, bdos::from_name(&e.name), attr, e.size());
+            }
+        }
+        ("get", [n, rest @ ..]) if rest.len() <= 1 => {
+            let (user, n) = name(n);
+            let out = rest.first().cloned().unwrap_or_else(|| bdos::from_name(&n).to_lowercase());
+            fs::write(out, cpm.extract(user, &n)?)?;
+        }
+        ("put", [f, rest @ ..]) if rest.len() <= 1 => {
+            let default = Path::new(f).file_name().and_then(|e| e.to_str()).unwrap_or_default().to_string();
+            let (user, n) = name(rest.first().unwrap_or(&default));
+            cpm.insert(user, &n, &fs::read(f)?)?;
+        }
+        ("rm", [n]) => {
+            let (user, n) = name(n);
+            if !cpm.erase(user, &n)? {
+                eprintln!("cpmtool: {} not found", bdos::from_name(&n));
+            }
+        }
+        _ => usage(),
+    }
+    Ok(())
+}
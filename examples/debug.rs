@@ -0,0 +1,107 @@
+// Debug a CP/M program from the command line. Symbols come from the program itself when it is a listing or a
+// relocatable file, and from the symbol files given. Source lines come from the listings given, and from the program
+// when it is one. The last instructions are kept to go back, within --history megabytes, 64 by default.
+//
+//   debug PROGRAM [--symbols FILE]... [--source LISTING]... [--history MB]
+//
+// Type h for the commands. Console output of the program is shown after each command, console input reads as ^Z.
+use std::cell::RefCell;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use i8080::bdos::{Bdos, Buffer};
+use i8080::debugger::Debugger;
+use i8080::history::History;
+use i8080::source::Sources;
+use i8080::symbol::Symbols;
+use i8080::{loader, Cpu, Linear};
+
+const HELP: &str = "\
+s [N]          step N instructions
+n [N]          step N source lines
+c              continue to a breakpoint, a halt or the end of the program
+b ADDR         set a breakpoint, b alone lists them
+d ADDR         delete a breakpoint
+g ADDR         go on from ADDR
+bs [N]         step back N instructions
+bc             continue back to a breakpoint
+bg ADDR        go back to the last time pc was at ADDR
+bw ADDR        go back to before the last write of ADDR
+r              show the registers
+x ADDR [N]     dump N bytes of memory
+l [ADDR] [N]   list N instructions
+src [ADDR] [N] list N source lines
+i ADDR         show the address and its name
+sym [TEXT]     list the symbols
+q              quit
+
+ADDR is a number, a symbol with an optional offset such as PRINT+3, or FILE:LINE of a listing";
+
+fn usage() -> ! {
+    eprintln!("usage: debug PROGRAM [--symbols FILE]... [--source LISTING]... [--history MB]");
+    std::process::exit(2);
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let program = args.next().unwrap_or_else(|| usage());
+    let mut symbols = Symbols::new();
+    let mut sources = Sources::new();
+    let mut history = 64;
+    let ext = std::path::Path::new(&program).extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    if ext == "prn" || ext == "lst" {
+        sources.load(&program)?;
+    }
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--symbols" => {
+                for (addr, name) in Symbols::load(args.next().unwrap_or_else(|| usage()))?.iter() {
+                    symbols.insert(name, addr);
+                }
+            }
+            "--history" => history = args.next().and_then(|e| e.parse().ok()).unwrap_or_else(|| usage()),
+            "--source" => sources.load(args.next().unwrap_or_else(|| usage()))?,
+            _ => usage(),
+        }
+    }
+
+    let mem = Rc::new(RefCell::new(Linear::new()));
+    let mut cpu = Cpu::power_up(mem.clone());
+    let image = loader::load_file(&mut cpu, &program, loader::COM_BASE)?;
+    symbols.extend(&image.symbols);
+    let con = Rc::new(RefCell::new(Buffer::default()));
+    let mut bdos = Bdos::new(con.clone());
+    bdos.install(&mut *mem.borrow_mut());
+    if history != 0 {
+        cpu.history = Some(History::new(history << 20));
+    }
+    let mut dbg = Debugger::new(cpu);
+    dbg.symbols = symbols;
+    dbg.sources = sources;
+    dbg.trap = Some(Box::new(move |cpu| bdos.trap(cpu)));
+
+    println!("{}", dbg.location());
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    loop {
+        print!("(i8080) ");
+        stdout.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let r = match line.trim() {
+            "q" | "quit" => break,
+            "h" | "help" => HELP.to_string(),
+            e => dbg.command(e),
+        };
+        let out: Vec<u8> = con.borrow_mut().output.drain(..).collect();
+        if !out.is_empty() {
+            println!("{}", String::from_utf8_lossy(&out));
+        }
+        if !r.is_empty() {
+            println!("{}", r);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,153 @@
+// Run a Midway 8080 game without a window and save screenshots, or play it on the terminal.
+//
+//   midway GAME ROMDIR [--frames N] [--screenshot-every N] [--out DIR] [--ppm] [--no-overlay] [--folded FILE]
+//   midway GAME ROMDIR --term [half|braille] [--no-overlay]
+//
+// Either can take --record FILE to save the inputs of the run, or --replay FILE [--verify] to run a recording again,
+// checking the state hashes it holds with --verify. A replay runs to the end of the recording.
+//
+// With --screenshot-every the picture is saved every N frames, otherwise only after the last frame. --folded profiles
+// the run and writes the cycles per call stack for flamegraph tools. With --term the
+// game runs in real time on the terminal until q or escape is pressed: c inserts a coin, 1 and 2 start, the arrows and
+// space play.
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use i8080::midway::{self, Board, FRAME_RATE};
+use i8080::profile::Profile;
+use i8080::replay::{Journal, Log};
+use i8080::term::{self, Key, Keyboard, Keymap, Mode, RawMode, Screen};
+
+fn usage() -> ! {
+    eprintln!("usage: midway GAME ROMDIR [--frames N] [--screenshot-every N] [--out DIR] [--ppm] [--no-overlay]");
+    eprintln!("                          [--folded FILE] [--record FILE | --replay FILE [--verify]]");
+    eprintln!("       midway GAME ROMDIR --term [half|braille] [--no-overlay] [--record FILE | --replay FILE]");
+    eprintln!("games: {}", midway::GAMES.iter().map(|e| e.name).collect::<Vec<_>>().join(" "));
+    std::process::exit(2);
+}
+
+// True once a replay has run to its end.
+fn replayed(board: &Board) -> bool {
+    board.cpu.journal.as_ref().is_some_and(|j| j.done(board.cpu.cycles))
+}
+
+// Save the recording, or tell how the replay went.
+fn finish(board: &mut Board, record: Option<&str>) -> std::io::Result<()> {
+    let Some(j) = board.cpu.journal.take() else { return Ok(()) };
+    if !j.replaying() {
+        let log = j.finish(board.cpu.cycles);
+        return log.save(&mut std::io::BufWriter::new(std::fs::File::create(record.unwrap())?));
+    }
+    match &j.desync {
+        Some(d) => eprintln!("replay desynchronized at cycle {}, event {}: {}", d.cycles, d.index, d.reason),
+        None => eprintln!("replayed {} events over {} frames", j.log.events.len(), board.frame),
+    }
+    Ok(())
+}
+
+fn play(mut board: Board, mode: Mode, overlay: bool, record: Option<&str>) -> std::io::Result<()> {
+    let raw = RawMode::enter()?;
+    let mut keyboard = Keyboard::new();
+    let mut keymap = Keymap::new(term::midway_keys(), 8);
+    let mut screen = Screen::new(mode);
+    let period = Duration::from_secs(1) / FRAME_RATE;
+    let mut next = Instant::now();
+    loop {
+        let keys = keyboard.poll();
+        if keys.iter().any(|k| matches!(k, Key::Char(b'q') | Key::Esc)) || replayed(&board) {
+            drop(raw);
+            return finish(&mut board, record);
+        }
+        let (press, release) = keymap.update(&keys);
+        press.into_iter().for_each(|b| board.press(b));
+        release.into_iter().for_each(|b| board.release(b));
+        board.run_frame();
+        let mut stdout = std::io::stdout();
+        stdout.write_all(screen.draw(&board.screenshot(overlay)).as_bytes())?;
+        stdout.flush()?;
+        next += period;
+        let now = Instant::now();
+        if next > now {
+            std::thread::sleep(next - now);
+        } else {
+            next = now;
+        }
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let game = args.next().and_then(|e| midway::game(&e)).unwrap_or_else(|| usage());
+    let dir = args.next().unwrap_or_else(|| usage());
+    let mut frames = 600u64;
+    let mut every = 0u64;
+    let mut out = PathBuf::from(".");
+    let mut ext = "png";
+    let mut overlay = true;
+    let mut tty = None;
+    let mut folded = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut verify = false;
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--term" => {
+                tty = Some(match args.next_if(|e| !e.starts_with("--")).as_deref() {
+                    None | Some("braille") => Mode::Braille,
+                    Some("half") => Mode::HalfBlock,
+                    _ => usage(),
+                })
+            }
+            "--no-overlay" => overlay = false,
+            "--ppm" => ext = "ppm",
+            "--verify" => verify = true,
+            _ => {
+                let value = args.next().unwrap_or_else(|| usage());
+                match a.as_str() {
+                    "--frames" => frames = value.parse().unwrap_or_else(|_| usage()),
+                    "--screenshot-every" => every = value.parse().unwrap_or_else(|_| usage()),
+                    "--out" => out = PathBuf::from(value),
+                    "--folded" => folded = Some(value),
+                    "--record" => record = Some(value),
+                    "--replay" => replay = Some(value),
+                    _ => usage(),
+                }
+            }
+        }
+    }
+    let mut board = Board::open(game, dir)?;
+    if let Some(path) = &replay {
+        let log = Log::load(&mut std::io::BufReader::new(std::fs::File::open(path)?))?;
+        board.cpu.journal = Some(Journal::replay(&mut board.cpu, log, verify));
+    } else if record.is_some() {
+        board.cpu.journal = Some(Journal::record(&board.cpu, 1_000_000));
+    }
+    if let Some(mode) = tty {
+        return play(board, mode, overlay, record.as_deref());
+    }
+    std::fs::create_dir_all(&out)?;
+    let profile = Rc::new(RefCell::new(Profile::new()));
+    if folded.is_some() {
+        board.cpu.tracer = Some(Box::new(profile.clone()));
+    }
+    let replaying = replay.is_some();
+    while if replaying { !replayed(&board) } else { board.frame < frames } {
+        board.run_frame();
+        let last = if replaying { replayed(&board) } else { board.frame == frames };
+        if (every != 0 && board.frame % every == 0) || (every == 0 && last) {
+            let p = out.join(format!("{}_{:06}.{}", game.name, board.frame, ext));
+            board.screenshot(overlay).save(&p)?;
+            println!("{}", p.display());
+        }
+    }
+    if let Some(path) = folded {
+        board.cpu.tracer = None;
+        let mut profile = profile.borrow_mut();
+        profile.finish();
+        std::fs::write(path, profile.folded())?;
+    }
+    finish(&mut board, record.as_deref())
+}
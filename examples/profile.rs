@@ -0,0 +1,64 @@
+// Run a CP/M program and report where it spent its time.
+//
+//   profile PROGRAM [--top N] [--annotate] [--symbols FILE] [--folded FILE]
+//
+// --folded writes the cycles per call stack for flamegraph tools, as in
+//
+//   $ profile prog.com --folded prog.folded && flamegraph.pl prog.folded > prog.svg
+//
+// Symbols are read from a .SYM file, a .PRN listing or a text file of "ADDR NAME" lines. Program output goes to
+// stdout, the report to stderr.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use i8080::bdos::{Bdos, Stdio};
+use i8080::profile::Profile;
+use i8080::symbol::Symbols;
+use i8080::{loader, Cpu, Linear};
+
+fn usage() -> ! {
+    eprintln!("usage: profile PROGRAM [--top N] [--annotate] [--symbols FILE] [--folded FILE]");
+    std::process::exit(2);
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let program = args.next().unwrap_or_else(|| usage());
+    let mut top = 20;
+    let mut annotate = false;
+    let mut folded = None;
+    let mut profile = Profile::new();
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--top" => top = args.next().and_then(|e| e.parse().ok()).unwrap_or_else(|| usage()),
+            "--annotate" => annotate = true,
+            "--folded" => folded = Some(args.next().unwrap_or_else(|| usage())),
+            "--symbols" => profile.symbols = Symbols::load(args.next().unwrap_or_else(|| usage()))?,
+            _ => usage(),
+        }
+    }
+
+    let mem = Rc::new(RefCell::new(Linear::new()));
+    let mut cpu = Cpu::power_up(mem.clone());
+    let image = loader::load_file(&mut cpu, &program, loader::COM_BASE)?;
+    profile.symbols.extend(&image.symbols);
+    let mut bdos = Bdos::new(Rc::new(RefCell::new(Stdio::new())));
+    bdos.install(&mut *mem.borrow_mut());
+    let profile = Rc::new(RefCell::new(profile));
+    cpu.tracer = Some(Box::new(profile.clone()));
+    bdos.run(&mut cpu);
+    cpu.tracer = None;
+
+    let mut profile = profile.borrow_mut();
+    profile.finish();
+    eprintln!();
+    eprint!("{}", profile.report(top));
+    if annotate {
+        eprintln!();
+        eprint!("{}", profile.annotate(&*mem.borrow()));
+    }
+    if let Some(path) = folded {
+        std::fs::write(path, profile.folded())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+// Recompile 8080 code to a Rust module, with a function for each block of the code found from the entry points.
+//
+//   recomp ROM... [--base ADDR] [--entry ADDR]... [--out FILE]
+//
+// The ROM files are loaded one after the other from the base, 0 by default, which is also the entry point when none
+// is given. Pass the interrupt vectors as entry points too. Addresses are hex. The module is written to FILE or to the
+// standard output, and runs with i8080::recomp::run(&mut cpu, module::block, until).
+use i8080::recomp;
+use i8080::Linear;
+
+fn usage() -> ! {
+    eprintln!("usage: recomp ROM... [--base ADDR] [--entry ADDR]... [--out FILE]");
+    std::process::exit(2);
+}
+
+fn hex(s: &str) -> u16 {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16).unwrap_or_else(|_| usage())
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut roms = vec![];
+    let mut base = 0;
+    let mut entries = vec![];
+    let mut out = None;
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--base" => base = hex(&args.next().unwrap_or_else(|| usage())),
+            "--entry" => entries.push(hex(&args.next().unwrap_or_else(|| usage()))),
+            "--out" => out = Some(args.next().unwrap_or_else(|| usage())),
+            _ if a.starts_with("--") => usage(),
+            _ => roms.push(a),
+        }
+    }
+    if roms.is_empty() {
+        usage();
+    }
+    if entries.is_empty() {
+        entries.push(base);
+    }
+    let mut mem = Linear::new();
+    let mut end = usize::from(base);
+    for path in roms {
+        let data = std::fs::read(&path)?;
+        if end + data.len() > 0x10000 {
+            eprintln!("recomp: {} does not fit below 0x10000", path);
+            std::process::exit(1);
+        }
+        mem.data[end..end + data.len()].copy_from_slice(&data);
+        end += data.len();
+    }
+    let blocks = recomp::discover(&mem, usize::from(base)..end, &entries);
+    let bytes: usize = blocks.values().map(|e| usize::from(e.size)).sum();
+    eprintln!("{} blocks, {} bytes of code", blocks.len(), bytes);
+    let module = recomp::emit(&blocks);
+    match out {
+        Some(path) => std::fs::write(path, module),
+        None => {
+            print!("{}", module);
+            Ok(())
+        }
+    }
+}
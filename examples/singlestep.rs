@@ -0,0 +1,70 @@
+// Run per-instruction JSON test vectors, such as the SingleStepTests 8080 suite.
+//
+//   singlestep PATH... [--show N]
+//
+// Each path is a test file or a directory of them. Prints the tests passed in each file and the first N failures,
+// 3 by default, with what differs, and exits with 1 when any test fails.
+use i8080::singlestep;
+use std::path::PathBuf;
+
+fn usage() -> ! {
+    eprintln!("usage: singlestep PATH... [--show N]");
+    std::process::exit(2);
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut paths = vec![];
+    let mut show = 3;
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--show" => show = args.next().and_then(|e| e.parse().ok()).unwrap_or_else(|| usage()),
+            _ if a.starts_with("--") => usage(),
+            _ => paths.push(PathBuf::from(a)),
+        }
+    }
+    if paths.is_empty() {
+        usage();
+    }
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut list = vec![];
+            for e in std::fs::read_dir(&path)? {
+                let e = e?.path();
+                if e.extension().is_some_and(|e| e == "json") {
+                    list.push(e);
+                }
+            }
+            list.sort();
+            files.extend(list);
+        } else {
+            files.push(path);
+        }
+    }
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let tests = singlestep::load(&file)?;
+        let mut shown = 0;
+        let mut ok = 0;
+        for t in &tests {
+            let diff = t.run();
+            if diff.is_empty() {
+                ok += 1;
+                continue;
+            }
+            if shown < show {
+                println!("  {}: {}", t.name, diff.join(", "));
+                shown += 1;
+            }
+        }
+        println!("{}: {}/{}", file.display(), ok, tests.len());
+        passed += ok;
+        failed += tests.len() - ok;
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed != 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
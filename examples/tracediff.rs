@@ -0,0 +1,90 @@
+// Run a CP/M program and compare its execution with the log of another emulator, stopping at the first difference.
+//
+//   tracediff PROGRAM LOG [--format zazu|doctor|TEMPLATE] [--context N] [--ignore FIELD,...] [--mask HEX]
+//                         [--skip-pc START-END]
+//
+// The BDOS is trapped, so log lines at its entry point 0x0005 are passed over by default.
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::BufReader;
+use std::rc::Rc;
+
+use i8080::bdos::{Bdos, Buffer};
+use i8080::tracediff::{Checker, Field, Format};
+use i8080::{loader, Cpu, Linear, Memory};
+
+fn usage() -> ! {
+    eprintln!("usage: tracediff PROGRAM LOG [--format zazu|doctor|TEMPLATE] [--context N] [--ignore FIELD,...]");
+    eprintln!("                             [--mask HEX] [--skip-pc START-END]");
+    std::process::exit(2);
+}
+
+fn hex(s: &str) -> u16 {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16).unwrap_or_else(|_| usage())
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let program = args.next().unwrap_or_else(|| usage());
+    let log = args.next().unwrap_or_else(|| usage());
+    let mut format = Format::preset("zazu").unwrap();
+    let mut context = 8;
+    let mut ignore = vec![];
+    let mut mask = None;
+    let mut skip = vec![0x0005..=0x0005];
+    while let Some(a) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match a.as_str() {
+            "--format" => format = Format::preset(&value).map_or_else(|| Format::parse(&value), Ok)?,
+            "--context" => context = value.parse().unwrap_or_else(|_| usage()),
+            "--ignore" => ignore = value.split(',').map(|e| Field::from_name(e).unwrap_or_else(|| usage())).collect(),
+            "--mask" => mask = Some(hex(&value) as u8),
+            "--skip-pc" => {
+                let (a, b) = value.split_once('-').unwrap_or_else(|| usage());
+                skip.push(hex(a)..=hex(b));
+            }
+            _ => usage(),
+        }
+    }
+
+    let mem = Rc::new(RefCell::new(Linear::new()));
+    let mut cpu = Cpu::power_up(mem.clone());
+    loader::load_file(&mut cpu, &program, loader::COM_BASE)?;
+    let con = Rc::new(RefCell::new(Buffer::default()));
+    let mut bdos = Bdos::new(con.clone());
+    bdos.install(&mut *mem.borrow_mut());
+
+    let mut checker = Checker::new(format, Box::new(BufReader::new(File::open(&log)?)));
+    checker.context = context;
+    checker.ignore = ignore;
+    checker.skip = skip;
+    if let Some(m) = mask {
+        checker.mask = m;
+    }
+    let checker = Rc::new(RefCell::new(checker));
+    cpu.tracer = Some(Box::new(checker.clone()));
+    while !checker.borrow().stopped() && !cpu.halted && cpu.reg.pc != 0x0000 {
+        // Other emulators trap the call at 0x0005 and return from it at once, so does this loop.
+        if cpu.reg.pc == 0x0005 {
+            bdos.call(&mut cpu);
+            if cpu.reg.pc == 0x0005 {
+                let sp = cpu.reg.sp;
+                cpu.reg.pc = mem.borrow().get_word(sp);
+                cpu.reg.sp = sp.wrapping_add(2);
+            }
+            continue;
+        }
+        cpu.next();
+    }
+    cpu.tracer = None;
+
+    let checker = checker.borrow();
+    match &checker.divergence {
+        Some(d) => {
+            println!("{}", d.report());
+            std::process::exit(1);
+        }
+        None => println!("{} instructions match", checker.matched),
+    }
+    Ok(())
+}
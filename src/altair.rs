@@ -0,0 +1,403 @@
+// MITS Altair 8800 with 64K of memory, an 88-2SIO serial board, an 88-DCDD floppy disk controller and the front
+// panel.
+//
+// I/O ports:
+//
+//   0x08  Disk drive select (out) and status (in)
+//   0x09  Disk control (out) and sector position (in)
+//   0x0a  Disk write data (out) and read data (in)
+//   0x10  2SIO port A control (out) and status (in)
+//   0x11  2SIO port A data
+//   0x12  2SIO port B control and status, nothing is connected
+//   0x13  2SIO port B data
+//   0xff  Sense switches, the high byte of the front panel address switches
+use super::bdos::Console;
+use super::cpu::Cpu;
+use super::device::{Bus, Device};
+use super::diskimg::{self, ALTAIR_SECTOR_SIZE};
+use super::memory::{Linear, Memory};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub const DCDD_SELECT: u8 = 0x08;
+pub const DCDD_CONTROL: u8 = 0x09;
+pub const DCDD_DATA: u8 = 0x0a;
+pub const SIO_A_STATUS: u8 = 0x10;
+pub const SIO_A_DATA: u8 = 0x11;
+pub const SIO_B_STATUS: u8 = 0x12;
+pub const SIO_B_DATA: u8 = 0x13;
+pub const SENSE: u8 = 0xff;
+
+// 6850 ACIA status bits.
+const SIO_RDRF: u8 = 0x01;
+const SIO_TDRE: u8 = 0x02;
+
+// 88-2SIO with port A bridged to a console. Port B is always ready to transmit and never receives.
+pub struct Sio {
+    pub console: Rc<RefCell<dyn Console>>,
+}
+
+impl Device for Sio {
+    fn get(&mut self, port: u8) -> u8 {
+        match port {
+            SIO_A_STATUS => {
+                if self.console.borrow_mut().status() {
+                    SIO_RDRF | SIO_TDRE
+                } else {
+                    SIO_TDRE
+                }
+            }
+            SIO_A_DATA => self.console.borrow_mut().read(),
+            SIO_B_STATUS => SIO_TDRE,
+            _ => 0x00,
+        }
+    }
+
+    // Control writes only reset or configure the ACIA. Terminals ignore the parity bit, so it is dropped.
+    fn set(&mut self, port: u8, v: u8) {
+        if port == SIO_A_DATA {
+            self.console.borrow_mut().write(v & 0x7f)
+        }
+    }
+}
+
+// Status bits of the disk controller, active low.
+const DCDD_ENWD: u8 = 0x01;
+const DCDD_MOVE_HEAD: u8 = 0x02;
+const DCDD_HEAD: u8 = 0x04;
+const DCDD_INTE: u8 = 0x20;
+const DCDD_TRACK_0: u8 = 0x40;
+const DCDD_NRDA: u8 = 0x80;
+
+// Control bits of the disk controller.
+const DCDD_STEP_IN: u8 = 0x01;
+const DCDD_STEP_OUT: u8 = 0x02;
+const DCDD_HEAD_LOAD: u8 = 0x04;
+const DCDD_HEAD_UNLOAD: u8 = 0x08;
+const DCDD_WRITE_ENABLE: u8 = 0x80;
+
+// 88-DCDD controller for up to 16 drives. Sectors pass under the head as the sector position register is polled,
+// and their 137 bytes are transferred one at a time through the data port.
+pub struct Dcdd {
+    pub drives: Vec<Option<diskimg::Altair>>,
+    drive: Option<u8>,
+    tracks: Vec<u16>,
+    head: bool,
+    sector: u16,
+    // Sector true, active low, toggled on every poll of the sector position.
+    sector_true: u8,
+    // Position in the sector buffer. None until the first byte of a new sector is read.
+    byte: Option<usize>,
+    buf: [u8; ALTAIR_SECTOR_SIZE],
+    writing: bool,
+}
+
+impl Default for Dcdd {
+    fn default() -> Self {
+        Self {
+            drives: (0..16).map(|_| None).collect(),
+            drive: None,
+            tracks: vec![0; 16],
+            head: false,
+            sector: 0,
+            sector_true: 1,
+            byte: None,
+            buf: [0; ALTAIR_SECTOR_SIZE],
+            writing: false,
+        }
+    }
+}
+
+impl Dcdd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, drive: u8, disk: diskimg::Altair) {
+        self.drives[usize::from(drive)] = Some(disk);
+    }
+
+    pub fn eject(&mut self, drive: u8) -> Option<diskimg::Altair> {
+        self.drives[usize::from(drive)].take()
+    }
+
+    fn disk(&mut self) -> Option<&mut diskimg::Altair> {
+        self.drives.get_mut(usize::from(self.drive?))?.as_mut()
+    }
+
+    fn track(&self) -> u16 {
+        self.drive.map(|e| self.tracks[usize::from(e)]).unwrap_or(0)
+    }
+
+    // Write out a sector whose data was sent, even if partially. Write errors are lost like on a real disk.
+    fn flush(&mut self) {
+        if !self.writing {
+            return;
+        }
+        self.writing = false;
+        let (track, sector, buf) = (self.track(), self.sector, self.buf);
+        if let Some(d) = self.disk() {
+            let _ = d.write_raw(track, sector, &buf);
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        if self.disk().is_none() {
+            return 0xff;
+        }
+        let mut r = DCDD_ENWD | DCDD_HEAD | DCDD_INTE | DCDD_TRACK_0 | DCDD_NRDA;
+        if self.writing {
+            r &= !DCDD_ENWD;
+        }
+        if self.head {
+            r &= !(DCDD_HEAD | DCDD_NRDA);
+        }
+        if self.track() == 0 {
+            r &= !DCDD_TRACK_0;
+        }
+        r & !DCDD_MOVE_HEAD
+    }
+
+    fn sector_position(&mut self) -> u8 {
+        if self.disk().is_none() || !self.head {
+            return 0xff;
+        }
+        self.sector_true ^= 1;
+        if self.sector_true == 0 {
+            self.flush();
+            self.sector = (self.sector + 1) % 32;
+            self.byte = None;
+        }
+        ((self.sector as u8) << 1) & 0x3e | 0xc0 | self.sector_true
+    }
+
+    fn control(&mut self, v: u8) {
+        let Some(drive) = self.drive else { return };
+        let t = &mut self.tracks[usize::from(drive)];
+        if v & DCDD_STEP_IN != 0 && *t < 76 {
+            *t += 1;
+        }
+        if v & DCDD_STEP_OUT != 0 && *t > 0 {
+            *t -= 1;
+        }
+        if v & (DCDD_STEP_IN | DCDD_STEP_OUT) != 0 {
+            self.writing = false;
+            self.byte = None;
+        }
+        if v & DCDD_HEAD_LOAD != 0 {
+            self.head = true;
+        }
+        if v & DCDD_HEAD_UNLOAD != 0 {
+            self.head = false;
+        }
+        if v & DCDD_WRITE_ENABLE != 0 {
+            self.writing = true;
+            self.byte = Some(0);
+            self.buf = [0; ALTAIR_SECTOR_SIZE];
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let i = match self.byte {
+            Some(i) => i,
+            None => {
+                let (track, sector) = (self.track(), self.sector);
+                let Some(d) = self.disk() else { return 0 };
+                let Ok(buf) = d.read_raw(track, sector) else { return 0 };
+                let buf: [u8; ALTAIR_SECTOR_SIZE] = buf.try_into().unwrap();
+                self.buf = buf;
+                0
+            }
+        };
+        if i >= ALTAIR_SECTOR_SIZE {
+            return 0;
+        }
+        self.byte = Some(i + 1);
+        self.buf[i]
+    }
+
+    fn write_data(&mut self, v: u8) {
+        let Some(i) = self.byte.filter(|_| self.writing) else { return };
+        if i < ALTAIR_SECTOR_SIZE {
+            self.buf[i] = v;
+            self.byte = Some(i + 1);
+        }
+        if i + 1 >= ALTAIR_SECTOR_SIZE {
+            self.flush();
+        }
+    }
+}
+
+impl Device for Dcdd {
+    fn get(&mut self, port: u8) -> u8 {
+        match port {
+            DCDD_SELECT => self.status(),
+            DCDD_CONTROL => self.sector_position(),
+            _ => self.read_data(),
+        }
+    }
+
+    fn set(&mut self, port: u8, v: u8) {
+        match port {
+            DCDD_SELECT => {
+                self.flush();
+                self.drive = if v & 0x80 != 0 { None } else { Some(v & 0x0f) };
+                self.head = false;
+                self.byte = None;
+            }
+            DCDD_CONTROL => self.control(v),
+            _ => self.write_data(v),
+        }
+    }
+}
+
+// Front panel switches and lights. The address and data lights latch the last memory access made from the panel
+// or, while running, the next instruction.
+#[derive(Default)]
+pub struct Panel {
+    pub switches: u16,
+    pub address: u16,
+    pub data: u8,
+}
+
+impl Device for Panel {
+    fn get(&mut self, _: u8) -> u8 {
+        (self.switches >> 8) as u8
+    }
+
+    fn set(&mut self, _: u8, _: u8) {}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Leds {
+    pub address: u16,
+    pub data: u8,
+    pub inte: bool,
+    // Stopped.
+    pub wait: bool,
+    // Halt acknowledged.
+    pub hlta: bool,
+}
+
+pub struct Machine {
+    pub cpu: Cpu,
+    pub mem: Rc<RefCell<Linear>>,
+    pub bus: Rc<RefCell<Bus>>,
+    pub dcdd: Rc<RefCell<Dcdd>>,
+    pub panel: Rc<RefCell<Panel>>,
+    pub running: bool,
+}
+
+impl Machine {
+    // A stopped machine with the console on 2SIO port A.
+    pub fn new(console: Rc<RefCell<dyn Console>>) -> Self {
+        let mem = Rc::new(RefCell::new(Linear::new()));
+        let dcdd = Rc::new(RefCell::new(Dcdd::new()));
+        let panel = Rc::new(RefCell::new(Panel::default()));
+        let mut bus = Bus::new();
+        bus.attach(DCDD_SELECT..=DCDD_DATA, dcdd.clone());
+        bus.attach(SIO_A_STATUS..=SIO_B_DATA, Rc::new(RefCell::new(Sio { console })));
+        bus.attach(SENSE..=SENSE, panel.clone());
+        let bus = Rc::new(RefCell::new(bus));
+        let mut cpu = Cpu::power_up(mem.clone());
+        cpu.dev = Some(bus.clone());
+        Self { cpu, mem, bus, dcdd, panel, running: false }
+    }
+
+    pub fn insert(&mut self, drive: u8, disk: diskimg::Altair) {
+        self.dcdd.borrow_mut().insert(drive, disk)
+    }
+
+    // Copy a program or ROM into memory, as toggled in or loaded from tape.
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        let mut mem = self.mem.borrow_mut();
+        for (i, e) in data.iter().enumerate() {
+            mem.set(addr.wrapping_add(i as u16), *e);
+        }
+    }
+
+    fn latch(&mut self) {
+        let pc = self.cpu.reg.pc;
+        let data = self.mem.borrow().get(pc);
+        let mut p = self.panel.borrow_mut();
+        p.address = pc;
+        p.data = data;
+    }
+
+    pub fn leds(&self) -> Leds {
+        let p = self.panel.borrow();
+        Leds { address: p.address, data: p.data, inte: self.cpu.inte, wait: !self.running, hlta: self.cpu.halted }
+    }
+
+    // EXAMINE: jump to the address and show the byte there.
+    pub fn examine(&mut self, addr: u16) -> u8 {
+        self.cpu.reg.pc = addr;
+        self.latch();
+        self.panel.borrow().data
+    }
+
+    pub fn examine_next(&mut self) -> u8 {
+        self.examine(self.cpu.reg.pc.wrapping_add(1))
+    }
+
+    // DEPOSIT: store a byte at the current address.
+    pub fn deposit(&mut self, v: u8) {
+        self.mem.borrow_mut().set(self.cpu.reg.pc, v);
+        self.latch();
+    }
+
+    pub fn deposit_next(&mut self, v: u8) {
+        self.cpu.reg.pc = self.cpu.reg.pc.wrapping_add(1);
+        self.deposit(v);
+    }
+
+    // RESET: restart at address zero with interrupts disabled.
+    pub fn reset(&mut self) {
+        self.cpu.reg.pc = 0x0000;
+        self.cpu.inte = false;
+        self.cpu.halted = false;
+        self.latch();
+    }
+
+    pub fn run(&mut self) {
+        self.cpu.halted = false;
+        self.running = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+        self.latch();
+    }
+
+    // SINGLE STEP: execute one instruction while stopped.
+    pub fn single_step(&mut self) -> u32 {
+        if self.running {
+            return 0;
+        }
+        let cycles = self.cpu.next();
+        self.latch();
+        cycles
+    }
+
+    // Execute one instruction at the speed of the real machine while running. A halt stops the machine.
+    pub fn step(&mut self) -> u32 {
+        if !self.running {
+            return 0;
+        }
+        let cycles = self.cpu.step();
+        if self.cpu.halted {
+            self.running = false;
+        }
+        self.latch();
+        cycles
+    }
+
+    // Run from an address until the machine halts or is stopped.
+    pub fn go(&mut self, addr: u16) {
+        self.examine(addr);
+        self.run();
+        while self.running {
+            self.step();
+        }
+    }
+}
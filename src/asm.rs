@@ -273,3 +273,38 @@ pub fn asm(opcode: u8) -> &'static str {
         0xFF => "RST 7     ",
     }
 }
+
+// Bytes taken by the instruction, operands included.
+pub fn length(opcode: u8) -> usize {
+    match opcode {
+        0x01 | 0x11 | 0x21 | 0x31 | 0x22 | 0x2a | 0x32 | 0x3a => 3,
+        0xc2 | 0xc3 | 0xc4 | 0xca | 0xcb | 0xcc | 0xcd | 0xd2 | 0xd4 | 0xda | 0xdc | 0xdd => 3,
+        0xe2 | 0xe4 | 0xea | 0xec | 0xed | 0xf2 | 0xf4 | 0xfa | 0xfc | 0xfd => 3,
+        0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e => 2,
+        0xc6 | 0xce | 0xd3 | 0xd6 | 0xdb | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe => 2,
+        _ => 1,
+    }
+}
+
+// The instruction at the start of bytes with its operand, such as "MVI B, 0x12" or "JMP 0x0100". Missing operand bytes
+// read as zero.
+pub fn disasm(bytes: &[u8]) -> String {
+    disasm_with(bytes, |_| None)
+}
+
+// Like disasm, with word operands that have a name written as the name, such as "CALL PRINT".
+pub fn disasm_with(bytes: &[u8], name: impl Fn(u16) -> Option<String>) -> String {
+    let opcode = bytes.first().copied().unwrap_or(0x00);
+    let m = asm(opcode).trim_end();
+    let b = |i: usize| bytes.get(i).copied().unwrap_or(0x00);
+    let operand = match length(opcode) {
+        2 => format!("0x{:02x}", b(1)),
+        3 => {
+            let w = u16::from(b(1)) | (u16::from(b(2)) << 8);
+            name(w).unwrap_or_else(|| format!("0x{:04x}", w))
+        }
+        _ => return m.to_string(),
+    };
+    let sep = if m.contains(' ') { ", " } else { " " };
+    format!("{}{}{}", m, sep, operand)
+}
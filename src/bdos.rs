@@ -0,0 +1,769 @@
+// High level emulation of the CP/M 2.2 BDOS. Transient programs call the BDOS at 0x0005 with the function number in
+// C and a parameter in E or DE. Instead of running a real BDOS, calls are trapped when the program counter reaches the
+// BDOS entry point and performed in Rust, with disk drives mapped to host directories.
+//
+// Page zero as seen by the program:
+//
+//   0x0000  JMP WBOOT       Warm boot, jumping here ends the program
+//   0x0005  JMP BDOS        BDOS entry, the word at 0x0006 is also the top of the TPA
+//   0x005c  FCB             Default file control block
+//   0x0080  DMA             Default DMA buffer, holds the command tail on entry
+use super::cpu::Cpu;
+use super::memory::Memory;
+use super::replay::{self, Noted};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+
+pub const BDOS: u16 = 0xfe06;
+pub const BIOS: u16 = 0xff00;
+pub const WBOOT: u16 = BIOS + 3;
+pub const FCB: u16 = 0x005c;
+pub const DMA: u16 = 0x0080;
+pub const RECORD: usize = 128;
+
+// CP/M uses ^Z to mark the end of text in the last record of a file.
+const EOF: u8 = 0x1a;
+
+pub trait Console {
+    // Whether a character is ready to be read.
+    fn status(&mut self) -> bool;
+
+    // Read a character, blocking until one is available.
+    fn read(&mut self) -> u8;
+
+    fn write(&mut self, c: u8);
+}
+
+// Console bridged to the host stdin and stdout. Stdin is read on a separate thread so the status can be polled.
+pub struct Stdio {
+    rx: mpsc::Receiver<u8>,
+    peek: Option<u8>,
+}
+
+impl Stdio {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 1];
+            while let Ok(1) = io::stdin().read(&mut buf) {
+                let b = if buf[0] == b'\n' { b'\r' } else { buf[0] };
+                if tx.send(b).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { rx, peek: None }
+    }
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console for Stdio {
+    fn status(&mut self) -> bool {
+        if self.peek.is_none() {
+            self.peek = self.rx.try_recv().ok();
+        }
+        self.peek.is_some()
+    }
+
+    fn read(&mut self) -> u8 {
+        // Once stdin is closed the program sees an endless stream of ^Z.
+        self.peek.take().unwrap_or_else(|| self.rx.recv().unwrap_or(EOF))
+    }
+
+    fn write(&mut self, c: u8) {
+        let mut stdout = io::stdout();
+        stdout.write_all(&[c]).unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+// Console backed by memory buffers, used to script input and capture output.
+#[derive(Default)]
+pub struct Buffer {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl Console for Buffer {
+    fn status(&mut self) -> bool {
+        !self.input.is_empty()
+    }
+
+    fn read(&mut self) -> u8 {
+        self.input.pop_front().unwrap_or(EOF)
+    }
+
+    fn write(&mut self, c: u8) {
+        self.output.push(c)
+    }
+}
+
+// File control block field offsets.
+const FCB_DR: u16 = 0;
+const FCB_NAME: u16 = 1;
+const FCB_EX: u16 = 12;
+const FCB_S2: u16 = 14;
+const FCB_RC: u16 = 15;
+const FCB_CR: u16 = 32;
+const FCB_R0: u16 = 33;
+
+// The 11 byte name of a file control block, in the upper case space padded form CP/M uses.
+pub type Name = [u8; 11];
+
+// Convert a host file name to a CP/M name. Names that do not fit the 8.3 form are not visible to CP/M.
+pub fn to_name(s: &str) -> Option<Name> {
+    let (n, t) = match s.rfind('.') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, ""),
+    };
+    let valid = |e: &str, len: usize| {
+        e.len() <= len && e.bytes().all(|c| c.is_ascii_graphic() && !b"<>.,;:=?*[]_%|()/\\".contains(&c))
+    };
+    if n.is_empty() || !valid(n, 8) || !valid(t, 3) {
+        return None;
+    }
+    let mut r = [b' '; 11];
+    for (i, c) in n.bytes().enumerate() {
+        r[i] = c.to_ascii_uppercase();
+    }
+    for (i, c) in t.bytes().enumerate() {
+        r[8 + i] = c.to_ascii_uppercase();
+    }
+    Some(r)
+}
+
+// Convert a CP/M name to the host file name, attribute bits are dropped.
+pub fn from_name(n: &Name) -> String {
+    let f = |e: &[u8]| e.iter().map(|c| char::from(c & 0x7f)).collect::<String>().trim_end().to_string();
+    let (a, b) = (f(&n[..8]), f(&n[8..]));
+    if b.is_empty() {
+        a
+    } else {
+        format!("{}.{}", a, b)
+    }
+}
+
+// Whether a name matches a pattern, where '?' matches any character.
+pub fn matches(pattern: &Name, n: &Name) -> bool {
+    pattern.iter().zip(n.iter()).all(|(p, c)| *p & 0x7f == b'?' || *p & 0x7f == *c & 0x7f)
+}
+
+pub struct Bdos {
+    pub console: Rc<RefCell<dyn Console>>,
+    pub dma: u16,
+    pub drive: u8,
+    pub user: u8,
+    pub iobyte: u8,
+    drives: Vec<Option<PathBuf>>,
+    // Pending results of a directory search.
+    search: VecDeque<(u8, Name, u64)>,
+}
+
+impl Bdos {
+    pub fn new(console: Rc<RefCell<dyn Console>>) -> Self {
+        Self { console, dma: DMA, drive: 0, user: 0, iobyte: 0, drives: vec![None; 16], search: VecDeque::new() }
+    }
+
+    // Map a drive (0 is A:) to a host directory. Files of user 0 are kept in the directory itself and files of other
+    // user areas in a subdirectory named after the user number.
+    pub fn mount(&mut self, drive: u8, path: impl AsRef<Path>) {
+        self.drives[usize::from(drive)] = Some(path.as_ref().to_path_buf());
+    }
+
+    // Set up page zero and the BDOS entry point.
+    pub fn install(&self, mem: &mut dyn Memory) {
+        mem.set(0x0000, 0xc3);
+        mem.set_word(0x0001, WBOOT);
+        mem.set(0x0003, self.iobyte);
+        mem.set(0x0004, (self.user << 4) | self.drive);
+        mem.set(0x0005, 0xc3);
+        mem.set_word(0x0006, BDOS);
+        // The trapped call returns through a RET at the entry point.
+        mem.set(BDOS, 0xc9);
+        mem.set(WBOOT, 0x76);
+    }
+
+    // Fill the command tail and the default file control blocks the way the CCP does.
+    pub fn command_tail(&self, mem: &mut dyn Memory, args: &str) {
+        let tail = args.trim().to_ascii_uppercase();
+        let tail = if tail.is_empty() { tail } else { format!(" {}", tail) };
+        let tail = &tail.as_bytes()[..tail.len().min(127)];
+        mem.set(DMA, tail.len() as u8);
+        for (i, c) in tail.iter().enumerate() {
+            mem.set(DMA + 1 + i as u16, *c);
+        }
+        mem.set(DMA + 1 + tail.len() as u16, 0x00);
+        let mut words = tail.split(|c| *c == b' ').filter(|e| !e.is_empty());
+        for a in [FCB, FCB + 0x10] {
+            let w = words.next().unwrap_or(&[]);
+            let (dr, w) = match w {
+                [d @ b'A'..=b'P', b':', rest @ ..] => (d - b'A' + 1, rest),
+                _ => (0, w),
+            };
+            let n = parse_name(w);
+            mem.set(a + FCB_DR, dr);
+            for (i, c) in n.iter().enumerate() {
+                mem.set(a + FCB_NAME + i as u16, *c);
+            }
+            for i in 12..16 {
+                mem.set(a + i, 0x00);
+            }
+        }
+        mem.set(FCB + FCB_CR, 0x00);
+    }
+
+    // Handle the cpu sitting at the BDOS entry point or at the warm boot address. Returns true when the program has
+    // ended with a warm boot.
+    pub fn trap(&mut self, cpu: &mut Cpu) -> bool {
+        match cpu.reg.pc {
+            0x0000 | WBOOT => true,
+            BDOS => {
+                let de = cpu.reg.get_de();
+                self.call(cpu);
+                // Code the call may have overwritten: the record at the DMA address, the buffer or FCB at DE.
+                cpu.invalidate(self.dma, RECORD);
+                cpu.invalidate(de, 0x102);
+                cpu.reg.pc == 0x0000
+            }
+            _ => false,
+        }
+    }
+
+    // Run the program until it warm boots or halts. With a block cache on the cpu the traps are checked between blocks,
+    // which the jumps and calls into the BDOS start.
+    pub fn run(&mut self, cpu: &mut Cpu) {
+        loop {
+            if cpu.halted || self.trap(cpu) {
+                break;
+            }
+            cpu.run_block();
+        }
+    }
+
+    fn put(&mut self, c: u8) {
+        self.console.borrow_mut().write(c)
+    }
+
+    fn get(&mut self) -> u8 {
+        self.console.borrow_mut().read()
+    }
+
+    // Perform the BDOS function in C. The result is returned in A and L, with B and H set to the high byte.
+    // Make the call in C with the argument in DE, returning in HL, A and B. With a journal on the cpu the calls that
+    // read the console or host files are journaled: when replaying their value and memory writes come from the log,
+    // and only the calls writing to the console are made, for their output.
+    pub fn call(&mut self, cpu: &mut Cpu) {
+        let output = matches!(cpu.reg.c, 0x00 | 0x02 | 0x04 | 0x05 | 0x09) || (cpu.reg.c == 0x06 && cpu.reg.e < 0xfe);
+        let mem = cpu.mem.clone();
+        let r = match cpu.journal.take() {
+            Some(mut j) if !output => {
+                let (r, writes) = j.call(cpu.cycles, || {
+                    let mut m = Noted { mem: &mut *mem.borrow_mut(), writes: vec![] };
+                    let r = self.dispatch(cpu, &mut m);
+                    (r, m.runs())
+                });
+                replay::put(&mut *mem.borrow_mut(), &writes);
+                cpu.journal = Some(j);
+                r
+            }
+            j => {
+                cpu.journal = j;
+                self.dispatch(cpu, &mut *mem.borrow_mut())
+            }
+        };
+        cpu.reg.set_hl(r);
+        cpu.reg.a = r as u8;
+        cpu.reg.b = (r >> 8) as u8;
+    }
+
+    fn dispatch(&mut self, cpu: &mut Cpu, mem: &mut dyn Memory) -> u16 {
+        let e = cpu.reg.e;
+        let de = cpu.reg.get_de();
+        match cpu.reg.c {
+            // System reset
+            0x00 => {
+                cpu.reg.pc = 0x0000;
+                0
+            }
+            // Console input
+            0x01 => {
+                let c = self.get();
+                self.put(c);
+                u16::from(c)
+            }
+            // Console output
+            0x02 => {
+                self.put(e);
+                0
+            }
+            // Reader input
+            0x03 => u16::from(EOF),
+            // Punch output, list output
+            0x04 | 0x05 => 0,
+            // Direct console I/O
+            0x06 => match e {
+                0xff => {
+                    let ready = self.console.borrow_mut().status();
+                    if ready {
+                        u16::from(self.get())
+                    } else {
+                        0
+                    }
+                }
+                0xfe => {
+                    if self.console.borrow_mut().status() {
+                        0xff
+                    } else {
+                        0
+                    }
+                }
+                _ => {
+                    self.put(e);
+                    0
+                }
+            },
+            // Get I/O byte
+            0x07 => u16::from(self.iobyte),
+            // Set I/O byte
+            0x08 => {
+                self.iobyte = e;
+                mem.set(0x0003, e);
+                0
+            }
+            // Print string
+            0x09 => {
+                let mut a = de;
+                loop {
+                    let c = mem.get(a);
+                    if c == b'$' {
+                        break;
+                    }
+                    self.put(c);
+                    a = a.wrapping_add(1);
+                }
+                0
+            }
+            // Read console buffer
+            0x0a => {
+                let max = mem.get(de);
+                let mut n: u8 = 0;
+                loop {
+                    let c = self.get();
+                    match c {
+                        b'\r' | b'\n' => break,
+                        0x08 | 0x7f if n > 0 => {
+                            n -= 1;
+                            for c in [0x08, b' ', 0x08] {
+                                self.put(c);
+                            }
+                        }
+                        0x08 | 0x7f => {}
+                        _ if n < max => {
+                            mem.set(de.wrapping_add(2 + u16::from(n)), c);
+                            n += 1;
+                            self.put(c);
+                        }
+                        _ => {}
+                    }
+                }
+                mem.set(de.wrapping_add(1), n);
+                self.put(b'\r');
+                0
+            }
+            // Get console status
+            0x0b => {
+                if self.console.borrow_mut().status() {
+                    0xff
+                } else {
+                    0
+                }
+            }
+            // Return version number
+            0x0c => 0x0022,
+            // Reset disk system
+            0x0d => {
+                self.dma = DMA;
+                self.drive = 0;
+                mem.set(0x0004, self.user << 4);
+                0
+            }
+            // Select disk
+            0x0e if self.drives.get(usize::from(e)).is_some_and(|e| e.is_some()) => {
+                self.drive = e;
+                mem.set(0x0004, (self.user << 4) | e);
+                0
+            }
+            0x0e => 0xff,
+            0x0f => self.open(mem, de),
+            // Close file, data is written through to the host on every write
+            0x10 => self.status(self.find(mem, de).is_some()),
+            0x11 => self.search_first(mem, de),
+            0x12 => self.search_next(mem),
+            0x13 => self.delete(mem, de),
+            0x14 => self.read_sequential(mem, de),
+            0x15 => self.write_sequential(mem, de),
+            0x16 => self.make(mem, de),
+            0x17 => self.rename(mem, de),
+            // Return login vector
+            0x18 => self.drives.iter().enumerate().filter(|(_, e)| e.is_some()).fold(0, |acc, (i, _)| acc | (1 << i)),
+            // Return current disk
+            0x19 => u16::from(self.drive),
+            // Set DMA address
+            0x1a => {
+                self.dma = de;
+                0
+            }
+            // Get allocation vector address, write protect disk, get read only vector. There are no disk structures
+            // behind a host directory.
+            0x1b..=0x1d => 0,
+            0x1e => self.set_attributes(mem, de),
+            // Get disk parameter block address
+            0x1f => 0,
+            // Set or get user code
+            0x20 => {
+                if e == 0xff {
+                    u16::from(self.user)
+                } else {
+                    self.user = e & 0x0f;
+                    mem.set(0x0004, (self.user << 4) | self.drive);
+                    0
+                }
+            }
+            0x21 => self.read_random(mem, de),
+            0x22 | 0x28 => self.write_random(mem, de),
+            0x23 => self.file_size(mem, de),
+            // Set random record
+            0x24 => {
+                let r = position(mem, de);
+                set_random(mem, de, r);
+                0
+            }
+            // Reset drive
+            0x25 => 0,
+            _ => 0xff,
+        }
+    }
+
+    fn status(&self, ok: bool) -> u16 {
+        if ok {
+            0
+        } else {
+            0xff
+        }
+    }
+
+    // Directory of a drive and user area on the host.
+    fn dir(&self, drive: u8, user: u8) -> Option<PathBuf> {
+        let d = self.drives.get(usize::from(drive))?.clone()?;
+        Some(if user == 0 { d } else { d.join(user.to_string()) })
+    }
+
+    fn fcb_drive(&self, mem: &dyn Memory, fcb: u16) -> u8 {
+        match mem.get(fcb.wrapping_add(FCB_DR)) & 0x1f {
+            0 => self.drive,
+            e => e - 1,
+        }
+    }
+
+    // List the CP/M visible files of a directory, with their sizes. Read only host files carry the CP/M read only
+    // attribute, the high bit of the first type character.
+    fn list(&self, dir: &Path) -> Vec<(Name, PathBuf, u64)> {
+        let mut r = Vec::new();
+        let Ok(rd) = fs::read_dir(dir) else { return r };
+        for e in rd.flatten() {
+            let Ok(meta) = e.metadata() else { continue };
+            if !meta.is_file() {
+                continue;
+            }
+            if let Some(mut n) = e.file_name().to_str().and_then(to_name) {
+                if meta.permissions().readonly() {
+                    n[8] |= 0x80;
+                }
+                r.push((n, e.path(), meta.len()));
+            }
+        }
+        r.sort();
+        r
+    }
+
+    // Host path of the file named by a file control block, if it exists.
+    fn find(&self, mem: &dyn Memory, fcb: u16) -> Option<PathBuf> {
+        let dir = self.dir(self.fcb_drive(mem, fcb), self.user)?;
+        let n = name(mem, fcb);
+        self.list(&dir).into_iter().find(|e| matches(&e.0, &n) && matches(&n, &e.0)).map(|e| e.1)
+    }
+
+    fn open(&mut self, mem: &mut dyn Memory, fcb: u16) -> u16 {
+        let dir = match self.dir(self.fcb_drive(mem, fcb), self.user) {
+            Some(d) => d,
+            None => return 0xff,
+        };
+        let n = name(mem, fcb);
+        let Some((n, _, size)) = self.list(&dir).into_iter().find(|e| matches(&n, &e.0)) else { return 0xff };
+        for (i, c) in n.iter().enumerate() {
+            mem.set(fcb.wrapping_add(FCB_NAME + i as u16), *c);
+        }
+        mem.set(fcb.wrapping_add(FCB_S2), 0x00);
+        let records = size.div_ceil(RECORD as u64);
+        let ex = u64::from(mem.get(fcb.wrapping_add(FCB_EX)) & 0x1f);
+        mem.set(fcb.wrapping_add(FCB_RC), records.saturating_sub(ex * 128).min(128) as u8);
+        0
+    }
+
+    fn make(&mut self, mem: &mut dyn Memory, fcb: u16) -> u16 {
+        let Some(dir) = self.dir(self.fcb_drive(mem, fcb), self.user) else { return 0xff };
+        let n = name(mem, fcb);
+        let path = self.find(mem, fcb).unwrap_or_else(|| dir.join(from_name(&n)));
+        if fs::create_dir_all(&dir).is_err() || fs::OpenOptions::new().create(true).append(true).open(&path).is_err() {
+            return 0xff;
+        }
+        for i in FCB_EX..=FCB_RC {
+            mem.set(fcb.wrapping_add(i), 0x00);
+        }
+        mem.set(fcb.wrapping_add(FCB_CR), 0x00);
+        0
+    }
+
+    fn delete(&mut self, mem: &dyn Memory, fcb: u16) -> u16 {
+        let Some(dir) = self.dir(self.fcb_drive(mem, fcb), self.user) else { return 0xff };
+        let n = name(mem, fcb);
+        let mut ok = false;
+        let files: Vec<_> = self.list(&dir).into_iter().filter(|e| matches(&n, &e.0)).collect();
+        if files.iter().any(|e| readonly(&e.0)) {
+            return 0xff;
+        }
+        for (_, p, _) in files {
+            ok |= fs::remove_file(p).is_ok();
+        }
+        self.status(ok)
+    }
+
+    // The new name is given in the second half of the file control block.
+    fn rename(&mut self, mem: &dyn Memory, fcb: u16) -> u16 {
+        let Some(dir) = self.dir(self.fcb_drive(mem, fcb), self.user) else { return 0xff };
+        let Some(src) = self.find(mem, fcb) else { return 0xff };
+        if fs::metadata(&src).is_ok_and(|e| e.permissions().readonly()) {
+            return 0xff;
+        }
+        let dst = dir.join(from_name(&name(mem, fcb.wrapping_add(0x10))));
+        self.status(self.find(mem, fcb.wrapping_add(0x10)).is_none() && fs::rename(src, dst).is_ok())
+    }
+
+    // Search results are returned as directory entries in the first slot of the DMA buffer.
+    fn search_first(&mut self, mem: &mut dyn Memory, fcb: u16) -> u16 {
+        self.search.clear();
+        let all = mem.get(fcb.wrapping_add(FCB_DR)) == b'?';
+        let drive = if all { self.drive } else { self.fcb_drive(mem, fcb) };
+        let n = name(mem, fcb);
+        for user in 0..16 {
+            if !all && user != self.user {
+                continue;
+            }
+            let Some(dir) = self.dir(drive, user) else { continue };
+            for (e, _, size) in self.list(&dir) {
+                if all || matches(&n, &e) {
+                    self.search.push_back((user, e, size));
+                }
+            }
+        }
+        self.search_next(mem)
+    }
+
+    fn search_next(&mut self, mem: &mut dyn Memory) -> u16 {
+        let Some((user, n, size)) = self.search.pop_front() else { return 0xff };
+        let records = size.div_ceil(RECORD as u64);
+        // Large files are described by a single entry for their last extent.
+        let ex = records.saturating_sub(1) / 128;
+        let mut entry = [0u8; 32];
+        entry[0] = user;
+        entry[1..12].copy_from_slice(&n);
+        entry[12] = (ex & 0x1f) as u8;
+        entry[14] = (ex >> 5) as u8;
+        entry[15] = (records - ex * 128) as u8;
+        for (i, b) in entry[16..].iter_mut().enumerate() {
+            *b = if (i as u64) < records.div_ceil(8) { 1 + i as u8 } else { 0 };
+        }
+        for (i, c) in entry.iter().enumerate() {
+            mem.set(self.dma.wrapping_add(i as u16), *c);
+        }
+        0
+    }
+
+    fn read_record(&self, mem: &mut dyn Memory, fcb: u16, r: u32) -> io::Result<bool> {
+        let path = self.find(mem, fcb).ok_or(io::ErrorKind::NotFound)?;
+        let mut f = fs::File::open(path)?;
+        let off = u64::from(r) * RECORD as u64;
+        if off >= f.metadata()?.len() {
+            return Ok(false);
+        }
+        f.seek(SeekFrom::Start(off))?;
+        let mut buf = [EOF; RECORD];
+        let mut n = 0;
+        while n < RECORD {
+            match f.read(&mut buf[n..])? {
+                0 => break,
+                e => n += e,
+            }
+        }
+        for (i, c) in buf.iter().enumerate() {
+            mem.set(self.dma.wrapping_add(i as u16), *c);
+        }
+        Ok(true)
+    }
+
+    fn write_record(&self, mem: &dyn Memory, fcb: u16, r: u32) -> io::Result<()> {
+        let path = self.find(mem, fcb).ok_or(io::ErrorKind::NotFound)?;
+        if fs::metadata(&path)?.permissions().readonly() {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let mut f = fs::OpenOptions::new().write(true).open(path)?;
+        f.seek(SeekFrom::Start(u64::from(r) * RECORD as u64))?;
+        let buf: Vec<u8> = (0..RECORD as u16).map(|i| mem.get(self.dma.wrapping_add(i))).collect();
+        f.write_all(&buf)
+    }
+
+    fn read_sequential(&mut self, mem: &mut dyn Memory, fcb: u16) -> u16 {
+        let r = position(mem, fcb);
+        match self.read_record(mem, fcb, r) {
+            Ok(true) => {
+                set_position(mem, fcb, r + 1);
+                0
+            }
+            Ok(false) => 1,
+            Err(_) => 0xff,
+        }
+    }
+
+    fn write_sequential(&mut self, mem: &mut dyn Memory, fcb: u16) -> u16 {
+        let r = position(mem, fcb);
+        match self.write_record(mem, fcb, r) {
+            Ok(()) => {
+                set_position(mem, fcb, r + 1);
+                0
+            }
+            Err(_) => 2,
+        }
+    }
+
+    fn read_random(&mut self, mem: &mut dyn Memory, fcb: u16) -> u16 {
+        let r = random(mem, fcb);
+        if r > 0xffff {
+            return 6;
+        }
+        match self.read_record(mem, fcb, r) {
+            Ok(true) => {
+                set_position(mem, fcb, r);
+                0
+            }
+            Ok(false) => 1,
+            Err(_) => 0xff,
+        }
+    }
+
+    fn write_random(&mut self, mem: &mut dyn Memory, fcb: u16) -> u16 {
+        let r = random(mem, fcb);
+        if r > 0xffff {
+            return 6;
+        }
+        match self.write_record(mem, fcb, r) {
+            Ok(()) => {
+                set_position(mem, fcb, r);
+                0
+            }
+            Err(_) => 2,
+        }
+    }
+
+    // Only the read only attribute has a host counterpart.
+    fn set_attributes(&mut self, mem: &dyn Memory, fcb: u16) -> u16 {
+        let Some(path) = self.find(mem, fcb) else { return 0xff };
+        let Ok(meta) = fs::metadata(&path) else { return 0xff };
+        let mut perm = meta.permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        perm.set_readonly(mem.get(fcb.wrapping_add(FCB_NAME + 8)) & 0x80 != 0);
+        self.status(fs::set_permissions(path, perm).is_ok())
+    }
+
+    fn file_size(&mut self, mem: &mut dyn Memory, fcb: u16) -> u16 {
+        match self.find(mem, fcb).and_then(|p| fs::metadata(p).ok()) {
+            Some(m) => {
+                set_random(mem, fcb, m.len().div_ceil(RECORD as u64) as u32);
+                0
+            }
+            None => 0xff,
+        }
+    }
+}
+
+fn readonly(n: &Name) -> bool {
+    n[8] & 0x80 != 0
+}
+
+fn name(mem: &dyn Memory, fcb: u16) -> Name {
+    let mut n = [0; 11];
+    for (i, c) in n.iter_mut().enumerate() {
+        *c = mem.get(fcb.wrapping_add(FCB_NAME + i as u16)) & 0x7f;
+    }
+    n
+}
+
+// Parse a file name typed on the command line into the padded form, expanding '*' to '?'.
+fn parse_name(s: &[u8]) -> Name {
+    let mut r = [b' '; 11];
+    let (n, t) = match s.iter().position(|c| *c == b'.') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, &[][..]),
+    };
+    for (field, len, off) in [(n, 8, 0), (t, 3, 8)] {
+        for i in 0..len {
+            match field.get(i) {
+                Some(b'*') => {
+                    for e in r[off + i..off + len].iter_mut() {
+                        *e = b'?';
+                    }
+                    break;
+                }
+                Some(c) => r[off + i] = *c,
+                None => break,
+            }
+        }
+    }
+    r
+}
+
+// The sequential record position, made of the extent number and the current record within the extent.
+fn position(mem: &dyn Memory, fcb: u16) -> u32 {
+    let ex = u32::from(mem.get(fcb.wrapping_add(FCB_EX)) & 0x1f)
+        + (u32::from(mem.get(fcb.wrapping_add(FCB_S2)) & 0x3f) << 5);
+    ex * 128 + u32::from(mem.get(fcb.wrapping_add(FCB_CR)) & 0x7f)
+}
+
+fn set_position(mem: &mut dyn Memory, fcb: u16, r: u32) {
+    let ex = r / 128;
+    mem.set(fcb.wrapping_add(FCB_EX), (ex & 0x1f) as u8);
+    mem.set(fcb.wrapping_add(FCB_S2), (ex >> 5) as u8);
+    mem.set(fcb.wrapping_add(FCB_CR), (r % 128) as u8);
+}
+
+fn random(mem: &dyn Memory, fcb: u16) -> u32 {
+    let a = fcb.wrapping_add(FCB_R0);
+    u32::from(mem.get(a)) | (u32::from(mem.get(a.wrapping_add(1))) << 8) | (u32::from(mem.get(a.wrapping_add(2))) << 16)
+}
+
+fn set_random(mem: &mut dyn Memory, fcb: u16, r: u32) {
+    let a = fcb.wrapping_add(FCB_R0);
+    mem.set(a, r as u8);
+    mem.set(a.wrapping_add(1), (r >> 8) as u8);
+    mem.set(a.wrapping_add(2), (r >> 16) as u8);
+}
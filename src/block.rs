@@ -0,0 +1,158 @@
+// A cache of decoded basic blocks, for running code faster than one instruction at a time. A block is the run of
+// instructions from an address up to and including the first that may change the flow of control: a jump, call,
+// return, RST, PCHL or HLT. Its instructions are kept decoded, each as its canonical opcode with the operand, so running
+// a block neither reads memory for the code nor maps the undocumented opcodes again. They run through the same code as
+// the interpreter, so the machine ends in the same state, cycle for cycle.
+//
+// Blocks are indexed by the 256 byte pages they cover. A write by the cpu into a block drops the block, and a block
+// being run stops after the instruction that wrote into it, so self-modifying code sees its new bytes. Memory written
+// from outside the cpu, such as by a BDOS call or a DMA device, must be reported with Cpu::invalidate.
+use super::asm;
+use super::cpu;
+use super::memory::Memory;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Instructions a block holds at most.
+pub const MAX_OPS: usize = 64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Op {
+    // Canonical opcode.
+    pub code: u8,
+    pub len: u8,
+    pub imm: u16,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    // Bytes the instructions take.
+    pub size: u16,
+    pub ops: Vec<Op>,
+}
+
+// True for the instructions ending a block.
+pub fn ends_block(code: u8) -> bool {
+    matches!(code, 0x76 | 0xe9 | 0xc3 | 0xc9 | 0xcd) || matches!(code & 0xc7, 0xc0 | 0xc2 | 0xc4 | 0xc7)
+}
+
+impl Block {
+    // Decode the block starting at an address.
+    pub fn decode(mem: &dyn Memory, start: u16) -> Self {
+        let mut ops = vec![];
+        let mut pc = start;
+        while ops.len() < MAX_OPS {
+            let opcode = mem.get(pc);
+            let len = asm::length(opcode);
+            let imm = match len {
+                1 => 0,
+                2 => u16::from(mem.get(pc.wrapping_add(1))),
+                _ => u16::from_le_bytes([mem.get(pc.wrapping_add(1)), mem.get(pc.wrapping_add(2))]),
+            };
+            let code = cpu::canonical(opcode);
+            ops.push(Op { code, len: len as u8, imm });
+            pc = pc.wrapping_add(len as u16);
+            if ends_block(code) {
+                break;
+            }
+        }
+        Self { start, size: pc.wrapping_sub(start), ops }
+    }
+
+    // True if the block holds the byte at the address.
+    pub fn contains(&self, addr: u16) -> bool {
+        addr.wrapping_sub(self.start) < self.size
+    }
+
+    // Pages the bytes of the block lie on.
+    fn pages(&self) -> impl Iterator<Item = usize> {
+        let first = usize::from(self.start >> 8);
+        let last = usize::from(self.start.wrapping_add(self.size - 1) >> 8);
+        let n = (last + 0x100 - first) % 0x100;
+        (0..=n).map(move |i| (first + i) % 0x100)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    // Blocks run from the cache and blocks decoded.
+    pub hits: u64,
+    pub misses: u64,
+    // Blocks dropped because their code was written.
+    pub invalidations: u64,
+}
+
+pub struct Cache {
+    blocks: HashMap<u16, Rc<Block>>,
+    // Start of the blocks on each page.
+    pages: Vec<Vec<u16>>,
+    // Counts the invalidations, for a running block to see its code changed.
+    pub(crate) generation: u64,
+    pub stats: Stats,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self { blocks: HashMap::new(), pages: vec![vec![]; 0x100], generation: 0, stats: Stats::default() }
+    }
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.pages.iter_mut().for_each(|e| e.clear());
+        self.generation += 1;
+    }
+
+    // The block at an address, decoded if it is not in the cache.
+    pub fn get(&mut self, mem: &dyn Memory, start: u16) -> Rc<Block> {
+        if let Some(b) = self.blocks.get(&start) {
+            self.stats.hits += 1;
+            return b.clone();
+        }
+        self.stats.misses += 1;
+        let b = Rc::new(Block::decode(mem, start));
+        for p in b.pages() {
+            self.pages[p].push(start);
+        }
+        self.blocks.insert(start, b.clone());
+        b
+    }
+
+    // Drop the blocks holding the byte at an address.
+    pub fn write(&mut self, addr: u16) {
+        let page = usize::from(addr >> 8);
+        if self.pages[page].is_empty() {
+            return;
+        }
+        let hit: Vec<u16> = self.pages[page].iter().copied().filter(|e| self.blocks[e].contains(addr)).collect();
+        for start in hit {
+            let b = self.blocks.remove(&start).unwrap();
+            for p in b.pages() {
+                self.pages[p].retain(|e| *e != start);
+            }
+            self.stats.invalidations += 1;
+            self.generation += 1;
+        }
+    }
+
+    // Drop the blocks holding any of n bytes from an address.
+    pub fn invalidate(&mut self, addr: u16, n: usize) {
+        for i in 0..n {
+            self.write(addr.wrapping_add(i as u16));
+        }
+    }
+}
@@ -0,0 +1,447 @@
+// A CP/M 2.2 machine that boots the CCP and BDOS from a system disk. The BIOS is a small piece of 8080 code built in
+// memory that talks to a console and a disk controller on the I/O bus, except for cold and warm boot which are
+// handled in Rust when the program counter reaches their jump table entries.
+//
+// Memory map of a 64K system:
+//
+//   0x0000  Page zero
+//   0x0100  TPA
+//   0xe400  CCP
+//   0xec00  BDOS
+//   0xfa00  BIOS jump table, followed by the BIOS code and disk tables
+//
+// I/O ports, following the layout used by the z80pack simulator:
+//
+//   0x00  Console status, 0xff if a character is ready
+//   0x01  Console data
+//   0x0a  Disk controller drive select
+//   0x0b  Track, low byte
+//   0x0c  Sector, one based
+//   0x0d  Command, 0 to read and 1 to write a sector at the DMA address
+//   0x0e  Status of the last command, 0 on success
+//   0x0f  DMA address, low byte
+//   0x10  DMA address, high byte
+//   0x11  Track, high byte
+use super::bdos::Console;
+use super::cpu::Cpu;
+use super::device::{Bus, Device};
+use super::diskimg::{Disk, Geometry};
+use super::memory::{Linear, Memory};
+use super::replay;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+pub const CON_STATUS: u8 = 0x00;
+pub const CON_DATA: u8 = 0x01;
+pub const FDC_DRIVE: u8 = 0x0a;
+pub const FDC_TRACK: u8 = 0x0b;
+pub const FDC_SECTOR: u8 = 0x0c;
+pub const FDC_COMMAND: u8 = 0x0d;
+pub const FDC_STATUS: u8 = 0x0e;
+pub const FDC_DMA_LO: u8 = 0x0f;
+pub const FDC_DMA_HI: u8 = 0x10;
+pub const FDC_TRACK_HI: u8 = 0x11;
+
+// Size of the CCP and BDOS, loaded from the system tracks on every boot.
+pub const SYSTEM_SIZE: u16 = 0x1600;
+pub const CCP_SIZE: u16 = 0x0800;
+
+// Console on the I/O bus.
+pub struct Tty {
+    pub console: Rc<RefCell<dyn Console>>,
+}
+
+impl Device for Tty {
+    fn get(&mut self, port: u8) -> u8 {
+        let mut c = self.console.borrow_mut();
+        match port {
+            CON_STATUS => {
+                if c.status() {
+                    0xff
+                } else {
+                    0x00
+                }
+            }
+            _ => c.read(),
+        }
+    }
+
+    fn set(&mut self, port: u8, v: u8) {
+        if port == CON_DATA {
+            self.console.borrow_mut().write(v)
+        }
+    }
+}
+
+// Disk controller transferring whole sectors between a disk and memory.
+pub struct Fdc {
+    pub drives: Vec<Option<Box<dyn Disk>>>,
+    mem: Rc<RefCell<dyn Memory>>,
+    drive: u8,
+    track: u16,
+    sector: u8,
+    dma: u16,
+    status: u8,
+    // Sectors read into memory, with their addresses, kept for the journal while the machine has one.
+    reads: Option<Vec<(u16, Vec<u8>)>>,
+}
+
+impl Fdc {
+    pub fn new(mem: Rc<RefCell<dyn Memory>>) -> Self {
+        Self {
+            drives: (0..16).map(|_| None).collect(),
+            mem,
+            drive: 0,
+            track: 0,
+            sector: 1,
+            dma: 0x0080,
+            status: 0,
+            reads: None,
+        }
+    }
+
+    pub fn insert(&mut self, drive: u8, disk: Box<dyn Disk>) {
+        self.drives[usize::from(drive)] = Some(disk);
+    }
+
+    pub fn eject(&mut self, drive: u8) -> Option<Box<dyn Disk>> {
+        self.drives[usize::from(drive)].take()
+    }
+
+    pub fn geometry(&self, drive: u8) -> Option<&Geometry> {
+        self.drives.get(usize::from(drive))?.as_ref().map(|d| d.geometry())
+    }
+
+    // Read a sector into memory, with the sector numbered from one.
+    pub fn read(&mut self, drive: u8, track: u16, sector: u8, dma: u16) -> io::Result<()> {
+        let disk = self.disk(drive)?;
+        let mut buf = vec![0; disk.geometry().sector_size];
+        disk.read(track, u16::from(sector).wrapping_sub(1), &mut buf)?;
+        if let Some(r) = &mut self.reads {
+            r.push((dma, buf.clone()));
+        }
+        let mut mem = self.mem.borrow_mut();
+        for (i, e) in buf.iter().enumerate() {
+            mem.set(dma.wrapping_add(i as u16), *e);
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, drive: u8, track: u16, sector: u8, dma: u16) -> io::Result<()> {
+        let size = self.disk(drive)?.geometry().sector_size;
+        let buf: Vec<u8> = (0..size).map(|i| self.mem.borrow().get(dma.wrapping_add(i as u16))).collect();
+        self.disk(drive)?.write(track, u16::from(sector).wrapping_sub(1), &buf)
+    }
+
+    fn disk(&mut self, drive: u8) -> io::Result<&mut Box<dyn Disk>> {
+        self.drives
+            .get_mut(usize::from(drive))
+            .and_then(|e| e.as_mut())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cpm: no disk in drive"))
+    }
+}
+
+impl Device for Fdc {
+    fn get(&mut self, port: u8) -> u8 {
+        match port {
+            FDC_DRIVE => self.drive,
+            FDC_TRACK => self.track as u8,
+            FDC_SECTOR => self.sector,
+            FDC_STATUS => self.status,
+            FDC_DMA_LO => self.dma as u8,
+            FDC_DMA_HI => (self.dma >> 8) as u8,
+            FDC_TRACK_HI => (self.track >> 8) as u8,
+            _ => 0xff,
+        }
+    }
+
+    fn set(&mut self, port: u8, v: u8) {
+        match port {
+            FDC_DRIVE => self.drive = v,
+            FDC_TRACK => self.track = (self.track & 0xff00) | u16::from(v),
+            FDC_SECTOR => self.sector = v,
+            FDC_COMMAND => {
+                let r = match v {
+                    0 => self.read(self.drive, self.track, self.sector, self.dma),
+                    1 => self.write(self.drive, self.track, self.sector, self.dma),
+                    _ => Err(io::ErrorKind::InvalidInput.into()),
+                };
+                self.status = u8::from(r.is_err());
+            }
+            FDC_DMA_LO => self.dma = (self.dma & 0xff00) | u16::from(v),
+            FDC_DMA_HI => self.dma = (self.dma & 0x00ff) | (u16::from(v) << 8),
+            FDC_TRACK_HI => self.track = (self.track & 0x00ff) | (u16::from(v) << 8),
+            _ => {}
+        }
+    }
+}
+
+// Builds the BIOS image, keeping track of the address of the next byte.
+struct Asm {
+    base: u16,
+    code: Vec<u8>,
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "cpm: BIOS does not fit in memory")
+}
+
+impl Asm {
+    // The address of the next byte, an error once the code has reached the end of memory.
+    fn here(&self) -> io::Result<u16> {
+        u16::try_from(usize::from(self.base) + self.code.len()).map_err(|_| too_large())
+    }
+
+    fn db(&mut self, data: &[u8]) -> &mut Self {
+        self.code.extend_from_slice(data);
+        self
+    }
+
+    fn dw(&mut self, v: u16) -> &mut Self {
+        self.db(&v.to_le_bytes())
+    }
+
+    fn patch(&mut self, a: u16, v: u16) {
+        let i = usize::from(a - self.base);
+        self.code[i..i + 2].copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+pub struct Machine {
+    pub cpu: Cpu,
+    pub mem: Rc<RefCell<Linear>>,
+    pub bus: Rc<RefCell<Bus>>,
+    pub fdc: Rc<RefCell<Fdc>>,
+    pub ccp: u16,
+}
+
+impl Machine {
+    // A 64K system with the console on the I/O bus.
+    pub fn new(console: Rc<RefCell<dyn Console>>) -> Self {
+        let mem = Rc::new(RefCell::new(Linear::new()));
+        let fdc = Rc::new(RefCell::new(Fdc::new(mem.clone())));
+        let mut bus = Bus::new();
+        bus.attach(CON_STATUS..=CON_DATA, Rc::new(RefCell::new(Tty { console })));
+        bus.attach(FDC_DRIVE..=FDC_TRACK_HI, fdc.clone());
+        let bus = Rc::new(RefCell::new(bus));
+        let mut cpu = Cpu::power_up(mem.clone());
+        cpu.dev = Some(bus.clone());
+        Self { cpu, mem, bus, fdc, ccp: 0xe400 }
+    }
+
+    pub fn insert(&mut self, drive: u8, disk: Box<dyn Disk>) {
+        self.fdc.borrow_mut().insert(drive, disk)
+    }
+
+    // Where the BDOS and BIOS go with the CCP at ccp. A ccp too high for them fails to boot.
+    pub fn bdos(&self) -> u16 {
+        self.ccp.wrapping_add(CCP_SIZE)
+    }
+
+    pub fn bios(&self) -> u16 {
+        self.ccp.wrapping_add(SYSTEM_SIZE)
+    }
+
+    // End of the CCP and BDOS, which is where the BIOS starts.
+    fn system_end(&self) -> io::Result<u16> {
+        self.ccp.checked_add(SYSTEM_SIZE).ok_or_else(too_large)
+    }
+
+    // Build the BIOS for the drives currently inserted. Drives must be inserted from A: on without gaps, and fail to
+    // boot when their tables do not fit in memory.
+    fn build_bios(&self) -> io::Result<Vec<u8>> {
+        let fdc = self.fdc.borrow();
+        let geometries: Vec<Geometry> = (0..16).map_while(|i| fdc.geometry(i).cloned()).collect();
+        let mut a = Asm { base: self.system_end()?, code: vec![] };
+        // Jump table, patched below once the entry points are known. BOOT and WBOOT are trapped and halt if the
+        // machine is not driven by step().
+        a.db(&[0x76, 0x00, 0x00, 0x76, 0x00, 0x00]);
+        for _ in 2..17 {
+            a.db(&[0xc3, 0x00, 0x00]);
+        }
+        let entry = |a: &mut Asm, i: u16| -> io::Result<()> {
+            let h = a.here()?;
+            a.patch(a.base + 3 * i + 1, h);
+            Ok(())
+        };
+        // CONST: IN 0; RET
+        entry(&mut a, 2)?;
+        a.db(&[0xdb, CON_STATUS, 0xc9]);
+        // CONIN: IN 0; ORA A; JZ CONIN; IN 1; ANI 7FH; RET
+        entry(&mut a, 3)?;
+        let h = a.here()?;
+        a.db(&[0xdb, CON_STATUS, 0xb7, 0xca]).dw(h).db(&[0xdb, CON_DATA, 0xe6, 0x7f, 0xc9]);
+        // CONOUT: MOV A,C; OUT 1; RET
+        entry(&mut a, 4)?;
+        a.db(&[0x79, 0xd3, CON_DATA, 0xc9]);
+        // LIST, PUNCH: RET
+        entry(&mut a, 5)?;
+        entry(&mut a, 6)?;
+        a.db(&[0xc9]);
+        // READER: MVI A,1AH; RET
+        entry(&mut a, 7)?;
+        a.db(&[0x3e, 0x1a, 0xc9]);
+        // HOME: LXI B,0; falls through to SETTRK
+        entry(&mut a, 8)?;
+        a.db(&[0x01, 0x00, 0x00]);
+        // SETTRK: MOV A,C; OUT 0BH; MOV A,B; OUT 11H; RET
+        entry(&mut a, 10)?;
+        a.db(&[0x79, 0xd3, FDC_TRACK, 0x78, 0xd3, FDC_TRACK_HI, 0xc9]);
+        // SETSEC: MOV A,C; OUT 0CH; RET
+        entry(&mut a, 11)?;
+        a.db(&[0x79, 0xd3, FDC_SECTOR, 0xc9]);
+        // SETDMA: MOV A,C; OUT 0FH; MOV A,B; OUT 10H; RET
+        entry(&mut a, 12)?;
+        a.db(&[0x79, 0xd3, FDC_DMA_LO, 0x78, 0xd3, FDC_DMA_HI, 0xc9]);
+        // READ: XRA A; OUT 0DH; IN 0EH; RET
+        entry(&mut a, 13)?;
+        a.db(&[0xaf, 0xd3, FDC_COMMAND, 0xdb, FDC_STATUS, 0xc9]);
+        // WRITE: MVI A,1; OUT 0DH; IN 0EH; RET
+        entry(&mut a, 14)?;
+        a.db(&[0x3e, 0x01, 0xd3, FDC_COMMAND, 0xdb, FDC_STATUS, 0xc9]);
+        // LISTST: XRA A; RET
+        entry(&mut a, 15)?;
+        a.db(&[0xaf, 0xc9]);
+        // SECTRAN: MOV A,D; ORA E; JNZ T; MOV H,B; MOV L,C; INX H; RET; T: XCHG; DAD B; MOV L,M; MVI H,0; RET
+        entry(&mut a, 16)?;
+        let h = a.here()?;
+        a.db(&[0x7a, 0xb3, 0xc2])
+            .dw(h.wrapping_add(9))
+            .db(&[0x60, 0x69, 0x23, 0xc9, 0xeb, 0x09, 0x6e, 0x26, 0x00, 0xc9]);
+        // SELDSK: LXI H,0; MOV A,C; CPI N; RNC; OUT 0AH; MOV L,C; DAD H; DAD H; DAD H; DAD H; LXI D,DPBASE; DAD D; RET
+        entry(&mut a, 9)?;
+        let h = a.here()?;
+        a.db(&[0x21, 0x00, 0x00, 0x79, 0xfe, geometries.len() as u8, 0xd0, 0xd3, FDC_DRIVE, 0x69]);
+        a.db(&[0x29, 0x29, 0x29, 0x29, 0x11]).dw(0x0000).db(&[0x19, 0xc9]);
+        // Disk parameter headers, then the tables they point to.
+        let dph = a.here()?;
+        a.patch(h.wrapping_add(15), dph);
+        a.db(&vec![0; 16 * geometries.len()]);
+        let dirbuf = a.here()?;
+        a.db(&[0; 128]);
+        for (i, g) in geometries.iter().enumerate() {
+            let xlt = if g.xlt.is_empty() {
+                0
+            } else {
+                let h = a.here()?;
+                a.db(&g.xlt);
+                h
+            };
+            let dpb = a.here()?;
+            a.db(&g.dpb.encode());
+            let csv = a.here()?;
+            a.db(&vec![0; usize::from(g.dpb.cks)]);
+            let alv = a.here()?;
+            a.db(&vec![0; usize::from(g.dpb.dsm) / 8 + 1]);
+            let e = dph + 16 * i as u16;
+            a.patch(e, xlt);
+            a.patch(e + 8, dirbuf);
+            a.patch(e + 10, dpb);
+            a.patch(e + 12, csv);
+            a.patch(e + 14, alv);
+        }
+        if usize::from(a.base) + a.code.len() > 0x10000 {
+            return Err(too_large());
+        }
+        Ok(a.code)
+    }
+
+    // Load the CCP and BDOS from the system tracks of drive A:, starting right after the boot sector.
+    fn load_system(&mut self) -> io::Result<()> {
+        let mut fdc = self.fdc.borrow_mut();
+        let g =
+            fdc.geometry(0).cloned().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cpm: no disk in A:"))?;
+        let end = usize::from(self.system_end()?);
+        let mut dma = usize::from(self.ccp);
+        let mut n = 1;
+        while dma < end {
+            let track = n / g.sectors;
+            let sector = (n % g.sectors) as u8 + 1;
+            fdc.read(0, track, sector, dma as u16)?;
+            dma += g.sector_size;
+            n += 1;
+        }
+        Ok(())
+    }
+
+    // Set up page zero and enter the CCP with the current drive in C.
+    fn go_cpm(&mut self) {
+        let bios = self.bios();
+        let bdos = self.bdos();
+        {
+            let mut mem = self.mem.borrow_mut();
+            mem.set(0x0000, 0xc3);
+            mem.set_word(0x0001, bios + 3);
+            mem.set(0x0005, 0xc3);
+            mem.set_word(0x0006, bdos + 6);
+        }
+        self.bus.borrow_mut().set(FDC_DMA_LO, 0x80);
+        self.bus.borrow_mut().set(FDC_DMA_HI, 0x00);
+        self.cpu.reg.c = self.mem.borrow().get(0x0004);
+        self.cpu.reg.sp = 0x0100;
+        self.cpu.reg.pc = self.ccp;
+    }
+
+    // Cold boot: install the BIOS, load the system and start the CCP on drive A:.
+    pub fn boot(&mut self) -> io::Result<()> {
+        let bios = self.build_bios()?;
+        let base = self.bios();
+        {
+            let mut mem = self.mem.borrow_mut();
+            for (i, e) in bios.iter().enumerate() {
+                mem.set(base + i as u16, *e);
+            }
+            mem.set(0x0003, 0x00);
+            mem.set(0x0004, 0x00);
+        }
+        self.load_system()?;
+        self.cpu.halted = false;
+        self.go_cpm();
+        Ok(())
+    }
+
+    pub fn wboot(&mut self) -> io::Result<()> {
+        self.load_system()?;
+        self.go_cpm();
+        Ok(())
+    }
+
+    // Execute one instruction, handling the boot entry points of the BIOS first. A failed boot halts the cpu. With a
+    // journal on the cpu the sectors read into memory, by the boot or by the instruction, are journaled, and replaced
+    // by the logged ones when replaying.
+    pub fn step(&mut self) -> u32 {
+        self.fdc.borrow_mut().reads = self.cpu.journal.is_some().then(Vec::new);
+        let pc = self.cpu.reg.pc;
+        let cycles = self.cpu.cycles;
+        if pc == self.bios() || pc == self.bios().wrapping_add(3) {
+            let r = if pc == self.bios() { self.boot() } else { self.wboot() };
+            if r.is_err() {
+                self.cpu.halted = true;
+            }
+            // Before the instruction, which reads from the system it loaded.
+            self.journal_reads(cycles);
+        }
+        let n = self.cpu.next();
+        self.journal_reads(cycles);
+        n
+    }
+
+    // Give the sectors read so far to the journal, and put what it gives back in memory.
+    fn journal_reads(&mut self, cycles: u64) {
+        let Some(j) = &mut self.cpu.journal else { return };
+        let reads = self.fdc.borrow_mut().reads.replace(vec![]).unwrap_or_default();
+        let writes = j.writes(cycles, reads);
+        replay::put(&mut *self.mem.borrow_mut(), &writes);
+        for (addr, data) in &writes {
+            self.cpu.invalidate(*addr, data.len());
+        }
+    }
+
+    // Run until the cpu halts.
+    pub fn run(&mut self) {
+        while !self.cpu.halted {
+            self.step();
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use super::asm;
-use super::bit;
+use super::block::{Block, Cache};
+use super::device::Device;
+use super::history::History;
 use super::memory::Memory;
 use super::register::{Flag, Register};
+use super::replay::Journal;
+use super::trace::{Access, Step, Tracer};
 use rog::debugln;
 use std::cell::RefCell;
 use std::mem;
@@ -13,8 +17,20 @@ pub const CLOCK_FREQUENCY: u32 = 2_000_000;
 pub const STEP_TIME: u32 = 16;
 pub const STEP_CYCLES: u32 = (STEP_TIME as f64 / (1000_f64 / CLOCK_FREQUENCY as f64)) as u32;
 
+// The documented opcode an undocumented one behaves as.
+pub(crate) fn canonical(opcode: u8) -> u8 {
+    match opcode {
+        0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => 0x00,
+        0xcb => 0xc3,
+        0xd9 => 0xc9,
+        0xdd | 0xed | 0xfd => 0xcd,
+        _ => opcode,
+    }
+}
+
 //  0   1   2   3   4   5   6   7   8   9   a   b   c   d   e   f
-const OP_CYCLES: [u32; 256] = [
+#[allow(clippy::zero_prefixed_literal)]
+pub(crate) const OP_CYCLES: [u32; 256] = [
     04, 10, 07, 05, 05, 05, 07, 04, 04, 10, 07, 05, 05, 05, 07, 04, // 0
     04, 10, 07, 05, 05, 05, 07, 04, 04, 10, 07, 05, 05, 05, 07, 04, // 1
     04, 10, 16, 05, 05, 05, 07, 04, 04, 10, 16, 05, 05, 05, 07, 04, // 2
@@ -36,9 +52,25 @@ const OP_CYCLES: [u32; 256] = [
 pub struct Cpu {
     pub reg: Register,
     pub mem: Rc<RefCell<dyn Memory>>,
+    // Device answering IN and OUT. Without one the instructions only skip their port operand.
+    pub dev: Option<Rc<RefCell<dyn Device>>>,
     pub halted: bool,
     pub inte: bool,
-
+    // Cycles run since power up.
+    pub cycles: u64,
+    // Called after every instruction. Memory accesses are only recorded while a tracer is set.
+    pub tracer: Option<Box<dyn Tracer>>,
+    // Kept before every instruction when set, to undo it with step_back.
+    pub history: Option<History>,
+    // Records the inputs when set, or replays them.
+    pub journal: Option<Journal>,
+    // Decoded blocks for run_block and run_until.
+    pub cache: Option<Cache>,
+    // Runs the hot blocks of the cache as host code when set.
+    #[cfg(feature = "jit")]
+    pub jit: Option<super::jit::Jit>,
+
+    accesses: RefCell<Vec<Access>>,
     step_cycles: u32,
     step_zero: time::SystemTime,
 }
@@ -48,231 +80,163 @@ impl Cpu {
         Self {
             reg: Register::power_up(),
             mem,
+            dev: None,
             halted: false,
             inte: false,
+            cycles: 0,
+            tracer: None,
+            history: None,
+            journal: None,
+            cache: None,
+            #[cfg(feature = "jit")]
+            jit: None,
+            accesses: RefCell::new(vec![]),
             step_cycles: 0,
             step_zero: time::SystemTime::now(),
         }
     }
 
-    fn imm_ds(&mut self) -> u8 {
-        let v = self.mem.borrow().get(self.reg.pc);
-        self.reg.pc += 1;
+    fn record(&self, addr: u16, value: u8, write: bool) {
+        if self.tracer.is_some() {
+            self.accesses.borrow_mut().push(Access { addr, value, write });
+        }
+    }
+
+    fn mem_get(&self, a: u16) -> u8 {
+        let v = self.mem.borrow().get(a);
+        self.record(a, v, false);
         v
     }
 
-    fn imm_dw(&mut self) -> u16 {
-        let v = self.mem.borrow().get_word(self.reg.pc);
-        self.reg.pc += 2;
+    fn mem_set(&mut self, a: u16, v: u8) {
+        if let Some(c) = &mut self.cache {
+            c.write(a);
+        }
+        if let Some(h) = &mut self.history {
+            h.write(a, self.mem.borrow().get(a));
+        }
+        self.mem.borrow_mut().set(a, v);
+        self.record(a, v, true);
+    }
+
+    fn mem_get_word(&self, a: u16) -> u16 {
+        let v = self.mem.borrow().get_word(a);
+        self.record(a, v as u8, false);
+        self.record(a.wrapping_add(1), (v >> 8) as u8, false);
         v
     }
 
+    fn mem_set_word(&mut self, a: u16, v: u16) {
+        if let Some(c) = &mut self.cache {
+            c.write(a);
+            c.write(a.wrapping_add(1));
+        }
+        if let Some(h) = &mut self.history {
+            let mem = self.mem.borrow();
+            h.write(a, mem.get(a));
+            h.write(a.wrapping_add(1), mem.get(a.wrapping_add(1)));
+        }
+        self.mem.borrow_mut().set_word(a, v);
+        self.record(a, v as u8, true);
+        self.record(a.wrapping_add(1), (v >> 8) as u8, true);
+    }
+
     fn get_m(&self) -> u8 {
         let a = self.reg.get_hl();
-        self.mem.borrow().get(a)
+        self.mem_get(a)
     }
 
     fn set_m(&mut self, v: u8) {
         let a = self.reg.get_hl();
-        self.mem.borrow_mut().set(a, v)
+        self.mem_set(a, v)
     }
 
     fn stack_add(&mut self, v: u16) {
         self.reg.sp = self.reg.sp.wrapping_sub(2);
-        self.mem.borrow_mut().set_word(self.reg.sp, v);
+        self.mem_set_word(self.reg.sp, v);
     }
 
     fn stack_pop(&mut self) -> u16 {
-        let r = self.mem.borrow().get_word(self.reg.sp);
+        let r = self.mem_get_word(self.reg.sp);
         self.reg.sp = self.reg.sp.wrapping_add(2);
         r
     }
 
-    fn alu_inr(&mut self, n: u8) -> u8 {
-        let r = n.wrapping_add(1);
-        self.reg.set_flag(Flag::S, bit::get(r, 7));
-        self.reg.set_flag(Flag::Z, r == 0x00);
-        self.reg.set_flag(Flag::A, (n & 0x0f) + 0x01 > 0x0f);
-        self.reg.set_flag(Flag::P, r.count_ones() & 0x01 == 0x00);
-        r
-    }
-
-    fn alu_dcr(&mut self, n: u8) -> u8 {
-        let r = n.wrapping_sub(1);
-        self.reg.set_flag(Flag::S, bit::get(r, 7));
-        self.reg.set_flag(Flag::Z, r == 0x00);
-        self.reg.set_flag(Flag::A, (r & 0x0f) != 0x0f);
-        self.reg.set_flag(Flag::P, r.count_ones() & 0x01 == 0x00);
-        r
-    }
-
-    // The integht-bit hexadecimal number in the accumulator is.adjusted to form tow four bit binary codecd decimal
-    // digits by the following two process
-    fn alu_daa(&mut self) {
-        let mut a: u8 = 0;
-        let mut c = self.reg.get_flag(Flag::C);
-        let lsb = self.reg.a & 0x0f;
-        let msb = self.reg.a >> 4;
-        // If the least significant four bits of the accumulator represents a number greater than 9, or if the Auxiliary
-        // Carry bit is equal to one, the accumulator is incremented by six. Otherwise, no incrementing occurs.
-        if (lsb > 9) || self.reg.get_flag(Flag::A) {
-            a += 0x06;
-        }
-        // If the most significant four bits of the accumulator now represent a number greater than 9, or if the normal
-        // carry bit is equal to one, the most sign ificant four bits of the accumulator are incremented by six.
-        if (msb > 9) || self.reg.get_flag(Flag::C) || (msb >= 9 && lsb > 9) {
-            a += 0x60;
-            c = true;
-        }
-        self.alu_add(a);
-        self.reg.set_flag(Flag::C, c);
-    }
-
-    fn alu_add(&mut self, n: u8) {
-        let a = self.reg.a;
-        let r = a.wrapping_add(n);
-        self.reg.set_flag(Flag::S, bit::get(r, 7));
-        self.reg.set_flag(Flag::Z, r == 0x00);
-        self.reg.set_flag(Flag::A, (a & 0x0f) + (n & 0x0f) > 0x0f);
-        self.reg.set_flag(Flag::P, r.count_ones() & 0x01 == 0x00);
-        self.reg.set_flag(Flag::C, u16::from(a) + u16::from(n) > 0xff);
-        self.reg.a = r;
-    }
-
-    fn alu_adc(&mut self, n: u8) {
-        let c = u8::from(self.reg.get_flag(Flag::C));
-        let a = self.reg.a;
-        let r = a.wrapping_add(n).wrapping_add(c);
-        self.reg.set_flag(Flag::S, bit::get(r, 7));
-        self.reg.set_flag(Flag::Z, r == 0x00);
-        self.reg.set_flag(Flag::A, (a & 0x0f) + (n & 0x0f) + c > 0x0f);
-        self.reg.set_flag(Flag::P, r.count_ones() & 0x01 == 0x00);
-        self.reg.set_flag(Flag::C, u16::from(a) + u16::from(n) + u16::from(c) > 0xff);
-        self.reg.a = r;
-    }
-
-    fn alu_sub(&mut self, n: u8) {
-        let a = self.reg.a;
-        let r = a.wrapping_sub(n);
-        self.reg.set_flag(Flag::S, bit::get(r, 7));
-        self.reg.set_flag(Flag::Z, r == 0x00);
-        self.reg.set_flag(Flag::A, (a as i8 & 0x0f) - (n as i8 & 0x0f) >= 0x00);
-        self.reg.set_flag(Flag::P, r.count_ones() & 0x01 == 0x00);
-        self.reg.set_flag(Flag::C, u16::from(a) < u16::from(n));
-        self.reg.a = r;
-    }
-
-    fn alu_sbb(&mut self, n: u8) {
-        let c = u8::from(self.reg.get_flag(Flag::C));
-        let a = self.reg.a;
-        let r = a.wrapping_sub(n).wrapping_sub(c);
-        self.reg.set_flag(Flag::S, bit::get(r, 7));
-        self.reg.set_flag(Flag::Z, r == 0x00);
-        self.reg.set_flag(Flag::A, (a as i8 & 0x0f) - (n as i8 & 0x0f) - (c as i8) >= 0x00);
-        self.reg.set_flag(Flag::P, r.count_ones() & 0x01 == 0x00);
-        self.reg.set_flag(Flag::C, u16::from(a) < u16::from(n) + u16::from(c));
-        self.reg.a = r;
-    }
-
-    fn alu_ana(&mut self, n: u8) {
-        let r = self.reg.a & n;
-        self.reg.set_flag(Flag::S, bit::get(r, 7));
-        self.reg.set_flag(Flag::Z, r == 0x00);
-        self.reg.set_flag(Flag::A, ((self.reg.a | n) & 0x08) != 0);
-        self.reg.set_flag(Flag::P, r.count_ones() & 0x01 == 0x00);
-        self.reg.set_flag(Flag::C, false);
-        self.reg.a = r;
-    }
-
-    fn alu_xra(&mut self, n: u8) {
-        let r = self.reg.a ^ n;
-        self.reg.set_flag(Flag::S, bit::get(r, 7));
-        self.reg.set_flag(Flag::Z, r == 0x00);
-        self.reg.set_flag(Flag::A, false);
-        self.reg.set_flag(Flag::P, r.count_ones() & 0x01 == 0x00);
-        self.reg.set_flag(Flag::C, false);
-        self.reg.a = r;
-    }
-
-    fn alu_ora(&mut self, n: u8) {
-        let r = self.reg.a | n;
-        self.reg.set_flag(Flag::S, bit::get(r, 7));
-        self.reg.set_flag(Flag::Z, r == 0x00);
-        self.reg.set_flag(Flag::A, false);
-        self.reg.set_flag(Flag::P, r.count_ones() & 0x01 == 0x00);
-        self.reg.set_flag(Flag::C, false);
-        self.reg.a = r;
-    }
-
-    fn alu_cmp(&mut self, n: u8) {
-        let r = self.reg.a;
-        self.alu_sub(n);
-        self.reg.a = r;
-    }
-
-    fn alu_rlc(&mut self) {
-        let c = bit::get(self.reg.a, 7);
-        let r = (self.reg.a << 1) | u8::from(c);
-        self.reg.set_flag(Flag::C, c);
-        self.reg.a = r;
-    }
-
-    fn alu_rrc(&mut self) {
-        let c = bit::get(self.reg.a, 0);
-        let r = if c { 0x80 | (self.reg.a >> 1) } else { self.reg.a >> 1 };
-        self.reg.set_flag(Flag::C, c);
-        self.reg.a = r;
-    }
-
-    fn alu_ral(&mut self) {
-        let c = bit::get(self.reg.a, 7);
-        let r = (self.reg.a << 1) | u8::from(self.reg.get_flag(Flag::C));
-        self.reg.set_flag(Flag::C, c);
-        self.reg.a = r;
-    }
-
-    fn alu_rar(&mut self) {
-        let c = bit::get(self.reg.a, 0);
-        let r = if self.reg.get_flag(Flag::C) { 0x80 | (self.reg.a >> 1) } else { self.reg.a >> 1 };
-        self.reg.set_flag(Flag::C, c);
-        self.reg.a = r;
-    }
-
-    fn alu_dad(&mut self, n: u16) {
-        let a = self.reg.get_hl();
-        let r = a.wrapping_add(n);
-        self.reg.set_flag(Flag::C, a > 0xffff - n);
-        self.reg.set_hl(r);
-    }
-
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> u32 {
+        while let Some(addr) = self
+            .journal
+            .as_mut()
+            .and_then(|j| j.before(self.cycles, &self.reg, self.halted, self.inte, &*self.mem.borrow()))
+        {
+            self.halted = false;
+            self.interrupt(addr);
+        }
         if self.halted {
             return 0;
         }
-        let opcode = self.imm_ds();
-        let opcode = match opcode {
-            0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => 0x00,
-            0xcb => 0xc3,
-            0xd9 => 0xc9,
-            0xdd | 0xed | 0xfd => 0xcd,
-            _ => opcode,
+        if let Some(h) = &mut self.history {
+            h.begin(self.reg, self.halted, self.inte, self.cycles);
+        }
+        let Some(mut tracer) = self.tracer.take() else {
+            let cycles = self.execute();
+            self.cycles += u64::from(cycles);
+            self.reg.settle();
+            return cycles;
         };
+        let before = self.reg;
+        let pc = self.reg.pc;
+        let mut opcode = [0u8; 3];
+        {
+            let mem = self.mem.borrow();
+            opcode[0] = mem.get(pc);
+            for i in 1..asm::length(opcode[0]) {
+                opcode[i] = mem.get(pc.wrapping_add(i as u16));
+            }
+        }
+        // Put back for the duration of the instruction, so its memory accesses are recorded.
+        self.tracer = Some(tracer);
+        let cycles = self.execute();
+        self.cycles += u64::from(cycles);
+        self.reg.settle();
+        tracer = self.tracer.take().unwrap();
+        let step = Step {
+            pc,
+            opcode,
+            len: asm::length(opcode[0]) as u8,
+            before,
+            after: self.reg,
+            accesses: mem::take(self.accesses.get_mut()),
+            cycles,
+            total: self.cycles,
+        };
+        tracer.trace(&step);
+        let mut accesses = step.accesses;
+        accesses.clear();
+        *self.accesses.get_mut() = accesses;
+        self.tracer = Some(tracer);
+        cycles
+    }
 
-        debugln!(
-            "{} PC={:04x} SP={:04x} A={:02x} F={:02x} B={:02x} C={:02x} D={:02x} E={:02x} H={:02x} L={:02x}",
-            asm::asm(opcode),
-            self.reg.pc.wrapping_sub(1),
-            self.reg.sp,
-            self.reg.a,
-            self.reg.f,
-            self.reg.b,
-            self.reg.c,
-            self.reg.d,
-            self.reg.e,
-            self.reg.h,
-            self.reg.l
-        );
+    // Fetch and run one instruction.
+    fn execute(&mut self) -> u32 {
+        let pc = self.reg.pc;
+        let (opcode, imm) = {
+            let mem = self.mem.borrow();
+            let opcode = mem.get(pc);
+            let imm = match asm::length(opcode) {
+                1 => 0,
+                2 => u16::from(mem.get(pc.wrapping_add(1))),
+                _ => u16::from_le_bytes([mem.get(pc.wrapping_add(1)), mem.get(pc.wrapping_add(2))]),
+            };
+            (opcode, imm)
+        };
+        self.reg.pc = pc.wrapping_add(asm::length(opcode) as u16);
+        self.exec(canonical(opcode), imm)
+    }
 
+    // Run an instruction whose bytes were fetched and pc moved past them: the opcode, canonical, with its operand.
+    pub(crate) fn exec(&mut self, opcode: u8, imm: u16) -> u32 {
         let mut ecycle = 0;
         match opcode {
             // CARRY BIT INSTRUCTIONS
@@ -280,38 +244,38 @@ impl Cpu {
             0x37 => self.reg.set_flag(Flag::C, true),
 
             // INR Increment Register or Memory
-            0x04 => self.reg.b = self.alu_inr(self.reg.b),
-            0x0c => self.reg.c = self.alu_inr(self.reg.c),
-            0x14 => self.reg.d = self.alu_inr(self.reg.d),
-            0x1c => self.reg.e = self.alu_inr(self.reg.e),
-            0x24 => self.reg.h = self.alu_inr(self.reg.h),
-            0x2c => self.reg.l = self.alu_inr(self.reg.l),
+            0x04 => self.reg.b = self.reg.alu_inr(self.reg.b),
+            0x0c => self.reg.c = self.reg.alu_inr(self.reg.c),
+            0x14 => self.reg.d = self.reg.alu_inr(self.reg.d),
+            0x1c => self.reg.e = self.reg.alu_inr(self.reg.e),
+            0x24 => self.reg.h = self.reg.alu_inr(self.reg.h),
+            0x2c => self.reg.l = self.reg.alu_inr(self.reg.l),
             0x34 => {
                 let a = self.get_m();
-                let b = self.alu_inr(a);
+                let b = self.reg.alu_inr(a);
                 self.set_m(b);
             }
-            0x3c => self.reg.a = self.alu_inr(self.reg.a),
+            0x3c => self.reg.a = self.reg.alu_inr(self.reg.a),
 
             // DCR Decrement Register or Memory
-            0x05 => self.reg.b = self.alu_dcr(self.reg.b),
-            0x0d => self.reg.c = self.alu_dcr(self.reg.c),
-            0x15 => self.reg.d = self.alu_dcr(self.reg.d),
-            0x1d => self.reg.e = self.alu_dcr(self.reg.e),
-            0x25 => self.reg.h = self.alu_dcr(self.reg.h),
-            0x2d => self.reg.l = self.alu_dcr(self.reg.l),
+            0x05 => self.reg.b = self.reg.alu_dcr(self.reg.b),
+            0x0d => self.reg.c = self.reg.alu_dcr(self.reg.c),
+            0x15 => self.reg.d = self.reg.alu_dcr(self.reg.d),
+            0x1d => self.reg.e = self.reg.alu_dcr(self.reg.e),
+            0x25 => self.reg.h = self.reg.alu_dcr(self.reg.h),
+            0x2d => self.reg.l = self.reg.alu_dcr(self.reg.l),
             0x35 => {
                 let a = self.get_m();
-                let b = self.alu_dcr(a);
+                let b = self.reg.alu_dcr(a);
                 self.set_m(b);
             }
-            0x3d => self.reg.a = self.alu_dcr(self.reg.a),
+            0x3d => self.reg.a = self.reg.alu_dcr(self.reg.a),
 
             // CMA Complement Accumulator
             0x2f => self.reg.a = !self.reg.a,
 
             // DAA Decimal Adjust Accumulator
-            0x27 => self.alu_daa(),
+            0x27 => self.reg.alu_daa(),
 
             // NOP INSTRUCTIONS
             0x00 => {}
@@ -382,104 +346,104 @@ impl Cpu {
             0x7f => {}
 
             // STAX Store Accumulator
-            0x02 => self.mem.borrow_mut().set(self.reg.get_bc(), self.reg.a),
-            0x12 => self.mem.borrow_mut().set(self.reg.get_de(), self.reg.a),
+            0x02 => self.mem_set(self.reg.get_bc(), self.reg.a),
+            0x12 => self.mem_set(self.reg.get_de(), self.reg.a),
 
             // LDAX Load Accumulator
-            0x0a => self.reg.a = self.mem.borrow().get(self.reg.get_bc()),
-            0x1a => self.reg.a = self.mem.borrow().get(self.reg.get_de()),
+            0x0a => self.reg.a = self.mem_get(self.reg.get_bc()),
+            0x1a => self.reg.a = self.mem_get(self.reg.get_de()),
 
             // ADD ADD Register or Memory To Accumulator
-            0x80 => self.alu_add(self.reg.b),
-            0x81 => self.alu_add(self.reg.c),
-            0x82 => self.alu_add(self.reg.d),
-            0x83 => self.alu_add(self.reg.e),
-            0x84 => self.alu_add(self.reg.h),
-            0x85 => self.alu_add(self.reg.l),
-            0x86 => self.alu_add(self.get_m()),
-            0x87 => self.alu_add(self.reg.a),
+            0x80 => self.reg.alu_add(self.reg.b),
+            0x81 => self.reg.alu_add(self.reg.c),
+            0x82 => self.reg.alu_add(self.reg.d),
+            0x83 => self.reg.alu_add(self.reg.e),
+            0x84 => self.reg.alu_add(self.reg.h),
+            0x85 => self.reg.alu_add(self.reg.l),
+            0x86 => self.reg.alu_add(self.get_m()),
+            0x87 => self.reg.alu_add(self.reg.a),
 
             // ADC ADD Register or Memory To Accumulator With Carry
-            0x88 => self.alu_adc(self.reg.b),
-            0x89 => self.alu_adc(self.reg.c),
-            0x8a => self.alu_adc(self.reg.d),
-            0x8b => self.alu_adc(self.reg.e),
-            0x8c => self.alu_adc(self.reg.h),
-            0x8d => self.alu_adc(self.reg.l),
-            0x8e => self.alu_adc(self.get_m()),
-            0x8f => self.alu_adc(self.reg.a),
+            0x88 => self.reg.alu_adc(self.reg.b),
+            0x89 => self.reg.alu_adc(self.reg.c),
+            0x8a => self.reg.alu_adc(self.reg.d),
+            0x8b => self.reg.alu_adc(self.reg.e),
+            0x8c => self.reg.alu_adc(self.reg.h),
+            0x8d => self.reg.alu_adc(self.reg.l),
+            0x8e => self.reg.alu_adc(self.get_m()),
+            0x8f => self.reg.alu_adc(self.reg.a),
 
             // SUB Subtract Register or Memory From Accumulator
-            0x90 => self.alu_sub(self.reg.b),
-            0x91 => self.alu_sub(self.reg.c),
-            0x92 => self.alu_sub(self.reg.d),
-            0x93 => self.alu_sub(self.reg.e),
-            0x94 => self.alu_sub(self.reg.h),
-            0x95 => self.alu_sub(self.reg.l),
-            0x96 => self.alu_sub(self.get_m()),
-            0x97 => self.alu_sub(self.reg.a),
+            0x90 => self.reg.alu_sub(self.reg.b),
+            0x91 => self.reg.alu_sub(self.reg.c),
+            0x92 => self.reg.alu_sub(self.reg.d),
+            0x93 => self.reg.alu_sub(self.reg.e),
+            0x94 => self.reg.alu_sub(self.reg.h),
+            0x95 => self.reg.alu_sub(self.reg.l),
+            0x96 => self.reg.alu_sub(self.get_m()),
+            0x97 => self.reg.alu_sub(self.reg.a),
 
             // SBB Subtract Register or Memory From Accumulator With Borrow
-            0x98 => self.alu_sbb(self.reg.b),
-            0x99 => self.alu_sbb(self.reg.c),
-            0x9a => self.alu_sbb(self.reg.d),
-            0x9b => self.alu_sbb(self.reg.e),
-            0x9c => self.alu_sbb(self.reg.h),
-            0x9d => self.alu_sbb(self.reg.l),
-            0x9e => self.alu_sbb(self.get_m()),
-            0x9f => self.alu_sbb(self.reg.a),
+            0x98 => self.reg.alu_sbb(self.reg.b),
+            0x99 => self.reg.alu_sbb(self.reg.c),
+            0x9a => self.reg.alu_sbb(self.reg.d),
+            0x9b => self.reg.alu_sbb(self.reg.e),
+            0x9c => self.reg.alu_sbb(self.reg.h),
+            0x9d => self.reg.alu_sbb(self.reg.l),
+            0x9e => self.reg.alu_sbb(self.get_m()),
+            0x9f => self.reg.alu_sbb(self.reg.a),
 
             // ANA Logical and Register or Memory With Accumulator
-            0xa0 => self.alu_ana(self.reg.b),
-            0xa1 => self.alu_ana(self.reg.c),
-            0xa2 => self.alu_ana(self.reg.d),
-            0xa3 => self.alu_ana(self.reg.e),
-            0xa4 => self.alu_ana(self.reg.h),
-            0xa5 => self.alu_ana(self.reg.l),
-            0xa6 => self.alu_ana(self.get_m()),
-            0xa7 => self.alu_ana(self.reg.a),
+            0xa0 => self.reg.alu_ana(self.reg.b),
+            0xa1 => self.reg.alu_ana(self.reg.c),
+            0xa2 => self.reg.alu_ana(self.reg.d),
+            0xa3 => self.reg.alu_ana(self.reg.e),
+            0xa4 => self.reg.alu_ana(self.reg.h),
+            0xa5 => self.reg.alu_ana(self.reg.l),
+            0xa6 => self.reg.alu_ana(self.get_m()),
+            0xa7 => self.reg.alu_ana(self.reg.a),
 
             // XRA Logical Exclusive-Or Register or Memory With Accumulator (Zero Accumulator)
-            0xa8 => self.alu_xra(self.reg.b),
-            0xa9 => self.alu_xra(self.reg.c),
-            0xaa => self.alu_xra(self.reg.d),
-            0xab => self.alu_xra(self.reg.e),
-            0xac => self.alu_xra(self.reg.h),
-            0xad => self.alu_xra(self.reg.l),
-            0xae => self.alu_xra(self.get_m()),
-            0xaf => self.alu_xra(self.reg.a),
+            0xa8 => self.reg.alu_xra(self.reg.b),
+            0xa9 => self.reg.alu_xra(self.reg.c),
+            0xaa => self.reg.alu_xra(self.reg.d),
+            0xab => self.reg.alu_xra(self.reg.e),
+            0xac => self.reg.alu_xra(self.reg.h),
+            0xad => self.reg.alu_xra(self.reg.l),
+            0xae => self.reg.alu_xra(self.get_m()),
+            0xaf => self.reg.alu_xra(self.reg.a),
 
             // ORA Logical or Register or Memory With Accumulator
-            0xb0 => self.alu_ora(self.reg.b),
-            0xb1 => self.alu_ora(self.reg.c),
-            0xb2 => self.alu_ora(self.reg.d),
-            0xb3 => self.alu_ora(self.reg.e),
-            0xb4 => self.alu_ora(self.reg.h),
-            0xb5 => self.alu_ora(self.reg.l),
-            0xb6 => self.alu_ora(self.get_m()),
-            0xb7 => self.alu_ora(self.reg.a),
+            0xb0 => self.reg.alu_ora(self.reg.b),
+            0xb1 => self.reg.alu_ora(self.reg.c),
+            0xb2 => self.reg.alu_ora(self.reg.d),
+            0xb3 => self.reg.alu_ora(self.reg.e),
+            0xb4 => self.reg.alu_ora(self.reg.h),
+            0xb5 => self.reg.alu_ora(self.reg.l),
+            0xb6 => self.reg.alu_ora(self.get_m()),
+            0xb7 => self.reg.alu_ora(self.reg.a),
 
             // CMP Compare Register or Memory With Accumulator
-            0xb8 => self.alu_cmp(self.reg.b),
-            0xb9 => self.alu_cmp(self.reg.c),
-            0xba => self.alu_cmp(self.reg.d),
-            0xbb => self.alu_cmp(self.reg.e),
-            0xbc => self.alu_cmp(self.reg.h),
-            0xbd => self.alu_cmp(self.reg.l),
-            0xbe => self.alu_cmp(self.get_m()),
-            0xbf => self.alu_cmp(self.reg.a),
+            0xb8 => self.reg.alu_cmp(self.reg.b),
+            0xb9 => self.reg.alu_cmp(self.reg.c),
+            0xba => self.reg.alu_cmp(self.reg.d),
+            0xbb => self.reg.alu_cmp(self.reg.e),
+            0xbc => self.reg.alu_cmp(self.reg.h),
+            0xbd => self.reg.alu_cmp(self.reg.l),
+            0xbe => self.reg.alu_cmp(self.get_m()),
+            0xbf => self.reg.alu_cmp(self.reg.a),
 
             // RLC Rotate Accumulator Left
-            0x07 => self.alu_rlc(),
+            0x07 => self.reg.alu_rlc(),
 
             // RRC Rotate Accumulator Right
-            0x0f => self.alu_rrc(),
+            0x0f => self.reg.alu_rrc(),
 
             // RAL Rotate Accumulator Left Through Carry
-            0x17 => self.alu_ral(),
+            0x17 => self.reg.alu_ral(),
 
             // RAR Rotate Accumulator Right Through Carry
-            0x1f => self.alu_rar(),
+            0x1f => self.reg.alu_rar(),
 
             // PUSH Push Data Onto Stack
             0xc5 => self.stack_add(self.reg.get_bc()),
@@ -506,10 +470,10 @@ impl Cpu {
             }
 
             // DAD Double Add
-            0x09 => self.alu_dad(self.reg.get_bc()),
-            0x19 => self.alu_dad(self.reg.get_de()),
-            0x29 => self.alu_dad(self.reg.get_hl()),
-            0x39 => self.alu_dad(self.reg.sp),
+            0x09 => self.reg.alu_dad(self.reg.get_bc()),
+            0x19 => self.reg.alu_dad(self.reg.get_de()),
+            0x29 => self.reg.alu_dad(self.reg.get_hl()),
+            0x39 => self.reg.alu_dad(self.reg.sp),
 
             // INX Increment Register Pair
             0x03 => self.reg.set_bc(self.reg.get_bc().wrapping_add(1)),
@@ -531,10 +495,10 @@ impl Cpu {
 
             // XTHL Exchange Stack
             0xe3 => {
-                let a = self.mem.borrow().get_word(self.reg.sp);
+                let a = self.mem_get_word(self.reg.sp);
                 let b = self.reg.get_hl();
                 self.reg.set_hl(a);
-                self.mem.borrow_mut().set_word(self.reg.sp, b)
+                self.mem_set_word(self.reg.sp, b)
             }
 
             // SPHL Load SP From H And L
@@ -542,106 +506,106 @@ impl Cpu {
 
             // LXI Load Immediate Data
             0x01 => {
-                let a = self.imm_dw();
+                let a = imm;
                 self.reg.set_bc(a);
             }
             0x11 => {
-                let a = self.imm_dw();
+                let a = imm;
                 self.reg.set_de(a);
             }
             0x21 => {
-                let a = self.imm_dw();
+                let a = imm;
                 self.reg.set_hl(a);
             }
             0x31 => {
-                let a = self.imm_dw();
+                let a = imm;
                 self.reg.sp = a;
             }
 
             // MVI Move Immediate Data
-            0x06 => self.reg.b = self.imm_ds(),
-            0x0e => self.reg.c = self.imm_ds(),
-            0x16 => self.reg.d = self.imm_ds(),
-            0x1e => self.reg.e = self.imm_ds(),
-            0x26 => self.reg.h = self.imm_ds(),
-            0x2e => self.reg.l = self.imm_ds(),
+            0x06 => self.reg.b = imm as u8,
+            0x0e => self.reg.c = imm as u8,
+            0x16 => self.reg.d = imm as u8,
+            0x1e => self.reg.e = imm as u8,
+            0x26 => self.reg.h = imm as u8,
+            0x2e => self.reg.l = imm as u8,
             0x36 => {
-                let a = self.imm_ds();
+                let a = imm as u8;
                 self.set_m(a);
             }
-            0x3e => self.reg.a = self.imm_ds(),
+            0x3e => self.reg.a = imm as u8,
 
             // ADI Add Immediate To Accumulator
             0xc6 => {
-                let a = self.imm_ds();
-                self.alu_add(a);
+                let a = imm as u8;
+                self.reg.alu_add(a);
             }
 
             // ACI Add Immediate To Accumulator With Carry
             0xce => {
-                let a = self.imm_ds();
-                self.alu_adc(a);
+                let a = imm as u8;
+                self.reg.alu_adc(a);
             }
 
             // SUI Subtract Immediate From Accumulator
             0xd6 => {
-                let a = self.imm_ds();
-                self.alu_sub(a);
+                let a = imm as u8;
+                self.reg.alu_sub(a);
             }
 
             // SBI Subtract Immediate from Accumulator With Borrow
             0xde => {
-                let v = self.imm_ds();
-                self.alu_sbb(v);
+                let v = imm as u8;
+                self.reg.alu_sbb(v);
             }
 
             // ANI And Immediate With AccumulatorLabel
             0xe6 => {
-                let a = self.imm_ds();
-                self.alu_ana(a);
+                let a = imm as u8;
+                self.reg.alu_ana(a);
             }
 
             // XRI Exclusive-Or Immediate With Accumulator
             0xee => {
-                let a = self.imm_ds();
-                self.alu_xra(a);
+                let a = imm as u8;
+                self.reg.alu_xra(a);
             }
 
             // ORI Or Immediate With Accumulator
             0xf6 => {
-                let a = self.imm_ds();
-                self.alu_ora(a);
+                let a = imm as u8;
+                self.reg.alu_ora(a);
             }
 
             // CPI Compare Immediate With Accumulator
             0xfe => {
-                let a = self.imm_ds();
-                self.alu_cmp(a);
+                let a = imm as u8;
+                self.reg.alu_cmp(a);
             }
 
             // STA Store Accumulator Direct
             0x32 => {
-                let a = self.imm_dw();
-                self.mem.borrow_mut().set(a, self.reg.a);
+                let a = imm;
+                self.mem_set(a, self.reg.a);
             }
 
             // LDA Load Accumulator Direct
             0x3a => {
-                let a = self.imm_dw();
-                let b = self.mem.borrow().get(a);
+                let a = imm;
+                let b = self.mem_get(a);
                 self.reg.a = b;
             }
 
             // SHLD Store Hand L Direct
             0x22 => {
-                let a = self.imm_dw();
-                self.mem.borrow_mut().set_word(a, self.reg.get_hl());
+                let a = imm;
+                self.mem_set_word(a, self.reg.get_hl());
             }
 
             // LHLD Load HAnd L Direct
             0x2a => {
-                let a = self.imm_dw();
-                let b = self.mem.borrow().get_word(a);
+                let a = imm;
+                let b = self.mem_get_word(a);
                 self.reg.set_hl(b);
             }
 
@@ -650,7 +614,7 @@ impl Cpu {
 
             // JUMP INSTRUCTIONS
             0xc3 | 0xda | 0xd2 | 0xca | 0xc2 | 0xfa | 0xf2 | 0xea | 0xe2 => {
-                let a = self.imm_dw();
+                let a = imm;
                 let cond = match opcode {
                     // JMP JUMP
                     0xc3 => true,
@@ -679,7 +643,7 @@ impl Cpu {
 
             // CALL SUBROUTINE INSTRUCTIONS
             0xcd | 0xdc | 0xd4 | 0xcc | 0xc4 | 0xfc | 0xf4 | 0xec | 0xe4 => {
-                let a = self.imm_dw();
+                let a = imm;
                 let cond = match opcode {
                     // CALL Call
                     0xcd => true,
@@ -748,12 +712,20 @@ impl Cpu {
             0xf3 => self.inte = false,
 
             // INPUT/OUTPUT INSTRUCTIONS
-            // Note: You need to implement this instr yourself
             0xdb => {
-                let _ = self.imm_ds();
+                let a = imm as u8;
+                if let Some(dev) = &self.dev {
+                    self.reg.a = match &mut self.journal {
+                        Some(j) => j.input(self.cycles, a, || dev.borrow_mut().get(a)),
+                        None => dev.borrow_mut().get(a),
+                    };
+                }
             }
             0xd3 => {
-                let _ = self.imm_ds();
+                let a = imm as u8;
+                if let Some(dev) = &self.dev {
+                    dev.borrow_mut().set(a, self.reg.a);
+                }
             }
 
             // HLT HALT INSTRUCTION
@@ -764,6 +736,74 @@ impl Cpu {
         OP_CYCLES[opcode as usize] + ecycle
     }
 
+    // Run the rest of the block at pc from the cache, stopping early once the cycles reach until. Falls back to next
+    // without a cache, or with a tracer, history or journal, which see every instruction.
+    fn block(&mut self, until: u64) -> u32 {
+        if self.halted || self.tracer.is_some() || self.history.is_some() || self.journal.is_some() {
+            return self.next();
+        }
+        let Some(cache) = &mut self.cache else {
+            return self.next();
+        };
+        let b = cache.get(&*self.mem.borrow(), self.reg.pc);
+        let generation = cache.generation;
+        let (done, mut cycles) = self.jit(&b, until).unwrap_or((0, 0));
+        if done != 0
+            && (done == b.ops.len()
+                || self.cycles >= until
+                || self.cache.as_ref().is_some_and(|e| e.generation != generation))
+        {
+            self.reg.settle();
+            return cycles;
+        }
+        for op in &b.ops[done..] {
+            self.reg.pc = self.reg.pc.wrapping_add(u16::from(op.len));
+            let c = self.exec(op.code, op.imm);
+            cycles += c;
+            self.cycles += u64::from(c);
+            if self.cycles >= until || self.cache.as_ref().is_some_and(|e| e.generation != generation) {
+                break;
+            }
+        }
+        self.reg.settle();
+        cycles
+    }
+
+    // Run the compiled code of a block when it has some, giving the instructions run and their cycles.
+    #[cfg(feature = "jit")]
+    fn jit(&mut self, b: &Rc<Block>, until: u64) -> Option<(usize, u32)> {
+        let mut jit = self.jit.take()?;
+        let r = jit.run(self, b, until);
+        self.jit = Some(jit);
+        r
+    }
+
+    #[cfg(not(feature = "jit"))]
+    fn jit(&mut self, _: &Rc<Block>, _: u64) -> Option<(usize, u32)> {
+        None
+    }
+
+    // Run one block: the instructions up to the next jump, call, return or halt, or up to an instruction that changed
+    // the code of the block. Returns the cycles run.
+    pub fn run_block(&mut self) -> u32 {
+        self.block(u64::MAX)
+    }
+
+    // Run until the cycles reach until or the cpu halts. It stops at the same instruction as calling next while the
+    // cycles are below until would.
+    pub fn run_until(&mut self, until: u64) {
+        while self.cycles < until && !self.halted {
+            self.block(until);
+        }
+    }
+
+    // Tell the block cache memory from an address was written by something other than the cpu.
+    pub fn invalidate(&mut self, addr: u16, n: usize) {
+        if let Some(c) = &mut self.cache {
+            c.invalidate(addr, n);
+        }
+    }
+
     pub fn step(&mut self) -> u32 {
         if self.step_cycles > STEP_CYCLES {
             self.step_cycles -= STEP_CYCLES;
@@ -778,12 +818,55 @@ impl Cpu {
         cycles
     }
 
+    // Undo the last instruction kept in the history. False when there is none.
+    pub fn step_back(&mut self) -> bool {
+        let Some(e) = self.history.as_mut().and_then(|h| h.pop()) else {
+            return false;
+        };
+        for (a, v) in e.writes.iter().rev() {
+            self.mem.borrow_mut().set(*a, *v);
+            // The bytes put back may be code the cache decoded since.
+            self.invalidate(*a, 1);
+        }
+        self.reg = e.reg;
+        self.halted = e.halted;
+        self.inte = e.inte;
+        self.cycles = e.cycles;
+        true
+    }
+
+    // Go back to the last time pc was at an address, at least one instruction. False when the history ran out first.
+    pub fn run_back_to(&mut self, pc: u16) -> bool {
+        while self.step_back() {
+            if self.reg.pc == pc {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Go back to before the last instruction writing a byte. False when the history ran out first.
+    pub fn run_back_to_write(&mut self, addr: u16) -> bool {
+        while let Some(wrote) = self.history.as_ref().and_then(|h| h.last()).map(|e| e.wrote(addr)) {
+            self.step_back();
+            if wrote {
+                return true;
+            }
+        }
+        false
+    }
+
     pub fn inte_handle(&mut self, addr: u16) {
-        if self.inte {
-            self.inte = false;
-            self.stack_add(self.reg.pc);
-            self.reg.pc = addr;
-            self.step_cycles += OP_CYCLES[0xcd];
+        if self.inte && self.journal.as_mut().is_none_or(|j| j.interrupt(self.cycles, addr)) {
+            self.interrupt(addr);
         }
     }
+
+    fn interrupt(&mut self, addr: u16) {
+        self.inte = false;
+        self.stack_add(self.reg.pc);
+        self.reg.pc = addr;
+        self.step_cycles += OP_CYCLES[0xcd];
+        self.cycles += u64::from(OP_CYCLES[0xcd]);
+    }
 }
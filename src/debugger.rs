@@ -0,0 +1,397 @@
+// A command line debugger over the cpu: breakpoints, stepping, registers, memory and disassembly. Addresses may be
+// given as numbers, as symbol names with an optional offset, or as FILE:LINE of a source listing, and are shown by
+// name and source line where they are known.
+//
+//   s [N]          step N instructions
+//   n [N]          step N source lines
+//   c              continue to a breakpoint, a halt or the end of the program
+//   b ADDR         set a breakpoint, b alone lists them
+//   d ADDR         delete a breakpoint
+//   g ADDR         go on from ADDR at the next step or continue
+//   bs [N]         step back N instructions
+//   bc             continue back to a breakpoint
+//   bg ADDR        go back to the last time pc was at ADDR
+//   bw ADDR        go back to before the last write of the byte at ADDR
+//   r              show the registers
+//   x ADDR [N]     dump N bytes of memory
+//   l [ADDR] [N]   list N instructions, from pc by default
+//   src [ADDR] [N] list N source lines around an address, pc by default
+//   i ADDR         show the address and its name
+//   sym [TEXT]     list the symbols, those containing TEXT
+//
+// Going back needs the cpu to keep a history.
+use super::asm;
+use super::cpu::Cpu;
+use super::source::{Loc, Sources};
+use super::symbol::Symbols;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    // Stepped as asked.
+    Step,
+    Breakpoint(u16),
+    Halted,
+    // The trap ended the program.
+    Exited,
+    // Went back to the oldest instruction in the history.
+    Start,
+}
+
+pub type Trap = Box<dyn FnMut(&mut Cpu) -> bool>;
+
+pub struct Debugger {
+    pub cpu: Cpu,
+    pub symbols: Symbols,
+    pub sources: Sources,
+    pub breakpoints: BTreeSet<u16>,
+    // Called before every instruction to handle calls the host serves, such as the BDOS. Returns true when the program
+    // has ended.
+    pub trap: Option<Trap>,
+    pub exited: bool,
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu) -> Self {
+        Self {
+            cpu,
+            symbols: Symbols::new(),
+            sources: Sources::new(),
+            breakpoints: BTreeSet::new(),
+            trap: None,
+            exited: false,
+        }
+    }
+
+    // Run one instruction.
+    pub fn step(&mut self) -> Stop {
+        if self.exited {
+            return Stop::Exited;
+        }
+        if self.cpu.halted {
+            return Stop::Halted;
+        }
+        if let Some(trap) = &mut self.trap {
+            if trap(&mut self.cpu) {
+                self.exited = true;
+                return Stop::Exited;
+            }
+        }
+        self.cpu.next();
+        if self.cpu.halted {
+            return Stop::Halted;
+        }
+        Stop::Step
+    }
+
+    // Run to the start of the next source line, through code the listings do not cover. A breakpoint on the way stops
+    // it. Without listings it is one instruction.
+    pub fn step_line(&mut self) -> Stop {
+        loop {
+            match self.step() {
+                Stop::Step => {}
+                e => return e,
+            }
+            let pc = self.cpu.reg.pc;
+            if self.sources.is_empty() || self.sources.is_start(pc) {
+                return Stop::Step;
+            }
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+        }
+    }
+
+    // Undo one instruction.
+    pub fn step_back(&mut self) -> Stop {
+        if !self.cpu.step_back() {
+            return Stop::Start;
+        }
+        self.exited = false;
+        Stop::Step
+    }
+
+    // Go back until a breakpoint is reached, leaving the one at pc behind first.
+    pub fn run_back(&mut self) -> Stop {
+        loop {
+            match self.step_back() {
+                Stop::Step => {}
+                e => return e,
+            }
+            if self.breakpoints.contains(&self.cpu.reg.pc) {
+                return Stop::Breakpoint(self.cpu.reg.pc);
+            }
+        }
+    }
+
+    // Run until a breakpoint is reached, leaving the one at pc behind first.
+    pub fn run(&mut self) -> Stop {
+        loop {
+            match self.step() {
+                Stop::Step => {}
+                e => return e,
+            }
+            if self.breakpoints.contains(&self.cpu.reg.pc) {
+                return Stop::Breakpoint(self.cpu.reg.pc);
+            }
+        }
+    }
+
+    // Instruction bytes at an address.
+    fn fetch(&self, addr: u16) -> Vec<u8> {
+        let mem = self.cpu.mem.borrow();
+        let n = asm::length(mem.get(addr));
+        (0..n).map(|i| mem.get(addr.wrapping_add(i as u16))).collect()
+    }
+
+    // The instruction at an address with named operands.
+    pub fn instruction(&self, addr: u16) -> String {
+        asm::disasm_with(&self.fetch(addr), |w| self.symbols.name(w).map(String::from))
+    }
+
+    // Where the cpu stands: "0103 PRINT+0x3  MVI C, 0x09", followed by the source line when there is one.
+    pub fn location(&self) -> String {
+        let pc = self.cpu.reg.pc;
+        let mut r = match self.symbols.label(pc) {
+            Some(l) => format!("{:04x} {}  {}", pc, l, self.instruction(pc)),
+            None => format!("{:04x}  {}", pc, self.instruction(pc)),
+        };
+        if let Some(loc) = self.sources.find(pc) {
+            let _ = write!(r, "\n{}  {}", self.sources.describe(loc), self.sources.text(loc));
+        }
+        r
+    }
+
+    pub fn registers(&self) -> String {
+        let r = &self.cpu.reg;
+        let f = |c: char, b: u8| if r.flags() & (1 << b) != 0 { c } else { '-' };
+        format!(
+            "A={:02x} F={:02x} [{}{}{}{}{}] BC={:04x} DE={:04x} HL={:04x} SP={:04x} PC={:04x}{}",
+            r.a,
+            r.flags(),
+            f('S', 7),
+            f('Z', 6),
+            f('A', 4),
+            f('P', 2),
+            f('C', 0),
+            r.get_bc(),
+            r.get_de(),
+            r.get_hl(),
+            r.sp,
+            r.pc,
+            if self.cpu.inte { " EI" } else { "" }
+        )
+    }
+
+    // Disassembly of n instructions, with a label line for each named address.
+    pub fn disassemble(&self, addr: u16, n: usize) -> String {
+        let mut r = String::new();
+        let mut a = addr;
+        for _ in 0..n {
+            if let Some(name) = self.symbols.name(a) {
+                let _ = writeln!(r, "{}:", name);
+            }
+            let bytes = self.fetch(a);
+            let hex: Vec<String> = bytes.iter().map(|e| format!("{:02x}", e)).collect();
+            let mark = if a == self.cpu.reg.pc {
+                '>'
+            } else if self.breakpoints.contains(&a) {
+                '*'
+            } else {
+                ' '
+            };
+            let _ = writeln!(r, "{} {:04x}  {:<8}  {}", mark, a, hex.join(" "), self.instruction(a));
+            a = a.wrapping_add(bytes.len() as u16);
+        }
+        r
+    }
+
+    // Hex dump of n bytes, 16 a line.
+    pub fn dump(&self, addr: u16, n: usize) -> String {
+        let mem = self.cpu.mem.borrow();
+        let mut r = String::new();
+        for row in (0..n).step_by(16) {
+            let a = addr.wrapping_add(row as u16);
+            let data: Vec<u8> = (0..16.min(n - row)).map(|i| mem.get(a.wrapping_add(i as u16))).collect();
+            let hex: Vec<String> = data.iter().map(|e| format!("{:02x}", e)).collect();
+            let text: String = data.iter().map(|e| if (0x20..0x7f).contains(e) { *e as char } else { '.' }).collect();
+            let _ = writeln!(r, "{:04x}  {:<47}  {}", a, hex.join(" "), text);
+        }
+        r
+    }
+
+    // Source lines around a line, marking the one at pc and those with a breakpoint.
+    pub fn source(&self, loc: Loc, n: usize) -> String {
+        let mut r = String::new();
+        let pc = self.sources.find(self.cpu.reg.pc);
+        let start = loc.line.saturating_sub(n / 2).max(1);
+        let count = self.sources.files[loc.file].lines.len();
+        for line in start..(start + n).min(count + 1) {
+            let l = Loc { file: loc.file, line };
+            let mark = if pc == Some(l) {
+                '>'
+            } else if self.breakpoints.iter().any(|a| self.sources.find(*a) == Some(l)) {
+                '*'
+            } else {
+                ' '
+            };
+            let _ = writeln!(r, "{} {:>5}  {}", mark, line, self.sources.text(l));
+        }
+        r
+    }
+
+    fn line(&self, s: &str) -> Result<Loc, String> {
+        self.sources.parse(s).ok_or_else(|| format!("unknown line {}", s))
+    }
+
+    fn address(&self, s: Option<&str>) -> Result<u16, String> {
+        let s = s.ok_or("address expected")?;
+        if s.contains(':') {
+            let loc = self.line(s)?;
+            return self.sources.address(loc).ok_or_else(|| format!("no code at or after {}", s));
+        }
+        self.symbols.resolve(s).ok_or_else(|| format!("unknown address {}", s))
+    }
+
+    // An address with its name and source line: "0103 PRINT+0x3 TST8080:120".
+    fn describe(&self, a: u16) -> String {
+        let mut r = vec![format!("{:04x}", a)];
+        r.extend(self.symbols.label(a));
+        r.extend(self.sources.find(a).map(|e| self.sources.describe(e)));
+        r.join(" ")
+    }
+
+    fn stopped(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Step => self.location(),
+            Stop::Breakpoint(_) => format!("breakpoint at {}", self.location()),
+            Stop::Halted => format!("halted at {}", self.location()),
+            Stop::Exited => "program exited".to_string(),
+            Stop::Start => format!("start of history at {}", self.location()),
+        }
+    }
+
+    // Run one command line and return what it prints.
+    pub fn command(&mut self, line: &str) -> String {
+        let mut args = line.split_whitespace();
+        let cmd = args.next().unwrap_or("");
+        let r = match cmd {
+            "" => Ok(String::new()),
+            "s" | "step" => match args.next().map(|e| e.parse::<u64>()) {
+                Some(Err(_)) => Err("count expected".to_string()),
+                n => {
+                    let mut stop = Stop::Step;
+                    for _ in 0..n.map_or(1, |e| e.unwrap()) {
+                        stop = self.step();
+                        if stop != Stop::Step {
+                            break;
+                        }
+                    }
+                    Ok(self.stopped(stop))
+                }
+            },
+            "n" | "next" => match args.next().map(|e| e.parse::<u64>()) {
+                Some(Err(_)) => Err("count expected".to_string()),
+                n => {
+                    let mut stop = Stop::Step;
+                    for _ in 0..n.map_or(1, |e| e.unwrap()) {
+                        stop = self.step_line();
+                        if stop != Stop::Step {
+                            break;
+                        }
+                    }
+                    Ok(self.stopped(stop))
+                }
+            },
+            "c" | "continue" => {
+                let stop = self.run();
+                Ok(self.stopped(stop))
+            }
+            "b" | "break" => match args.next() {
+                None => Ok(self.breakpoints.iter().map(|a| format!("{}\n", self.describe(*a))).collect()),
+                s => self.address(s).map(|a| {
+                    self.breakpoints.insert(a);
+                    format!("breakpoint at {}", self.describe(a))
+                }),
+            },
+            "d" | "delete" => self.address(args.next()).and_then(|a| {
+                if self.breakpoints.remove(&a) {
+                    Ok(String::new())
+                } else {
+                    Err(format!("no breakpoint at {:04x}", a))
+                }
+            }),
+            "g" | "go" => self.address(args.next()).map(|a| {
+                self.cpu.reg.pc = a;
+                self.cpu.halted = false;
+                self.location()
+            }),
+            "bs" | "bc" | "bg" | "bw" if self.cpu.history.is_none() => Err("no history kept".to_string()),
+            "bs" => match args.next().map(|e| e.parse::<u64>()) {
+                Some(Err(_)) => Err("count expected".to_string()),
+                n => {
+                    let mut stop = Stop::Step;
+                    for _ in 0..n.map_or(1, |e| e.unwrap()) {
+                        stop = self.step_back();
+                        if stop != Stop::Step {
+                            break;
+                        }
+                    }
+                    Ok(self.stopped(stop))
+                }
+            },
+            "bc" => {
+                let stop = self.run_back();
+                Ok(self.stopped(stop))
+            }
+            "bg" => self.address(args.next()).map(|a| {
+                let found = self.cpu.run_back_to(a);
+                self.exited = false;
+                self.stopped(if found { Stop::Step } else { Stop::Start })
+            }),
+            "bw" => self.address(args.next()).map(|a| {
+                let found = self.cpu.run_back_to_write(a);
+                self.exited = false;
+                self.stopped(if found { Stop::Step } else { Stop::Start })
+            }),
+            "r" | "regs" => Ok(self.registers()),
+            "x" => self.address(args.next()).map(|a| {
+                let n = args.next().and_then(|e| e.parse().ok()).unwrap_or(64);
+                self.dump(a, n)
+            }),
+            "l" | "list" => {
+                let a = match args.next() {
+                    None => Ok(self.cpu.reg.pc),
+                    s => self.address(s),
+                };
+                a.map(|a| self.disassemble(a, args.next().and_then(|e| e.parse().ok()).unwrap_or(10)))
+            }
+            "i" | "info" => self.address(args.next()).map(|a| match self.sources.find(a) {
+                Some(loc) => format!("{} {}", self.describe(a), self.sources.text(loc)),
+                None => format!("{:04x} {}", a, self.symbols.describe(a)),
+            }),
+            "src" | "source" => {
+                let loc = match args.next() {
+                    None => self.sources.find(self.cpu.reg.pc).ok_or_else(|| "no source at pc".to_string()),
+                    Some(s) if s.contains(':') => self.line(s),
+                    s => self
+                        .address(s)
+                        .and_then(|a| self.sources.find(a).ok_or_else(|| format!("no source at {:04x}", a))),
+                };
+                loc.map(|e| self.source(e, args.next().and_then(|e| e.parse().ok()).unwrap_or(10)))
+            }
+            "sym" => {
+                let pattern = args.next().unwrap_or("").to_ascii_uppercase();
+                Ok(self
+                    .symbols
+                    .iter()
+                    .filter(|(_, n)| n.to_ascii_uppercase().contains(&pattern))
+                    .map(|(a, n)| format!("{:04x} {}\n", a, n))
+                    .collect())
+            }
+            _ => Err(format!("unknown command {}", cmd)),
+        };
+        r.unwrap_or_else(|e| format!("error: {}", e)).trim_end().to_string()
+    }
+}
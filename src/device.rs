@@ -0,0 +1,45 @@
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+// A device on the I/O bus, reached by the IN and OUT instructions.
+pub trait Device {
+    fn get(&mut self, port: u8) -> u8;
+
+    fn set(&mut self, port: u8, v: u8);
+}
+
+// A device together with the ports it answers.
+type Slot = (RangeInclusive<u8>, Rc<RefCell<dyn Device>>);
+
+// The I/O bus routes each port to the device attached to it. Reading a port nobody answers gives 0xff.
+#[derive(Default)]
+pub struct Bus {
+    devices: Vec<Slot>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attach(&mut self, ports: RangeInclusive<u8>, dev: Rc<RefCell<dyn Device>>) {
+        self.devices.push((ports, dev));
+    }
+
+    fn find(&self, port: u8) -> Option<&Rc<RefCell<dyn Device>>> {
+        self.devices.iter().find(|(r, _)| r.contains(&port)).map(|(_, d)| d)
+    }
+}
+
+impl Device for Bus {
+    fn get(&mut self, port: u8) -> u8 {
+        self.find(port).map_or(0xff, |d| d.borrow_mut().get(port))
+    }
+
+    fn set(&mut self, port: u8, v: u8) {
+        if let Some(d) = self.find(port) {
+            d.borrow_mut().set(port, v)
+        }
+    }
+}
@@ -0,0 +1,725 @@
+// Disk images, the CP/M disk parameters describing them and the CP/M file system stored on them.
+use std::fs;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// CP/M disk parameter block, as found in memory through the disk parameter header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Dpb {
+    // 128 byte records per track.
+    pub spt: u16,
+    // Block shift and mask, the block size is 128 << bsh.
+    pub bsh: u8,
+    pub blm: u8,
+    // Extent mask.
+    pub exm: u8,
+    // Highest block number.
+    pub dsm: u16,
+    // Highest directory entry number.
+    pub drm: u16,
+    // Blocks reserved for the directory, as a bit map.
+    pub al0: u8,
+    pub al1: u8,
+    // Size of the directory check vector.
+    pub cks: u16,
+    // Reserved system tracks.
+    pub off: u16,
+}
+
+impl Dpb {
+    pub const IBM_3740: Dpb =
+        Dpb { spt: 26, bsh: 3, blm: 7, exm: 0, dsm: 242, drm: 63, al0: 0xc0, al1: 0x00, cks: 16, off: 2 };
+    pub const ALTAIR: Dpb =
+        Dpb { spt: 32, bsh: 4, blm: 15, exm: 1, dsm: 149, drm: 63, al0: 0x80, al1: 0x00, cks: 16, off: 2 };
+    pub const SIMH_HDSK: Dpb =
+        Dpb { spt: 32, bsh: 5, blm: 31, exm: 1, dsm: 2041, drm: 1023, al0: 0xff, al1: 0x00, cks: 0, off: 6 };
+
+    pub fn block_size(&self) -> usize {
+        128 << self.bsh
+    }
+
+    pub fn encode(&self) -> [u8; 15] {
+        let mut r = [0; 15];
+        r[0..2].copy_from_slice(&self.spt.to_le_bytes());
+        r[2] = self.bsh;
+        r[3] = self.blm;
+        r[4] = self.exm;
+        r[5..7].copy_from_slice(&self.dsm.to_le_bytes());
+        r[7..9].copy_from_slice(&self.drm.to_le_bytes());
+        r[9] = self.al0;
+        r[10] = self.al1;
+        r[11..13].copy_from_slice(&self.cks.to_le_bytes());
+        r[13..15].copy_from_slice(&self.off.to_le_bytes());
+        r
+    }
+}
+
+// Sector skew of the standard 8" single density CP/M disk.
+pub const XLT_IBM_3740: [u8; 26] =
+    [1, 7, 13, 19, 25, 5, 11, 17, 23, 3, 9, 15, 21, 2, 8, 14, 20, 26, 6, 12, 18, 24, 4, 10, 16, 22];
+
+// Sector skew of Altair CP/M, odd sectors first and then the even ones, so a sector can be processed while the next
+// one passes the head.
+pub const XLT_ALTAIR: [u8; 32] = [
+    1, 9, 17, 25, 7, 15, 23, 31, 13, 21, 29, 5, 19, 27, 3, 11, 2, 10, 18, 26, 8, 16, 24, 32, 14, 22, 30, 6, 20, 28, 4,
+    12,
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    pub tracks: u16,
+    // Physical sectors per track.
+    pub sectors: u16,
+    pub sector_size: usize,
+    // Sector translation table used by the BIOS, with one based sector numbers. Empty if sectors are not skewed.
+    pub xlt: Vec<u8>,
+    pub dpb: Dpb,
+}
+
+impl Geometry {
+    // 8" IBM 3740 single sided single density: 77 tracks of 26 sectors of 128 bytes.
+    pub fn ibm_3740() -> Self {
+        Self { tracks: 77, sectors: 26, sector_size: 128, xlt: XLT_IBM_3740.to_vec(), dpb: Dpb::IBM_3740 }
+    }
+
+    // Altair 88-DCDD 8" disk as used by Altair CP/M: 77 tracks of 32 sectors, each with a 137 byte frame around 128
+    // bytes of data.
+    pub fn altair() -> Self {
+        Self { tracks: 77, sectors: 32, sector_size: 128, xlt: XLT_ALTAIR.to_vec(), dpb: Dpb::ALTAIR }
+    }
+
+    // SIMH AltairZ80 8M hard disk.
+    pub fn simh_hdsk() -> Self {
+        Self { tracks: 2048, sectors: 32, sector_size: 128, xlt: vec![], dpb: Dpb::SIMH_HDSK }
+    }
+
+    pub fn size(&self) -> usize {
+        usize::from(self.tracks) * usize::from(self.sectors) * self.sector_size
+    }
+}
+
+// Sector level access to a disk, as used by emulated disk controllers. Sectors are numbered from zero in the order
+// they are stored on the track.
+pub trait Disk {
+    fn geometry(&self) -> &Geometry;
+
+    fn read(&mut self, track: u16, sector: u16, buf: &mut [u8]) -> io::Result<()>;
+
+    fn write(&mut self, track: u16, sector: u16, buf: &[u8]) -> io::Result<()>;
+}
+
+fn out_of_range() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "diskimg: sector out of range")
+}
+
+// A raw image holds every sector of every track back to back. Writes go through to the image file if the image was
+// opened from one.
+pub struct Raw {
+    pub geometry: Geometry,
+    pub data: Vec<u8>,
+    path: Option<PathBuf>,
+}
+
+impl Raw {
+    pub fn new(geometry: Geometry, mut data: Vec<u8>) -> Self {
+        data.resize(geometry.size(), 0xe5);
+        Self { geometry, data, path: None }
+    }
+
+    // A freshly formatted disk. CP/M marks free directory entries with 0xe5.
+    pub fn blank(geometry: Geometry) -> Self {
+        Self::new(geometry, vec![])
+    }
+
+    pub fn open(path: impl AsRef<Path>, geometry: Geometry) -> io::Result<Self> {
+        let data = fs::read(path.as_ref())?;
+        let mut r = Self::new(geometry, data);
+        r.path = Some(path.as_ref().to_path_buf());
+        Ok(r)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, &self.data)
+    }
+
+    fn offset(&self, track: u16, sector: u16) -> io::Result<usize> {
+        let g = &self.geometry;
+        if track >= g.tracks || sector >= g.sectors {
+            return Err(out_of_range());
+        }
+        Ok((usize::from(track) * usize::from(g.sectors) + usize::from(sector)) * g.sector_size)
+    }
+}
+
+impl Disk for Raw {
+    fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    fn read(&mut self, track: u16, sector: u16, buf: &mut [u8]) -> io::Result<()> {
+        let a = self.offset(track, sector)?;
+        let n = buf.len().min(self.geometry.sector_size);
+        buf[..n].copy_from_slice(&self.data[a..a + n]);
+        Ok(())
+    }
+
+    fn write(&mut self, track: u16, sector: u16, buf: &[u8]) -> io::Result<()> {
+        let a = self.offset(track, sector)?;
+        let n = buf.len().min(self.geometry.sector_size);
+        self.data[a..a + n].copy_from_slice(&buf[..n]);
+        if let Some(p) = &self.path {
+            let mut f = fs::OpenOptions::new().write(true).open(p)?;
+            f.seek(SeekFrom::Start(a as u64))?;
+            f.write_all(&buf[..n])?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(s: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, s)
+}
+
+// Sector of an ImageDisk track. Sectors whose data could not be read are kept as None.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImdSector {
+    pub id: u8,
+    pub data: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImdTrack {
+    // Recording mode, 0 to 2 for FM at 500, 300 and 250 kbps and 3 to 5 for MFM.
+    pub mode: u8,
+    pub cylinder: u8,
+    pub head: u8,
+    pub sector_size: usize,
+    pub sectors: Vec<ImdSector>,
+}
+
+// ImageDisk .IMD image: an ASCII header ended by ^Z, then every track with its sector numbering map and sector data,
+// where sectors filled with a single value are compressed to that value. Tracks are numbered in the order they are
+// stored and sectors from the lowest sector number on the track.
+pub struct Imd {
+    pub geometry: Geometry,
+    pub header: Vec<u8>,
+    pub tracks: Vec<ImdTrack>,
+    path: Option<PathBuf>,
+}
+
+impl Imd {
+    pub fn blank(geometry: Geometry) -> Self {
+        let mode = if geometry.sector_size == 128 { 0 } else { 3 };
+        let tracks = (0..geometry.tracks)
+            .map(|t| ImdTrack {
+                mode,
+                cylinder: t as u8,
+                head: 0,
+                sector_size: geometry.sector_size,
+                sectors: (0..geometry.sectors)
+                    .map(|s| ImdSector { id: s as u8 + 1, data: Some(vec![0xe5; geometry.sector_size]) })
+                    .collect(),
+            })
+            .collect();
+        Self { geometry, header: b"IMD 1.18: i8080\r\n".to_vec(), tracks, path: None }
+    }
+
+    pub fn decode(data: &[u8], geometry: Geometry) -> io::Result<Self> {
+        if !data.starts_with(b"IMD ") {
+            return Err(invalid("diskimg: not an IMD image"));
+        }
+        let end = data.iter().position(|e| *e == 0x1a).ok_or_else(|| invalid("diskimg: IMD header not ended"))?;
+        let mut tracks = Vec::new();
+        let mut i = end + 1;
+        let take = |i: &mut usize, n: usize| -> io::Result<&[u8]> {
+            let r = data.get(*i..*i + n).ok_or_else(|| invalid("diskimg: IMD image truncated"))?;
+            *i += n;
+            Ok(r)
+        };
+        while i < data.len() {
+            let h = take(&mut i, 5)?;
+            let (mode, cylinder, head, n, size) = (h[0], h[1], h[2], usize::from(h[3]), h[4]);
+            if size > 6 {
+                return Err(invalid("diskimg: IMD sector size not supported"));
+            }
+            let sector_size = 128 << size;
+            let map = take(&mut i, n)?.to_vec();
+            if head & 0x80 != 0 {
+                take(&mut i, n)?;
+            }
+            if head & 0x40 != 0 {
+                take(&mut i, n)?;
+            }
+            let mut sectors = Vec::with_capacity(n);
+            for id in map {
+                let data = match take(&mut i, 1)?[0] {
+                    0 => None,
+                    t @ 1..=8 if t % 2 == 1 => Some(take(&mut i, sector_size)?.to_vec()),
+                    2..=8 => Some(vec![take(&mut i, 1)?[0]; sector_size]),
+                    _ => return Err(invalid("diskimg: IMD sector type not supported")),
+                };
+                sectors.push(ImdSector { id, data });
+            }
+            tracks.push(ImdTrack { mode, cylinder, head: head & 0x0f, sector_size, sectors });
+        }
+        Ok(Self { geometry, header: data[..end].to_vec(), tracks, path: None })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut r = self.header.clone();
+        r.push(0x1a);
+        for t in &self.tracks {
+            let size = (t.sector_size / 128).trailing_zeros() as u8;
+            r.extend([t.mode, t.cylinder, t.head, t.sectors.len() as u8, size]);
+            r.extend(t.sectors.iter().map(|e| e.id));
+            for s in &t.sectors {
+                match &s.data {
+                    None => r.push(0),
+                    Some(d) if d.iter().all(|e| *e == d[0]) => r.extend([2, d[0]]),
+                    Some(d) => {
+                        r.push(1);
+                        r.extend(d);
+                    }
+                }
+            }
+        }
+        r
+    }
+
+    pub fn open(path: impl AsRef<Path>, geometry: Geometry) -> io::Result<Self> {
+        let mut r = Self::decode(&fs::read(path.as_ref())?, geometry)?;
+        r.path = Some(path.as_ref().to_path_buf());
+        Ok(r)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.encode())
+    }
+
+    fn sector(&mut self, track: u16, sector: u16) -> io::Result<&mut ImdSector> {
+        let t = self.tracks.get_mut(usize::from(track)).ok_or_else(out_of_range)?;
+        let first = t.sectors.iter().map(|e| e.id).min().unwrap_or(0);
+        let id = u16::from(first) + sector;
+        t.sectors.iter_mut().find(|e| u16::from(e.id) == id).ok_or_else(out_of_range)
+    }
+}
+
+impl Disk for Imd {
+    fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    fn read(&mut self, track: u16, sector: u16, buf: &mut [u8]) -> io::Result<()> {
+        let s = self.sector(track, sector)?;
+        let d = s.data.as_ref().ok_or_else(|| io::Error::other("diskimg: sector data unavailable"))?;
+        let n = buf.len().min(d.len());
+        buf[..n].copy_from_slice(&d[..n]);
+        Ok(())
+    }
+
+    // The image is rewritten as a whole, as compression changes the position of everything after the sector.
+    fn write(&mut self, track: u16, sector: u16, buf: &[u8]) -> io::Result<()> {
+        let size = self.geometry.sector_size;
+        let s = self.sector(track, sector)?;
+        let d = s.data.get_or_insert_with(|| vec![0xe5; size]);
+        let n = buf.len().min(d.len());
+        d[..n].copy_from_slice(&buf[..n]);
+        if let Some(p) = &self.path {
+            self.save(p)?;
+        }
+        Ok(())
+    }
+}
+
+// Altair 88-DCDD image, as written by SIMH and the Altair disk tools: 137 byte sectors stored in physical order. On
+// the system tracks the data follows the track number and a 16 bit length, and is followed by a stop byte and a
+// checksum. On the other tracks the data follows the track and sector numbers, a file number, a byte count, a checksum
+// and a 16 bit link, and is followed by a stop byte.
+pub struct Altair {
+    pub geometry: Geometry,
+    pub data: Vec<u8>,
+    path: Option<PathBuf>,
+}
+
+pub const ALTAIR_SECTOR_SIZE: usize = 137;
+// First track using the data sector layout.
+const ALTAIR_DATA_TRACK: u16 = 6;
+
+impl Altair {
+    pub fn new(mut data: Vec<u8>) -> Self {
+        let g = Geometry::altair();
+        let size = usize::from(g.tracks) * usize::from(g.sectors) * ALTAIR_SECTOR_SIZE;
+        let old = data.len().min(size);
+        data.resize(size, 0xe5);
+        let mut r = Self { geometry: g, data, path: None };
+        // Frame the sectors added by padding, so that short images read back as formatted sectors.
+        for i in old.div_ceil(ALTAIR_SECTOR_SIZE)..size / ALTAIR_SECTOR_SIZE {
+            let (t, s) = ((i / 32) as u16, (i % 32) as u16);
+            r.frame(t, s, &[0xe5; 128]);
+        }
+        r
+    }
+
+    pub fn blank() -> Self {
+        Self::new(vec![])
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut r = Self::new(fs::read(path.as_ref())?);
+        r.path = Some(path.as_ref().to_path_buf());
+        Ok(r)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, &self.data)
+    }
+
+    // Offset of the raw sector in the image and of the data in the sector.
+    fn offset(&self, track: u16, sector: u16) -> io::Result<(usize, usize)> {
+        let g = &self.geometry;
+        if track >= g.tracks || sector >= g.sectors {
+            return Err(out_of_range());
+        }
+        let a = (usize::from(track) * usize::from(g.sectors) + usize::from(sector)) * ALTAIR_SECTOR_SIZE;
+        Ok((a, if track < ALTAIR_DATA_TRACK { 3 } else { 7 }))
+    }
+
+    fn frame(&mut self, track: u16, sector: u16, buf: &[u8]) {
+        let (a, d) = self.offset(track, sector).unwrap();
+        let s = &mut self.data[a..a + ALTAIR_SECTOR_SIZE];
+        s[d..d + 128].copy_from_slice(&buf[..128]);
+        let sum = buf[..128].iter().fold(0u8, |acc, e| acc.wrapping_add(*e));
+        s[0] = track as u8 | 0x80;
+        if track < ALTAIR_DATA_TRACK {
+            s[1..3].copy_from_slice(&0x0100u16.to_le_bytes());
+            s[131] = 0xff;
+            s[132] = sum;
+        } else {
+            s[1] = ((sector * 17) % 32) as u8;
+            s[2] = 0;
+            s[3] = 0;
+            s[5] = 0;
+            s[6] = 0;
+            s[4] = sum;
+            s[135] = 0xff;
+        }
+    }
+
+    // The whole 137 byte sector, as seen by the disk controller.
+    pub fn read_raw(&self, track: u16, sector: u16) -> io::Result<&[u8]> {
+        let (a, _) = self.offset(track, sector)?;
+        Ok(&self.data[a..a + ALTAIR_SECTOR_SIZE])
+    }
+
+    pub fn write_raw(&mut self, track: u16, sector: u16, buf: &[u8]) -> io::Result<()> {
+        let (a, _) = self.offset(track, sector)?;
+        let n = buf.len().min(ALTAIR_SECTOR_SIZE);
+        self.data[a..a + n].copy_from_slice(&buf[..n]);
+        self.flush(a)
+    }
+
+    fn flush(&self, a: usize) -> io::Result<()> {
+        if let Some(p) = &self.path {
+            let mut f = fs::OpenOptions::new().write(true).open(p)?;
+            f.seek(SeekFrom::Start(a as u64))?;
+            f.write_all(&self.data[a..a + ALTAIR_SECTOR_SIZE])?;
+        }
+        Ok(())
+    }
+}
+
+impl Disk for Altair {
+    fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    fn read(&mut self, track: u16, sector: u16, buf: &mut [u8]) -> io::Result<()> {
+        let (a, d) = self.offset(track, sector)?;
+        let n = buf.len().min(128);
+        buf[..n].copy_from_slice(&self.data[a + d..a + d + n]);
+        Ok(())
+    }
+
+    fn write(&mut self, track: u16, sector: u16, buf: &[u8]) -> io::Result<()> {
+        let (a, _) = self.offset(track, sector)?;
+        let mut data = [0; 128];
+        self.read(track, sector, &mut data)?;
+        let n = buf.len().min(128);
+        data[..n].copy_from_slice(&buf[..n]);
+        self.frame(track, sector, &data);
+        self.flush(a)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    // 8" single sided single density, sectors back to back.
+    Raw,
+    // ImageDisk, holding an 8" single sided single density disk.
+    Imd,
+    // Altair 88-DCDD.
+    Altair,
+    // SIMH AltairZ80 hard disk.
+    Hdsk,
+}
+
+impl Format {
+    // Guess the format from the file extension, then from the image size.
+    pub fn detect(path: &Path, size: usize) -> Self {
+        let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).unwrap_or_default();
+        match ext.as_str() {
+            "imd" => Format::Imd,
+            "hdsk" | "hd" => Format::Hdsk,
+            _ if size == Geometry::simh_hdsk().size() => Format::Hdsk,
+            _ if size == 77 * 32 * ALTAIR_SECTOR_SIZE || (ext == "dsk" && size == 0) => Format::Altair,
+            _ => Format::Raw,
+        }
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        match self {
+            Format::Raw | Format::Imd => Geometry::ibm_3740(),
+            Format::Altair => Geometry::altair(),
+            Format::Hdsk => Geometry::simh_hdsk(),
+        }
+    }
+}
+
+// Open an image of the given format, writes go through to the file.
+pub fn open(path: impl AsRef<Path>, format: Format) -> io::Result<Box<dyn Disk>> {
+    let p = path.as_ref();
+    Ok(match format {
+        Format::Raw | Format::Hdsk => Box::new(Raw::open(p, format.geometry())?),
+        Format::Imd => Box::new(Imd::open(p, format.geometry())?),
+        Format::Altair => Box::new(Altair::open(p)?),
+    })
+}
+
+// Create a formatted image file.
+pub fn create(path: impl AsRef<Path>, format: Format) -> io::Result<()> {
+    match format {
+        Format::Raw | Format::Hdsk => Raw::blank(format.geometry()).save(path),
+        Format::Imd => Imd::blank(format.geometry()).save(path),
+        Format::Altair => Altair::blank().save(path),
+    }
+}
+
+// A file in a CP/M directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub user: u8,
+    // Name without attribute bits.
+    pub name: [u8; 11],
+    pub readonly: bool,
+    pub system: bool,
+    // Size in 128 byte records.
+    pub records: u32,
+    blocks: Vec<u16>,
+}
+
+impl Entry {
+    pub fn size(&self) -> usize {
+        self.records as usize * 128
+    }
+}
+
+// The CP/M file system on a disk, accessed through logical 128 byte records after the system tracks in the way the
+// BIOS would: logical sectors are skewed through the translation table and blocked into larger physical sectors.
+pub struct Cpm<'a> {
+    pub disk: &'a mut dyn Disk,
+    dpb: Dpb,
+}
+
+impl<'a> Cpm<'a> {
+    pub fn new(disk: &'a mut dyn Disk) -> Self {
+        let dpb = disk.geometry().dpb;
+        Self { disk, dpb }
+    }
+
+    fn locate(&self, r: usize) -> (u16, u16, usize) {
+        let g = self.disk.geometry();
+        let spt = usize::from(self.dpb.spt);
+        let rps = g.sector_size / 128;
+        let track = usize::from(self.dpb.off) + r / spt;
+        let s = (r % spt) / rps;
+        let sector = if g.xlt.is_empty() { s } else { usize::from(g.xlt[s]) - 1 };
+        (track as u16, sector as u16, (r % spt) % rps * 128)
+    }
+
+    pub fn read_record(&mut self, r: usize) -> io::Result<[u8; 128]> {
+        let (t, s, o) = self.locate(r);
+        let mut buf = vec![0; self.disk.geometry().sector_size];
+        self.disk.read(t, s, &mut buf)?;
+        let mut r = [0; 128];
+        r.copy_from_slice(&buf[o..o + 128]);
+        Ok(r)
+    }
+
+    pub fn write_record(&mut self, r: usize, data: &[u8; 128]) -> io::Result<()> {
+        let (t, s, o) = self.locate(r);
+        let mut buf = vec![0; self.disk.geometry().sector_size];
+        if buf.len() != 128 {
+            self.disk.read(t, s, &mut buf)?;
+        }
+        buf[o..o + 128].copy_from_slice(data);
+        self.disk.write(t, s, &buf)
+    }
+
+    fn records_per_block(&self) -> usize {
+        self.dpb.block_size() / 128
+    }
+
+    // Block pointers are 8 bits wide on disks of up to 256 blocks, 16 bits otherwise.
+    fn wide(&self) -> bool {
+        self.dpb.dsm > 255
+    }
+
+    fn directory(&mut self) -> io::Result<Vec<u8>> {
+        let n = (usize::from(self.dpb.drm) + 1) * 32;
+        let mut r = Vec::with_capacity(n);
+        for i in 0..n.div_ceil(128) {
+            r.extend(self.read_record(i)?);
+        }
+        r.truncate(n);
+        Ok(r)
+    }
+
+    fn write_directory(&mut self, dir: &[u8]) -> io::Result<()> {
+        for (i, c) in dir.chunks(128).enumerate() {
+            let mut buf = [0xe5; 128];
+            buf[..c.len()].copy_from_slice(c);
+            if self.read_record(i)? != buf {
+                self.write_record(i, &buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn pointers(&self, e: &[u8]) -> Vec<u16> {
+        if self.wide() {
+            e[16..32].chunks(2).map(|e| u16::from_le_bytes([e[0], e[1]])).filter(|e| *e != 0).collect()
+        } else {
+            e[16..32].iter().filter(|e| **e != 0).map(|e| u16::from(*e)).collect()
+        }
+    }
+
+    // Files in the directory, ordered by user and name.
+    pub fn list(&mut self) -> io::Result<Vec<Entry>> {
+        let dir = self.directory()?;
+        let mut exts: Vec<(u8, [u8; 11], u32, &[u8])> = Vec::new();
+        for e in dir.chunks(32) {
+            if e[0] > 15 {
+                continue;
+            }
+            let mut name = [0; 11];
+            for (i, c) in e[1..12].iter().enumerate() {
+                name[i] = c & 0x7f;
+            }
+            exts.push((e[0], name, u32::from(e[14] & 0x3f) << 5 | u32::from(e[12] & 0x1f), e));
+        }
+        exts.sort_by_key(|e| (e.0, e.1, e.2));
+        let mut r: Vec<Entry> = Vec::new();
+        for (user, name, ex, e) in exts {
+            // The entry holds exm + 1 logical extents, ex is the last one used.
+            let records = ex * 128 + u32::from(e[15]);
+            let blocks = self.pointers(e);
+            match r.last_mut() {
+                Some(f) if f.user == user && f.name == name => {
+                    f.records = records;
+                    f.blocks.extend(blocks);
+                }
+                _ => {
+                    r.push(Entry { user, name, readonly: e[9] & 0x80 != 0, system: e[10] & 0x80 != 0, records, blocks })
+                }
+            }
+        }
+        Ok(r)
+    }
+
+    pub fn find(&mut self, user: u8, name: &[u8; 11]) -> io::Result<Option<Entry>> {
+        Ok(self.list()?.into_iter().find(|e| e.user == user && &e.name == name))
+    }
+
+    pub fn extract(&mut self, user: u8, name: &[u8; 11]) -> io::Result<Vec<u8>> {
+        let f = self.find(user, name)?.ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        let rpb = self.records_per_block();
+        let mut r = Vec::with_capacity(f.size());
+        for i in 0..f.records as usize {
+            let b = *f.blocks.get(i / rpb).ok_or_else(|| invalid("diskimg: file shorter than its record count"))?;
+            r.extend(self.read_record(usize::from(b) * rpb + i % rpb)?);
+        }
+        Ok(r)
+    }
+
+    pub fn erase(&mut self, user: u8, name: &[u8; 11]) -> io::Result<bool> {
+        let mut dir = self.directory()?;
+        let mut found = false;
+        for e in dir.chunks_mut(32) {
+            let n: Vec<u8> = e[1..12].iter().map(|c| c & 0x7f).collect();
+            if e[0] == user && n == name {
+                e[0] = 0xe5;
+                found = true;
+            }
+        }
+        self.write_directory(&dir)?;
+        Ok(found)
+    }
+
+    // Write a file, replacing any file of the same name. The last record is padded with ^Z.
+    pub fn insert(&mut self, user: u8, name: &[u8; 11], data: &[u8]) -> io::Result<()> {
+        self.erase(user, name)?;
+        let mut dir = self.directory()?;
+        let rpb = self.records_per_block();
+        let mut used = vec![false; usize::from(self.dpb.dsm) + 1];
+        let al = u16::from_be_bytes([self.dpb.al0, self.dpb.al1]);
+        for (i, u) in used.iter_mut().enumerate().take(16) {
+            *u = al & (0x8000 >> i) != 0;
+        }
+        for e in dir.chunks(32).filter(|e| e[0] <= 15) {
+            for b in self.pointers(e) {
+                if let Some(u) = used.get_mut(usize::from(b)) {
+                    *u = true;
+                }
+            }
+        }
+        let records = data.len().div_ceil(128);
+        let mut free = (0..used.len()).filter(|e| !used[*e]).map(|e| e as u16);
+        let blocks: Vec<u16> = (0..records.div_ceil(rpb)).map_while(|_| free.next()).collect();
+        if blocks.len() < records.div_ceil(rpb) {
+            return Err(io::Error::other("diskimg: disk full"));
+        }
+        let per_entry = if self.wide() { 8 } else { 16 };
+        let per_extent = (usize::from(self.dpb.exm) + 1) * 128;
+        let extents = records.div_ceil(per_extent).max(1);
+        let mut slots = dir.chunks(32).enumerate().filter(|e| e.1[0] == 0xe5).map(|e| e.0).collect::<Vec<_>>();
+        if slots.len() < extents {
+            return Err(io::Error::other("diskimg: directory full"));
+        }
+        slots.truncate(extents);
+        for (i, slot) in slots.into_iter().enumerate() {
+            let n = records.saturating_sub(i * per_extent).min(per_extent);
+            let ex = i * (usize::from(self.dpb.exm) + 1) + n.saturating_sub(1) / 128;
+            let e = &mut dir[slot * 32..slot * 32 + 32];
+            e.fill(0);
+            e[0] = user;
+            e[1..12].copy_from_slice(name);
+            e[12] = (ex & 0x1f) as u8;
+            e[14] = (ex >> 5) as u8;
+            e[15] = (n - n.saturating_sub(1) / 128 * 128) as u8;
+            for (k, b) in blocks.iter().skip(i * per_entry).take(per_entry).enumerate() {
+                if self.wide() {
+                    e[16 + 2 * k..18 + 2 * k].copy_from_slice(&b.to_le_bytes());
+                } else {
+                    e[16 + k] = *b as u8;
+                }
+            }
+        }
+        for i in 0..records {
+            let mut buf = [0x1a; 128];
+            let c = &data[i * 128..data.len().min(i * 128 + 128)];
+            buf[..c.len()].copy_from_slice(c);
+            self.write_record(usize::from(blocks[i / rpb]) * rpb + i % rpb, &buf)?;
+        }
+        self.write_directory(&dir)
+    }
+}
@@ -0,0 +1,173 @@
+// Intel HEX is a text format where each line is a record of the form ":LLAAAATT<data>CC".
+//
+//   LL   Byte count of the data field
+//   AAAA Load address
+//   TT   Record type
+//   CC   Two's complement of the sum of all preceding bytes in the record
+//
+// Only the 16-bit address space matters for 8080, so extended address records are accepted as long as they keep the
+// upper address bits zero and rejected otherwise.
+use super::cpu::Cpu;
+use super::memory::Memory;
+use std::io;
+use std::ops::RangeInclusive;
+
+pub const RECORD_DATA: u8 = 0x00;
+pub const RECORD_EOF: u8 = 0x01;
+pub const RECORD_EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+pub const RECORD_START_SEGMENT_ADDRESS: u8 = 0x03;
+pub const RECORD_EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+pub const RECORD_START_LINEAR_ADDRESS: u8 = 0x05;
+
+// The number of data bytes written per record by dump().
+const RECORD_SIZE: usize = 16;
+
+fn invalid(line: usize, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("hex: line {}: {}", line, msg))
+}
+
+#[derive(Default)]
+pub struct Hex {
+    // Contiguous runs of data in the order they appear in the file.
+    pub data: Vec<(u16, Vec<u8>)>,
+    // Entry point given by a start address record.
+    pub start: Option<u16>,
+}
+
+impl Hex {
+    pub fn load(&self, mem: &mut dyn Memory) {
+        for (a, d) in &self.data {
+            for (i, e) in d.iter().enumerate() {
+                mem.set(a.wrapping_add(i as u16), *e);
+            }
+        }
+    }
+
+    // Address ranges covered by the data records, each given as (first, last).
+    pub fn ranges(&self) -> Vec<RangeInclusive<u16>> {
+        self.data
+            .iter()
+            .filter(|(_, d)| !d.is_empty())
+            .map(|(a, d)| *a..=a.wrapping_add((d.len() - 1) as u16))
+            .collect()
+    }
+}
+
+fn decode_line(n: usize, line: &str) -> io::Result<Vec<u8>> {
+    let body = line.strip_prefix(':').ok_or_else(|| invalid(n, "missing start code"))?;
+    if body.len() % 2 != 0 || body.len() < 10 {
+        return Err(invalid(n, "bad record length"));
+    }
+    let mut r = Vec::with_capacity(body.len() / 2);
+    for i in (0..body.len()).step_by(2) {
+        let b = body.get(i..i + 2).ok_or_else(|| invalid(n, "bad hex digit"))?;
+        r.push(u8::from_str_radix(b, 16).map_err(|_| invalid(n, "bad hex digit"))?);
+    }
+    if usize::from(r[0]) + 5 != r.len() {
+        return Err(invalid(n, "byte count mismatch"));
+    }
+    if r.iter().fold(0u8, |acc, e| acc.wrapping_add(*e)) != 0 {
+        return Err(invalid(n, "checksum mismatch"));
+    }
+    Ok(r)
+}
+
+pub fn decode(s: &str) -> io::Result<Hex> {
+    let mut hex = Hex::default();
+    let mut eof = false;
+    for (i, line) in s.lines().enumerate() {
+        let n = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if eof {
+            return Err(invalid(n, "data after end of file record"));
+        }
+        let r = decode_line(n, line)?;
+        let addr = u16::from_be_bytes([r[1], r[2]]);
+        let data = &r[4..r.len() - 1];
+        match r[3] {
+            RECORD_DATA => {
+                if usize::from(addr) + data.len() > 0x10000 {
+                    return Err(invalid(n, "data exceeds 64K address space"));
+                }
+                match hex.data.last_mut() {
+                    Some((a, d)) if usize::from(*a) + d.len() == usize::from(addr) => d.extend_from_slice(data),
+                    _ => hex.data.push((addr, data.to_vec())),
+                }
+            }
+            RECORD_EOF => eof = true,
+            RECORD_EXTENDED_SEGMENT_ADDRESS | RECORD_EXTENDED_LINEAR_ADDRESS => {
+                if data.len() != 2 {
+                    return Err(invalid(n, "bad extended address record"));
+                }
+                if data != [0x00, 0x00] {
+                    return Err(invalid(n, "extended address outside 64K address space"));
+                }
+            }
+            RECORD_START_SEGMENT_ADDRESS => {
+                if data.len() != 4 {
+                    return Err(invalid(n, "bad start segment address record"));
+                }
+                let cs = u32::from(u16::from_be_bytes([data[0], data[1]]));
+                let ip = u32::from(u16::from_be_bytes([data[2], data[3]]));
+                let a = (cs << 4) + ip;
+                if a > 0xffff {
+                    return Err(invalid(n, "start address outside 64K address space"));
+                }
+                hex.start = Some(a as u16);
+            }
+            RECORD_START_LINEAR_ADDRESS => {
+                if data.len() != 4 {
+                    return Err(invalid(n, "bad start linear address record"));
+                }
+                let a = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                if a > 0xffff {
+                    return Err(invalid(n, "start address outside 64K address space"));
+                }
+                hex.start = Some(a as u16);
+            }
+            _ => return Err(invalid(n, "unknown record type")),
+        }
+    }
+    Ok(hex)
+}
+
+// Load Intel HEX text into the cpu's memory. The program counter is set from the start address record if present.
+pub fn load(cpu: &mut Cpu, s: &str) -> io::Result<Hex> {
+    let hex = decode(s)?;
+    hex.load(&mut *cpu.mem.borrow_mut());
+    if let Some(a) = hex.start {
+        cpu.reg.pc = a;
+    }
+    Ok(hex)
+}
+
+fn encode_record(kind: u8, addr: u16, data: &[u8]) -> String {
+    let mut r = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+    r.extend_from_slice(data);
+    let c = r.iter().fold(0u8, |acc, e| acc.wrapping_add(*e)).wrapping_neg();
+    r.push(c);
+    let mut s = String::from(":");
+    for e in r {
+        s.push_str(&format!("{:02X}", e));
+    }
+    s.push('\n');
+    s
+}
+
+// Dump a memory range as Intel HEX, with an optional start address record.
+pub fn dump(mem: &dyn Memory, range: RangeInclusive<u16>, start: Option<u16>) -> String {
+    let mut s = String::new();
+    let data: Vec<u8> = range.clone().map(|a| mem.get(a)).collect();
+    for (i, e) in data.chunks(RECORD_SIZE).enumerate() {
+        let a = range.start().wrapping_add((i * RECORD_SIZE) as u16);
+        s.push_str(&encode_record(RECORD_DATA, a, e));
+    }
+    if let Some(a) = start {
+        s.push_str(&encode_record(RECORD_START_LINEAR_ADDRESS, 0x0000, &u32::from(a).to_be_bytes()));
+    }
+    s.push_str(&encode_record(RECORD_EOF, 0x0000, &[]));
+    s
+}
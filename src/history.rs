@@ -0,0 +1,99 @@
+// Execution history, to run a program backwards. Before each instruction the cpu saves its registers, the halted and
+// inte flags and the cycle count, and as the instruction runs the old value of every byte it writes. Undoing the
+// entries one by one takes the machine back an instruction at a time. An interrupt taken between two instructions is
+// undone with the instruction before it. What devices did, and memory the host wrote, such as the buffer of a trapped
+// BDOS call, is not undone.
+//
+// Entries are kept within a budget of bytes, the oldest dropped first.
+use super::register::Register;
+use std::collections::VecDeque;
+use std::mem;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub reg: Register,
+    pub halted: bool,
+    pub inte: bool,
+    pub cycles: u64,
+    // Bytes the instruction overwrote with their old values, in the order written.
+    pub writes: Vec<(u16, u8)>,
+}
+
+impl Entry {
+    // Bytes the entry takes in the budget.
+    pub fn size(&self) -> usize {
+        mem::size_of::<Entry>() + self.writes.len() * mem::size_of::<(u16, u8)>()
+    }
+
+    pub fn wrote(&self, addr: u16) -> bool {
+        self.writes.iter().any(|e| e.0 == addr)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    // Bytes the entries may take.
+    pub budget: usize,
+    entries: VecDeque<Entry>,
+    size: usize,
+}
+
+impl History {
+    pub fn new(budget: usize) -> Self {
+        Self { budget, entries: VecDeque::new(), size: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Bytes the entries take.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+
+    // The entry of the last instruction run.
+    pub fn last(&self) -> Option<&Entry> {
+        self.entries.back()
+    }
+
+    // Start the entry of an instruction about to run.
+    pub fn begin(&mut self, reg: Register, halted: bool, inte: bool, cycles: u64) {
+        let e = Entry { reg, halted, inte, cycles, writes: vec![] };
+        self.size += e.size();
+        self.entries.push_back(e);
+        self.trim();
+    }
+
+    // Note the old value of a byte the instruction writes.
+    pub fn write(&mut self, addr: u16, old: u8) {
+        if let Some(e) = self.entries.back_mut() {
+            e.writes.push((addr, old));
+            self.size += mem::size_of::<(u16, u8)>();
+        }
+    }
+
+    // Take the entry of the last instruction run.
+    pub fn pop(&mut self) -> Option<Entry> {
+        let e = self.entries.pop_back()?;
+        self.size -= e.size();
+        Some(e)
+    }
+
+    // Drop the oldest entries over the budget, keeping the last.
+    fn trim(&mut self) {
+        while self.size > self.budget && self.entries.len() > 1 {
+            let e = self.entries.pop_front().unwrap();
+            self.size -= e.size();
+        }
+    }
+}
@@ -0,0 +1,386 @@
+// A host directory presented as a CP/M disk. The directory and the block allocation are synthesized from the host
+// files, so the stock BDOS can read them through the BIOS like any other disk. Writes go back to the host:
+//
+//   - Data written to a block that belongs to a file goes straight into the host file.
+//   - Data written to a free block is held until a directory entry claims the block.
+//   - Directory writes are compared with the previous directory to create, extend, truncate, rename and delete host
+//     files, and to follow the read-only attribute.
+//
+// Files of user 0 live in the directory itself and files of other user areas in a subdirectory named after the user
+// number. Host names that do not fit the upper case 8.3 form are not visible.
+use super::bdos::{from_name, to_name, Name};
+use super::diskimg::{Disk, Dpb, Geometry};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const RECORD: usize = 128;
+// CP/M pads the last record of a text file with ^Z.
+const EOF: u8 = 0x1a;
+const EMPTY: u8 = 0xe5;
+
+// 4M disk of 2K blocks with 512 directory entries and no system tracks.
+pub fn geometry() -> Geometry {
+    Geometry {
+        tracks: 512,
+        sectors: 64,
+        sector_size: RECORD,
+        xlt: vec![],
+        dpb: Dpb { spt: 64, bsh: 4, blm: 15, exm: 0, dsm: 2047, drm: 511, al0: 0xff, al1: 0x00, cks: 0, off: 0 },
+    }
+}
+
+// A host file as listed: user, name, path, size and whether it is read only.
+type Listed = (u8, Name, PathBuf, u64, bool);
+// A directory entry as decoded: extent number, read only attribute, record count and blocks.
+type Extent = (u32, bool, u8, Vec<u16>);
+
+#[derive(Clone, Debug, PartialEq)]
+struct File {
+    // Host file, whose name may differ in case from the CP/M name.
+    path: PathBuf,
+    user: u8,
+    name: Name,
+    readonly: bool,
+    records: u32,
+    blocks: Vec<u16>,
+}
+
+pub struct HostDir {
+    pub root: PathBuf,
+    geometry: Geometry,
+    dir: Vec<u8>,
+    files: Vec<File>,
+    // Owner of each block, as the index of the file and of the block within the file.
+    owner: HashMap<u16, (usize, usize)>,
+    // Data written to blocks no directory entry has claimed yet.
+    pending: HashMap<u16, Vec<u8>>,
+    // Host listing at the last scan, used to notice changes made on the host.
+    listing: Vec<(PathBuf, u64, bool)>,
+}
+
+impl HostDir {
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        let g = geometry();
+        let mut r = Self {
+            root: root.as_ref().to_path_buf(),
+            dir: vec![EMPTY; (usize::from(g.dpb.drm) + 1) * 32],
+            geometry: g,
+            files: vec![],
+            owner: HashMap::new(),
+            pending: HashMap::new(),
+            listing: vec![],
+        };
+        r.refresh()?;
+        Ok(r)
+    }
+
+    fn records_per_block(&self) -> usize {
+        self.geometry.dpb.block_size() / RECORD
+    }
+
+    fn dir_blocks(&self) -> u16 {
+        u16::from(self.geometry.dpb.al0).count_ones() as u16 + u16::from(self.geometry.dpb.al1).count_ones() as u16
+    }
+
+    fn path(&self, user: u8, name: &Name) -> PathBuf {
+        let d = if user == 0 { self.root.clone() } else { self.root.join(user.to_string()) };
+        d.join(from_name(name))
+    }
+
+    fn list(&self) -> io::Result<Vec<Listed>> {
+        let mut r = Vec::new();
+        for user in 0..16u8 {
+            let d = if user == 0 { self.root.clone() } else { self.root.join(user.to_string()) };
+            let Ok(rd) = fs::read_dir(&d) else { continue };
+            for e in rd.flatten() {
+                let Ok(meta) = e.metadata() else { continue };
+                if !meta.is_file() {
+                    continue;
+                }
+                if let Some(n) = e.file_name().to_str().and_then(to_name) {
+                    r.push((user, n, e.path(), meta.len(), meta.permissions().readonly()));
+                }
+            }
+        }
+        r.sort();
+        Ok(r)
+    }
+
+    // Pick up files added, removed or resized on the host. Blocks of files that did not change keep their place,
+    // so open files of a running program are not disturbed.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let list = self.list()?;
+        let listing: Vec<(PathBuf, u64, bool)> = list.iter().map(|e| (e.2.clone(), e.3, e.4)).collect();
+        if listing == self.listing && !self.listing.is_empty() {
+            return Ok(());
+        }
+        self.listing = listing;
+        let rpb = self.records_per_block() as u32;
+        let mut used: Vec<bool> = vec![false; usize::from(self.geometry.dpb.dsm) + 1];
+        for b in 0..self.dir_blocks() {
+            used[usize::from(b)] = true;
+        }
+        for b in self.pending.keys() {
+            used[usize::from(*b)] = true;
+        }
+        let old = std::mem::take(&mut self.files);
+        let mut files = Vec::new();
+        for (user, name, path, len, readonly) in list {
+            let records = len.div_ceil(RECORD as u64) as u32;
+            let keep = old.iter().find(|e| e.user == user && e.name == name && e.records == records);
+            let blocks = match keep {
+                Some(f) => f.blocks.clone(),
+                None => vec![],
+            };
+            files.push(File { path, user, name, readonly, records, blocks });
+        }
+        for f in &files {
+            for b in &f.blocks {
+                used[usize::from(*b)] = true;
+            }
+        }
+        for f in files.iter_mut().filter(|e| e.blocks.is_empty()) {
+            let n = f.records.div_ceil(rpb) as usize;
+            let free: Vec<u16> = (0..used.len()).filter(|e| !used[*e]).take(n).map(|e| e as u16).collect();
+            if free.len() < n {
+                // The disk is full, the file stays invisible.
+                f.records = u32::MAX;
+                continue;
+            }
+            for b in &free {
+                used[usize::from(*b)] = true;
+            }
+            f.blocks = free;
+        }
+        files.retain(|e| e.records != u32::MAX);
+        self.set_files(files);
+        self.dir = self.encode_dir();
+        Ok(())
+    }
+
+    fn set_files(&mut self, files: Vec<File>) {
+        self.owner.clear();
+        for (i, f) in files.iter().enumerate() {
+            for (j, b) in f.blocks.iter().enumerate() {
+                self.owner.insert(*b, (i, j));
+            }
+        }
+        self.files = files;
+    }
+
+    // Directory entries hold eight 16 bit block numbers, one extent of 16K each.
+    fn encode_dir(&self) -> Vec<u8> {
+        let mut dir = vec![EMPTY; self.dir.len()];
+        let bpe = 8;
+        let rpb = self.records_per_block() as u32;
+        let mut n = 0;
+        for f in &self.files {
+            let extents = f.records.div_ceil(rpb * bpe as u32).max(1);
+            for ex in 0..extents {
+                if (n + 1) * 32 > dir.len() {
+                    return dir;
+                }
+                let e = &mut dir[n * 32..(n + 1) * 32];
+                e[0] = f.user;
+                e[1..12].copy_from_slice(&f.name);
+                if f.readonly {
+                    e[9] |= 0x80;
+                }
+                e[12] = (ex & 0x1f) as u8;
+                e[13] = 0;
+                e[14] = (ex >> 5) as u8;
+                e[15] = f.records.saturating_sub(ex * 128).min(128) as u8;
+                for k in 0..bpe {
+                    let b = f.blocks.get(ex as usize * bpe + k).copied().unwrap_or(0);
+                    e[16 + 2 * k..18 + 2 * k].copy_from_slice(&b.to_le_bytes());
+                }
+                n += 1;
+            }
+        }
+        dir
+    }
+
+    fn decode_dir(&self) -> Vec<File> {
+        let mut m: BTreeMap<(u8, Name), Vec<Extent>> = BTreeMap::new();
+        for e in self.dir.chunks(32) {
+            if e[0] >= 16 {
+                continue;
+            }
+            let mut name = [0; 11];
+            for (i, c) in e[1..12].iter().enumerate() {
+                name[i] = c & 0x7f;
+            }
+            let ex = u32::from(e[12] & 0x1f) | (u32::from(e[14] & 0x3f) << 5);
+            let blocks = (0..8).map(|k| u16::from_le_bytes([e[16 + 2 * k], e[17 + 2 * k]])).collect();
+            m.entry((e[0], name)).or_default().push((ex, e[9] & 0x80 != 0, e[15], blocks));
+        }
+        let mut r = Vec::new();
+        for ((user, name), mut exs) in m {
+            exs.sort_by_key(|e| e.0);
+            let last = exs.last().unwrap();
+            let records = last.0 * 128 + u32::from(last.2.min(128));
+            let readonly = exs.iter().any(|e| e.1);
+            let blocks: Vec<u16> = exs.iter().flat_map(|e| e.3.iter().copied()).filter(|b| *b != 0).collect();
+            let path = self.path(user, &name);
+            r.push(File { path, user, name, readonly, records, blocks });
+        }
+        r
+    }
+
+    // Bring the host in line with the directory the BDOS wrote.
+    fn sync(&mut self) -> io::Result<()> {
+        let mut new = self.decode_dir();
+        let old = self.files.clone();
+        for f in new.iter_mut() {
+            if let Some(e) = old.iter().find(|e| e.user == f.user && e.name == f.name) {
+                f.path = e.path.clone();
+            }
+        }
+        for f in &old {
+            if new.iter().any(|e| e.user == f.user && e.name == f.name) {
+                continue;
+            }
+            let src = &f.path;
+            let renamed = new.iter().find(|e| {
+                !e.blocks.is_empty()
+                    && e.blocks.first() == f.blocks.first()
+                    && !old.iter().any(|o| o.user == e.user && o.name == e.name)
+            });
+            match renamed {
+                Some(e) => {
+                    fs::create_dir_all(e.path.parent().unwrap())?;
+                    set_readonly(src, false)?;
+                    fs::rename(src, &e.path)?;
+                }
+                None => {
+                    if src.exists() {
+                        set_readonly(src, false)?;
+                        fs::remove_file(src)?;
+                    }
+                }
+            }
+        }
+        let rpb = self.records_per_block();
+        for f in &new {
+            if old.contains(f) && !f.blocks.iter().any(|b| self.pending.contains_key(b)) {
+                continue;
+            }
+            let p = &f.path;
+            if !p.exists() {
+                fs::create_dir_all(p.parent().unwrap())?;
+                fs::File::create(p)?;
+            }
+            set_readonly(p, false)?;
+            let mut h = fs::OpenOptions::new().read(true).write(true).open(p)?;
+            for (j, b) in f.blocks.iter().enumerate() {
+                if let Some(d) = self.pending.remove(b) {
+                    h.seek(SeekFrom::Start((j * rpb * RECORD) as u64))?;
+                    let n = d.len().min((f.records as usize).saturating_sub(j * rpb) * RECORD);
+                    h.write_all(&d[..n])?;
+                }
+            }
+            let len = h.metadata()?.len();
+            if len.div_ceil(RECORD as u64) != u64::from(f.records) {
+                h.set_len(u64::from(f.records) * RECORD as u64)?;
+            }
+            drop(h);
+            if f.readonly {
+                set_readonly(p, true)?;
+            }
+        }
+        self.set_files(new);
+        self.listing = self.list()?.iter().map(|e| (e.2.clone(), e.3, e.4)).collect();
+        Ok(())
+    }
+
+    fn locate(&self, track: u16, sector: u16) -> io::Result<(u16, usize)> {
+        let g = &self.geometry;
+        if track >= g.tracks || sector >= g.sectors {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "hostfs: sector out of range"));
+        }
+        let r = usize::from(track) * usize::from(g.sectors) + usize::from(sector);
+        Ok(((r / self.records_per_block()) as u16, r % self.records_per_block()))
+    }
+}
+
+fn set_readonly(p: &Path, readonly: bool) -> io::Result<()> {
+    let mut perm = fs::metadata(p)?.permissions();
+    if perm.readonly() != readonly {
+        #[allow(clippy::permissions_set_readonly_false)]
+        perm.set_readonly(readonly);
+        fs::set_permissions(p, perm)?;
+    }
+    Ok(())
+}
+
+impl Disk for HostDir {
+    fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    fn read(&mut self, track: u16, sector: u16, buf: &mut [u8]) -> io::Result<()> {
+        let (b, k) = self.locate(track, sector)?;
+        let n = buf.len().min(RECORD);
+        if b < self.dir_blocks() {
+            // The BDOS reads the directory from the start when a disk is logged in.
+            if b == 0 && k == 0 && self.pending.is_empty() {
+                self.refresh()?;
+            }
+            let a = (usize::from(b) * self.records_per_block() + k) * RECORD;
+            buf[..n].copy_from_slice(&self.dir[a..a + n]);
+            return Ok(());
+        }
+        if let Some(d) = self.pending.get(&b) {
+            buf[..n].copy_from_slice(&d[k * RECORD..k * RECORD + n]);
+            return Ok(());
+        }
+        buf[..n].fill(EMPTY);
+        let Some((i, j)) = self.owner.get(&b).copied() else { return Ok(()) };
+        let f = &self.files[i];
+        let mut h = fs::File::open(&f.path)?;
+        h.seek(SeekFrom::Start(((j * self.records_per_block() + k) * RECORD) as u64))?;
+        let mut m = 0;
+        while m < n {
+            match h.read(&mut buf[m..n])? {
+                0 => break,
+                e => m += e,
+            }
+        }
+        if m > 0 {
+            buf[m..n].fill(EOF);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, track: u16, sector: u16, buf: &[u8]) -> io::Result<()> {
+        let (b, k) = self.locate(track, sector)?;
+        let n = buf.len().min(RECORD);
+        if b < self.dir_blocks() {
+            let a = (usize::from(b) * self.records_per_block() + k) * RECORD;
+            if self.dir[a..a + n] != buf[..n] {
+                self.dir[a..a + n].copy_from_slice(&buf[..n]);
+                self.sync()?;
+            }
+            return Ok(());
+        }
+        match self.owner.get(&b).copied() {
+            Some((i, j)) => {
+                let f = &self.files[i];
+                if f.readonly {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "hostfs: file is read only"));
+                }
+                let mut h = fs::OpenOptions::new().write(true).open(&f.path)?;
+                h.seek(SeekFrom::Start(((j * self.records_per_block() + k) * RECORD) as u64))?;
+                h.write_all(&buf[..n])
+            }
+            None => {
+                let size = self.geometry.dpb.block_size();
+                let d = self.pending.entry(b).or_insert_with(|| vec![EMPTY; size]);
+                d[k * RECORD..k * RECORD + n].copy_from_slice(&buf[..n]);
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// Space Invaders on the Midway 8080 board, without video or audio output: the host reads the frame buffer and the
+// sound events after each frame. This adds names for the DIP switches and sounds of the game to the generic board.
+//
+// ROM invaders.h, invaders.g, invaders.f and invaders.e are loaded from 0x0000.
+//
+// The monitor is rotated a quarter turn counterclockwise in the cabinet, so the lines of video RAM are the columns of
+// the picture seen by the player, from left to right, each drawn from the bottom up.
+//
+// I/O ports:
+//
+//   in  0  Inputs 0, unused by the game
+//   in  1  Inputs 1, coin, start buttons and player 1 controls
+//   in  2  Inputs 2, DIP switches, tilt and player 2 controls
+//   in  3  Shift register result
+//   out 2  Shift amount
+//   out 3  Sounds 1
+//   out 4  Shift data
+//   out 5  Sounds 2
+//   out 6  Watchdog
+use super::midway::{self, Board, Game};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+
+pub use super::midway::{
+    Button, Io, Map, Shifter, FRAME_CYCLES, FRAME_RATE, HEIGHT, MID_SCREEN, VBLANK, VRAM, VRAM_SIZE, WIDTH,
+};
+
+pub const GAME: &Game = &midway::INVADERS;
+pub const ROM_SIZE: usize = 0x2000;
+
+// ROM files in load order.
+pub const ROM_FILES: [&str; 4] = ["invaders.h", "invaders.g", "invaders.f", "invaders.e"];
+
+// Settings of the DIP switches on the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dips {
+    // Ships per game, 3 to 6.
+    pub lives: u8,
+    // Extra ship at 1000 points instead of 1500.
+    pub bonus_at_1000: bool,
+    // Show the coin information in the attract mode.
+    pub coin_info: bool,
+}
+
+impl Default for Dips {
+    fn default() -> Self {
+        Self { lives: 3, bonus_at_1000: false, coin_info: true }
+    }
+}
+
+impl Dips {
+    pub fn apply(&self, board: &mut Board) {
+        board.set_dip("lives", self.lives.clamp(3, 6) - 3);
+        board.set_dip("bonus", u8::from(self.bonus_at_1000));
+        board.set_dip("coin_info", u8::from(!self.coin_info));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    // Repeats while on.
+    Ufo,
+    Shot,
+    PlayerDie,
+    InvaderDie,
+    ExtendedPlay,
+    // Enables the sound amplifier.
+    Amp,
+    // The four notes of the marching fleet.
+    Fleet(u8),
+    UfoHit,
+}
+
+impl Sound {
+    pub fn from_event(e: &midway::Event) -> Option<Self> {
+        match (e.port, e.bit) {
+            (3, 0) => Some(Sound::Ufo),
+            (3, 1) => Some(Sound::Shot),
+            (3, 2) => Some(Sound::PlayerDie),
+            (3, 3) => Some(Sound::InvaderDie),
+            (3, 4) => Some(Sound::ExtendedPlay),
+            (3, 5) => Some(Sound::Amp),
+            (5, 0..=3) => Some(Sound::Fleet(e.bit)),
+            (5, 4) => Some(Sound::UfoHit),
+            _ => None,
+        }
+    }
+}
+
+// A sound output switched on or off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub sound: Sound,
+    pub on: bool,
+}
+
+// A bit of a sound port is the sound it switches.
+impl PartialEq<Event> for midway::Event {
+    fn eq(&self, other: &Event) -> bool {
+        self.on == other.on && Sound::from_event(self) == Some(other.sound)
+    }
+}
+
+// The board running the game, with the DIP switches and sounds by name. Everything else is the board's.
+pub struct Invaders {
+    pub board: Board,
+}
+
+impl Invaders {
+    pub fn new(rom: &[u8]) -> Self {
+        Self { board: Board::new(GAME, rom) }
+    }
+
+    // Load the four 2K ROM files from a directory.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self { board: Board::open(GAME, dir)? })
+    }
+
+    pub fn set_dips(&mut self, dips: Dips) {
+        dips.apply(&mut self.board)
+    }
+
+    // Sound events since the last call.
+    pub fn events(&mut self) -> Vec<Event> {
+        let events = self.board.events();
+        events.iter().filter_map(|e| Some(Event { sound: Sound::from_event(e)?, on: e.on })).collect()
+    }
+}
+
+impl Deref for Invaders {
+    type Target = Board;
+
+    fn deref(&self) -> &Board {
+        &self.board
+    }
+}
+
+impl DerefMut for Invaders {
+    fn deref_mut(&mut self) -> &mut Board {
+        &mut self.board
+    }
+}
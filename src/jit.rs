@@ -0,0 +1,913 @@
+// Translation of hot blocks to host code with Cranelift, built with the jit feature. A block of the cache run often
+// enough is compiled to a function keeping the registers in host registers and reaching memory through calls back
+// into the emulator, so the memory can be any Memory and writes are seen by the cache as the interpreter's are.
+//
+// Instructions the compiled code does not run, I/O, EI, DI, HLT and DAA, are left to the interpreter: a block is
+// compiled up to the first of them and the interpreter runs the rest. Interrupts come between blocks as before. A
+// compiled block that writes into code stops after the instruction that wrote it, and a block whose code changed is
+// compiled again from the new bytes, up to MAX_COMPILES times.
+//
+// With lockstep set every compiled run is checked: the memory it wrote is put back and the same instructions run again
+// with Cpu::next, and the first difference in the registers, cycles or memory is kept in divergence. The interpreter's
+// state is the one kept, and the block is not run compiled again.
+use super::block::{Block, Cache, Op, MAX_OPS};
+use super::cpu::{Cpu, OP_CYCLES};
+use super::history::History;
+use super::memory::Memory;
+use super::register::Register;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, FuncRef, InstBuilder, MemFlags, UserFuncName, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::mem::ManuallyDrop;
+use std::rc::Rc;
+
+// Runs of a block before it is compiled.
+pub const THRESHOLD: u32 = 16;
+// Times a block is compiled at most, as its code keeps being written.
+pub const MAX_COMPILES: u32 = 8;
+
+// Registers as the compiled code loads and stores them.
+#[repr(C)]
+#[derive(Default)]
+struct State {
+    a: u8,
+    f: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    h: u8,
+    l: u8,
+    sp: u16,
+    pc: u16,
+}
+
+impl State {
+    fn new(r: &Register) -> Self {
+        Self { a: r.a, f: r.flags(), b: r.b, c: r.c, d: r.d, e: r.e, h: r.h, l: r.l, sp: r.sp, pc: r.pc }
+    }
+
+    fn store(&self, r: &mut Register) {
+        *r = Register {
+            a: self.a,
+            f: self.f,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+            ..Default::default()
+        };
+    }
+}
+
+// What the calls back into the emulator reach.
+struct Ctx {
+    mem: *const RefCell<dyn Memory>,
+    cache: *mut Cache,
+    // Generation of the cache when the block started.
+    generation: u64,
+    // Bytes written with their old values, in lockstep.
+    writes: Option<Vec<(u16, u8)>>,
+}
+
+impl Ctx {
+    // Note a write about to be made to a byte.
+    fn write(&mut self, a: u16) {
+        unsafe { (*self.cache).write(a) };
+        if let Some(w) = &mut self.writes {
+            w.push((a, unsafe { (*self.mem).borrow().get(a) }));
+        }
+    }
+
+    // 1 when the writes changed code, so the block stops.
+    fn changed(&self) -> u32 {
+        u32::from(unsafe { (*self.cache).generation } != self.generation)
+    }
+}
+
+extern "C" fn read(ctx: *mut Ctx, addr: u32) -> u32 {
+    let ctx = unsafe { &*ctx };
+    u32::from(unsafe { (*ctx.mem).borrow().get(addr as u16) })
+}
+
+extern "C" fn read_word(ctx: *mut Ctx, addr: u32) -> u32 {
+    let ctx = unsafe { &*ctx };
+    u32::from(unsafe { (*ctx.mem).borrow().get_word(addr as u16) })
+}
+
+extern "C" fn write(ctx: *mut Ctx, addr: u32, v: u32) -> u32 {
+    let ctx = unsafe { &mut *ctx };
+    ctx.write(addr as u16);
+    unsafe { (*ctx.mem).borrow_mut().set(addr as u16, v as u8) };
+    ctx.changed()
+}
+
+extern "C" fn write_word(ctx: *mut Ctx, addr: u32, v: u32) -> u32 {
+    let ctx = unsafe { &mut *ctx };
+    ctx.write(addr as u16);
+    ctx.write((addr as u16).wrapping_add(1));
+    unsafe { (*ctx.mem).borrow_mut().set_word(addr as u16, v as u16) };
+    ctx.changed()
+}
+
+type Code = unsafe extern "C" fn(*mut State, *mut Ctx) -> u32;
+
+// True for the instructions compiled code runs.
+pub fn compiles(code: u8) -> bool {
+    !matches!(code, 0x27 | 0x76 | 0xd3 | 0xdb | 0xf3 | 0xfb)
+}
+
+#[derive(Clone, Copy)]
+struct Compiled {
+    code: Code,
+    // Instructions of the block it runs, from the start.
+    ops: usize,
+    // Cycles of those instructions but the last, which is the only one whose cycles vary.
+    prefix: u32,
+}
+
+struct Entry {
+    block: Rc<Block>,
+    runs: u32,
+    compiles: u32,
+    compiled: Option<Compiled>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    // Blocks compiled, counting those compiled again.
+    pub compiled: u64,
+    // Blocks run as host code.
+    pub runs: u64,
+    // Instructions run as host code.
+    pub ops: u64,
+}
+
+pub struct Jit {
+    // Runs of a block before it is compiled.
+    pub threshold: u32,
+    // Check every compiled run against the interpreter.
+    pub lockstep: bool,
+    // First difference lockstep found.
+    pub divergence: Option<String>,
+    pub stats: Stats,
+    entries: HashMap<u16, Entry>,
+    // Boxed, as the cpu moves the Jit out and back for every block.
+    compiler: Box<Compiler>,
+}
+
+struct Compiler {
+    module: ManuallyDrop<JITModule>,
+    ctx: Context,
+    fctx: FunctionBuilderContext,
+    helpers: [FuncId; 4],
+}
+
+impl Drop for Compiler {
+    fn drop(&mut self) {
+        // No compiled code is left running once the cpu lets go of it.
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+fn error(msg: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("jit: {}", msg))
+}
+
+impl Jit {
+    pub fn new() -> io::Result<Self> {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").map_err(error)?;
+        flags.set("is_pic", "false").map_err(error)?;
+        flags.set("opt_level", "speed").map_err(error)?;
+        let isa = cranelift_native::builder().map_err(error)?.finish(settings::Flags::new(flags)).map_err(error)?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("i8080_read", read as *const u8);
+        builder.symbol("i8080_read_word", read_word as *const u8);
+        builder.symbol("i8080_write", write as *const u8);
+        builder.symbol("i8080_write_word", write_word as *const u8);
+        let mut module = JITModule::new(builder);
+        let ptr = module.target_config().pointer_type();
+        let mut helpers = vec![];
+        for (name, args) in [("i8080_read", 1), ("i8080_read_word", 1), ("i8080_write", 2), ("i8080_write_word", 2)] {
+            let mut sig = module.make_signature();
+            sig.params.push(AbiParam::new(ptr));
+            for _ in 0..args {
+                sig.params.push(AbiParam::new(types::I32));
+            }
+            sig.returns.push(AbiParam::new(types::I32));
+            helpers.push(module.declare_function(name, Linkage::Import, &sig).map_err(error)?);
+        }
+        let ctx = module.make_context();
+        Ok(Self {
+            threshold: THRESHOLD,
+            lockstep: false,
+            divergence: None,
+            stats: Stats::default(),
+            entries: HashMap::new(),
+            compiler: Box::new(Compiler {
+                module: ManuallyDrop::new(module),
+                ctx,
+                fctx: FunctionBuilderContext::new(),
+                helpers: helpers.try_into().unwrap(),
+            }),
+        })
+    }
+
+    // Blocks with compiled code.
+    pub fn len(&self) -> usize {
+        self.entries.values().filter(|e| e.compiled.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Run the compiled code of a block the cpu is at, if it has some, compiling it once it is hot. Returns the
+    // instructions of the block run and their cycles, or None for the interpreter to run the block.
+    pub(crate) fn run(&mut self, cpu: &mut Cpu, b: &Rc<Block>, until: u64) -> Option<(usize, u32)> {
+        let e = self.entries.entry(b.start).or_insert_with(|| Entry {
+            block: b.clone(),
+            runs: 0,
+            compiles: 0,
+            compiled: None,
+        });
+        if !Rc::ptr_eq(&e.block, b) {
+            // The code was written and decoded again.
+            e.block = b.clone();
+            e.runs = 0;
+            e.compiled = None;
+        }
+        if e.compiled.is_none() {
+            e.runs += 1;
+            if e.runs < self.threshold || e.compiles >= MAX_COMPILES {
+                return None;
+            }
+            e.compiles += 1;
+            let compiled = self.compiler.compile(b);
+            let e = self.entries.get_mut(&b.start).unwrap();
+            match compiled {
+                Some(c) => {
+                    e.compiled = Some(c);
+                    self.stats.compiled += 1;
+                }
+                None => {
+                    e.compiles = MAX_COMPILES;
+                    return None;
+                }
+            }
+        }
+        let c = self.entries[&b.start].compiled?;
+        // The interpreter would stop inside the block.
+        if cpu.cycles + u64::from(c.prefix) >= until {
+            return None;
+        }
+        let before = (cpu.reg, cpu.cycles);
+        let mut state = State::new(&cpu.reg);
+        let cache = cpu.cache.as_mut()?;
+        let mut ctx = Ctx {
+            mem: Rc::as_ptr(&cpu.mem),
+            generation: cache.generation,
+            cache,
+            writes: self.lockstep.then(Vec::new),
+        };
+        let cycles = unsafe { (c.code)(&mut state, &mut ctx) };
+        state.store(&mut cpu.reg);
+        cpu.cycles += u64::from(cycles);
+        self.stats.runs += 1;
+        self.stats.ops += c.ops as u64;
+        if let Some(writes) = ctx.writes {
+            if let Some(d) = lockstep(cpu, before, &writes) {
+                self.divergence.get_or_insert(format!("block {:04x}: {}", b.start, d));
+                let e = self.entries.get_mut(&b.start).unwrap();
+                e.compiled = None;
+                e.compiles = MAX_COMPILES;
+                return Some((b.ops.len(), (cpu.cycles - before.1) as u32));
+            }
+        }
+        Some((c.ops, cycles))
+    }
+}
+
+impl Compiler {
+    // Compile the instructions of a block up to the first the compiled code does not run. None when there are none.
+    fn compile(&mut self, b: &Block) -> Option<Compiled> {
+        let n = b.ops.iter().position(|e| !compiles(e.code)).unwrap_or(b.ops.len());
+        if n == 0 {
+            return None;
+        }
+        let ptr = self.module.target_config().pointer_type();
+        let mut sig = self.module.make_signature();
+        sig.params.push(AbiParam::new(ptr));
+        sig.params.push(AbiParam::new(ptr));
+        sig.returns.push(AbiParam::new(types::I32));
+        let id = self.module.declare_anonymous_function(&sig).ok()?;
+        self.ctx.func.signature = sig;
+        self.ctx.func.name = UserFuncName::user(0, id.as_u32());
+        {
+            let mut fb = FunctionBuilder::new(&mut self.ctx.func, &mut self.fctx);
+            let helpers = self.helpers.map(|e| self.module.declare_func_in_func(e, fb.func));
+            Emit::new(&mut fb, helpers, b.start).block(&b.ops[..n], n == b.ops.len());
+            fb.seal_all_blocks();
+            fb.finalize();
+        }
+        let r = self.module.define_function(id, &mut self.ctx);
+        self.module.clear_context(&mut self.ctx);
+        r.ok()?;
+        self.module.finalize_definitions().ok()?;
+        let code = unsafe { std::mem::transmute::<*const u8, Code>(self.module.get_finalized_function(id)) };
+        let prefix = b.ops[..n - 1].iter().map(|e| OP_CYCLES[usize::from(e.code)]).sum();
+        Some(Compiled { code, ops: n, prefix })
+    }
+}
+
+// Undo the writes of a compiled run and run its instructions again with the interpreter, returning how the two differ.
+fn lockstep(cpu: &mut Cpu, before: (Register, u64), writes: &[(u16, u8)]) -> Option<String> {
+    let (reg, cycles) = (cpu.reg, cpu.cycles);
+    let mem = cpu.mem.clone();
+    let written: Vec<(u16, u8)> = writes.iter().map(|e| (e.0, mem.borrow().get(e.0))).collect();
+    for (a, v) in writes.iter().rev() {
+        mem.borrow_mut().set(*a, *v);
+    }
+    cpu.reg = before.0;
+    cpu.cycles = before.1;
+    let history = cpu.history.replace(History::new(usize::MAX));
+    while cpu.cycles < cycles && !cpu.halted {
+        cpu.next();
+    }
+    let mut h = std::mem::replace(&mut cpu.history, history).unwrap();
+    let mut addrs: BTreeSet<u16> = writes.iter().map(|e| e.0).collect();
+    while let Some(e) = h.pop() {
+        addrs.extend(e.writes.iter().map(|e| e.0));
+    }
+    if cpu.reg != reg {
+        return Some(format!("registers {:?} where the interpreter has {:?}", reg, cpu.reg));
+    }
+    if cpu.cycles != cycles {
+        return Some(format!("{} cycles where the interpreter has {}", cycles, cpu.cycles));
+    }
+    let mem = mem.borrow();
+    for a in addrs {
+        let v = written.iter().rev().find(|e| e.0 == a).map_or_else(|| mem.get(a), |e| e.1);
+        if v != mem.get(a) {
+            return Some(format!("{:04x} is {:02x} where the interpreter has {:02x}", a, v, mem.get(a)));
+        }
+    }
+    None
+}
+
+// Register variables, by the number instructions give them: B C D E H L, 6 is M, A. Then F and SP.
+const F: u8 = 8;
+const SP: u8 = 9;
+
+// Offsets in State.
+fn offset(r: u8) -> i32 {
+    match r {
+        7 => 0,
+        F => 1,
+        SP => 8,
+        _ => 2 + i32::from(r),
+    }
+}
+
+// Writes an instruction at a time into the function. Values are i32 holding a byte or a word.
+struct Emit<'a, 'b> {
+    fb: &'b mut FunctionBuilder<'a>,
+    helpers: [FuncRef; 4],
+    state: Value,
+    ctx: Value,
+    // Address after the instruction.
+    pc: u16,
+    // Cycles up to the instruction.
+    cycles: u32,
+    // Set when the instruction wrote memory: nonzero when the write changed code.
+    changed: Option<Value>,
+}
+
+impl<'a, 'b> Emit<'a, 'b> {
+    fn new(fb: &'b mut FunctionBuilder<'a>, helpers: [FuncRef; 4], pc: u16) -> Self {
+        let entry = fb.create_block();
+        fb.append_block_params_for_function_params(entry);
+        fb.switch_to_block(entry);
+        let (state, ctx) = (fb.block_params(entry)[0], fb.block_params(entry)[1]);
+        for r in (0..=SP).filter(|e| *e != 6) {
+            fb.declare_var(Variable::from_u32(u32::from(r)), types::I32);
+            let v = if r == SP {
+                fb.ins().uload16(types::I32, MemFlags::trusted(), state, offset(r))
+            } else {
+                fb.ins().uload8(types::I32, MemFlags::trusted(), state, offset(r))
+            };
+            fb.def_var(Variable::from_u32(u32::from(r)), v);
+        }
+        Self { fb, helpers, state, ctx, pc, cycles: 0, changed: None }
+    }
+
+    fn imm(&mut self, v: i64) -> Value {
+        self.fb.ins().iconst(types::I32, v)
+    }
+
+    fn var(&mut self, r: u8) -> Value {
+        self.fb.use_var(Variable::from_u32(u32::from(r)))
+    }
+
+    fn def(&mut self, r: u8, v: Value) {
+        self.fb.def_var(Variable::from_u32(u32::from(r)), v)
+    }
+
+    fn call(&mut self, helper: usize, args: &[Value]) -> Value {
+        let mut a = vec![self.ctx];
+        a.extend(args);
+        let call = self.fb.ins().call(self.helpers[helper], &a);
+        self.fb.inst_results(call)[0]
+    }
+
+    fn read(&mut self, addr: Value) -> Value {
+        self.call(0, &[addr])
+    }
+
+    fn read_word(&mut self, addr: Value) -> Value {
+        self.call(1, &[addr])
+    }
+
+    fn write(&mut self, addr: Value, v: Value) {
+        let c = self.call(2, &[addr, v]);
+        self.changed = Some(c);
+    }
+
+    fn write_word(&mut self, addr: Value, v: Value) {
+        let c = self.call(3, &[addr, v]);
+        self.changed = Some(c);
+    }
+
+    // A register, or M, the byte HL points to.
+    fn get(&mut self, r: u8) -> Value {
+        if r == 6 {
+            let hl = self.pair(2);
+            return self.read(hl);
+        }
+        self.var(r)
+    }
+
+    fn set(&mut self, r: u8, v: Value) {
+        if r == 6 {
+            let hl = self.pair(2);
+            return self.write(hl, v);
+        }
+        self.def(r, v)
+    }
+
+    // A register pair by its number: BC DE HL SP.
+    fn pair(&mut self, p: u8) -> Value {
+        if p == 3 {
+            return self.var(SP);
+        }
+        let hi = self.var(p * 2);
+        let lo = self.var(p * 2 + 1);
+        let hi = self.fb.ins().ishl_imm(hi, 8);
+        self.fb.ins().bor(hi, lo)
+    }
+
+    // Set a pair from a value that may have bits over 16.
+    fn set_pair(&mut self, p: u8, v: Value) {
+        let v = self.fb.ins().band_imm(v, 0xffff);
+        if p == 3 {
+            return self.def(SP, v);
+        }
+        let hi = self.fb.ins().ushr_imm(v, 8);
+        let lo = self.fb.ins().band_imm(v, 0xff);
+        self.def(p * 2, hi);
+        self.def(p * 2 + 1, lo);
+    }
+
+    // 1 when the condition of a conditional instruction holds.
+    fn cond(&mut self, code: u8) -> Value {
+        let cc = (code >> 3) & 7;
+        let bit = [6, 0, 2, 7][usize::from(cc >> 1)];
+        let f = self.var(F);
+        let f = self.fb.ins().ushr_imm(f, bit);
+        let f = self.fb.ins().band_imm(f, 1);
+        self.fb.ins().icmp_imm(IntCC::Equal, f, i64::from(cc & 1))
+    }
+
+    // The sign, zero and parity flags of a byte.
+    fn szp(&mut self, r: Value) -> Value {
+        let s = self.fb.ins().band_imm(r, 0x80);
+        let z = self.fb.ins().icmp_imm(IntCC::Equal, r, 0);
+        let z = self.fb.ins().uextend(types::I32, z);
+        let z = self.fb.ins().ishl_imm(z, 6);
+        let p = self.fb.ins().popcnt(r);
+        let p = self.fb.ins().band_imm(p, 1);
+        let p = self.fb.ins().bxor_imm(p, 1);
+        let p = self.fb.ins().ishl_imm(p, 2);
+        let sz = self.fb.ins().bor(s, z);
+        self.fb.ins().bor(sz, p)
+    }
+
+    // Put flags in F, keeping the bits of keep.
+    fn flags(&mut self, keep: i64, v: Value) {
+        let f = self.var(F);
+        let f = self.fb.ins().band_imm(f, keep);
+        let f = self.fb.ins().bor(f, v);
+        self.def(F, f);
+    }
+
+    fn carry(&mut self) -> Value {
+        let f = self.var(F);
+        self.fb.ins().band_imm(f, 1)
+    }
+
+    // ADD ADC SUB SBB ANA XRA ORA CMP, by the operation bits of the opcode.
+    fn alu(&mut self, op: u8, n: Value) {
+        let a = self.var(7);
+        // CMP is SUB keeping A.
+        let cmp = op == 7;
+        let op = if cmp { 2 } else { op };
+        let (r, ac, c) = match op {
+            0..=3 => {
+                let c = if op & 1 == 1 { self.carry() } else { self.imm(0) };
+                let lo_a = self.fb.ins().band_imm(a, 0x0f);
+                let lo_n = self.fb.ins().band_imm(n, 0x0f);
+                let (r, lo) = if op < 2 {
+                    let r = self.fb.ins().iadd(a, n);
+                    let lo = self.fb.ins().iadd(lo_a, lo_n);
+                    (r, lo)
+                } else {
+                    let r = self.fb.ins().isub(a, n);
+                    let lo = self.fb.ins().isub(lo_a, lo_n);
+                    (r, lo)
+                };
+                let (r, lo) = if op < 2 {
+                    (self.fb.ins().iadd(r, c), self.fb.ins().iadd(lo, c))
+                } else {
+                    (self.fb.ins().isub(r, c), self.fb.ins().isub(lo, c))
+                };
+                // Bit 4 of the sum of the low nibbles is the half carry, and of their difference the half borrow.
+                let mut ac = self.fb.ins().band_imm(lo, 0x10);
+                if op >= 2 {
+                    ac = self.fb.ins().bxor_imm(ac, 0x10);
+                }
+                let c = self.fb.ins().ushr_imm(r, 8);
+                let c = self.fb.ins().band_imm(c, 1);
+                (self.fb.ins().band_imm(r, 0xff), ac, c)
+            }
+            4 => {
+                let r = self.fb.ins().band(a, n);
+                let or = self.fb.ins().bor(a, n);
+                let ac = self.fb.ins().band_imm(or, 0x08);
+                let ac = self.fb.ins().ishl_imm(ac, 1);
+                (r, ac, self.imm(0))
+            }
+            5 => (self.fb.ins().bxor(a, n), self.imm(0), self.imm(0)),
+            _ => (self.fb.ins().bor(a, n), self.imm(0), self.imm(0)),
+        };
+        let szp = self.szp(r);
+        let f = self.fb.ins().bor(szp, ac);
+        let f = self.fb.ins().bor(f, c);
+        self.flags(0x2a, f);
+        if !cmp {
+            self.def(7, r);
+        }
+    }
+
+    // INR and DCR.
+    fn incdec(&mut self, r: u8, inc: bool) {
+        let n = self.get(r);
+        let v = if inc {
+            let v = self.fb.ins().iadd_imm(n, 1);
+            self.fb.ins().band_imm(v, 0xff)
+        } else {
+            let v = self.fb.ins().iadd_imm(n, -1);
+            self.fb.ins().band_imm(v, 0xff)
+        };
+        let ac = if inc {
+            let lo = self.fb.ins().band_imm(n, 0x0f);
+            let lo = self.fb.ins().iadd_imm(lo, 1);
+            self.fb.ins().band_imm(lo, 0x10)
+        } else {
+            let lo = self.fb.ins().band_imm(v, 0x0f);
+            let ne = self.fb.ins().icmp_imm(IntCC::NotEqual, lo, 0x0f);
+            let ne = self.fb.ins().uextend(types::I32, ne);
+            self.fb.ins().ishl_imm(ne, 4)
+        };
+        let szp = self.szp(v);
+        let f = self.fb.ins().bor(szp, ac);
+        self.flags(0x2b, f);
+        self.set(r, v);
+    }
+
+    // RLC RRC RAL RAR.
+    fn rotate(&mut self, op: u8) {
+        let a = self.var(7);
+        let left = op & 1 == 0;
+        let c = if left { self.fb.ins().ushr_imm(a, 7) } else { self.fb.ins().band_imm(a, 1) };
+        // The bit coming in: the one going out, or the carry.
+        let b = if op < 2 { c } else { self.carry() };
+        let r = if left {
+            let r = self.fb.ins().ishl_imm(a, 1);
+            let r = self.fb.ins().bor(r, b);
+            self.fb.ins().band_imm(r, 0xff)
+        } else {
+            let r = self.fb.ins().ushr_imm(a, 1);
+            let b = self.fb.ins().ishl_imm(b, 7);
+            self.fb.ins().bor(r, b)
+        };
+        self.flags(0xfe, c);
+        self.def(7, r);
+    }
+
+    fn push(&mut self, v: Value) {
+        let sp = self.var(SP);
+        let sp = self.fb.ins().iadd_imm(sp, -2);
+        let sp = self.fb.ins().band_imm(sp, 0xffff);
+        self.def(SP, sp);
+        self.write_word(sp, v);
+    }
+
+    fn pop(&mut self) -> Value {
+        let sp = self.var(SP);
+        let v = self.read_word(sp);
+        let sp = self.fb.ins().iadd_imm(sp, 2);
+        let sp = self.fb.ins().band_imm(sp, 0xffff);
+        self.def(SP, sp);
+        v
+    }
+
+    // Store the registers and return the cycles.
+    fn exit(&mut self, pc: Value, cycles: Value) {
+        for r in (0..=SP).filter(|e| *e != 6) {
+            let v = self.var(r);
+            if r == SP {
+                self.fb.ins().istore16(MemFlags::trusted(), v, self.state, offset(r));
+            } else {
+                self.fb.ins().istore8(MemFlags::trusted(), v, self.state, offset(r));
+            }
+        }
+        self.fb.ins().istore16(MemFlags::trusted(), pc, self.state, 10);
+        self.fb.ins().return_(&[cycles]);
+    }
+
+    // Leave after the instruction when its writes changed code.
+    fn check(&mut self) {
+        let Some(changed) = self.changed.take() else { return };
+        let leave = self.fb.create_block();
+        let next = self.fb.create_block();
+        self.fb.ins().brif(changed, leave, &[], next, &[]);
+        self.fb.switch_to_block(leave);
+        let pc = self.imm(i64::from(self.pc));
+        let cycles = self.imm(i64::from(self.cycles));
+        self.exit(pc, cycles);
+        self.fb.switch_to_block(next);
+    }
+
+    // The instructions, the last one ending the block when last is set.
+    fn block(&mut self, ops: &[Op], last: bool) {
+        debug_assert!(ops.len() <= MAX_OPS);
+        let n = if last { ops.len() - 1 } else { ops.len() };
+        for op in &ops[..n] {
+            self.pc = self.pc.wrapping_add(u16::from(op.len));
+            self.op(op.code, op.imm);
+            self.cycles += OP_CYCLES[usize::from(op.code)];
+            self.check();
+        }
+        if last {
+            let op = ops[n];
+            self.pc = self.pc.wrapping_add(u16::from(op.len));
+            self.flow(op.code, op.imm);
+        } else {
+            let pc = self.imm(i64::from(self.pc));
+            let cycles = self.imm(i64::from(self.cycles));
+            self.exit(pc, cycles);
+        }
+    }
+
+    // An instruction ending a block, leaving the function.
+    fn flow(&mut self, code: u8, imm: u16) {
+        let cycles = self.cycles + OP_CYCLES[usize::from(code)];
+        let next = self.imm(i64::from(self.pc));
+        let target = self.imm(i64::from(imm));
+        match code {
+            // JMP and conditional jumps
+            0xc3 => {
+                let c = self.imm(i64::from(cycles));
+                self.exit(target, c);
+            }
+            _ if code & 0xc7 == 0xc2 => {
+                let cond = self.cond(code);
+                let pc = self.fb.ins().select(cond, target, next);
+                let c = self.imm(i64::from(cycles));
+                self.exit(pc, c);
+            }
+            // CALL and conditional calls
+            _ if code == 0xcd || code & 0xc7 == 0xc4 => {
+                let taken = self.fb.create_block();
+                if code != 0xcd {
+                    let skip = self.fb.create_block();
+                    let cond = self.cond(code);
+                    self.fb.ins().brif(cond, taken, &[], skip, &[]);
+                    self.fb.switch_to_block(skip);
+                    let c = self.imm(i64::from(cycles));
+                    self.exit(next, c);
+                } else {
+                    self.fb.ins().jump(taken, &[]);
+                }
+                self.fb.switch_to_block(taken);
+                self.push(next);
+                let c = self.imm(i64::from(cycles + 6));
+                self.exit(target, c);
+            }
+            // RET and conditional returns
+            _ if code == 0xc9 || code & 0xc7 == 0xc0 => {
+                let taken = self.fb.create_block();
+                if code != 0xc9 {
+                    let skip = self.fb.create_block();
+                    let cond = self.cond(code);
+                    self.fb.ins().brif(cond, taken, &[], skip, &[]);
+                    self.fb.switch_to_block(skip);
+                    let c = self.imm(i64::from(cycles));
+                    self.exit(next, c);
+                } else {
+                    self.fb.ins().jump(taken, &[]);
+                }
+                self.fb.switch_to_block(taken);
+                let pc = self.pop();
+                let c = self.imm(i64::from(cycles + 6));
+                self.exit(pc, c);
+            }
+            // RST
+            _ if code & 0xc7 == 0xc7 => {
+                self.push(next);
+                let pc = self.imm(i64::from(code & 0x38));
+                let c = self.imm(i64::from(cycles));
+                self.exit(pc, c);
+            }
+            // PCHL
+            0xe9 => {
+                let pc = self.pair(2);
+                let c = self.imm(i64::from(cycles));
+                self.exit(pc, c);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // An instruction that does not change the flow of control.
+    fn op(&mut self, code: u8, imm: u16) {
+        let p = (code >> 4) & 3;
+        match code {
+            0x00 => {}
+            // MOV
+            0x40..=0x7f => {
+                let v = self.get(code & 7);
+                self.set((code >> 3) & 7, v);
+            }
+            // ADD ADC SUB SBB ANA XRA ORA CMP
+            0x80..=0xbf => {
+                let n = self.get(code & 7);
+                self.alu((code >> 3) & 7, n);
+            }
+            // ADI ACI SUI SBI ANI XRI ORI CPI
+            _ if code & 0xc7 == 0xc6 => {
+                let n = self.imm(i64::from(imm & 0xff));
+                self.alu((code >> 3) & 7, n);
+            }
+            // MVI
+            _ if code & 0xc7 == 0x06 => {
+                let v = self.imm(i64::from(imm & 0xff));
+                self.set((code >> 3) & 7, v);
+            }
+            // INR DCR
+            _ if code & 0xc7 == 0x04 => self.incdec((code >> 3) & 7, true),
+            _ if code & 0xc7 == 0x05 => self.incdec((code >> 3) & 7, false),
+            // LXI
+            _ if code & 0xcf == 0x01 => {
+                let v = self.imm(i64::from(imm));
+                self.set_pair(p, v);
+            }
+            // INX DCX
+            _ if code & 0xcf == 0x03 => {
+                let v = self.pair(p);
+                let v = self.fb.ins().iadd_imm(v, 1);
+                self.set_pair(p, v);
+            }
+            _ if code & 0xcf == 0x0b => {
+                let v = self.pair(p);
+                let v = self.fb.ins().iadd_imm(v, -1);
+                self.set_pair(p, v);
+            }
+            // DAD
+            _ if code & 0xcf == 0x09 => {
+                let hl = self.pair(2);
+                let n = self.pair(p);
+                let r = self.fb.ins().iadd(hl, n);
+                let c = self.fb.ins().ushr_imm(r, 16);
+                self.flags(0xfe, c);
+                self.set_pair(2, r);
+            }
+            // PUSH POP
+            0xc5 | 0xd5 | 0xe5 => {
+                let v = self.pair(p);
+                self.push(v);
+            }
+            0xf5 => {
+                let a = self.var(7);
+                let a = self.fb.ins().ishl_imm(a, 8);
+                let f = self.var(F);
+                let v = self.fb.ins().bor(a, f);
+                self.push(v);
+            }
+            0xc1 | 0xd1 | 0xe1 => {
+                let v = self.pop();
+                self.set_pair(p, v);
+            }
+            0xf1 => {
+                let v = self.pop();
+                let a = self.fb.ins().ushr_imm(v, 8);
+                let f = self.fb.ins().band_imm(v, 0xd5);
+                let f = self.fb.ins().bor_imm(f, 0x02);
+                self.def(7, a);
+                self.def(F, f);
+            }
+            // STAX LDAX
+            0x02 | 0x12 => {
+                let addr = self.pair(p);
+                let a = self.var(7);
+                self.write(addr, a);
+            }
+            0x0a | 0x1a => {
+                let addr = self.pair(p);
+                let v = self.read(addr);
+                self.def(7, v);
+            }
+            // SHLD LHLD STA LDA
+            0x22 => {
+                let addr = self.imm(i64::from(imm));
+                let hl = self.pair(2);
+                self.write_word(addr, hl);
+            }
+            0x2a => {
+                let addr = self.imm(i64::from(imm));
+                let v = self.read_word(addr);
+                self.set_pair(2, v);
+            }
+            0x32 => {
+                let addr = self.imm(i64::from(imm));
+                let a = self.var(7);
+                self.write(addr, a);
+            }
+            0x3a => {
+                let addr = self.imm(i64::from(imm));
+                let v = self.read(addr);
+                self.def(7, v);
+            }
+            // RLC RRC RAL RAR
+            0x07 | 0x0f | 0x17 | 0x1f => self.rotate(code >> 3),
+            // CMA STC CMC
+            0x2f => {
+                let a = self.var(7);
+                let a = self.fb.ins().bxor_imm(a, 0xff);
+                self.def(7, a);
+            }
+            0x37 => {
+                let f = self.var(F);
+                let f = self.fb.ins().bor_imm(f, 1);
+                self.def(F, f);
+            }
+            0x3f => {
+                let f = self.var(F);
+                let f = self.fb.ins().bxor_imm(f, 1);
+                self.def(F, f);
+            }
+            // XCHG XTHL SPHL
+            0xeb => {
+                let (d, e, h, l) = (self.var(2), self.var(3), self.var(4), self.var(5));
+                self.def(2, h);
+                self.def(3, l);
+                self.def(4, d);
+                self.def(5, e);
+            }
+            0xe3 => {
+                let sp = self.var(SP);
+                let v = self.read_word(sp);
+                let hl = self.pair(2);
+                self.set_pair(2, v);
+                self.write_word(sp, hl);
+            }
+            0xf9 => {
+                let hl = self.pair(2);
+                self.def(SP, hl);
+            }
+            _ => unreachable!("{:02x} is not compiled", code),
+        }
+    }
+}
@@ -1,9 +1,39 @@
-mod asm;
+pub mod altair;
+pub mod asm;
+pub mod bdos;
 pub mod bit;
+pub mod block;
+pub mod cpm;
 mod cpu;
+pub mod debugger;
+mod device;
+pub mod diskimg;
+pub mod hex;
+pub mod history;
+pub mod hostfs;
+pub mod invaders;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod loader;
+pub mod lockstep;
 mod memory;
+pub mod midway;
+pub mod pixmap;
+pub mod prn;
+pub mod profile;
+pub mod recomp;
 mod register;
+pub mod rel;
+pub mod replay;
+#[cfg(feature = "singlestep")]
+pub mod singlestep;
+pub mod source;
+pub mod symbol;
+pub mod term;
+pub mod trace;
+pub mod tracediff;
 
 pub use cpu::Cpu;
+pub use device::{Bus, Device};
 pub use memory::{Linear, Memory};
-pub use register::Flag;
+pub use register::{Flag, Register};
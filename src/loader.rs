@@ -0,0 +1,146 @@
+// Load program images in the formats 8080 toolchains usually produce. Every format is decoded into an Image first,
+// which is then copied into memory.
+use super::cpu::Cpu;
+use super::hex;
+use super::memory::Memory;
+use super::prn;
+use super::rel;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+// CP/M loads transient programs at the start of the TPA.
+pub const COM_BASE: u16 = 0x0100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    // CP/M command file, loaded at 0x0100.
+    Com,
+    // Raw binary loaded at the given base.
+    Bin,
+    // Intel HEX.
+    Hex,
+    // Assembler listing, the code is taken from the listing's code field.
+    Prn,
+    // Microsoft relocatable object file, relocated to the given base.
+    Rel,
+}
+
+impl Format {
+    // Detect the format from the file extension, falling back to the file content.
+    pub fn detect(path: impl AsRef<Path>, data: &[u8]) -> Self {
+        let ext = path.as_ref().extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        match ext.as_str() {
+            "com" => Format::Com,
+            "hex" | "ihx" | "ihex" => Format::Hex,
+            "prn" | "lst" => Format::Prn,
+            "rel" => Format::Rel,
+            "bin" | "rom" => Format::Bin,
+            _ => {
+                let text = std::str::from_utf8(data).unwrap_or("");
+                if !text.trim().is_empty() && text.lines().all(|e| e.trim().is_empty() || e.trim().starts_with(':')) {
+                    Format::Hex
+                } else {
+                    Format::Bin
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Image {
+    // Contiguous runs of data to be copied into memory.
+    pub data: Vec<(u16, Vec<u8>)>,
+    pub entry: u16,
+    pub symbols: Vec<(String, u16)>,
+}
+
+impl Image {
+    pub fn load(&self, mem: &mut dyn Memory) {
+        for (a, d) in &self.data {
+            for (i, e) in d.iter().enumerate() {
+                mem.set(a.wrapping_add(i as u16), *e);
+            }
+        }
+    }
+
+    // Address ranges covered by the image, each given as (first, last).
+    pub fn ranges(&self) -> Vec<RangeInclusive<u16>> {
+        self.data
+            .iter()
+            .filter(|(_, d)| !d.is_empty())
+            .map(|(a, d)| *a..=a.wrapping_add((d.len() - 1) as u16))
+            .collect()
+    }
+
+    fn push(&mut self, addr: u16, data: &[u8]) {
+        match self.data.last_mut() {
+            Some((a, d)) if usize::from(*a) + d.len() == usize::from(addr) => d.extend_from_slice(data),
+            _ => self.data.push((addr, data.to_vec())),
+        }
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "loader: image exceeds 64K address space")
+}
+
+// Decode an image. The base address is used by the raw binary and relocatable formats and ignored by the others.
+pub fn decode(format: Format, data: &[u8], base: u16) -> io::Result<Image> {
+    let mut image = Image::default();
+    match format {
+        Format::Com | Format::Bin => {
+            let a = if format == Format::Com { COM_BASE } else { base };
+            if usize::from(a) + data.len() > 0x10000 {
+                return Err(too_large());
+            }
+            image.push(a, data);
+            image.entry = a;
+        }
+        Format::Hex => {
+            let text = std::str::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let h = hex::decode(text)?;
+            image.entry = h.start.unwrap_or_else(|| h.data.first().map_or(0, |e| e.0));
+            image.data = h.data;
+        }
+        Format::Prn => {
+            let text = String::from_utf8_lossy(data);
+            let lines = prn::parse(&text);
+            for e in &lines {
+                if let Some(a) = e.addr {
+                    if usize::from(a) + e.data.len() > 0x10000 {
+                        return Err(too_large());
+                    }
+                    // EQU and ORG lines show an address but have no code.
+                    if !e.data.is_empty() {
+                        image.push(a, &e.data);
+                    }
+                    if let Some(l) = e.label() {
+                        image.symbols.push((l.to_string(), a));
+                    }
+                }
+            }
+            image.entry = image.data.first().map_or(0, |e| e.0);
+        }
+        Format::Rel => image = rel::decode(data, base)?,
+    }
+    Ok(image)
+}
+
+// Load an image into memory.
+pub fn load(mem: &mut dyn Memory, format: Format, data: &[u8], base: u16) -> io::Result<Image> {
+    let image = decode(format, data, base)?;
+    image.load(mem);
+    Ok(image)
+}
+
+// Load a program file into the cpu's memory and point the program counter at its entry point.
+pub fn load_file(cpu: &mut Cpu, path: impl AsRef<Path>, base: u16) -> io::Result<Image> {
+    let data = fs::read(path.as_ref())?;
+    let format = Format::detect(path.as_ref(), &data);
+    let image = load(&mut *cpu.mem.borrow_mut(), format, &data, base)?;
+    cpu.reg.pc = image.entry;
+    Ok(image)
+}
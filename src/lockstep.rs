@@ -0,0 +1,173 @@
+// Run two cpus side by side and stop at the first difference between them. The other cpu runs one block at a time,
+// with run_block or an engine given to run, and the reference follows with next until its cycles reach those of the
+// other, so the block cache and the JIT are checked against the interpreter at the boundaries of their blocks, with
+// compiled blocks run whole.
+//
+// Both cpus start from the same state on their own copies of the memory. After each block the registers, flags,
+// cycles, halted and inte and the memory written by the block are compared. A divergence holds both states and the
+// instructions of the reference leading up to it.
+use super::cpu::Cpu;
+use super::memory::Memory;
+use super::register::Register;
+use super::trace::{self, Step, Tracer};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::rc::Rc;
+
+// Addresses and values written, in order.
+type Writes = Rc<RefCell<Vec<(u16, u8)>>>;
+
+// Memory recording the writes made to it.
+struct Watch {
+    mem: Rc<RefCell<dyn Memory>>,
+    writes: Writes,
+}
+
+impl Memory for Watch {
+    fn get(&self, a: u16) -> u8 {
+        self.mem.borrow().get(a)
+    }
+
+    fn set(&mut self, a: u16, v: u8) {
+        self.writes.borrow_mut().push((a, v));
+        self.mem.borrow_mut().set(a, v)
+    }
+}
+
+// The last steps of the reference.
+#[derive(Default)]
+struct Window {
+    steps: VecDeque<Step>,
+    size: usize,
+}
+
+impl Tracer for Window {
+    fn trace(&mut self, step: &Step) {
+        if self.steps.len() > self.size {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(step.clone());
+    }
+}
+
+// What is compared after each block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct State {
+    pub reg: Register,
+    pub cycles: u64,
+    pub halted: bool,
+    pub inte: bool,
+    // Memory written by the block, in order.
+    pub writes: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn new(cpu: &Cpu, writes: &RefCell<Vec<(u16, u8)>>) -> Self {
+        Self { reg: cpu.reg, cycles: cpu.cycles, halted: cpu.halted, inte: cpu.inte, writes: writes.take() }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    // Index of the last instruction of the block, from 0.
+    pub index: u64,
+    // The last instruction of the block as the reference ran it.
+    pub step: Step,
+    pub expected: State,
+    pub actual: State,
+    // Instructions run before it, oldest first.
+    pub before: Vec<Step>,
+}
+
+fn writes(w: &[(u16, u8)]) -> String {
+    let r: Vec<String> = w.iter().map(|(a, v)| format!("{:04x}={:02x}", a, v)).collect();
+    format!("[{}]", r.join(" "))
+}
+
+impl Divergence {
+    // What differs, one item each, such as "a expected 12 got 13".
+    pub fn differences(&self) -> Vec<String> {
+        let (x, y) = (&self.expected, &self.actual);
+        let mut r = y.reg.differences(&x.reg);
+        if x.cycles != y.cycles {
+            r.push(format!("cycles expected {} got {}", x.cycles, y.cycles));
+        }
+        if x.halted != y.halted {
+            r.push(format!("halted expected {} got {}", x.halted, y.halted));
+        }
+        if x.inte != y.inte {
+            r.push(format!("inte expected {} got {}", x.inte, y.inte));
+        }
+        if x.writes != y.writes {
+            r.push(format!("writes expected {} got {}", writes(&x.writes), writes(&y.writes)));
+        }
+        r
+    }
+
+    pub fn report(&self) -> String {
+        let mut r = String::new();
+        for step in &self.before {
+            let _ = writeln!(r, "  {}", trace::text(step));
+        }
+        let _ = writeln!(r, "> {}", trace::text(&self.step));
+        let _ = write!(r, "diverged at instruction {}: {}", self.index, self.differences().join(", "));
+        r
+    }
+}
+
+pub struct Lockstep {
+    pub reference: Cpu,
+    pub other: Cpu,
+    // Instructions compared so far.
+    pub steps: u64,
+    pub divergence: Option<Divergence>,
+    window: Rc<RefCell<Window>>,
+    writes: [Writes; 2],
+}
+
+impl Lockstep {
+    // Takes both cpus, which should be in the same state on separate memories. The memory of each is wrapped to see
+    // its writes, and the tracer of the reference is replaced to keep the last context instructions.
+    pub fn new(mut reference: Cpu, mut other: Cpu, context: usize) -> Self {
+        let window = Rc::new(RefCell::new(Window { steps: VecDeque::new(), size: context }));
+        reference.tracer = Some(Box::new(window.clone()));
+        let writes = [Rc::new(RefCell::new(vec![])), Rc::new(RefCell::new(vec![]))];
+        for (cpu, w) in [&mut reference, &mut other].into_iter().zip(&writes) {
+            cpu.mem = Rc::new(RefCell::new(Watch { mem: cpu.mem.clone(), writes: w.clone() }));
+        }
+        Self { reference, other, steps: 0, divergence: None, window, writes }
+    }
+
+    // Run until the other cpu reaches until cycles or halts, moving it with run_block. Returns false once they
+    // diverge.
+    pub fn run_until(&mut self, until: u64) -> bool {
+        self.run(until, |cpu| {
+            cpu.run_block();
+        })
+    }
+
+    // Run as run_until, moving the other cpu with advance, which runs it one block.
+    pub fn run(&mut self, until: u64, mut advance: impl FnMut(&mut Cpu)) -> bool {
+        while self.divergence.is_none() && self.other.cycles < until && !self.other.halted {
+            advance(&mut self.other);
+            let mut n = 0;
+            while self.reference.cycles < self.other.cycles && !self.reference.halted {
+                self.reference.next();
+                n += 1;
+            }
+            let expected = State::new(&self.reference, &self.writes[0]);
+            let actual = State::new(&self.other, &self.writes[1]);
+            if expected != actual {
+                let mut window = self.window.borrow_mut();
+                let step = window.steps.pop_back().unwrap_or_default();
+                let before = window.steps.drain(..).collect();
+                let index = (self.steps + n).saturating_sub(1);
+                self.divergence = Some(Divergence { index, step, expected, actual, before });
+                break;
+            }
+            self.steps += n;
+        }
+        self.divergence.is_none()
+    }
+}
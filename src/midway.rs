@@ -0,0 +1,604 @@
+// The Midway 8080 black and white board, shared by a family of arcade games that differ in their ROMs, the wiring of
+// their I/O ports and the cellophane overlay on the monitor. Each game is described by a Game, the board does the
+// rest. Port layouts follow MAME's mw8080bw driver.
+//
+// Memory map, mirrored every 32K:
+//
+//   0x0000  ROM
+//   0x2000  Work RAM
+//   0x2400  Video RAM, 224 lines of 256 pixels, one bit per pixel with the least significant bit first
+//   0x4000  ROM, on the games that need more than 8K
+//   0x6000  Mirror of the RAM
+//
+// The cpu gets RST 1 when the beam reaches the middle of the screen and RST 2 at the start of vertical blank.
+use super::cpu::{Cpu, CLOCK_FREQUENCY};
+use super::device::Device;
+use super::memory::Memory;
+use super::pixmap::Image;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 224;
+pub const VRAM: u16 = 0x2400;
+pub const VRAM_SIZE: usize = WIDTH * HEIGHT / 8;
+pub const FRAME_RATE: u32 = 60;
+pub const FRAME_CYCLES: u32 = CLOCK_FREQUENCY / FRAME_RATE;
+pub const MID_SCREEN: u16 = 0x0008;
+pub const VBLANK: u16 = 0x0010;
+
+// A ROM file and where it sits in memory.
+#[derive(Clone, Copy, Debug)]
+pub struct Rom {
+    pub file: &'static str,
+    pub addr: u16,
+    pub size: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Coin,
+    Start1,
+    Start2,
+    Fire1,
+    Left1,
+    Right1,
+    Up1,
+    Down1,
+    Fire2,
+    Left2,
+    Right2,
+    Up2,
+    Down2,
+    Tilt,
+}
+
+// Wiring of a button to an input port bit.
+#[derive(Clone, Copy, Debug)]
+pub struct Input {
+    pub button: Button,
+    pub port: u8,
+    pub mask: u8,
+    pub active_low: bool,
+}
+
+// A DIP switch or group of switches, read through an input port. Values are given right aligned.
+#[derive(Clone, Copy, Debug)]
+pub struct Dip {
+    pub name: &'static str,
+    pub port: u8,
+    pub mask: u8,
+    pub default: u8,
+}
+
+// Ports of the MB14241 barrel shifter. Some games also read the result with its bits reversed.
+#[derive(Clone, Copy, Debug)]
+pub struct ShifterPorts {
+    pub count: u8,
+    pub data: u8,
+    pub result: u8,
+    pub reversed: Option<u8>,
+}
+
+// A strip of colored cellophane in front of the monitor, in the coordinates of the video RAM: x along the scan line
+// and y across.
+#[derive(Clone, Debug)]
+pub struct Overlay {
+    pub x: Range<usize>,
+    pub y: Range<usize>,
+    pub color: [u8; 3],
+}
+
+#[derive(Clone, Debug)]
+pub struct Game {
+    pub name: &'static str,
+    pub roms: &'static [Rom],
+    // Bits of the input ports 0 to 7 that are set with no button pressed, besides those of active low buttons.
+    pub inputs: [u8; 8],
+    pub buttons: &'static [Input],
+    pub dips: &'static [Dip],
+    pub shifter: Option<ShifterPorts>,
+    pub watchdog: Option<u8>,
+    // Output ports driving the sound board, reported as events.
+    pub sounds: &'static [u8],
+    pub overlay: &'static [Overlay],
+    // The monitor is turned a quarter counterclockwise in the cabinet.
+    pub rotated: bool,
+}
+
+impl Game {
+    pub fn rom_size(&self) -> usize {
+        self.roms.iter().map(|e| e.size).sum()
+    }
+
+    // Color of a pixel seen through the overlay. White where there is no cellophane.
+    pub fn color(&self, x: usize, y: usize) -> [u8; 3] {
+        self.overlay.iter().find(|e| e.x.contains(&x) && e.y.contains(&y)).map_or([0xff; 3], |e| e.color)
+    }
+}
+
+// ROM at 0x0000 and 0x4000 and RAM at 0x2000, with writes to ROM ignored.
+pub struct Map {
+    pub rom: Vec<u8>,
+    pub ram: Vec<u8>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self { rom: vec![0; 0x4000], ram: vec![0; 0x2000] }
+    }
+
+    fn rom_index(a: u16) -> Option<usize> {
+        match a & 0x7fff {
+            a @ 0x0000..=0x1fff => Some(usize::from(a)),
+            a @ 0x4000..=0x5fff => Some(usize::from(a - 0x2000)),
+            _ => None,
+        }
+    }
+
+    // Place a ROM image at its address.
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        for (i, e) in data.iter().enumerate() {
+            if let Some(a) = Self::rom_index(addr.wrapping_add(i as u16)) {
+                self.rom[a] = *e;
+            }
+        }
+    }
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory for Map {
+    fn get(&self, a: u16) -> u8 {
+        match Self::rom_index(a) {
+            Some(i) => self.rom[i],
+            None => self.ram[usize::from(a & 0x1fff)],
+        }
+    }
+
+    fn set(&mut self, a: u16, v: u8) {
+        if Self::rom_index(a).is_none() {
+            self.ram[usize::from(a & 0x1fff)] = v
+        }
+    }
+}
+
+// MB14241 barrel shifter: the last two bytes written form a 16 bit value, from which a byte is read at a given
+// offset from the top.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Shifter {
+    pub data: u16,
+    pub amount: u8,
+}
+
+impl Shifter {
+    pub fn push(&mut self, v: u8) {
+        self.data = (u16::from(v) << 8) | (self.data >> 8);
+    }
+
+    pub fn result(&self) -> u8 {
+        (self.data >> (8 - self.amount)) as u8
+    }
+}
+
+// A bit of a sound port switched on or off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub port: u8,
+    pub bit: u8,
+    pub on: bool,
+}
+
+// Input ports, shifter, sound outputs and watchdog.
+pub struct Io {
+    pub game: &'static Game,
+    pub inputs: [u8; 8],
+    pub shifter: Shifter,
+    // Last value written to each output port.
+    pub outputs: [u8; 8],
+    pub events: VecDeque<Event>,
+    // Frames since the game last wrote the watchdog port.
+    pub watchdog: u32,
+}
+
+impl Io {
+    pub fn new(game: &'static Game) -> Self {
+        let mut inputs = game.inputs;
+        for b in game.buttons.iter().filter(|e| e.active_low) {
+            inputs[usize::from(b.port & 7)] |= b.mask;
+        }
+        let mut r =
+            Self { game, inputs, shifter: Shifter::default(), outputs: [0; 8], events: VecDeque::new(), watchdog: 0 };
+        for d in game.dips {
+            r.set_dip(d.name, d.default);
+        }
+        r
+    }
+
+    // Set a DIP switch by name, returning false if the game has no such switch.
+    pub fn set_dip(&mut self, name: &str, v: u8) -> bool {
+        let Some(d) = self.game.dips.iter().find(|e| e.name == name) else { return false };
+        let p = &mut self.inputs[usize::from(d.port & 7)];
+        *p = (*p & !d.mask) | ((v << d.mask.trailing_zeros()) & d.mask);
+        true
+    }
+
+    fn button(&mut self, b: Button, pressed: bool) {
+        for i in self.game.buttons.iter().filter(|e| e.button == b) {
+            let p = &mut self.inputs[usize::from(i.port & 7)];
+            if pressed != i.active_low {
+                *p |= i.mask;
+            } else {
+                *p &= !i.mask;
+            }
+        }
+    }
+
+    pub fn press(&mut self, b: Button) {
+        self.button(b, true)
+    }
+
+    pub fn release(&mut self, b: Button) {
+        self.button(b, false)
+    }
+}
+
+impl Device for Io {
+    fn get(&mut self, port: u8) -> u8 {
+        let port = port & 7;
+        if let Some(s) = self.game.shifter {
+            if port == s.result {
+                return self.shifter.result();
+            }
+            if Some(port) == s.reversed {
+                return self.shifter.result().reverse_bits();
+            }
+        }
+        self.inputs[usize::from(port)]
+    }
+
+    fn set(&mut self, port: u8, v: u8) {
+        let port = port & 7;
+        if let Some(s) = self.game.shifter {
+            if port == s.count {
+                self.shifter.amount = v & 0x07;
+            }
+            if port == s.data {
+                self.shifter.push(v);
+            }
+        }
+        if Some(port) == self.game.watchdog {
+            self.watchdog = 0;
+        }
+        if self.game.sounds.contains(&port) {
+            let changed = self.outputs[usize::from(port)] ^ v;
+            for bit in (0..8).filter(|e| changed & (1 << e) != 0) {
+                self.events.push_back(Event { port, bit, on: v & (1 << bit) != 0 });
+            }
+        }
+        self.outputs[usize::from(port)] = v;
+    }
+}
+
+pub struct Board {
+    pub game: &'static Game,
+    pub cpu: Cpu,
+    pub mem: Rc<RefCell<Map>>,
+    pub io: Rc<RefCell<Io>>,
+    pub frame: u64,
+    // Cycles run past the end of the last frame, taken off the next one.
+    debt: u32,
+}
+
+impl Board {
+    // Build the board with the ROM files of the game concatenated in the order they are listed.
+    pub fn new(game: &'static Game, rom: &[u8]) -> Self {
+        let mut map = Map::new();
+        let mut i = 0;
+        for r in game.roms {
+            let data = &rom[i.min(rom.len())..(i + r.size).min(rom.len())];
+            map.load(r.addr, data);
+            i += r.size;
+        }
+        let mem = Rc::new(RefCell::new(map));
+        let io = Rc::new(RefCell::new(Io::new(game)));
+        let mut cpu = Cpu::power_up(mem.clone());
+        cpu.dev = Some(io.clone());
+        Self { game, cpu, mem, io, frame: 0, debt: 0 }
+    }
+
+    // Load the ROM files of the game from a directory.
+    pub fn open(game: &'static Game, dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut rom = Vec::with_capacity(game.rom_size());
+        for r in game.roms {
+            let mut data = fs::read(dir.as_ref().join(r.file))?;
+            data.resize(r.size, 0x00);
+            rom.extend(data);
+        }
+        Ok(Self::new(game, &rom))
+    }
+
+    pub fn press(&mut self, b: Button) {
+        self.io.borrow_mut().press(b)
+    }
+
+    pub fn release(&mut self, b: Button) {
+        self.io.borrow_mut().release(b)
+    }
+
+    pub fn set_dip(&mut self, name: &str, v: u8) -> bool {
+        self.io.borrow_mut().set_dip(name, v)
+    }
+
+    // Sound events since the last call.
+    pub fn events(&mut self) -> Vec<Event> {
+        self.io.borrow_mut().events.drain(..).collect()
+    }
+
+    fn run_until(&mut self, cycles: &mut u32, until: u32) {
+        while *cycles < until {
+            if self.cpu.halted {
+                *cycles = until;
+                break;
+            }
+            *cycles += self.cpu.next();
+        }
+    }
+
+    fn interrupt(&mut self, addr: u16, cycles: &mut u32) {
+        if self.cpu.inte {
+            self.cpu.halted = false;
+            self.cpu.inte_handle(addr);
+            *cycles += 11;
+        }
+    }
+
+    // Run the cpu for one 60 Hz frame, with the mid screen and vertical blank interrupts.
+    pub fn run_frame(&mut self) {
+        let mut cycles = self.debt;
+        self.run_until(&mut cycles, FRAME_CYCLES / 2);
+        self.interrupt(MID_SCREEN, &mut cycles);
+        self.run_until(&mut cycles, FRAME_CYCLES);
+        self.interrupt(VBLANK, &mut cycles);
+        self.debt = cycles - FRAME_CYCLES;
+        self.frame += 1;
+        self.io.borrow_mut().watchdog += 1;
+    }
+
+    // Video RAM: 224 lines of 32 bytes, as the hardware scans it.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        let mem = self.mem.borrow();
+        (0..VRAM_SIZE as u16).map(|i| mem.get(VRAM + i)).collect()
+    }
+
+    // Pixel of the video RAM, with x from 0 to 255 along the scan line and y from 0 to 223.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let a = VRAM + (y * WIDTH / 8 + x / 8) as u16;
+        self.mem.borrow().get(a) & (1 << (x % 8)) != 0
+    }
+
+    // The picture as the player sees it, turned upright on rotated cabinets and optionally tinted by the overlay.
+    pub fn screenshot(&self, overlay: bool) -> Image {
+        let vram = self.framebuffer();
+        let (w, h) = if self.game.rotated { (HEIGHT, WIDTH) } else { (WIDTH, HEIGHT) };
+        let mut r = Image::new(w, h);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                if vram[y * WIDTH / 8 + x / 8] & (1 << (x % 8)) == 0 {
+                    continue;
+                }
+                let color = if overlay { self.game.color(x, y) } else { [0xff; 3] };
+                if self.game.rotated {
+                    r.set(y, WIDTH - 1 - x, color);
+                } else {
+                    r.set(x, y, color);
+                }
+            }
+        }
+        r
+    }
+}
+
+const fn rom(file: &'static str, addr: u16, size: usize) -> Rom {
+    Rom { file, addr, size }
+}
+
+const fn button(button: Button, port: u8, mask: u8) -> Input {
+    Input { button, port, mask, active_low: false }
+}
+
+const fn button_low(button: Button, port: u8, mask: u8) -> Input {
+    Input { button, port, mask, active_low: true }
+}
+
+const fn dip(name: &'static str, port: u8, mask: u8, default: u8) -> Dip {
+    Dip { name, port, mask, default }
+}
+
+// Inputs, shifter, sounds and watchdog of Space Invaders, shared by its Taito derivatives.
+const INVADERS_BUTTONS: &[Input] = &[
+    button(Button::Coin, 1, 0x01),
+    button(Button::Start2, 1, 0x02),
+    button(Button::Start1, 1, 0x04),
+    button(Button::Fire1, 1, 0x10),
+    button(Button::Left1, 1, 0x20),
+    button(Button::Right1, 1, 0x40),
+    button(Button::Tilt, 2, 0x04),
+    button(Button::Fire2, 2, 0x10),
+    button(Button::Left2, 2, 0x20),
+    button(Button::Right2, 2, 0x40),
+];
+const INVADERS_DIPS: &[Dip] = &[dip("lives", 2, 0x03, 0), dip("bonus", 2, 0x08, 0), dip("coin_info", 2, 0x80, 0)];
+const INVADERS_SHIFTER: ShifterPorts = ShifterPorts { count: 2, data: 4, result: 3, reversed: None };
+
+pub const INVADERS: Game = Game {
+    name: "invaders",
+    roms: &[
+        rom("invaders.h", 0x0000, 0x0800),
+        rom("invaders.g", 0x0800, 0x0800),
+        rom("invaders.f", 0x1000, 0x0800),
+        rom("invaders.e", 0x1800, 0x0800),
+    ],
+    inputs: [0x0e, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    buttons: INVADERS_BUTTONS,
+    dips: INVADERS_DIPS,
+    shifter: Some(INVADERS_SHIFTER),
+    watchdog: Some(6),
+    sounds: &[3, 5],
+    overlay: &[
+        // Flying saucer
+        Overlay { x: 192..224, y: 0..224, color: [0xff, 0x20, 0x20] },
+        // Shields and player
+        Overlay { x: 16..72, y: 0..224, color: [0x20, 0xff, 0x20] },
+        // Reserve ships, the credit count stays white
+        Overlay { x: 0..16, y: 16..134, color: [0x20, 0xff, 0x20] },
+    ],
+    rotated: true,
+};
+
+pub const INVADERS_PART_II: Game = Game {
+    name: "invadpt2",
+    roms: &[
+        rom("pv01", 0x0000, 0x0800),
+        rom("pv02", 0x0800, 0x0800),
+        rom("pv03", 0x1000, 0x0800),
+        rom("pv04", 0x1800, 0x0800),
+        rom("pv05", 0x4000, 0x0800),
+    ],
+    dips: &[dip("lives", 2, 0x01, 0), dip("coin_info", 2, 0x80, 0)],
+    ..INVADERS
+};
+
+pub const LUNAR_RESCUE: Game = Game {
+    name: "lrescue",
+    roms: &[
+        rom("lrescue.1", 0x0000, 0x0800),
+        rom("lrescue.2", 0x0800, 0x0800),
+        rom("lrescue.3", 0x1000, 0x0800),
+        rom("lrescue.4", 0x1800, 0x0800),
+        rom("lrescue.5", 0x4000, 0x0800),
+        rom("lrescue.6", 0x4800, 0x0800),
+    ],
+    dips: &[dip("lives", 2, 0x03, 0), dip("coin_info", 2, 0x80, 0)],
+    overlay: &[],
+    ..INVADERS
+};
+
+// Two gunfighters, each with a stick to walk and a gun that aims up or down.
+pub const GUN_FIGHT: Game = Game {
+    name: "gunfight",
+    roms: &[
+        rom("7609h.bin", 0x0000, 0x0400),
+        rom("7609g.bin", 0x0400, 0x0400),
+        rom("7609f.bin", 0x0800, 0x0400),
+        rom("7609e.bin", 0x0c00, 0x0400),
+    ],
+    inputs: [0x00; 8],
+    buttons: &[
+        button_low(Button::Up1, 0, 0x01),
+        button_low(Button::Down1, 0, 0x02),
+        button_low(Button::Left1, 0, 0x04),
+        button_low(Button::Right1, 0, 0x08),
+        button_low(Button::Fire1, 0, 0x80),
+        button_low(Button::Up2, 1, 0x01),
+        button_low(Button::Down2, 1, 0x02),
+        button_low(Button::Left2, 1, 0x04),
+        button_low(Button::Right2, 1, 0x08),
+        button_low(Button::Fire2, 1, 0x80),
+        button(Button::Coin, 2, 0x40),
+        button(Button::Start1, 2, 0x80),
+    ],
+    dips: &[dip("coinage", 2, 0x03, 0), dip("time", 2, 0x0c, 1)],
+    shifter: Some(ShifterPorts { count: 2, data: 3, result: 3, reversed: None }),
+    watchdog: Some(4),
+    sounds: &[1],
+    overlay: &[],
+    rotated: false,
+};
+
+// The periscope is a potentiometer on port 1, set through the inputs of the Io.
+pub const SEA_WOLF: Game = Game {
+    name: "seawolf",
+    roms: &[
+        rom("sw0041.h", 0x0000, 0x0400),
+        rom("sw0042.g", 0x0400, 0x0400),
+        rom("sw0043.f", 0x0800, 0x0400),
+        rom("sw0044.e", 0x0c00, 0x0400),
+    ],
+    inputs: [0x00; 8],
+    buttons: &[button(Button::Fire1, 1, 0x20), button(Button::Coin, 2, 0x40), button(Button::Start1, 2, 0x80)],
+    dips: &[dip("time", 2, 0x03, 0), dip("coinage", 2, 0x0c, 0), dip("extended", 2, 0x30, 0)],
+    shifter: Some(ShifterPorts { count: 4, data: 3, result: 3, reversed: Some(0) }),
+    watchdog: None,
+    sounds: &[5],
+    overlay: &[Overlay { x: 0..256, y: 0..224, color: [0x80, 0xc0, 0xff] }],
+    rotated: false,
+};
+
+pub const BOOT_HILL: Game = Game {
+    name: "boothill",
+    roms: &[
+        rom("romh.cpu", 0x0000, 0x0800),
+        rom("romg.cpu", 0x0800, 0x0800),
+        rom("romf.cpu", 0x1000, 0x0800),
+        rom("rome.cpu", 0x1800, 0x0800),
+    ],
+    inputs: [0x00; 8],
+    buttons: &[
+        button_low(Button::Up2, 0, 0x01),
+        button_low(Button::Down2, 0, 0x02),
+        button_low(Button::Left2, 0, 0x04),
+        button_low(Button::Right2, 0, 0x08),
+        button_low(Button::Fire2, 0, 0x80),
+        button_low(Button::Up1, 1, 0x01),
+        button_low(Button::Down1, 1, 0x02),
+        button_low(Button::Left1, 1, 0x04),
+        button_low(Button::Right1, 1, 0x08),
+        button_low(Button::Fire1, 1, 0x80),
+        button(Button::Coin, 2, 0x40),
+        button(Button::Start1, 2, 0x80),
+    ],
+    dips: &[dip("coinage", 2, 0x03, 0), dip("time", 2, 0x0c, 1)],
+    shifter: Some(ShifterPorts { count: 1, data: 2, result: 3, reversed: None }),
+    watchdog: Some(4),
+    sounds: &[3],
+    overlay: &[],
+    rotated: false,
+};
+
+// No shifter. The steering wheel and the pedal are potentiometers on ports 0 and 1.
+pub const ZZZAP: Game = Game {
+    name: "280zzzap",
+    roms: &[
+        rom("zzzaph", 0x0000, 0x0400),
+        rom("zzzapg", 0x0400, 0x0400),
+        rom("zzzapf", 0x0800, 0x0400),
+        rom("zzzape", 0x0c00, 0x0400),
+        rom("zzzapd", 0x1000, 0x0400),
+        rom("zzzapc", 0x1400, 0x0400),
+    ],
+    inputs: [0x00; 8],
+    buttons: &[button(Button::Fire1, 0, 0x10), button(Button::Coin, 2, 0x40), button(Button::Start1, 2, 0x80)],
+    dips: &[dip("coinage", 2, 0x03, 0), dip("time", 2, 0x0c, 0), dip("language", 2, 0x30, 0)],
+    shifter: None,
+    watchdog: Some(4),
+    sounds: &[2, 5],
+    overlay: &[],
+    rotated: false,
+};
+
+pub const GAMES: &[&Game] = &[&INVADERS, &INVADERS_PART_II, &LUNAR_RESCUE, &GUN_FIGHT, &SEA_WOLF, &BOOT_HILL, &ZZZAP];
+
+// Look a game up by its short name.
+pub fn game(name: &str) -> Option<&'static Game> {
+    GAMES.iter().copied().find(|e| e.name == name)
+}
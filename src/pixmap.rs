@@ -0,0 +1,112 @@
+// RGB images written as PNG or PPM without outside help, for screenshots of emulated video hardware. PNG data is
+// stored in uncompressed deflate blocks: the files are larger than they could be, but the writer stays small.
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // Rows from the top, three bytes per pixel.
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, data: vec![0; width * height * 3] }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * self.width + x) * 3;
+        [self.data[i], self.data[i + 1], self.data[i + 2]]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let i = (y * self.width + x) * 3;
+        self.data[i..i + 3].copy_from_slice(&rgb);
+    }
+
+    // Binary PPM, P6.
+    pub fn ppm(&self) -> Vec<u8> {
+        let mut r = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        r.extend(&self.data);
+        r
+    }
+
+    // PNG has no empty images, so one without pixels is an error.
+    pub fn png(&self) -> io::Result<Vec<u8>> {
+        if self.width == 0 || self.height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "pixmap: PNG image without pixels"));
+        }
+        let mut r = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend((self.width as u32).to_be_bytes());
+        ihdr.extend((self.height as u32).to_be_bytes());
+        // 8 bit truecolor, deflate, adaptive filtering, no interlace.
+        ihdr.extend([8, 2, 0, 0, 0]);
+        chunk(&mut r, b"IHDR", &ihdr);
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.data.chunks(self.width * 3) {
+            raw.push(0);
+            raw.extend(row);
+        }
+        chunk(&mut r, b"IDAT", &zlib(&raw));
+        chunk(&mut r, b"IEND", &[]);
+        Ok(r)
+    }
+
+    // Write the image as PPM if the extension says so, PNG otherwise.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let p = path.as_ref();
+        let ppm = p.extension().is_some_and(|e| e.eq_ignore_ascii_case("ppm"));
+        fs::write(p, if ppm { self.ppm() } else { self.png()? })
+    }
+}
+
+fn chunk(w: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    w.extend((data.len() as u32).to_be_bytes());
+    let start = w.len();
+    w.extend(kind);
+    w.extend(data);
+    let crc = crc32(&w[start..]);
+    w.extend(crc.to_be_bytes());
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut c = 0xffff_ffffu32;
+    for b in data {
+        c ^= u32::from(*b);
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+    }
+    !c
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for e in data {
+        a = (a + u32::from(*e)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// zlib stream of stored deflate blocks.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut r = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        r.extend([0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(b) = blocks.next() {
+        r.push(u8::from(blocks.peek().is_none()));
+        let n = b.len() as u16;
+        r.extend(n.to_le_bytes());
+        r.extend((!n).to_le_bytes());
+        r.extend(b);
+    }
+    r.extend(adler32(data).to_be_bytes());
+    r
+}
@@ -0,0 +1,102 @@
+// Assembler listings put the load address and the generated bytes in front of every source line. Two layouts are
+// common for 8080 tools:
+//
+//   CP/M ASM:  " 0100 3E01      START:  MVI A,1"             bytes packed together in memory order
+//   M80:       "  0100'  C3 0103'       JMP NEXT"            bytes and words separated by spaces, with relocation marks
+//
+// In the spaced layout a four digit field is a word and is stored little-endian.
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Line {
+    // One based line number in the listing.
+    pub number: usize,
+    pub addr: Option<u16>,
+    pub data: Vec<u8>,
+    pub source: String,
+}
+
+impl Line {
+    // The label defined on this line, if any. Labels are the first word of the source field and end with a colon.
+    pub fn label(&self) -> Option<&str> {
+        let t = self.source.split(|c: char| c.is_whitespace() || c == ';').next()?;
+        let t = t.strip_suffix(':')?.trim_end_matches(':');
+        if t.is_empty() || t.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        if !t.chars().all(|c| c.is_ascii_alphanumeric() || "_?@$.".contains(c)) {
+            return None;
+        }
+        Some(t)
+    }
+}
+
+const RELOCATION_MARK: &[char] = &['\'', '"', '!', '*'];
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// A listing line split into the address, the code field tokens and the source text.
+type Fields<'a> = (u16, Vec<&'a str>, &'a str);
+
+fn split(line: &str) -> Option<Fields<'_>> {
+    let s = line.trim_start();
+    let off = line.len() - s.len();
+    let end = s.find(|c: char| c.is_whitespace()).unwrap_or(s.len());
+    let a = s[..end].trim_end_matches(RELOCATION_MARK);
+    if a.len() != 4 || !is_hex(a) {
+        return None;
+    }
+    let addr = u16::from_str_radix(a, 16).ok()?;
+    let mut toks = Vec::new();
+    let mut i = off + end;
+    let b = line.as_bytes();
+    loop {
+        // The code field ends at a tab or at a gap wider than the layouts above put between fields.
+        let mut j = i;
+        while j < b.len() && b[j] == b' ' {
+            j += 1;
+        }
+        if j >= b.len() {
+            return Some((addr, toks, ""));
+        }
+        if b[j] == b'\t' || j - i >= if toks.is_empty() { 5 } else { 3 } {
+            break;
+        }
+        let k = line[j..].find(|c: char| c.is_whitespace()).map_or(line.len(), |e| j + e);
+        let t = line[j..k].trim_end_matches(RELOCATION_MARK);
+        if !is_hex(t) || t.len() % 2 == 1 {
+            i = j;
+            break;
+        }
+        toks.push(t);
+        i = k;
+    }
+    Some((addr, toks, line[i..].trim()))
+}
+
+pub fn parse(s: &str) -> Vec<Line> {
+    let raw: Vec<(usize, &str, Option<Fields>)> = s.lines().enumerate().map(|(i, e)| (i + 1, e, split(e))).collect();
+    let spaced = raw.iter().any(|(_, _, e)| matches!(e, Some((_, t, _)) if t.len() > 1));
+    let mut r = Vec::new();
+    for (number, line, e) in raw {
+        match e {
+            Some((addr, toks, source)) => {
+                let mut data = Vec::new();
+                for t in toks {
+                    if spaced && t.len() == 4 {
+                        let w = u16::from_str_radix(t, 16).unwrap();
+                        data.extend_from_slice(&w.to_le_bytes());
+                    } else {
+                        for k in (0..t.len()).step_by(2) {
+                            data.push(u8::from_str_radix(&t[k..k + 2], 16).unwrap());
+                        }
+                    }
+                }
+                r.push(Line { number, addr: Some(addr), data, source: source.to_string() });
+            }
+            None => r.push(Line { number, addr: None, data: vec![], source: line.trim().to_string() }),
+        }
+    }
+    r
+}
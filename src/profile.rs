@@ -0,0 +1,252 @@
+// Where a program spends its time. The profiler is a tracer counting instructions and cycles per address and per
+// subroutine. Subroutines are followed on a shadow call stack through CALL, RST and RET: a call opens a frame for its
+// target, a return closes the frames whose return address it pops. Interrupts are seen as a jump between two steps
+// that pushed a word, returns done by the host, such as a trapped BDOS call, as a jump that popped one. Cycles of an
+// instruction count to the routine running it (self) and to every routine on the stack (total).
+//
+// The cycles are also kept per call stack and written as folded stacks, one "outer;inner;innermost cycles" line per
+// stack, the input of flamegraph tools. It is every cycle sampled.
+use super::asm;
+use super::memory::Memory;
+use super::register::Register;
+use super::symbol::Symbols;
+use super::trace::{Step, Tracer};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counter {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Routine {
+    pub calls: u64,
+    // Instructions and cycles run in the routine itself.
+    pub instructions: u64,
+    pub self_cycles: u64,
+    // Cycles run in the routine and the routines it called.
+    pub total_cycles: u64,
+}
+
+// A routine on the call stack.
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    entry: u16,
+    // Stack pointer holding the return address, None for the frame the program started in.
+    sp: Option<u16>,
+    // Profile cycles when the frame was opened.
+    start: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Call,
+    Return,
+    Other,
+}
+
+// What a step did to the flow of control: a CALL or RST taken, a RET taken, or neither.
+pub fn flow(step: &Step) -> Flow {
+    let next = step.pc.wrapping_add(u16::from(step.len));
+    match step.opcode[0] {
+        0xcd | 0xdd | 0xed | 0xfd => Flow::Call,
+        0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => Flow::Call,
+        0xc4 | 0xcc | 0xd4 | 0xdc | 0xe4 | 0xec | 0xf4 | 0xfc if step.after.pc != next => Flow::Call,
+        0xc9 | 0xd9 => Flow::Return,
+        0xc0 | 0xc8 | 0xd0 | 0xd8 | 0xe0 | 0xe8 | 0xf0 | 0xf8 if step.after.pc != next => Flow::Return,
+        _ => Flow::Other,
+    }
+}
+
+#[derive(Default)]
+pub struct Profile {
+    pub addrs: HashMap<u16, Counter>,
+    pub routines: HashMap<u16, Routine>,
+    pub total: Counter,
+    // Names of addresses, used for routines and in the reports.
+    pub symbols: Symbols,
+    // Cycles per call stack, outermost routine first.
+    pub stacks: HashMap<Vec<u16>, u64>,
+    stack: Vec<Frame>,
+    // Cycles run on the current stack since it last changed.
+    pending: u64,
+    // Registers after the previous step, to see what happened between steps.
+    last: Option<Register>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Name of an address: its symbol, the nearest symbol below it with an offset, or the address itself.
+    pub fn name(&self, addr: u16) -> String {
+        self.symbols.describe(addr)
+    }
+
+    // Entry points of the routines on the call stack, outermost first.
+    pub fn stack(&self) -> Vec<u16> {
+        self.stack.iter().map(|e| e.entry).collect()
+    }
+
+    // Put the cycles run on the current stack to its count, before the stack changes.
+    fn flush(&mut self) {
+        if self.pending != 0 {
+            *self.stacks.entry(self.stack()).or_default() += self.pending;
+            self.pending = 0;
+        }
+    }
+
+    fn open(&mut self, entry: u16, sp: Option<u16>) {
+        self.flush();
+        self.routines.entry(entry).or_default().calls += 1;
+        self.stack.push(Frame { entry, sp, start: self.total.cycles });
+    }
+
+    fn close(&mut self) {
+        self.flush();
+        let f = self.stack.pop().unwrap();
+        // Recursive routines count their total once, in the outermost frame.
+        if !self.stack.iter().any(|e| e.entry == f.entry) {
+            self.routines.get_mut(&f.entry).unwrap().total_cycles += self.total.cycles - f.start;
+        }
+    }
+
+    // Close the frames whose return address sits at or below sp, those the return or a reset of the stack pointer
+    // discarded.
+    fn unwind(&mut self, sp: u16) {
+        while self.stack.last().is_some_and(|f| f.sp.is_some_and(|e| e <= sp)) {
+            self.close();
+        }
+    }
+
+    // Close every frame, counting the totals of the routines still running. Call when the program ends, before the
+    // reports.
+    pub fn finish(&mut self) {
+        while !self.stack.is_empty() {
+            self.close();
+        }
+        self.last = None;
+    }
+
+    // Folded stacks with routines by name, heaviest first.
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(k, v)| (k.iter().map(|e| self.name(*e).replace([';', ' '], "_")).collect::<Vec<_>>().join(";"), *v))
+            .collect();
+        stacks.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        stacks.iter().map(|(k, v)| format!("{} {}\n", k, v)).collect()
+    }
+
+    // The addresses and the routines taking the most cycles, top of each.
+    pub fn report(&self, top: usize) -> String {
+        let mut r = String::new();
+        let total = self.total.cycles.max(1) as f64;
+        let _ = writeln!(r, "{} instructions, {} cycles", self.total.count, self.total.cycles);
+        let _ = writeln!(r);
+        let _ = writeln!(r, "{:>12} {:>6} {:>10} {:>12} {:>6}  routine", "total", "%", "calls", "self", "%");
+        let mut routines: Vec<_> = self.routines.iter().collect();
+        routines.sort_by_key(|(a, e)| (std::cmp::Reverse(e.total_cycles), **a));
+        for (a, e) in routines.iter().take(top) {
+            let _ = writeln!(
+                r,
+                "{:>12} {:>6.2} {:>10} {:>12} {:>6.2}  {}",
+                e.total_cycles,
+                e.total_cycles as f64 * 100.0 / total,
+                e.calls,
+                e.self_cycles,
+                e.self_cycles as f64 * 100.0 / total,
+                self.name(**a)
+            );
+        }
+        let _ = writeln!(r);
+        let _ = writeln!(r, "{:>12} {:>6} {:>10}  address", "cycles", "%", "count");
+        let mut addrs: Vec<_> = self.addrs.iter().collect();
+        addrs.sort_by_key(|(a, e)| (std::cmp::Reverse(e.cycles), **a));
+        for (a, e) in addrs.iter().take(top) {
+            let _ = writeln!(
+                r,
+                "{:>12} {:>6.2} {:>10}  {:04x}{}",
+                e.cycles,
+                e.cycles as f64 * 100.0 / total,
+                e.count,
+                a,
+                self.symbols.label(**a).map(|e| format!(" {}", e)).unwrap_or_default()
+            );
+        }
+        r
+    }
+
+    // Disassembly of the code that ran, each instruction with its count and cycles. Routine entries are labelled and
+    // gaps between the runs of code are marked.
+    pub fn annotate(&self, mem: &dyn Memory) -> String {
+        let mut r = String::new();
+        let mut addrs: Vec<u16> = self.addrs.keys().copied().collect();
+        addrs.sort();
+        let mut next = None;
+        for a in addrs {
+            if next.is_some_and(|e| e != a) {
+                let _ = writeln!(r, "{:>10} {:>12}  ...", "", "");
+            }
+            if self.symbols.name(a).is_some() || self.routines.contains_key(&a) {
+                let _ = writeln!(r, "{:>10} {:>12}  {}:", "", "", self.name(a));
+            }
+            let op = mem.get(a);
+            let n = asm::length(op);
+            let bytes: Vec<u8> = (0..n).map(|i| mem.get(a.wrapping_add(i as u16))).collect();
+            let hex: Vec<String> = bytes.iter().map(|e| format!("{:02x}", e)).collect();
+            let c = self.addrs[&a];
+            let _ = writeln!(
+                r,
+                "{:>10} {:>12}  {:04x}  {:<8}  {}",
+                c.count,
+                c.cycles,
+                a,
+                hex.join(" "),
+                asm::disasm_with(&bytes, |w| self.symbols.name(w).map(String::from))
+            );
+            next = Some(a.wrapping_add(n as u16));
+        }
+        r
+    }
+}
+
+impl Tracer for Profile {
+    fn trace(&mut self, step: &Step) {
+        if let Some(last) = self.last.filter(|e| e.pc != step.pc) {
+            if step.before.sp == last.sp.wrapping_sub(2) {
+                self.unwind(step.before.sp);
+                self.open(step.pc, Some(step.before.sp));
+            } else if step.before.sp == last.sp.wrapping_add(2) {
+                self.unwind(last.sp);
+            }
+        }
+        self.last = Some(step.after);
+        if self.stack.is_empty() {
+            self.open(step.pc, None);
+        }
+        let cycles = u64::from(step.cycles);
+        let c = self.addrs.entry(step.pc).or_default();
+        c.count += 1;
+        c.cycles += cycles;
+        self.total.count += 1;
+        self.total.cycles += cycles;
+        let top = self.stack.last().unwrap().entry;
+        let routine = self.routines.get_mut(&top).unwrap();
+        routine.instructions += 1;
+        routine.self_cycles += cycles;
+        self.pending += cycles;
+        match flow(step) {
+            Flow::Call => {
+                self.unwind(step.after.sp);
+                self.open(step.after.pc, Some(step.after.sp));
+            }
+            Flow::Return => self.unwind(step.before.sp),
+            Flow::Other => {}
+        }
+    }
+}
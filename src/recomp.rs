@@ -0,0 +1,297 @@
+// Static recompilation of 8080 code to Rust. From the entry points of a ROM the code is found by following the jumps,
+// calls and returns it can see, as blocks of the cache decode it, and each block is written out as a Rust function
+// running its instructions on a Register and a Memory. The functions are built with the program, so the ROM runs as
+// native code.
+//
+// What cannot be found or run that way is left to the interpreter: code reached only through PCHL or a table of
+// addresses, code outside the ROM, and the instructions that need the cpu, I/O, EI, DI and HLT. A block ends before
+// them and run takes the next instruction with Cpu::next wherever no function starts. The code is taken to stay as it
+// was: a program writing its own code must run with the interpreter.
+use super::asm;
+use super::block::{self, Block, Op};
+use super::cpu::{Cpu, OP_CYCLES};
+use super::memory::Memory;
+use super::register::Register;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+// A recompiled block: runs the instructions, leaving pc at the next one, and returns their cycles.
+pub type BlockFn = fn(&mut Register, &mut dyn Memory) -> u32;
+
+// True for the instructions a recompiled block runs.
+pub fn recompiles(code: u8) -> bool {
+    !matches!(code, 0x76 | 0xd3 | 0xdb | 0xf3 | 0xfb)
+}
+
+// The blocks reached from the entry points, with the bytes of their instructions within code. A block ends before the
+// first instruction it does not run, and is not made when that is the first.
+pub fn discover(mem: &dyn Memory, code: Range<usize>, entries: &[u16]) -> BTreeMap<u16, Block> {
+    let mut blocks = BTreeMap::new();
+    let mut seen = vec![false; 0x10000];
+    let mut todo: Vec<u16> = entries.to_vec();
+    while let Some(start) = todo.pop() {
+        if seen[usize::from(start)] || !code.contains(&usize::from(start)) {
+            continue;
+        }
+        seen[usize::from(start)] = true;
+        let mut b = Block::decode(mem, start);
+        let mut pc = start;
+        let mut n = 0;
+        for op in &b.ops {
+            let end = usize::from(pc) + usize::from(op.len);
+            if !recompiles(op.code) || end > code.end {
+                break;
+            }
+            pc = pc.wrapping_add(u16::from(op.len));
+            n += 1;
+        }
+        let next = pc;
+        if n < b.ops.len() {
+            // The interpreter runs the instruction, then the code after it.
+            let op = b.ops[n];
+            if usize::from(next) + usize::from(op.len) <= code.end {
+                todo.push(next.wrapping_add(u16::from(op.len)));
+            }
+            b.ops.truncate(n);
+        } else {
+            let last = b.ops[n - 1];
+            let target = last.imm;
+            match last.code {
+                0xc3 => todo.push(target),
+                0xc9 | 0xe9 => {}
+                0xcd => todo.extend([target, next]),
+                c if c & 0xc7 == 0xc2 || c & 0xc7 == 0xc4 => todo.extend([target, next]),
+                c if c & 0xc7 == 0xc7 => todo.extend([u16::from(c & 0x38), next]),
+                // A conditional return, or a block cut at MAX_OPS.
+                _ => todo.push(next),
+            }
+        }
+        if n != 0 {
+            b.size = next.wrapping_sub(start);
+            blocks.insert(start, b);
+        }
+    }
+    blocks
+}
+
+// Run the recompiled blocks block gives, and the interpreter where there is none, until the cycles reach until or
+// the cpu halts. A block runs whole, so the cycles may go past until. The tracer, history and journal of the cpu see
+// only the instructions of the interpreter.
+pub fn run(cpu: &mut Cpu, block: impl Fn(u16) -> Option<BlockFn>, until: u64) {
+    while cpu.cycles < until && !cpu.halted {
+        match block(cpu.reg.pc) {
+            Some(f) => {
+                let c = f(&mut cpu.reg, &mut *cpu.mem.borrow_mut());
+                cpu.reg.settle();
+                cpu.cycles += u64::from(c);
+            }
+            None => {
+                cpu.next();
+            }
+        }
+    }
+}
+
+const REGS: [&str; 8] = ["b", "c", "d", "e", "h", "l", "m", "a"];
+const PAIRS: [&str; 4] = ["bc", "de", "hl", "sp"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbb", "ana", "xra", "ora", "cmp"];
+const CONDS: [&str; 8] = [
+    "!r.get_flag(Flag::Z)",
+    "r.get_flag(Flag::Z)",
+    "!r.get_flag(Flag::C)",
+    "r.get_flag(Flag::C)",
+    "!r.get_flag(Flag::P)",
+    "r.get_flag(Flag::P)",
+    "!r.get_flag(Flag::S)",
+    "r.get_flag(Flag::S)",
+];
+
+// A register or M as an expression.
+fn get(r: u8) -> String {
+    match r {
+        6 => "m.get(r.get_hl())".to_string(),
+        _ => format!("r.{}", REGS[usize::from(r)]),
+    }
+}
+
+// Set a register or M to an expression.
+fn set(r: u8, v: &str) -> String {
+    match r {
+        6 => format!("m.set(r.get_hl(), {});", v),
+        _ => format!("r.{} = {};", REGS[usize::from(r)], v),
+    }
+}
+
+fn get_pair(p: u8) -> String {
+    match p {
+        3 => "r.sp".to_string(),
+        _ => format!("r.get_{}()", PAIRS[usize::from(p)]),
+    }
+}
+
+fn set_pair(p: u8, v: &str) -> String {
+    match p {
+        3 => format!("r.sp = {};", v),
+        _ => format!("r.set_{}({});", PAIRS[usize::from(p)], v),
+    }
+}
+
+fn push(v: &str) -> Vec<String> {
+    vec!["r.sp = r.sp.wrapping_sub(2);".to_string(), format!("m.set_word(r.sp, {});", v)]
+}
+
+fn pop(to: &str) -> Vec<String> {
+    vec![format!("{}(m.get_word(r.sp));", to), "r.sp = r.sp.wrapping_add(2);".to_string()]
+}
+
+// The statements of an instruction that does not change the flow of control.
+fn statements(code: u8, imm: u16) -> Vec<String> {
+    let (d, s, p) = ((code >> 3) & 7, code & 7, (code >> 4) & 3);
+    let one = |e: String| vec![e];
+    match code {
+        0x00 => vec![],
+        0x40..=0x7f if d == s => vec![],
+        0x40..=0x7f => one(set(d, &get(s))),
+        0x80..=0xbf => one(format!("r.alu_{}({});", ALU[usize::from(d)], get(s))),
+        _ if code & 0xc7 == 0xc6 => one(format!("r.alu_{}(0x{:02x});", ALU[usize::from(d)], imm)),
+        _ if code & 0xc7 == 0x06 => one(set(d, &format!("0x{:02x}", imm))),
+        _ if code & 0xc7 == 0x04 || code & 0xc7 == 0x05 => {
+            let f = if code & 1 == 0 { "inr" } else { "dcr" };
+            match d {
+                6 => vec![format!("let v = r.alu_{}({});", f, get(d)), set(d, "v")],
+                _ => one(set(d, &format!("r.alu_{}({})", f, get(d)))),
+            }
+        }
+        _ if code & 0xcf == 0x01 => one(set_pair(p, &format!("0x{:04x}", imm))),
+        _ if code & 0xcf == 0x03 => one(set_pair(p, &format!("{}.wrapping_add(1)", get_pair(p)))),
+        _ if code & 0xcf == 0x0b => one(set_pair(p, &format!("{}.wrapping_sub(1)", get_pair(p)))),
+        _ if code & 0xcf == 0x09 => one(format!("r.alu_dad({});", get_pair(p))),
+        0xc5 | 0xd5 | 0xe5 => push(&get_pair(p)),
+        0xf5 => push("r.get_af()"),
+        0xc1 | 0xd1 | 0xe1 => pop(&format!("r.set_{}", PAIRS[usize::from(p)])),
+        0xf1 => pop("r.set_af"),
+        0x02 | 0x12 => one(format!("m.set({}, r.a);", get_pair(p))),
+        0x0a | 0x1a => one(format!("r.a = m.get({});", get_pair(p))),
+        0x22 => one(format!("m.set_word(0x{:04x}, r.get_hl());", imm)),
+        0x2a => one(format!("r.set_hl(m.get_word(0x{:04x}));", imm)),
+        0x32 => one(format!("m.set(0x{:04x}, r.a);", imm)),
+        0x3a => one(format!("r.a = m.get(0x{:04x});", imm)),
+        0x07 => one("r.alu_rlc();".to_string()),
+        0x0f => one("r.alu_rrc();".to_string()),
+        0x17 => one("r.alu_ral();".to_string()),
+        0x1f => one("r.alu_rar();".to_string()),
+        0x27 => one("r.alu_daa();".to_string()),
+        0x2f => one("r.a = !r.a;".to_string()),
+        0x37 => one("r.set_flag(Flag::C, true);".to_string()),
+        0x3f => one("r.set_flag(Flag::C, !r.get_flag(Flag::C));".to_string()),
+        0xeb => {
+            vec!["std::mem::swap(&mut r.h, &mut r.d);".to_string(), "std::mem::swap(&mut r.l, &mut r.e);".to_string()]
+        }
+        0xe3 => vec![
+            "let v = m.get_word(r.sp);".to_string(),
+            "m.set_word(r.sp, r.get_hl());".to_string(),
+            "r.set_hl(v);".to_string(),
+        ],
+        0xf9 => one("r.sp = r.get_hl();".to_string()),
+        _ => unreachable!("{:02x} is not recompiled", code),
+    }
+}
+
+// Statements ending a block, leaving pc at the next instruction and returning the cycles: those of the control
+// instruction ending it, if any, with the cycles before it and the address after it.
+fn end(last: Option<Op>, next: u16, cycles: u32) -> Vec<String> {
+    let Some(op) = last else {
+        return vec![format!("r.pc = 0x{:04x};", next), cycles.to_string()];
+    };
+    let cycles = cycles + OP_CYCLES[usize::from(op.code)];
+    let cond = CONDS[usize::from((op.code >> 3) & 7)];
+    let target = op.imm;
+    let taken = match op.code {
+        0xc3 => return vec![format!("r.pc = 0x{:04x};", target), cycles.to_string()],
+        0xe9 => return vec!["r.pc = r.get_hl();".to_string(), cycles.to_string()],
+        c if c & 0xc7 == 0xc2 => {
+            let pc = format!("r.pc = if {} {{ 0x{:04x} }} else {{ 0x{:04x} }};", cond, target, next);
+            return vec![pc, cycles.to_string()];
+        }
+        c if c & 0xc7 == 0xc7 => {
+            let mut lines = push(&format!("0x{:04x}", next));
+            lines.push(format!("r.pc = 0x{:04x};", c & 0x38));
+            lines.push(cycles.to_string());
+            return lines;
+        }
+        c if c == 0xcd || c & 0xc7 == 0xc4 => {
+            let mut lines = push(&format!("0x{:04x}", next));
+            lines.push(format!("r.pc = 0x{:04x};", target));
+            lines
+        }
+        _ => vec!["r.pc = m.get_word(r.sp);".to_string(), "r.sp = r.sp.wrapping_add(2);".to_string()],
+    };
+    // Calls and returns take 6 cycles more when they go.
+    let mut lines = vec![];
+    if op.code == 0xcd || op.code == 0xc9 {
+        lines.extend(taken);
+        lines.push((cycles + 6).to_string());
+        return lines;
+    }
+    lines.push(format!("if {} {{", cond));
+    lines.extend(taken.into_iter().map(|e| format!("    {}", e)));
+    lines.push(format!("    return {};", cycles + 6));
+    lines.push("}".to_string());
+    lines.push(format!("r.pc = 0x{:04x};", next));
+    lines.push(cycles.to_string());
+    lines
+}
+
+// The Rust module of the blocks: a function for each, named after its address, and block, giving the function of an
+// address for run.
+pub fn emit(blocks: &BTreeMap<u16, Block>) -> String {
+    let mut fns = String::new();
+    let mut flags = false;
+    for b in blocks.values() {
+        let last = b.ops.last().copied().filter(|e| block::ends_block(e.code));
+        let mut lines = vec![];
+        let mut pc = b.start;
+        let mut cycles = 0;
+        for (i, op) in b.ops.iter().enumerate() {
+            let bytes = [op.code, op.imm as u8, (op.imm >> 8) as u8];
+            lines.push(format!("// {:04x}  {}", pc, asm::disasm(&bytes[..usize::from(op.len)])));
+            pc = pc.wrapping_add(u16::from(op.len));
+            if last.is_none() || i + 1 < b.ops.len() {
+                lines.extend(statements(op.code, op.imm));
+                cycles += OP_CYCLES[usize::from(op.code)];
+            }
+        }
+        lines.extend(end(last, pc, cycles));
+        let code = lines.iter().filter(|e| !e.starts_with("//"));
+        let m = if code.clone().any(|e| e.contains("m.")) { "m" } else { "_" };
+        flags |= code.clone().any(|e| e.contains("Flag::"));
+        writeln!(fns).unwrap();
+        writeln!(fns, "fn b_{:04x}(r: &mut Register, {}: &mut dyn Memory) -> u32 {{", b.start, m).unwrap();
+        for e in lines {
+            writeln!(fns, "    {}", e).unwrap();
+        }
+        writeln!(fns, "}}").unwrap();
+    }
+    let mut s = String::new();
+    writeln!(
+        s,
+        "// Recompiled from 8080 code by i8080::recomp. Each function runs the block at the address in its name."
+    )
+    .unwrap();
+    writeln!(s, "use i8080::recomp::BlockFn;").unwrap();
+    writeln!(s, "use i8080::{{{}Memory, Register}};", if flags { "Flag, " } else { "" }).unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "// The function of the block at an address.").unwrap();
+    if blocks.is_empty() {
+        writeln!(s, "pub fn block(_: u16) -> Option<BlockFn> {{\n    None\n}}").unwrap();
+        return s;
+    }
+    writeln!(s, "pub fn block(pc: u16) -> Option<BlockFn> {{").unwrap();
+    writeln!(s, "    match pc {{").unwrap();
+    for a in blocks.keys() {
+        writeln!(s, "        0x{:04x} => Some(b_{:04x}),", a, a).unwrap();
+    }
+    writeln!(s, "        _ => None,\n    }}\n}}").unwrap();
+    s + &fns
+}
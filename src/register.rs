@@ -1,4 +1,5 @@
 use super::bit;
+use std::fmt;
 
 // -------------
 // | A   Flags |  ---> Program Status Word
@@ -8,7 +9,13 @@ use super::bit;
 // |    SP     |  ---> Stack Pointer
 // |    PC     |  ---> Program Counter
 // -------------
-#[derive(Default)]
+//
+// The flags of the arithmetic and logic instructions are computed when they are read rather than when the instruction
+// runs, as most are replaced by the next instruction before anything looks at them. Until then the operation is kept
+// pending and f is behind: get_flag, get_af and flags see the pending flags, and settle writes them into f. The cpu
+// settles them before returning from next or a block, so f is up to date whenever the registers are looked at from
+// outside.
+#[derive(Clone, Copy, Default)]
 pub struct Register {
     pub a: u8,
     pub f: u8, // The F register is indirectly accessible by the programer.
@@ -20,13 +27,79 @@ pub struct Register {
     pub l: u8,
     pub sp: u16,
     pub pc: u16,
+    pub(crate) pending: Pending,
+}
+
+// The last operation setting all of S, Z, A, P and C, with what its flags are computed from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Pending {
+    #[default]
+    None,
+    // ADD and ADC, SUB, SBB and CMP: the operands and the sum or difference with the carry, over 0xff for a carry.
+    Add {
+        a: u8,
+        n: u8,
+        r: u16,
+    },
+    Sub {
+        a: u8,
+        n: u8,
+        r: u16,
+    },
+    Ana {
+        a: u8,
+        n: u8,
+    },
+    // XRA and ORA, by the result.
+    Logic {
+        r: u8,
+    },
+}
+
+// The S, Z and P flags of each result.
+const SZP: [u8; 256] = {
+    let mut t = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let r = i as u8;
+        t[i] = (r & 0x80) | if r == 0 { 0x40 } else { 0 } | if r.count_ones() & 1 == 0 { 0x04 } else { 0 };
+        i += 1;
+    }
+    t
+};
+
+// Registers are equal with the same flags, whether or not they are settled.
+impl PartialEq for Register {
+    fn eq(&self, other: &Self) -> bool {
+        let key = |r: &Self| (r.a, r.flags(), r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc);
+        key(self) == key(other)
+    }
+}
+
+impl Eq for Register {}
+
+impl fmt::Debug for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Register")
+            .field("a", &self.a)
+            .field("f", &self.flags())
+            .field("b", &self.b)
+            .field("c", &self.c)
+            .field("d", &self.d)
+            .field("e", &self.e)
+            .field("h", &self.h)
+            .field("l", &self.l)
+            .field("sp", &self.sp)
+            .field("pc", &self.pc)
+            .finish()
+    }
 }
 
 // Some instructions, however, allow you to use the registers A,B,C,D,E,H,L as 16-bit registers by pairing them up
 // in the following manner: AF,BC,DE,HL.
 impl Register {
     pub fn get_af(&self) -> u16 {
-        (u16::from(self.a) << 8) | u16::from(self.f)
+        (u16::from(self.a) << 8) | u16::from(self.flags())
     }
 
     pub fn get_bc(&self) -> u16 {
@@ -44,6 +117,7 @@ impl Register {
     pub fn set_af(&mut self, v: u16) {
         self.a = (v >> 8) as u8;
         self.f = (v & 0x00d5 | 0x0002) as u8;
+        self.pending = Pending::None;
     }
 
     pub fn set_bc(&mut self, v: u16) {
@@ -71,11 +145,33 @@ pub enum Flag {
 }
 
 impl Register {
+    // The flags with those of a pending operation.
+    pub fn flags(&self) -> u8 {
+        let (szp, ac, c) = match self.pending {
+            Pending::None => return self.f,
+            Pending::Add { a, n, r } => (SZP[usize::from(r as u8)], (a ^ n ^ r as u8) & 0x10, u8::from(r > 0xff)),
+            // The 8080 sets A when the low nibble does not borrow.
+            Pending::Sub { a, n, r } => (SZP[usize::from(r as u8)], !(a ^ n ^ r as u8) & 0x10, u8::from(r > 0xff)),
+            Pending::Ana { a, n } => (SZP[usize::from(a & n)], ((a | n) & 0x08) << 1, 0),
+            Pending::Logic { r } => (SZP[usize::from(r)], 0, 0),
+        };
+        (self.f & 0x2a) | szp | ac | c
+    }
+
+    // Write the flags of a pending operation into f.
+    pub fn settle(&mut self) {
+        if self.pending != Pending::None {
+            self.f = self.flags();
+            self.pending = Pending::None;
+        }
+    }
+
     pub fn get_flag(&self, f: Flag) -> bool {
-        bit::get(self.f, f as usize)
+        bit::get(self.flags(), f as usize)
     }
 
     pub fn set_flag(&mut self, f: Flag, v: bool) {
+        self.settle();
         if v {
             self.f = bit::set(self.f, f as usize)
         } else {
@@ -88,4 +184,166 @@ impl Register {
     pub fn power_up() -> Self {
         Self { f: 0b0000_0010, ..Default::default() }
     }
+
+    // What differs from the registers expected, one item each, such as "a expected 12 got 13". The flags are
+    // followed by the names of those differing, as in "f expected 16 got 06 (A)".
+    pub fn differences(&self, expected: &Self) -> Vec<String> {
+        let (x, y) = (expected, self);
+        let mut r = vec![];
+        let bytes = [
+            ("a", x.a, y.a),
+            ("b", x.b, y.b),
+            ("c", x.c, y.c),
+            ("d", x.d, y.d),
+            ("e", x.e, y.e),
+            ("h", x.h, y.h),
+            ("l", x.l, y.l),
+        ];
+        for (name, e, a) in bytes {
+            if e != a {
+                r.push(format!("{} expected {:02x} got {:02x}", name, e, a));
+            }
+        }
+        let (e, a) = (x.flags(), y.flags());
+        if e != a {
+            let flags = [(Flag::S, 'S'), (Flag::Z, 'Z'), (Flag::A, 'A'), (Flag::P, 'P'), (Flag::C, 'C')];
+            let names: String =
+                flags.into_iter().filter_map(|(f, c)| ((e ^ a) & (1 << f as u8) != 0).then_some(c)).collect();
+            r.push(format!("f expected {:02x} got {:02x} ({})", e, a, names));
+        }
+        for (name, e, a) in [("sp", x.sp, y.sp), ("pc", x.pc, y.pc)] {
+            if e != a {
+                r.push(format!("{} expected {:04x} got {:04x}", name, e, a));
+            }
+        }
+        r
+    }
+}
+
+// The arithmetic and logic of the instructions, setting the flags.
+impl Register {
+    // INR and DCR leave C as it is.
+    pub fn alu_inr(&mut self, n: u8) -> u8 {
+        self.settle();
+        let r = n.wrapping_add(1);
+        let ac = if n & 0x0f == 0x0f { 0x10 } else { 0 };
+        self.f = (self.f & 0x2b) | SZP[usize::from(r)] | ac;
+        r
+    }
+
+    pub fn alu_dcr(&mut self, n: u8) -> u8 {
+        self.settle();
+        let r = n.wrapping_sub(1);
+        let ac = if r & 0x0f != 0x0f { 0x10 } else { 0 };
+        self.f = (self.f & 0x2b) | SZP[usize::from(r)] | ac;
+        r
+    }
+
+    // The integht-bit hexadecimal number in the accumulator is.adjusted to form tow four bit binary codecd decimal
+    // digits by the following two process
+    pub fn alu_daa(&mut self) {
+        let mut a: u8 = 0;
+        let mut c = self.get_flag(Flag::C);
+        let lsb = self.a & 0x0f;
+        let msb = self.a >> 4;
+        // If the least significant four bits of the accumulator represents a number greater than 9, or if the Auxiliary
+        // Carry bit is equal to one, the accumulator is incremented by six. Otherwise, no incrementing occurs.
+        if (lsb > 9) || self.get_flag(Flag::A) {
+            a += 0x06;
+        }
+        // If the most significant four bits of the accumulator now represent a number greater than 9, or if the normal
+        // carry bit is equal to one, the most sign ificant four bits of the accumulator are incremented by six.
+        if (msb > 9) || self.get_flag(Flag::C) || (msb >= 9 && lsb > 9) {
+            a += 0x60;
+            c = true;
+        }
+        self.alu_add(a);
+        self.set_flag(Flag::C, c);
+    }
+
+    pub fn alu_add(&mut self, n: u8) {
+        let a = self.a;
+        let r = u16::from(a) + u16::from(n);
+        self.pending = Pending::Add { a, n, r };
+        self.a = r as u8;
+    }
+
+    pub fn alu_adc(&mut self, n: u8) {
+        let c = u16::from(self.get_flag(Flag::C));
+        let a = self.a;
+        let r = u16::from(a) + u16::from(n) + c;
+        self.pending = Pending::Add { a, n, r };
+        self.a = r as u8;
+    }
+
+    pub fn alu_sub(&mut self, n: u8) {
+        let a = self.a;
+        let r = u16::from(a).wrapping_sub(u16::from(n));
+        self.pending = Pending::Sub { a, n, r };
+        self.a = r as u8;
+    }
+
+    pub fn alu_sbb(&mut self, n: u8) {
+        let c = u16::from(self.get_flag(Flag::C));
+        let a = self.a;
+        let r = u16::from(a).wrapping_sub(u16::from(n)).wrapping_sub(c);
+        self.pending = Pending::Sub { a, n, r };
+        self.a = r as u8;
+    }
+
+    pub fn alu_ana(&mut self, n: u8) {
+        self.pending = Pending::Ana { a: self.a, n };
+        self.a &= n;
+    }
+
+    pub fn alu_xra(&mut self, n: u8) {
+        self.a ^= n;
+        self.pending = Pending::Logic { r: self.a };
+    }
+
+    pub fn alu_ora(&mut self, n: u8) {
+        self.a |= n;
+        self.pending = Pending::Logic { r: self.a };
+    }
+
+    pub fn alu_cmp(&mut self, n: u8) {
+        let r = self.a;
+        self.alu_sub(n);
+        self.a = r;
+    }
+
+    pub fn alu_rlc(&mut self) {
+        let c = bit::get(self.a, 7);
+        let r = (self.a << 1) | u8::from(c);
+        self.set_flag(Flag::C, c);
+        self.a = r;
+    }
+
+    pub fn alu_rrc(&mut self) {
+        let c = bit::get(self.a, 0);
+        let r = if c { 0x80 | (self.a >> 1) } else { self.a >> 1 };
+        self.set_flag(Flag::C, c);
+        self.a = r;
+    }
+
+    pub fn alu_ral(&mut self) {
+        let c = bit::get(self.a, 7);
+        let r = (self.a << 1) | u8::from(self.get_flag(Flag::C));
+        self.set_flag(Flag::C, c);
+        self.a = r;
+    }
+
+    pub fn alu_rar(&mut self) {
+        let c = bit::get(self.a, 0);
+        let r = if self.get_flag(Flag::C) { 0x80 | (self.a >> 1) } else { self.a >> 1 };
+        self.set_flag(Flag::C, c);
+        self.a = r;
+    }
+
+    pub fn alu_dad(&mut self, n: u16) {
+        let a = self.get_hl();
+        let r = a.wrapping_add(n);
+        self.set_flag(Flag::C, a > 0xffff - n);
+        self.set_hl(r);
+    }
 }
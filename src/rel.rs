@@ -0,0 +1,300 @@
+// Microsoft relocatable object format, as written by M80 and read by LINK-80 and L80. The file is a bit stream, most
+// significant bit first:
+//
+//   0 xxxxxxxx                 Absolute byte
+//   1 01 xxxxxxxx xxxxxxxx     Program relative word, low byte first
+//   1 10 xxxxxxxx xxxxxxxx     Data relative word
+//   1 11 xxxxxxxx xxxxxxxx     Common relative word
+//   1 00 cccc [A] [B]          Special link item
+//
+// Special link items carry an A field (2 bit address type and a word) and/or a B field (3 bit length and a name).
+use super::loader::Image;
+use std::collections::HashMap;
+use std::io;
+
+const LINK_ENTRY_SYMBOL: u8 = 0x00;
+const LINK_SELECT_COMMON: u8 = 0x01;
+const LINK_PROGRAM_NAME: u8 = 0x02;
+const LINK_LIBRARY_SEARCH: u8 = 0x03;
+const LINK_EXTENSION: u8 = 0x04;
+const LINK_COMMON_SIZE: u8 = 0x05;
+const LINK_CHAIN_EXTERNAL: u8 = 0x06;
+const LINK_ENTRY_POINT: u8 = 0x07;
+const LINK_EXTERNAL_MINUS_OFFSET: u8 = 0x08;
+const LINK_EXTERNAL_PLUS_OFFSET: u8 = 0x09;
+const LINK_DATA_SIZE: u8 = 0x0a;
+const LINK_LOCATION_COUNTER: u8 = 0x0b;
+const LINK_CHAIN_ADDRESS: u8 = 0x0c;
+const LINK_PROGRAM_SIZE: u8 = 0x0d;
+const LINK_END_PROGRAM: u8 = 0x0e;
+const LINK_END_FILE: u8 = 0x0f;
+
+const SEGMENT_ABSOLUTE: u8 = 0x00;
+const SEGMENT_PROGRAM: u8 = 0x01;
+const SEGMENT_DATA: u8 = 0x02;
+const SEGMENT_COMMON: u8 = 0x03;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("rel: {}", msg))
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn get(&mut self, n: usize) -> io::Result<u16> {
+        let mut r = 0;
+        for _ in 0..n {
+            let b = self.data.get(self.pos / 8).ok_or_else(|| invalid("unexpected end of file"))?;
+            r = (r << 1) | u16::from((b >> (7 - self.pos % 8)) & 0x01);
+            self.pos += 1;
+        }
+        Ok(r)
+    }
+
+    fn get_word(&mut self) -> io::Result<u16> {
+        let lo = self.get(8)?;
+        let hi = self.get(8)?;
+        Ok((hi << 8) | lo)
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+}
+
+// The state of the module being loaded. Program, data and common areas are laid out one after another.
+#[derive(Default)]
+struct Module {
+    program: u16,
+    data: u16,
+    data_size: u16,
+    // Base of the selected common block.
+    common: u16,
+    segment: u8,
+    location: u16,
+}
+
+impl Module {
+    fn base(&self, segment: u8) -> u16 {
+        match segment {
+            SEGMENT_PROGRAM => self.program,
+            SEGMENT_DATA => self.data,
+            SEGMENT_COMMON => self.common,
+            _ => 0,
+        }
+    }
+
+    fn relocate(&self, segment: u8, v: u16) -> u16 {
+        self.base(segment).wrapping_add(v)
+    }
+
+    fn here(&self) -> u16 {
+        self.relocate(self.segment, self.location)
+    }
+
+    // First free address after the module and the common blocks allocated up to top.
+    fn end(&self, top: u16) -> u16 {
+        self.data.wrapping_add(self.data_size).max(top)
+    }
+}
+
+struct Loader {
+    mem: Vec<u8>,
+    used: Vec<bool>,
+    entries: Vec<(String, u16)>,
+    chains: Vec<(String, u16)>,
+    offsets: Vec<(u16, u16)>,
+    start: Option<u16>,
+    // Named common blocks, shared by every module in the file.
+    commons: HashMap<String, u16>,
+    // Blocks declared before the sizes of the module are known, with their sizes.
+    pending: Vec<(String, u16)>,
+    // End of the last allocated common block.
+    top: u16,
+}
+
+impl Loader {
+    fn set(&mut self, a: u16, v: u8) {
+        self.mem[usize::from(a)] = v;
+        self.used[usize::from(a)] = true;
+    }
+
+    fn get_word(&self, a: u16) -> u16 {
+        u16::from(self.mem[usize::from(a)]) | (u16::from(self.mem[usize::from(a.wrapping_add(1))]) << 8)
+    }
+
+    fn set_word(&mut self, a: u16, v: u16) {
+        self.set(a, v as u8);
+        self.set(a.wrapping_add(1), (v >> 8) as u8);
+    }
+
+    // Place the pending common blocks after the module.
+    fn allocate(&mut self, m: &Module) {
+        for (n, size) in std::mem::take(&mut self.pending) {
+            if !self.commons.contains_key(&n) {
+                let a = m.end(self.top);
+                self.commons.insert(n, a);
+                self.top = a.wrapping_add(size);
+            }
+        }
+    }
+
+    // Replace every link of a chain with a value. Each location holds the address of the previous reference and the
+    // chain ends with an absolute zero.
+    fn resolve(&mut self, head: u16, v: u16) -> io::Result<()> {
+        let mut a = head;
+        let mut n = 0;
+        while a != 0 {
+            n += 1;
+            if n > 0x8000 {
+                return Err(invalid("chain does not terminate"));
+            }
+            let next = self.get_word(a);
+            self.set_word(a, v);
+            a = next;
+        }
+        Ok(())
+    }
+}
+
+fn name(bits: &mut Bits) -> io::Result<String> {
+    let n = bits.get(3)?;
+    let mut r = String::new();
+    for _ in 0..n {
+        r.push(char::from(bits.get(8)? as u8));
+    }
+    Ok(r)
+}
+
+fn field(bits: &mut Bits) -> io::Result<(u8, u16)> {
+    let t = bits.get(2)? as u8;
+    let v = bits.get_word()?;
+    Ok((t, v))
+}
+
+// Decode a relocatable object file or library with the first module's program area placed at base. Later modules
+// follow the previous module's data and common areas. Externals are resolved against the entry points of all
+// modules in the file.
+pub fn decode(data: &[u8], base: u16) -> io::Result<Image> {
+    let mut bits = Bits { data, pos: 0 };
+    let mut ld = Loader {
+        mem: vec![0; 0x10000],
+        used: vec![false; 0x10000],
+        entries: vec![],
+        chains: vec![],
+        offsets: vec![],
+        start: None,
+        commons: HashMap::new(),
+        pending: vec![],
+        top: 0,
+    };
+    let mut m = Module { program: base, segment: SEGMENT_PROGRAM, ..Default::default() };
+    m.data = base;
+    loop {
+        if bits.pos >= data.len() * 8 {
+            break;
+        }
+        if bits.get(1)? == 0 {
+            let v = bits.get(8)? as u8;
+            ld.set(m.here(), v);
+            m.location = m.location.wrapping_add(1);
+            continue;
+        }
+        let t = bits.get(2)? as u8;
+        if t != 0 {
+            let v = bits.get_word()?;
+            ld.set_word(m.here(), m.relocate(t, v));
+            m.location = m.location.wrapping_add(2);
+            continue;
+        }
+        let control = bits.get(4)? as u8;
+        match control {
+            LINK_ENTRY_SYMBOL | LINK_PROGRAM_NAME | LINK_LIBRARY_SEARCH | LINK_EXTENSION => {
+                name(&mut bits)?;
+            }
+            LINK_SELECT_COMMON => {
+                let n = name(&mut bits)?;
+                ld.allocate(&m);
+                m.common = *ld.commons.get(&n).ok_or_else(|| invalid("undefined common block"))?;
+            }
+            LINK_COMMON_SIZE => {
+                let (_, v) = field(&mut bits)?;
+                let n = name(&mut bits)?;
+                // M80 declares them ahead of the program and data sizes.
+                ld.pending.push((n, v));
+            }
+            LINK_CHAIN_EXTERNAL => {
+                let (t, v) = field(&mut bits)?;
+                let n = name(&mut bits)?;
+                ld.chains.push((n, m.relocate(t, v)));
+            }
+            LINK_ENTRY_POINT => {
+                let (t, v) = field(&mut bits)?;
+                let n = name(&mut bits)?;
+                ld.entries.push((n, m.relocate(t, v)));
+            }
+            LINK_EXTERNAL_MINUS_OFFSET => {
+                let (t, v) = field(&mut bits)?;
+                ld.offsets.push((m.here(), m.relocate(t, v).wrapping_neg()));
+            }
+            LINK_EXTERNAL_PLUS_OFFSET => {
+                let (t, v) = field(&mut bits)?;
+                ld.offsets.push((m.here(), m.relocate(t, v)));
+            }
+            LINK_DATA_SIZE => {
+                let (_, v) = field(&mut bits)?;
+                m.data_size = v;
+            }
+            LINK_LOCATION_COUNTER => {
+                let (t, v) = field(&mut bits)?;
+                m.segment = t;
+                m.location = v;
+            }
+            LINK_CHAIN_ADDRESS => {
+                let (t, v) = field(&mut bits)?;
+                let here = m.here();
+                ld.resolve(m.relocate(t, v), here)?;
+            }
+            LINK_PROGRAM_SIZE => {
+                let (_, v) = field(&mut bits)?;
+                m.data = m.program.wrapping_add(v);
+            }
+            LINK_END_PROGRAM => {
+                let (t, v) = field(&mut bits)?;
+                if ld.start.is_none() && (t != SEGMENT_ABSOLUTE || v != 0) {
+                    ld.start = Some(m.relocate(t, v));
+                }
+                bits.align();
+                ld.allocate(&m);
+                let next = m.end(ld.top);
+                m = Module { program: next, data: next, segment: SEGMENT_PROGRAM, ..Default::default() };
+            }
+            LINK_END_FILE => break,
+            _ => unreachable!(),
+        }
+    }
+    for (n, head) in ld.chains.clone() {
+        let v = ld.entries.iter().find(|e| e.0 == n).map(|e| e.1);
+        let v = v.ok_or_else(|| invalid(&format!("undefined external {}", n)))?;
+        ld.resolve(head, v)?;
+    }
+    for (a, v) in ld.offsets.clone() {
+        let w = ld.get_word(a).wrapping_add(v);
+        ld.set_word(a, w);
+    }
+    let mut image = Image { entry: ld.start.unwrap_or(base), symbols: ld.entries.clone(), ..Default::default() };
+    let mut a = 0;
+    while a < 0x10000 {
+        if !ld.used[a] {
+            a += 1;
+            continue;
+        }
+        let b = (a..0x10000).find(|e| !ld.used[*e]).unwrap_or(0x10000);
+        image.data.push((a as u16, ld.mem[a..b].to_vec()));
+        a = b;
+    }
+    Ok(image)
+}
@@ -0,0 +1,478 @@
+// Record and replay a run. Everything coming into the machine from outside goes into a log with the cycle count it
+// came at: the value of every IN instruction and every interrupt taken, the bytes a device put in memory, such as a
+// disk controller reading a sector, and the value and memory writes of the calls a host makes for the program, such
+// as a trapped BDOS call reading the console or host files. With the snapshot of the machine the recording started
+// from, the log runs the program again bit for bit, whatever the front end does meanwhile: IN reads from the log
+// instead of the device, interrupts are taken at their cycle, those asked by the front end ignored, and the memory
+// and host calls come from the log.
+//
+// A hash of the registers and memory is logged every interval of cycles. Replaying with verify compares them, so a
+// replay that went its own way, because the emulator changed or the log does not belong to the program, is caught near
+// where it happened rather than at the end.
+//
+// The file is MAGIC, the interval and the cycles at the end of the recording, the snapshot, the number of events and
+// the events, all little-endian.
+use super::cpu::Cpu;
+use super::memory::Memory;
+use super::register::Register;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: &[u8; 8] = b"I8080RP\x01";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub reg: Register,
+    pub halted: bool,
+    pub inte: bool,
+    pub cycles: u64,
+    // The 64K of the address space.
+    pub mem: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn take(cpu: &Cpu) -> Self {
+        let mem = cpu.mem.borrow();
+        Self {
+            reg: cpu.reg,
+            halted: cpu.halted,
+            inte: cpu.inte,
+            cycles: cpu.cycles,
+            mem: (0..=0xffff).map(|a| mem.get(a)).collect(),
+        }
+    }
+
+    // Put the machine back in the state. Bytes the memory does not let be written, such as ROM, are left alone. The
+    // blocks the cache decoded, and the JIT compiled from them, are dropped with the memory they came from.
+    pub fn restore(&self, cpu: &mut Cpu) {
+        let mut mem = cpu.mem.borrow_mut();
+        for (a, e) in self.mem.iter().enumerate() {
+            mem.set(a as u16, *e);
+        }
+        drop(mem);
+        if let Some(c) = &mut cpu.cache {
+            c.clear();
+        }
+        cpu.reg = self.reg;
+        cpu.halted = self.halted;
+        cpu.inte = self.inte;
+        cpu.cycles = self.cycles;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    // An IN instruction starting at the cycle read the value from the port.
+    Input { cycles: u64, port: u8, value: u8 },
+    // An interrupt was taken at the cycle, calling the address.
+    Interrupt { cycles: u64, addr: u16 },
+    // Hash of the state at the first instruction at or after the cycle.
+    Hash { cycles: u64, hash: u64 },
+    // Bytes put in memory from outside the cpu at the cycle.
+    Write { cycles: u64, addr: u16, data: Vec<u8> },
+    // A call the host made for the program at the cycle returned the value.
+    Call { cycles: u64, value: u16 },
+}
+
+impl Event {
+    pub fn cycles(&self) -> u64 {
+        match self {
+            Event::Input { cycles, .. }
+            | Event::Interrupt { cycles, .. }
+            | Event::Hash { cycles, .. }
+            | Event::Write { cycles, .. }
+            | Event::Call { cycles, .. } => *cycles,
+        }
+    }
+}
+
+// The value of a host call and the memory it wrote.
+pub type Host = (u16, Vec<(u16, Vec<u8>)>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Log {
+    pub snapshot: Snapshot,
+    pub events: Vec<Event>,
+    // Cycles between two hashes, 0 for none.
+    pub interval: u64,
+    // Cycles when the recording stopped.
+    pub end: u64,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("replay: {}", msg))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+impl Log {
+    pub fn save(&self, w: &mut impl Write) -> io::Result<()> {
+        let s = &self.snapshot;
+        let r = &s.reg;
+        let mut b = MAGIC.to_vec();
+        b.extend(self.interval.to_le_bytes());
+        b.extend(self.end.to_le_bytes());
+        b.extend([r.a, r.flags(), r.b, r.c, r.d, r.e, r.h, r.l]);
+        b.extend(r.sp.to_le_bytes());
+        b.extend(r.pc.to_le_bytes());
+        b.extend([u8::from(s.halted), u8::from(s.inte)]);
+        b.extend(s.cycles.to_le_bytes());
+        b.extend(&s.mem);
+        b.extend((self.events.len() as u64).to_le_bytes());
+        for e in &self.events {
+            match e {
+                Event::Input { cycles, port, value } => {
+                    b.push(0);
+                    b.extend(cycles.to_le_bytes());
+                    b.extend([*port, *value]);
+                }
+                Event::Interrupt { cycles, addr } => {
+                    b.push(1);
+                    b.extend(cycles.to_le_bytes());
+                    b.extend(addr.to_le_bytes());
+                }
+                Event::Hash { cycles, hash } => {
+                    b.push(2);
+                    b.extend(cycles.to_le_bytes());
+                    b.extend(hash.to_le_bytes());
+                }
+                Event::Write { cycles, addr, data } => {
+                    b.push(3);
+                    b.extend(cycles.to_le_bytes());
+                    b.extend(addr.to_le_bytes());
+                    b.extend((data.len() as u64).to_le_bytes());
+                    b.extend(data);
+                }
+                Event::Call { cycles, value } => {
+                    b.push(4);
+                    b.extend(cycles.to_le_bytes());
+                    b.extend(value.to_le_bytes());
+                }
+            }
+        }
+        w.write_all(&b)
+    }
+
+    pub fn load(r: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a replay log"));
+        }
+        let interval = read_u64(r)?;
+        let end = read_u64(r)?;
+        let mut b = [0u8; 14];
+        r.read_exact(&mut b)?;
+        let reg = Register {
+            a: b[0],
+            f: b[1],
+            b: b[2],
+            c: b[3],
+            d: b[4],
+            e: b[5],
+            h: b[6],
+            l: b[7],
+            sp: u16::from_le_bytes([b[8], b[9]]),
+            pc: u16::from_le_bytes([b[10], b[11]]),
+            ..Default::default()
+        };
+        let (halted, inte) = (b[12] != 0, b[13] != 0);
+        let cycles = read_u64(r)?;
+        let mut mem = vec![0u8; 0x10000];
+        r.read_exact(&mut mem)?;
+        let n = read_u64(r)?;
+        let mut events = vec![];
+        for _ in 0..n {
+            let mut tag = [0u8; 1];
+            r.read_exact(&mut tag)?;
+            let cycles = read_u64(r)?;
+            events.push(match tag[0] {
+                0 => {
+                    let mut b = [0u8; 2];
+                    r.read_exact(&mut b)?;
+                    Event::Input { cycles, port: b[0], value: b[1] }
+                }
+                1 => {
+                    let mut b = [0u8; 2];
+                    r.read_exact(&mut b)?;
+                    Event::Interrupt { cycles, addr: u16::from_le_bytes(b) }
+                }
+                2 => Event::Hash { cycles, hash: read_u64(r)? },
+                3 => {
+                    let mut b = [0u8; 2];
+                    r.read_exact(&mut b)?;
+                    let n = read_u64(r)?;
+                    if n > 0x10000 {
+                        return Err(invalid("bad write"));
+                    }
+                    let mut data = vec![0u8; n as usize];
+                    r.read_exact(&mut data)?;
+                    Event::Write { cycles, addr: u16::from_le_bytes(b), data }
+                }
+                4 => {
+                    let mut b = [0u8; 2];
+                    r.read_exact(&mut b)?;
+                    Event::Call { cycles, value: u16::from_le_bytes(b) }
+                }
+                _ => return Err(invalid("bad event")),
+            });
+        }
+        Ok(Self { snapshot: Snapshot { reg, halted, inte, cycles, mem }, events, interval, end })
+    }
+}
+
+// Memory noting the bytes written to it, for the journal.
+pub(crate) struct Noted<'a> {
+    pub mem: &'a mut dyn Memory,
+    pub writes: Vec<(u16, u8)>,
+}
+
+impl Memory for Noted<'_> {
+    fn get(&self, a: u16) -> u8 {
+        self.mem.get(a)
+    }
+
+    fn set(&mut self, a: u16, v: u8) {
+        self.writes.push((a, v));
+        self.mem.set(a, v)
+    }
+}
+
+impl Noted<'_> {
+    // The writes as runs of consecutive addresses, in order.
+    pub fn runs(&self) -> Vec<(u16, Vec<u8>)> {
+        let mut r: Vec<(u16, Vec<u8>)> = vec![];
+        for &(a, v) in &self.writes {
+            match r.last_mut() {
+                Some((start, data)) if start.wrapping_add(data.len() as u16) == a && data.len() < 0x10000 => {
+                    data.push(v)
+                }
+                _ => r.push((a, vec![v])),
+            }
+        }
+        r
+    }
+}
+
+// Put runs of bytes in memory.
+pub(crate) fn put(mem: &mut dyn Memory, writes: &[(u16, Vec<u8>)]) {
+    for (addr, data) in writes {
+        for (i, v) in data.iter().enumerate() {
+            mem.set(addr.wrapping_add(i as u16), *v);
+        }
+    }
+}
+
+// FNV-1a of the registers, the halted and inte flags and the memory.
+pub fn hash(reg: &Register, halted: bool, inte: bool, mem: &dyn Memory) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |b: u8| h = (h ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3);
+    for b in [reg.a, reg.flags(), reg.b, reg.c, reg.d, reg.e, reg.h, reg.l] {
+        add(b);
+    }
+    for b in reg.sp.to_le_bytes().into_iter().chain(reg.pc.to_le_bytes()) {
+        add(b);
+    }
+    add(u8::from(halted));
+    add(u8::from(inte));
+    for a in 0..=0xffff {
+        add(mem.get(a));
+    }
+    h
+}
+
+// Where a replay left the log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Desync {
+    pub cycles: u64,
+    // Index of the event in the log.
+    pub index: usize,
+    pub reason: String,
+}
+
+// A recording or a replay in progress, set on the cpu.
+pub struct Journal {
+    pub log: Log,
+    // Compare the hashes while replaying.
+    pub verify: bool,
+    // Set when the replay went its own way. The journal is out of the way from then on.
+    pub desync: Option<Desync>,
+    replaying: bool,
+    // Next event to replay.
+    cursor: usize,
+    // Cycles of the next hash to record.
+    next_hash: u64,
+}
+
+impl Journal {
+    // Start recording the cpu from its current state, with a hash every interval cycles.
+    pub fn record(cpu: &Cpu, interval: u64) -> Self {
+        let snapshot = Snapshot::take(cpu);
+        let next_hash = snapshot.cycles;
+        Self {
+            log: Log { snapshot, events: vec![], interval, end: 0 },
+            verify: false,
+            desync: None,
+            replaying: false,
+            cursor: 0,
+            next_hash,
+        }
+    }
+
+    // Put the cpu in the state the log starts from and replay it.
+    pub fn replay(cpu: &mut Cpu, log: Log, verify: bool) -> Self {
+        log.snapshot.restore(cpu);
+        Self { log, verify, desync: None, replaying: true, cursor: 0, next_hash: 0 }
+    }
+
+    pub fn replaying(&self) -> bool {
+        self.replaying
+    }
+
+    // True once a replay reached the end of the recording, or left it.
+    pub fn done(&self, cycles: u64) -> bool {
+        self.replaying && (self.desync.is_some() || cycles >= self.log.end)
+    }
+
+    // The log of a recording, ending at the cycles given.
+    pub fn finish(mut self, cycles: u64) -> Log {
+        if !self.replaying {
+            self.log.end = cycles;
+        }
+        self.log
+    }
+
+    // Replaying, and still on the log.
+    fn active(&self) -> bool {
+        self.replaying && self.desync.is_none()
+    }
+
+    fn desync(&mut self, cycles: u64, reason: String) {
+        self.desync = Some(Desync { cycles, index: self.cursor, reason });
+    }
+
+    // Called before each instruction: records or checks the hash when it is due, and gives the interrupt a replay
+    // takes now, if any.
+    pub(crate) fn before(
+        &mut self,
+        cycles: u64,
+        reg: &Register,
+        halted: bool,
+        inte: bool,
+        mem: &dyn Memory,
+    ) -> Option<u16> {
+        if !self.replaying {
+            if self.log.interval != 0 && cycles >= self.next_hash {
+                let hash = hash(reg, halted, inte, mem);
+                self.log.events.push(Event::Hash { cycles, hash });
+                self.next_hash = cycles + self.log.interval;
+            }
+            return None;
+        }
+        while self.active() {
+            match self.log.events.get(self.cursor).cloned() {
+                Some(Event::Interrupt { cycles: c, addr }) if c <= cycles => {
+                    self.cursor += 1;
+                    if c != cycles {
+                        self.desync(cycles, format!("interrupt expected at cycle {}", c));
+                        return None;
+                    }
+                    return Some(addr);
+                }
+                Some(Event::Hash { cycles: c, hash: h }) if c <= cycles => {
+                    if self.verify && (c != cycles || hash(reg, halted, inte, mem) != h) {
+                        self.desync(cycles, format!("state differs from the hash at cycle {}", c));
+                        return None;
+                    }
+                    self.cursor += 1;
+                }
+                Some(e) if e.cycles() < cycles => {
+                    self.desync(cycles, format!("{:?} left behind", e));
+                    return None;
+                }
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    // The value an IN instruction reads: the one the device gives when recording, the logged one when replaying.
+    pub(crate) fn input(&mut self, cycles: u64, port: u8, device: impl FnOnce() -> u8) -> u8 {
+        if !self.replaying {
+            let value = device();
+            self.log.events.push(Event::Input { cycles, port, value });
+            return value;
+        }
+        if !self.active() {
+            return device();
+        }
+        match self.log.events.get(self.cursor).cloned() {
+            Some(Event::Input { cycles: c, port: p, value }) if c == cycles && p == port => {
+                self.cursor += 1;
+                value
+            }
+            e => {
+                self.desync(cycles, format!("IN {:02x} where the log has {:?}", port, e));
+                device()
+            }
+        }
+    }
+
+    // Bytes a device put in memory at the cycle, as (address, data) runs, which the caller puts in memory again after
+    // this. Recorded, or when replaying, the logged ones are given in their place.
+    pub(crate) fn writes(&mut self, cycles: u64, done: Vec<(u16, Vec<u8>)>) -> Vec<(u16, Vec<u8>)> {
+        if !self.replaying {
+            for (addr, data) in &done {
+                self.log.events.push(Event::Write { cycles, addr: *addr, data: data.clone() });
+            }
+            return done;
+        }
+        if !self.active() {
+            return done;
+        }
+        let mut r = vec![];
+        while let Some(Event::Write { cycles: c, addr, data }) = self.log.events.get(self.cursor) {
+            if *c != cycles {
+                break;
+            }
+            r.push((*addr, data.clone()));
+            self.cursor += 1;
+        }
+        r
+    }
+
+    // A call the host makes for the program at the cycle. Recording, run makes it and gives its value and the memory
+    // it wrote, which are logged; replaying, the logged ones are given instead. The caller puts the writes in memory.
+    pub(crate) fn call(&mut self, cycles: u64, run: impl FnOnce() -> Host) -> Host {
+        if !self.replaying {
+            let (value, writes) = run();
+            self.log.events.push(Event::Call { cycles, value });
+            return (value, self.writes(cycles, writes));
+        }
+        if !self.active() {
+            return run();
+        }
+        match self.log.events.get(self.cursor).cloned() {
+            Some(Event::Call { cycles: c, value }) if c == cycles => {
+                self.cursor += 1;
+                (value, self.writes(cycles, vec![]))
+            }
+            e => {
+                self.desync(cycles, format!("host call where the log has {:?}", e));
+                run()
+            }
+        }
+    }
+
+    // An interrupt the front end asks for. Recorded, or left out when replaying, as the log gives them.
+    pub(crate) fn interrupt(&mut self, cycles: u64, addr: u16) -> bool {
+        if !self.replaying {
+            self.log.events.push(Event::Interrupt { cycles, addr });
+            return true;
+        }
+        !self.active()
+    }
+}
@@ -0,0 +1,167 @@
+// Per-instruction test vectors, as published in JSON by the SingleStepTests suites. A file holds an array of tests,
+// each running one instruction from a given state:
+//
+//   {
+//     "name": "80 0000",
+//     "initial": {"pc": 0, "sp": 65535, "a": 1, "b": 2, "c": 0, "d": 0, "e": 0, "f": 2, "h": 0, "l": 0,
+//                 "ram": [[0, 128]]},
+//     "final": {"pc": 1, "sp": 65535, "a": 3, ...},
+//     "cycles": [[0, 128, "r--m"], [1, null, "----"], [1, null, "----"], [1, null, "----"]],
+//     "ports": [[16, 35, "r"]]
+//   }
+//
+// The ram lists the bytes set before the instruction and checked after it, as address and value pairs. The cycles are
+// the bus activity of each clock, of which only the count is checked; a plain number is taken too. The ports hold the
+// values IN reads and OUT must write, in order. An optional inte sets and checks the interrupt enable.
+use super::cpu::Cpu;
+use super::device::Device;
+use super::memory::{Linear, Memory};
+use super::register::Register;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct State {
+    pub reg: Register,
+    pub inte: Option<bool>,
+    pub ram: Vec<(u16, u8)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Test {
+    pub name: String,
+    pub initial: State,
+    pub expected: State,
+    pub cycles: Option<u32>,
+    // Port, value and whether the instruction writes it.
+    pub ports: Vec<(u8, u8, bool)>,
+}
+
+fn invalid(name: &str, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("singlestep: {}: {}", name, msg))
+}
+
+fn number(v: &Value, name: &str, field: &str, max: u64) -> io::Result<u64> {
+    v.as_u64().filter(|e| *e <= max).ok_or_else(|| invalid(name, &format!("bad {}", field)))
+}
+
+fn state(v: &Value, name: &str) -> io::Result<State> {
+    let field = |k: &str, max| number(v.get(k).unwrap_or(&Value::Null), name, k, max);
+    let reg = Register {
+        a: field("a", 0xff)? as u8,
+        f: field("f", 0xff)? as u8,
+        b: field("b", 0xff)? as u8,
+        c: field("c", 0xff)? as u8,
+        d: field("d", 0xff)? as u8,
+        e: field("e", 0xff)? as u8,
+        h: field("h", 0xff)? as u8,
+        l: field("l", 0xff)? as u8,
+        sp: field("sp", 0xffff)? as u16,
+        pc: field("pc", 0xffff)? as u16,
+        ..Default::default()
+    };
+    let inte = match v.get("inte") {
+        None => None,
+        Some(e) => Some(e.as_bool().or_else(|| e.as_u64().map(|e| e != 0)).ok_or_else(|| invalid(name, "bad inte"))?),
+    };
+    let mut ram = vec![];
+    for e in v.get("ram").and_then(Value::as_array).map_or(&[][..], |e| e) {
+        match e.as_array().map(|e| e.as_slice()) {
+            Some([a, v]) => ram.push((number(a, name, "ram", 0xffff)? as u16, number(v, name, "ram", 0xff)? as u8)),
+            _ => return Err(invalid(name, "bad ram")),
+        }
+    }
+    Ok(State { reg, inte, ram })
+}
+
+fn test(v: &Value) -> io::Result<Test> {
+    let name = v.get("name").and_then(Value::as_str).unwrap_or("?").to_string();
+    let initial = state(v.get("initial").ok_or_else(|| invalid(&name, "no initial state"))?, &name)?;
+    let expected = state(v.get("final").ok_or_else(|| invalid(&name, "no final state"))?, &name)?;
+    let cycles = match v.get("cycles") {
+        None => None,
+        Some(Value::Array(e)) => Some(e.len() as u32),
+        Some(e) => Some(number(e, &name, "cycles", u64::from(u32::MAX))? as u32),
+    };
+    let mut ports = vec![];
+    for e in v.get("ports").and_then(Value::as_array).map_or(&[][..], |e| e) {
+        match e.as_array().map(|e| e.as_slice()) {
+            Some([p, v, d]) => ports.push((
+                number(p, &name, "port", 0xff)? as u8,
+                number(v, &name, "port", 0xff)? as u8,
+                d.as_str().ok_or_else(|| invalid(&name, "bad port"))?.starts_with('w'),
+            )),
+            _ => return Err(invalid(&name, "bad port")),
+        }
+    }
+    Ok(Test { name, initial, expected, cycles, ports })
+}
+
+// The tests of a file's text.
+pub fn parse(text: &str) -> io::Result<Vec<Test>> {
+    let v: Value = serde_json::from_str(text).map_err(|e| invalid("json", &e.to_string()))?;
+    v.as_array().ok_or_else(|| invalid("json", "not an array of tests"))?.iter().map(test).collect()
+}
+
+pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<Test>> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+// Answers IN with the values of the test and keeps what OUT writes.
+#[derive(Default)]
+struct Ports {
+    input: VecDeque<u8>,
+    output: Vec<(u8, u8)>,
+}
+
+impl Device for Ports {
+    fn get(&mut self, _: u8) -> u8 {
+        self.input.pop_front().unwrap_or(0xff)
+    }
+
+    fn set(&mut self, port: u8, v: u8) {
+        self.output.push((port, v))
+    }
+}
+
+impl Test {
+    // Run the instruction and give what differs from the final state, such as "a expected 12 got 13". Nothing when
+    // the test passes.
+    pub fn run(&self) -> Vec<String> {
+        let mut mem = Linear::new();
+        for &(a, v) in &self.initial.ram {
+            mem.set(a, v);
+        }
+        let mem = Rc::new(RefCell::new(mem));
+        let ports = Rc::new(RefCell::new(Ports::default()));
+        ports.borrow_mut().input = self.ports.iter().filter(|e| !e.2).map(|e| e.1).collect();
+        let mut cpu = Cpu::power_up(mem.clone());
+        cpu.dev = Some(ports.clone());
+        cpu.reg = self.initial.reg;
+        cpu.inte = self.initial.inte.unwrap_or(false);
+        let cycles = cpu.next();
+
+        let mut r = cpu.reg.differences(&self.expected.reg);
+        if let Some(e) = self.expected.inte.filter(|e| *e != cpu.inte) {
+            r.push(format!("inte expected {} got {}", e, cpu.inte));
+        }
+        for &(a, e) in &self.expected.ram {
+            let v = mem.borrow().get(a);
+            if v != e {
+                r.push(format!("ram {:04x} expected {:02x} got {:02x}", a, e, v));
+            }
+        }
+        if let Some(e) = self.cycles.filter(|e| *e != cycles) {
+            r.push(format!("cycles expected {} got {}", e, cycles));
+        }
+        let output: Vec<(u8, u8)> = self.ports.iter().filter(|e| e.2).map(|e| (e.0, e.1)).collect();
+        if output != ports.borrow().output {
+            r.push(format!("ports expected {:02x?} got {:02x?}", output, ports.borrow().output));
+        }
+        r
+    }
+}
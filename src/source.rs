@@ -0,0 +1,142 @@
+// Source lines of a program, read from the assembler listings it was built with. Each line of code in a listing maps
+// the addresses of its bytes to a line of the source file, so a pc can be shown as "TST8080:120" with the text of the
+// line, and a "file:line" turned back into an address.
+//
+// Files are named by the stem of the listing path and matched without regard to case or extension, so TST8080:120,
+// tst8080.asm:120 and tst8080.prn:120 are the same line. Line numbers are those of the source file: page headers M80
+// starts with a form feed, and the lines holding the rest of the bytes of a long DB, are not counted.
+use super::prn;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default)]
+pub struct File {
+    pub name: String,
+    // Text of the source lines, the first at index 0.
+    pub lines: Vec<String>,
+}
+
+// A line of a source file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Loc {
+    // Index in Sources::files.
+    pub file: usize,
+    // Line number, from 1.
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    pub files: Vec<File>,
+    // Start address of each line of code, with the line and its length in bytes.
+    addrs: BTreeMap<u16, (Loc, u16)>,
+    // First address of each line of code.
+    lines: BTreeMap<Loc, u16>,
+}
+
+// The stem of a file name, upper case: "./res/TST8080.prn" is TST8080.
+fn stem(s: &str) -> String {
+    let p = Path::new(s);
+    p.file_stem().and_then(|e| e.to_str()).unwrap_or(s).to_ascii_uppercase()
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // Add the listing of a source file.
+    pub fn add(&mut self, name: &str, listing: &str) {
+        let file = self.files.len();
+        let mut lines = vec![];
+        let mut header = false;
+        let mut last = None;
+        // Start of the last line of code, which spilled bytes belong to.
+        let mut start = None;
+        let raw: Vec<&str> = listing.lines().collect();
+        for e in prn::parse(listing) {
+            // Page headers run from the form feed to the first blank line.
+            if raw[e.number - 1].contains('\x0c') {
+                header = true;
+            }
+            if header {
+                header = !e.source.is_empty();
+                continue;
+            }
+            let spill = e.source.is_empty() && !e.data.is_empty() && last.is_some() && last == e.addr;
+            last = e.addr.map(|a| a.wrapping_add(e.data.len() as u16));
+            if spill {
+                if let (Some(a), Some(end)) = (start, last) {
+                    self.addrs.get_mut(&a).unwrap().1 = end.wrapping_sub(a);
+                }
+                continue;
+            }
+            lines.push(e.source.clone());
+            let loc = Loc { file, line: lines.len() };
+            start = None;
+            if let (Some(a), false) = (e.addr, e.data.is_empty()) {
+                self.addrs.insert(a, (loc, e.data.len() as u16));
+                self.lines.entry(loc).or_insert(a);
+                start = Some(a);
+            }
+        }
+        self.files.push(File { name: stem(name), lines });
+    }
+
+    // Read a listing, named after its path.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let p = path.as_ref();
+        let text = String::from_utf8_lossy(&fs::read(p)?).into_owned();
+        self.add(&p.to_string_lossy(), &text);
+        Ok(())
+    }
+
+    // The source line holding the byte at an address.
+    pub fn find(&self, addr: u16) -> Option<Loc> {
+        let (a, (loc, n)) = self.addrs.range(..=addr).next_back()?;
+        (addr - a < *n).then_some(*loc)
+    }
+
+    // True when a line of code starts at the address.
+    pub fn is_start(&self, addr: u16) -> bool {
+        self.addrs.contains_key(&addr)
+    }
+
+    // The file of a name.
+    pub fn file(&self, name: &str) -> Option<usize> {
+        let name = stem(name);
+        self.files.iter().position(|e| e.name == name)
+    }
+
+    // A line given as "file:line", or as ":line" or "line" in the only file there is.
+    pub fn parse(&self, s: &str) -> Option<Loc> {
+        let (f, l) = s.rsplit_once(':').unwrap_or(("", s));
+        let file = match f {
+            "" if self.files.len() == 1 => 0,
+            _ => self.file(f)?,
+        };
+        let line = l.parse().ok().filter(|e| *e != 0)?;
+        Some(Loc { file, line })
+    }
+
+    // Address of the code of a line, or of the first line of code after it in the same file.
+    pub fn address(&self, loc: Loc) -> Option<u16> {
+        self.lines.range(loc..Loc { file: loc.file + 1, line: 0 }).next().map(|e| *e.1)
+    }
+
+    // Text of a line.
+    pub fn text(&self, loc: Loc) -> &str {
+        self.files.get(loc.file).and_then(|e| e.lines.get(loc.line - 1)).map_or("", |e| e.as_str())
+    }
+
+    // "TST8080:120".
+    pub fn describe(&self, loc: Loc) -> String {
+        format!("{}:{}", self.files[loc.file].name, loc.line)
+    }
+}
@@ -0,0 +1,170 @@
+// Symbol tables, naming addresses in disassembly, traces, profiles and the debugger, and turning names back into
+// addresses. Three sources are read:
+//
+//   .SYM   LINK-80 and L80 symbol files: "0100 START" pairs, several to a line, ended by ^Z
+//   .PRN   assembler listings: the labels of the source lines and the symbol table M80 appends to the listing
+//   text   one "address name" pair a line, with ; or # starting a comment
+//
+// Names are kept as given and looked up without regard to case, as CP/M tools fold them to upper case.
+use super::prn;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    // The first name given to each address.
+    names: BTreeMap<u16, String>,
+    addrs: HashMap<String, u16>,
+}
+
+fn hex(s: &str) -> Option<u16> {
+    let s = s.trim_end_matches(['\'', '"', '!', '*']);
+    if s.is_empty() || s.len() > 4 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || "_?@$.".contains(c))
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || "_?@$.".contains(c))
+}
+
+// A number as written in the debugger or an assembler: 1234, 1234h, 0x1234, $1234 in hexadecimal, #4660 in decimal.
+pub fn number(s: &str) -> Option<u16> {
+    if let Some(d) = s.strip_prefix('#') {
+        return d.parse().ok();
+    }
+    let h = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')).or_else(|| s.strip_suffix(['h', 'H'])).unwrap_or(s);
+    u16::from_str_radix(h, 16).ok()
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        self.names.entry(addr).or_insert_with(|| name.to_string());
+        self.addrs.insert(name.to_ascii_uppercase(), addr);
+    }
+
+    pub fn extend<'a>(&mut self, iter: impl IntoIterator<Item = &'a (String, u16)>) {
+        for (n, a) in iter {
+            self.insert(n, *a);
+        }
+    }
+
+    // Address of a name.
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.addrs.get(&name.to_ascii_uppercase()).copied()
+    }
+
+    // Name of an address.
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(|e| e.as_str())
+    }
+
+    // Nearest name at or below an address, with the offset from it: "PRINT" or "PRINT+0x3".
+    pub fn label(&self, addr: u16) -> Option<String> {
+        self.names.range(..=addr).next_back().map(|(a, s)| match addr - a {
+            0 => s.clone(),
+            n => format!("{}+0x{:x}", s, n),
+        })
+    }
+
+    // Name of an address, or the address in hexadecimal.
+    pub fn describe(&self, addr: u16) -> String {
+        self.label(addr).unwrap_or_else(|| format!("0x{:04x}", addr))
+    }
+
+    // An address given as a number, a name, or a name with an offset such as PRINT+3.
+    pub fn resolve(&self, s: &str) -> Option<u16> {
+        let s = s.trim();
+        if let Some(i) = s.rfind(['+', '-']).filter(|e| *e > 0) {
+            let base = self.resolve(&s[..i])?;
+            let off = number(s[i + 1..].trim())?;
+            return Some(if &s[i..i + 1] == "+" { base.wrapping_add(off) } else { base.wrapping_sub(off) });
+        }
+        self.get(s).or_else(|| number(s))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(a, n)| (*a, n.as_str()))
+    }
+
+    // "address name" pairs, any number to a line.
+    pub fn parse_sym(text: &str) -> Self {
+        let mut r = Self::new();
+        let text = text.split('\x1a').next().unwrap_or("");
+        let toks: Vec<&str> = text.split_whitespace().collect();
+        let mut i = 0;
+        while i + 1 < toks.len() {
+            match hex(toks[i]) {
+                Some(a) if is_name(toks[i + 1]) => {
+                    r.insert(toks[i + 1], a);
+                    i += 2;
+                }
+                _ => i += 1,
+            }
+        }
+        r
+    }
+
+    // One "address name" pair a line.
+    pub fn parse_text(text: &str) -> Self {
+        let mut r = Self::new();
+        for line in text.lines() {
+            let line = line.split([';', '#']).next().unwrap_or("");
+            let mut f = line.split_whitespace();
+            if let (Some(a), Some(n)) = (f.next(), f.next()) {
+                if let (Some(a), true) = (number(a), is_name(n)) {
+                    r.insert(n, a);
+                }
+            }
+        }
+        r
+    }
+
+    // Labels of an assembler listing, and the symbol table at its end when there is one.
+    pub fn parse_prn(text: &str) -> Self {
+        let mut r = Self::new();
+        let lines = prn::parse(text);
+        for e in &lines {
+            if let (Some(a), Some(l)) = (e.addr, e.label()) {
+                r.insert(l, a);
+            }
+        }
+        // M80 lists the symbols after a "Symbols:" line, as "0103'  NEXT" pairs.
+        if let Some(i) = text.find("Symbols:") {
+            for (a, n) in Self::parse_sym(&text[i + 8..]).iter() {
+                if r.get(n).is_none() {
+                    r.insert(n, a);
+                }
+            }
+        }
+        r
+    }
+
+    // Read a symbol file, choosing the format by the extension.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let p = path.as_ref();
+        let text = String::from_utf8_lossy(&fs::read(p)?).into_owned();
+        let ext = p.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        Ok(match ext.as_str() {
+            "sym" => Self::parse_sym(&text),
+            "prn" | "lst" => Self::parse_prn(&text),
+            _ => Self::parse_text(&text),
+        })
+    }
+}
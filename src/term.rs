@@ -0,0 +1,312 @@
+// Bitmap video drawn on an ANSI terminal, for playing and inspecting machines over SSH. Pixels are packed into Unicode
+// half blocks, two pixels a cell in 24 bit color, or braille patterns, eight pixels a cell in the color of the first
+// lit one. The keyboard is read from stdin in raw mode. Terminals send no key releases, so a key holds its button
+// down for a few frames after the last repeat.
+use super::memory::Memory;
+use super::midway::Button;
+use super::pixmap::Image;
+use std::fmt::Write as _;
+use std::io::{self, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    // ▀ with the upper pixel as foreground and the lower as background: 1x2 pixels a cell.
+    HalfBlock,
+    // U+2800 to U+28FF: 2x4 pixels a cell.
+    Braille,
+}
+
+impl Mode {
+    // Pixels covered by one character cell.
+    pub fn cell(&self) -> (usize, usize) {
+        match self {
+            Mode::HalfBlock => (1, 2),
+            Mode::Braille => (2, 4),
+        }
+    }
+}
+
+// Read a 1 bit per pixel frame buffer with the lowest bit of each byte leftmost, lit pixels in white.
+pub fn bitmap(mem: &dyn Memory, base: u16, width: usize, height: usize) -> Image {
+    let mut r = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let a = base.wrapping_add((y * width / 8 + x / 8) as u16);
+            if mem.get(a) & (1 << (x % 8)) != 0 {
+                r.set(x, y, [0xff; 3]);
+            }
+        }
+    }
+    r
+}
+
+fn lit(img: &Image, x: usize, y: usize) -> bool {
+    x < img.width && y < img.height && img.get(x, y) != [0; 3]
+}
+
+fn pixel(img: &Image, x: usize, y: usize) -> [u8; 3] {
+    if x < img.width && y < img.height {
+        img.get(x, y)
+    } else {
+        [0; 3]
+    }
+}
+
+// Braille dot bit of each pixel of a cell, indexed by [y][x].
+const DOTS: [[u8; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+// Cells of one terminal row: the character with its foreground and background.
+fn cells(img: &Image, mode: Mode, row: usize) -> Vec<(char, [u8; 3], [u8; 3])> {
+    let (cw, ch) = mode.cell();
+    let y = row * ch;
+    (0..img.width.div_ceil(cw))
+        .map(|c| {
+            let x = c * cw;
+            match mode {
+                Mode::HalfBlock => ('▀', pixel(img, x, y), pixel(img, x, y + 1)),
+                Mode::Braille => {
+                    let mut bits = 0u8;
+                    let mut fg = [0; 3];
+                    for (dy, line) in DOTS.iter().enumerate() {
+                        for (dx, dot) in line.iter().enumerate() {
+                            if lit(img, x + dx, y + dy) {
+                                if bits == 0 {
+                                    fg = img.get(x + dx, y + dy);
+                                }
+                                bits |= dot;
+                            }
+                        }
+                    }
+                    (char::from_u32(0x2800 + u32::from(bits)).unwrap(), fg, [0; 3])
+                }
+            }
+        })
+        .collect()
+}
+
+// One terminal row of the image with its color escapes, without cursor movement or line end. Colors are only sent
+// when they change.
+pub fn line(img: &Image, mode: Mode, row: usize) -> String {
+    let mut r = String::new();
+    let mut last: Option<([u8; 3], [u8; 3])> = None;
+    for (c, fg, bg) in cells(img, mode, row) {
+        if last != Some((fg, bg)) {
+            let _ = write!(r, "\x1b[38;2;{};{};{};48;2;{};{};{}m", fg[0], fg[1], fg[2], bg[0], bg[1], bg[2]);
+            last = Some((fg, bg));
+        }
+        r.push(c);
+    }
+    r.push_str("\x1b[0m");
+    r
+}
+
+// Terminal rows needed for the image.
+pub fn rows(img: &Image, mode: Mode) -> usize {
+    img.height.div_ceil(mode.cell().1)
+}
+
+// The whole image from the top left corner of the terminal.
+pub fn render(img: &Image, mode: Mode) -> String {
+    let mut r = String::from("\x1b[H");
+    for row in 0..rows(img, mode) {
+        r.push_str(&line(img, mode, row));
+        r.push_str("\r\n");
+    }
+    r
+}
+
+// Draws frames, sending only the rows that changed since the previous one.
+pub struct Screen {
+    pub mode: Mode,
+    last: Vec<String>,
+}
+
+impl Screen {
+    pub fn new(mode: Mode) -> Self {
+        Self { mode, last: vec![] }
+    }
+
+    // Forget what is on the terminal, so the next frame is drawn in full.
+    pub fn invalidate(&mut self) {
+        self.last.clear();
+    }
+
+    pub fn draw(&mut self, img: &Image) -> String {
+        let n = rows(img, self.mode);
+        let mut r = String::new();
+        if self.last.len() != n {
+            r.push_str("\x1b[2J");
+            self.last = vec![String::new(); n];
+        }
+        for row in 0..n {
+            let s = line(img, self.mode, row);
+            if s != self.last[row] {
+                let _ = write!(r, "\x1b[{};1H{}", row + 1, s);
+                self.last[row] = s;
+            }
+        }
+        r
+    }
+}
+
+// The terminal in raw mode, without echo and with the cursor hidden, until dropped.
+pub struct RawMode {
+    saved: String,
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let out = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+    if !out.status.success() {
+        return Err(io::Error::other("term: stty failed, stdin is not a terminal"));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+impl RawMode {
+    pub fn enter() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        print!("\x1b[?25l\x1b[2J");
+        Ok(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\r\n");
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Char(u8),
+    Up,
+    Down,
+    Right,
+    Left,
+    Enter,
+    Esc,
+}
+
+// Split the bytes read from the terminal into keys. A lone escape is the escape key.
+pub fn parse(data: &[u8]) -> Vec<Key> {
+    let mut r = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            0x1b if data.len() > i + 2 && (data[i + 1] == b'[' || data[i + 1] == b'O') => {
+                // Parameters up to the final byte of the sequence. Sequences other than the arrows are dropped.
+                i += 2;
+                while i + 1 < data.len() && !(0x40..=0x7e).contains(&data[i]) {
+                    i += 1;
+                }
+                match data[i] {
+                    b'A' => r.push(Key::Up),
+                    b'B' => r.push(Key::Down),
+                    b'C' => r.push(Key::Right),
+                    b'D' => r.push(Key::Left),
+                    _ => {}
+                }
+            }
+            0x1b => r.push(Key::Esc),
+            b'\r' | b'\n' => r.push(Key::Enter),
+            c => r.push(Key::Char(c)),
+        }
+        i += 1;
+    }
+    r
+}
+
+// Stdin read on a separate thread, so the keys can be polled once a frame.
+pub struct Keyboard {
+    rx: mpsc::Receiver<Vec<u8>>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            while let Ok(n @ 1..) = io::stdin().read(&mut buf) {
+                if tx.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { rx }
+    }
+
+    // Keys typed since the last call.
+    pub fn poll(&mut self) -> Vec<Key> {
+        let data: Vec<u8> = self.rx.try_iter().flatten().collect();
+        parse(&data)
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Keys bound to machine inputs, with the inputs they hold down.
+pub struct Keymap<T> {
+    pub bindings: Vec<(Key, T)>,
+    // Frames an input stays down after its key was last seen. Longer than the keyboard repeat delay keeps a held
+    // key from flickering.
+    pub hold: u32,
+    down: Vec<(T, u32)>,
+}
+
+impl<T: Copy + PartialEq> Keymap<T> {
+    pub fn new(bindings: Vec<(Key, T)>, hold: u32) -> Self {
+        Self { bindings, hold, down: vec![] }
+    }
+
+    // Advance one frame with the keys typed during it. Returns the inputs to press and the inputs to release.
+    pub fn update(&mut self, keys: &[Key]) -> (Vec<T>, Vec<T>) {
+        let mut press = vec![];
+        for e in &mut self.down {
+            e.1 = e.1.saturating_sub(1);
+        }
+        for k in keys {
+            for &(_, t) in self.bindings.iter().filter(|e| e.0 == *k) {
+                match self.down.iter_mut().find(|e| e.0 == t) {
+                    Some(e) => e.1 = self.hold,
+                    None => {
+                        self.down.push((t, self.hold));
+                        press.push(t);
+                    }
+                }
+            }
+        }
+        let release = self.down.iter().filter(|e| e.1 == 0).map(|e| e.0).collect();
+        self.down.retain(|e| e.1 != 0);
+        (press, release)
+    }
+}
+
+// Keys for the Midway games: arrows and space for player 1, wasd and tab for player 2, c for a coin and 1 or 2 to
+// start.
+pub fn midway_keys() -> Vec<(Key, Button)> {
+    vec![
+        (Key::Char(b'c'), Button::Coin),
+        (Key::Char(b'1'), Button::Start1),
+        (Key::Char(b'2'), Button::Start2),
+        (Key::Char(b' '), Button::Fire1),
+        (Key::Left, Button::Left1),
+        (Key::Right, Button::Right1),
+        (Key::Up, Button::Up1),
+        (Key::Down, Button::Down1),
+        (Key::Char(b'\t'), Button::Fire2),
+        (Key::Char(b'a'), Button::Left2),
+        (Key::Char(b'd'), Button::Right2),
+        (Key::Char(b'w'), Button::Up2),
+        (Key::Char(b's'), Button::Down2),
+        (Key::Char(b't'), Button::Tilt),
+    ]
+}
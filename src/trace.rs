@@ -0,0 +1,370 @@
+// Execution trace. A tracer set on the cpu sees every instruction with its address and bytes, the registers before and
+// after, the memory it read and wrote and the cycles it took. Nothing is decoded or formatted unless the tracer asks.
+//
+// Three sinks write the steps out: text for reading, JSON lines for other tools and a compact binary format that can
+// be read back. The binary trace starts with the 8 byte magic "I8080TR\x01", then one record per step:
+//
+//   pc        u16
+//   len       u8       1 to 3
+//   bytes     len x u8
+//   before    12 bytes a f b c d e h l sp pc, words little endian
+//   after     12 bytes
+//   cycles    u8
+//   total     u64
+//   accesses  u8 count, then for each: addr u16, value u8, 1 for a write or 0 for a read
+//
+// All words are little endian.
+use super::asm;
+use super::register::Register;
+use super::symbol::Symbols;
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+pub const MAGIC: &[u8; 8] = b"I8080TR\x01";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Step {
+    pub pc: u16,
+    // Instruction bytes, of which the first len are used.
+    pub opcode: [u8; 3],
+    pub len: u8,
+    pub before: Register,
+    pub after: Register,
+    // Memory read and written by the instruction, in order, without the instruction fetch.
+    pub accesses: Vec<Access>,
+    pub cycles: u32,
+    // Cycles run since power up, this instruction included.
+    pub total: u64,
+}
+
+impl Step {
+    pub fn bytes(&self) -> &[u8] {
+        &self.opcode[..usize::from(self.len)]
+    }
+
+    pub fn instruction(&self) -> String {
+        asm::disasm(self.bytes())
+    }
+
+    // The instruction with named operands, such as "CALL PRINT".
+    pub fn instruction_with(&self, symbols: &Symbols) -> String {
+        asm::disasm_with(self.bytes(), |w| symbols.name(w).map(String::from))
+    }
+}
+
+pub trait Tracer {
+    fn trace(&mut self, step: &Step);
+}
+
+// A tracer shared with the host, which can look at it while the cpu owns the other handle.
+impl<T: Tracer> Tracer for Rc<RefCell<T>> {
+    fn trace(&mut self, step: &Step) {
+        self.borrow_mut().trace(step)
+    }
+}
+
+// Keeps every step in memory.
+#[derive(Default)]
+pub struct Record {
+    pub steps: Vec<Step>,
+}
+
+impl Tracer for Record {
+    fn trace(&mut self, step: &Step) {
+        self.steps.push(step.clone())
+    }
+}
+
+// Sink state shared by the writers: trace() cannot fail, so the first error is kept for finish().
+struct Out<W: Write> {
+    w: W,
+    err: Option<io::Error>,
+}
+
+impl<W: Write> Out<W> {
+    fn write(&mut self, data: &[u8]) {
+        if self.err.is_none() {
+            if let Err(e) = self.w.write_all(data) {
+                self.err = Some(e);
+            }
+        }
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.err {
+            return Err(e);
+        }
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+fn registers(r: &Register) -> String {
+    format!(
+        "A={:02x} F={:02x} B={:02x} C={:02x} D={:02x} E={:02x} H={:02x} L={:02x} SP={:04x}",
+        r.a,
+        r.flags(),
+        r.b,
+        r.c,
+        r.d,
+        r.e,
+        r.h,
+        r.l,
+        r.sp
+    )
+}
+
+// One line a step: address, bytes, instruction, the registers after it, cycles and memory accesses.
+//
+//   0100  32 00 24  STA 0x2400       A=12 F=02 B=00 C=00 D=00 E=00 H=00 L=00 SP=0000 13 W 2400=12
+pub fn text(step: &Step) -> String {
+    text_with(step, &Symbols::new())
+}
+
+// One line a step with named operands.
+pub fn text_with(step: &Step, symbols: &Symbols) -> String {
+    let bytes: Vec<String> = step.bytes().iter().map(|e| format!("{:02x}", e)).collect();
+    let mut r = format!(
+        "{:04x}  {:<8}  {:<16} {} {:>2}",
+        step.pc,
+        bytes.join(" "),
+        step.instruction_with(symbols),
+        registers(&step.after),
+        step.cycles
+    );
+    for a in &step.accesses {
+        let _ = write!(r, " {} {:04x}={:02x}", if a.write { 'W' } else { 'R' }, a.addr, a.value);
+    }
+    r
+}
+
+fn json_registers(r: &Register) -> String {
+    format!(
+        "{{\"a\":{},\"f\":{},\"b\":{},\"c\":{},\"d\":{},\"e\":{},\"h\":{},\"l\":{},\"sp\":{},\"pc\":{}}}",
+        r.a,
+        r.flags(),
+        r.b,
+        r.c,
+        r.d,
+        r.e,
+        r.h,
+        r.l,
+        r.sp,
+        r.pc
+    )
+}
+
+// Quote a JSON string. Symbol names can hold any character.
+fn json_string(s: &str) -> String {
+    let mut r = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            c if c < ' ' => r.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+// One JSON object a step, numbers in decimal.
+pub fn json(step: &Step) -> String {
+    json_with(step, &Symbols::new())
+}
+
+// One JSON object a step with named operands in the instruction.
+pub fn json_with(step: &Step, symbols: &Symbols) -> String {
+    let bytes: Vec<String> = step.bytes().iter().map(|e| e.to_string()).collect();
+    let accesses: Vec<String> = step
+        .accesses
+        .iter()
+        .map(|a| format!("{{\"addr\":{},\"value\":{},\"write\":{}}}", a.addr, a.value, a.write))
+        .collect();
+    format!(
+        "{{\"pc\":{},\"bytes\":[{}],\"instr\":{},\"before\":{},\"after\":{},\"mem\":[{}],\"cycles\":{},\"total\":{}}}",
+        step.pc,
+        bytes.join(","),
+        json_string(&step.instruction_with(symbols)),
+        json_registers(&step.before),
+        json_registers(&step.after),
+        accesses.join(","),
+        step.cycles,
+        step.total
+    )
+}
+
+pub struct Text<W: Write> {
+    // Names for the operands of the instructions.
+    pub symbols: Symbols,
+    out: Out<W>,
+}
+
+impl<W: Write> Text<W> {
+    pub fn new(w: W) -> Self {
+        Self { symbols: Symbols::new(), out: Out { w, err: None } }
+    }
+
+    // Flush and hand back the writer, or the first error met while tracing.
+    pub fn finish(self) -> io::Result<W> {
+        self.out.finish()
+    }
+}
+
+impl<W: Write> Tracer for Text<W> {
+    fn trace(&mut self, step: &Step) {
+        let mut s = text_with(step, &self.symbols);
+        s.push('\n');
+        self.out.write(s.as_bytes())
+    }
+}
+
+pub struct Json<W: Write> {
+    // Names for the operands of the instructions.
+    pub symbols: Symbols,
+    out: Out<W>,
+}
+
+impl<W: Write> Json<W> {
+    pub fn new(w: W) -> Self {
+        Self { symbols: Symbols::new(), out: Out { w, err: None } }
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        self.out.finish()
+    }
+}
+
+impl<W: Write> Tracer for Json<W> {
+    fn trace(&mut self, step: &Step) {
+        let mut s = json_with(step, &self.symbols);
+        s.push('\n');
+        self.out.write(s.as_bytes())
+    }
+}
+
+fn encode_registers(w: &mut Vec<u8>, r: &Register) {
+    w.extend([r.a, r.flags(), r.b, r.c, r.d, r.e, r.h, r.l]);
+    w.extend(r.sp.to_le_bytes());
+    w.extend(r.pc.to_le_bytes());
+}
+
+fn decode_registers(b: &[u8]) -> Register {
+    Register {
+        a: b[0],
+        f: b[1],
+        b: b[2],
+        c: b[3],
+        d: b[4],
+        e: b[5],
+        h: b[6],
+        l: b[7],
+        sp: u16::from_le_bytes([b[8], b[9]]),
+        pc: u16::from_le_bytes([b[10], b[11]]),
+        ..Default::default()
+    }
+}
+
+pub fn encode(step: &Step) -> Vec<u8> {
+    let mut r = Vec::with_capacity(40 + step.accesses.len() * 4);
+    r.extend(step.pc.to_le_bytes());
+    r.push(step.len);
+    r.extend(step.bytes());
+    encode_registers(&mut r, &step.before);
+    encode_registers(&mut r, &step.after);
+    r.push(step.cycles as u8);
+    r.extend(step.total.to_le_bytes());
+    r.push(step.accesses.len() as u8);
+    for a in &step.accesses {
+        r.extend(a.addr.to_le_bytes());
+        r.extend([a.value, u8::from(a.write)]);
+    }
+    r
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("trace: {}", msg))
+}
+
+// Read the next step of a binary trace, None at the end of it.
+pub fn decode(r: &mut impl Read) -> io::Result<Option<Step>> {
+    let mut head = [0u8; 3];
+    match r.read_exact(&mut head[..1]) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    r.read_exact(&mut head[1..])?;
+    let len = head[2];
+    if !(1..=3).contains(&len) {
+        return Err(invalid("bad instruction length"));
+    }
+    let mut body = vec![0u8; usize::from(len) + 12 + 12 + 1 + 8 + 1];
+    r.read_exact(&mut body)?;
+    let mut step = Step { pc: u16::from_le_bytes([head[0], head[1]]), len, ..Default::default() };
+    let n = usize::from(len);
+    step.opcode[..n].copy_from_slice(&body[..n]);
+    step.before = decode_registers(&body[n..n + 12]);
+    step.after = decode_registers(&body[n + 12..n + 24]);
+    step.cycles = u32::from(body[n + 24]);
+    step.total = u64::from_le_bytes(body[n + 25..n + 33].try_into().unwrap());
+    let mut accesses = vec![0u8; usize::from(body[n + 33]) * 4];
+    r.read_exact(&mut accesses)?;
+    step.accesses = accesses
+        .chunks(4)
+        .map(|e| Access { addr: u16::from_le_bytes([e[0], e[1]]), value: e[2], write: e[3] != 0 })
+        .collect();
+    Ok(Some(step))
+}
+
+// Check the magic at the start of a binary trace.
+pub fn header(r: &mut impl Read) -> io::Result<()> {
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a binary trace"));
+    }
+    Ok(())
+}
+
+// Read a whole binary trace.
+pub fn load(r: &mut impl Read) -> io::Result<Vec<Step>> {
+    header(r)?;
+    let mut steps = vec![];
+    while let Some(e) = decode(r)? {
+        steps.push(e);
+    }
+    Ok(steps)
+}
+
+pub struct Binary<W: Write> {
+    out: Out<W>,
+}
+
+impl<W: Write> Binary<W> {
+    pub fn new(w: W) -> Self {
+        let mut out = Out { w, err: None };
+        out.write(MAGIC);
+        Self { out }
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        self.out.finish()
+    }
+}
+
+impl<W: Write> Tracer for Binary<W> {
+    fn trace(&mut self, step: &Step) {
+        self.out.write(&encode(step))
+    }
+}
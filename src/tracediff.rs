@@ -0,0 +1,381 @@
+// Compare an execution trace with the log of another emulator, instruction by instruction, and stop at the first
+// state that differs. The log is read with a line template naming the fields it holds, such as
+//
+//   PC: {pc}, AF: {af}, BC: {bc}, DE: {de}, HL: {hl}, SP: {sp}, CYC: {cyc}
+//
+// Fields are pc, sp, a, f, b, c, d, e, h, l, af, bc, de, hl in hexadecimal and cyc, the cycles run before the
+// instruction, in decimal. {*} skips text up to the next literal, spaces match any run of white space and the rest of
+// the line after the template is ignored. Each logged state is taken as the one before the instruction at its pc
+// runs. Lines not matching the template, such as program output, are passed over.
+use super::register::Register;
+use super::trace::{self, Step, Tracer};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, BufRead};
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Pc,
+    Sp,
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Af,
+    Bc,
+    De,
+    Hl,
+    Cycles,
+}
+
+impl Field {
+    pub fn from_name(s: &str) -> Option<Self> {
+        Some(match s {
+            "pc" => Field::Pc,
+            "sp" => Field::Sp,
+            "a" => Field::A,
+            "f" => Field::F,
+            "b" => Field::B,
+            "c" => Field::C,
+            "d" => Field::D,
+            "e" => Field::E,
+            "h" => Field::H,
+            "l" => Field::L,
+            "af" => Field::Af,
+            "bc" => Field::Bc,
+            "de" => Field::De,
+            "hl" => Field::Hl,
+            "cyc" => Field::Cycles,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Pc => "pc",
+            Field::Sp => "sp",
+            Field::A => "a",
+            Field::F => "f",
+            Field::B => "b",
+            Field::C => "c",
+            Field::D => "d",
+            Field::E => "e",
+            Field::H => "h",
+            Field::L => "l",
+            Field::Af => "af",
+            Field::Bc => "bc",
+            Field::De => "de",
+            Field::Hl => "hl",
+            Field::Cycles => "cyc",
+        }
+    }
+
+    // Hexadecimal digits the field takes at most, 0 for the decimal cycle count.
+    fn digits(&self) -> usize {
+        match self {
+            Field::A | Field::F | Field::B | Field::C | Field::D | Field::E | Field::H | Field::L => 2,
+            Field::Cycles => 0,
+            _ => 4,
+        }
+    }
+
+    // The field out of the registers, with f masked.
+    fn get(&self, r: &Register, cycles: u64, mask: u8) -> u64 {
+        let af = (u16::from(r.a) << 8) | u16::from(r.flags() & mask);
+        u64::from(match self {
+            Field::Pc => r.pc,
+            Field::Sp => r.sp,
+            Field::A => u16::from(r.a),
+            Field::F => u16::from(r.flags() & mask),
+            Field::B => u16::from(r.b),
+            Field::C => u16::from(r.c),
+            Field::D => u16::from(r.d),
+            Field::E => u16::from(r.e),
+            Field::H => u16::from(r.h),
+            Field::L => u16::from(r.l),
+            Field::Af => af,
+            Field::Bc => r.get_bc(),
+            Field::De => r.get_de(),
+            Field::Hl => r.get_hl(),
+            Field::Cycles => return cycles,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Space,
+    Skip,
+    Field(Field),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Format {
+    parts: Vec<Part>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("tracediff: {}", msg))
+}
+
+impl Format {
+    pub fn parse(template: &str) -> io::Result<Self> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while !rest.is_empty() {
+            if let Some(r) = rest.strip_prefix('{') {
+                let end = r.find('}').ok_or_else(|| invalid("unclosed field"))?;
+                let name = &r[..end];
+                parts.push(match name {
+                    "*" => Part::Skip,
+                    _ => Part::Field(Field::from_name(name).ok_or_else(|| invalid("unknown field"))?),
+                });
+                rest = &r[end + 1..];
+            } else if rest.starts_with(char::is_whitespace) {
+                parts.push(Part::Space);
+                rest = rest.trim_start();
+            } else {
+                let end = rest.find(|c: char| c == '{' || c.is_whitespace()).unwrap_or(rest.len());
+                parts.push(Part::Literal(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+        if !parts.iter().any(|e| matches!(e, Part::Field(_))) {
+            return Err(invalid("template has no fields"));
+        }
+        Ok(Self { parts })
+    }
+
+    // Templates for the logs of common emulators, by name.
+    //
+    //   zazu    PC: 0100, AF: 0002, BC: 0000, DE: 0000, HL: 0000, SP: 0000, CYC: 0
+    //   doctor  A:00 F:02 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:0100
+    pub fn preset(name: &str) -> Option<Self> {
+        let t = match name {
+            "zazu" => "PC: {pc}, AF: {af}, BC: {bc}, DE: {de}, HL: {hl}, SP: {sp}, CYC: {cyc}",
+            "doctor" => "A:{a} F:{f} B:{b} C:{c} D:{d} E:{e} H:{h} L:{l} SP:{sp} PC:{pc}",
+            _ => return None,
+        };
+        Self::parse(t).ok()
+    }
+
+    pub fn fields(&self) -> Vec<Field> {
+        self.parts
+            .iter()
+            .filter_map(|e| match e {
+                Part::Field(f) => Some(*f),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The fields of a log line, None if the line does not match.
+    pub fn read(&self, line: &str) -> Option<Vec<(Field, u64)>> {
+        let mut r = vec![];
+        let mut s = line.trim_start();
+        for (i, p) in self.parts.iter().enumerate() {
+            match p {
+                Part::Literal(l) => s = s.strip_prefix(l.as_str())?,
+                Part::Space => {
+                    let t = s.trim_start();
+                    if t.len() == s.len() && !s.is_empty() {
+                        return None;
+                    }
+                    s = t;
+                }
+                Part::Skip => {
+                    s = match self.parts.get(i + 1) {
+                        Some(Part::Literal(l)) => &s[s.find(l.as_str())?..],
+                        Some(Part::Space) => &s[s.find(char::is_whitespace).unwrap_or(s.len())..],
+                        _ => "",
+                    }
+                }
+                Part::Field(f) => {
+                    let n = f.digits();
+                    let end = if n == 0 {
+                        s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len())
+                    } else {
+                        s.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(s.len()).min(n)
+                    };
+                    if end == 0 {
+                        return None;
+                    }
+                    let v = u64::from_str_radix(&s[..end], if n == 0 { 10 } else { 16 }).ok()?;
+                    r.push((*f, v));
+                    s = &s[end..];
+                }
+            }
+        }
+        Some(r)
+    }
+}
+
+// A field that differs: the logged value and ours.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub field: Field,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    // Index of the instruction, from 0.
+    pub index: u64,
+    // Line number in the log, from 1.
+    pub line: u64,
+    pub step: Step,
+    pub reference: String,
+    pub mismatches: Vec<Mismatch>,
+    // Steps and log lines that matched before the divergence, oldest first.
+    pub before: Vec<(Step, String)>,
+    // Log lines following the one that differs.
+    pub after: Vec<String>,
+}
+
+impl Divergence {
+    pub fn report(&self) -> String {
+        let mut r = String::new();
+        for (step, line) in &self.before {
+            let _ = writeln!(r, "  {}", trace::text(step));
+            let _ = writeln!(r, "  {}", line);
+        }
+        let _ = writeln!(r, "> {}", trace::text(&self.step));
+        let _ = writeln!(r, "> {}", self.reference);
+        for line in &self.after {
+            let _ = writeln!(r, "  {}", line);
+        }
+        let _ = write!(r, "diverged at instruction {}, log line {}:", self.index, self.line);
+        for m in &self.mismatches {
+            let _ = match m.field {
+                Field::Cycles => write!(r, " cyc expected {} got {}", m.expected, m.actual),
+                f => write!(r, " {} expected {:0w$x} got {:0w$x}", f.name(), m.expected, m.actual, w = f.digits()),
+            };
+        }
+        r
+    }
+}
+
+// A tracer checking each instruction against the next line of the log.
+pub struct Checker {
+    pub format: Format,
+    // Fields left out of the comparison.
+    pub ignore: Vec<Field>,
+    // Flag bits compared, 0xd5 by default: the unused bits differ between emulators.
+    pub mask: u8,
+    // Log lines at these addresses are passed over, for code the other emulator ran and this one traps, such as the
+    // BDOS entry.
+    pub skip: Vec<RangeInclusive<u16>>,
+    // Matched instructions kept for the report.
+    pub context: usize,
+    // Instructions matched so far.
+    pub matched: u64,
+    pub divergence: Option<Divergence>,
+    // The log has ended.
+    pub done: bool,
+    log: Box<dyn BufRead>,
+    line: u64,
+    history: VecDeque<(Step, String)>,
+}
+
+impl Checker {
+    pub fn new(format: Format, log: Box<dyn BufRead>) -> Self {
+        Self {
+            format,
+            ignore: vec![],
+            mask: 0xd5,
+            skip: vec![],
+            context: 8,
+            matched: 0,
+            divergence: None,
+            done: false,
+            log,
+            line: 0,
+            history: VecDeque::new(),
+        }
+    }
+
+    // True once there is nothing left to compare.
+    pub fn stopped(&self) -> bool {
+        self.done || self.divergence.is_some()
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        let mut s = String::new();
+        match self.log.read_line(&mut s) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                self.line += 1;
+                Some(s.trim_end().to_string())
+            }
+        }
+    }
+
+    // The next line of the log holding a state to compare.
+    fn next_state(&mut self) -> Option<(String, Vec<(Field, u64)>)> {
+        while let Some(line) = self.next_line() {
+            let Some(fields) = self.format.read(&line) else {
+                continue;
+            };
+            let pc = fields.iter().find(|e| e.0 == Field::Pc).map(|e| e.1 as u16);
+            if pc.is_some_and(|pc| self.skip.iter().any(|r| r.contains(&pc))) {
+                continue;
+            }
+            return Some((line, fields));
+        }
+        None
+    }
+}
+
+impl Tracer for Checker {
+    fn trace(&mut self, step: &Step) {
+        if self.stopped() {
+            return;
+        }
+        let Some((line, fields)) = self.next_state() else {
+            self.done = true;
+            return;
+        };
+        let cycles = step.total - u64::from(step.cycles);
+        let mismatches: Vec<Mismatch> = fields
+            .iter()
+            .filter(|e| !self.ignore.contains(&e.0))
+            .map(|&(field, expected)| Mismatch { field, expected, actual: field.get(&step.before, cycles, self.mask) })
+            .filter(|e| {
+                let mask = match e.field {
+                    Field::F => u64::from(self.mask),
+                    Field::Af => 0xff00 | u64::from(self.mask),
+                    _ => u64::MAX,
+                };
+                e.expected & mask != e.actual
+            })
+            .collect();
+        if mismatches.is_empty() {
+            self.matched += 1;
+            if self.context != 0 {
+                if self.history.len() == self.context {
+                    self.history.pop_front();
+                }
+                self.history.push_back((step.clone(), line));
+            }
+            return;
+        }
+        let reference_line = self.line;
+        let after = (0..self.context).map_while(|_| self.next_line()).collect();
+        self.divergence = Some(Divergence {
+            index: self.matched,
+            line: reference_line,
+            step: step.clone(),
+            reference: line,
+            mismatches,
+            before: self.history.drain(..).collect(),
+            after,
+        });
+    }
+}
@@ -0,0 +1,120 @@
+// Recompiled from 8080 code by i8080::recomp. Each function runs the block at the address in its name.
+use i8080::recomp::BlockFn;
+use i8080::{Flag, Memory, Register};
+
+// The function of the block at an address.
+pub fn block(pc: u16) -> Option<BlockFn> {
+    match pc {
+        0x0000 => Some(b_0000),
+        0x0008 => Some(b_0008),
+        0x000e => Some(b_000e),
+        0x0014 => Some(b_0014),
+        0x001b => Some(b_001b),
+        0x0023 => Some(b_0023),
+        0x0030 => Some(b_0030),
+        _ => None,
+    }
+}
+
+fn b_0000(r: &mut Register, m: &mut dyn Memory) -> u32 {
+    // 0000  LXI SP, 0x0100
+    r.sp = 0x0100;
+    // 0003  MVI B, 0x10
+    r.b = 0x10;
+    // 0005  LXI HL, 0x0200
+    r.set_hl(0x0200);
+    // 0008  MOV (M, B)
+    m.set(r.get_hl(), r.b);
+    // 0009  INX HL
+    r.set_hl(r.get_hl().wrapping_add(1));
+    // 000a  DCR B
+    r.b = r.alu_dcr(r.b);
+    // 000b  JNZ 0x0008
+    r.pc = if !r.get_flag(Flag::Z) { 0x0008 } else { 0x000e };
+    54
+}
+
+fn b_0008(r: &mut Register, m: &mut dyn Memory) -> u32 {
+    // 0008  MOV (M, B)
+    m.set(r.get_hl(), r.b);
+    // 0009  INX HL
+    r.set_hl(r.get_hl().wrapping_add(1));
+    // 000a  DCR B
+    r.b = r.alu_dcr(r.b);
+    // 000b  JNZ 0x0008
+    r.pc = if !r.get_flag(Flag::Z) { 0x0008 } else { 0x000e };
+    27
+}
+
+fn b_000e(r: &mut Register, m: &mut dyn Memory) -> u32 {
+    // 000e  MVI B, 0x10
+    r.b = 0x10;
+    // 0010  LXI HL, 0x0200
+    r.set_hl(0x0200);
+    // 0013  XRA A
+    r.alu_xra(r.a);
+    // 0014  ADD M
+    r.alu_add(m.get(r.get_hl()));
+    // 0015  DAA
+    r.alu_daa();
+    // 0016  INX HL
+    r.set_hl(r.get_hl().wrapping_add(1));
+    // 0017  DCR B
+    r.b = r.alu_dcr(r.b);
+    // 0018  JNZ 0x0014
+    r.pc = if !r.get_flag(Flag::Z) { 0x0014 } else { 0x001b };
+    52
+}
+
+fn b_0014(r: &mut Register, m: &mut dyn Memory) -> u32 {
+    // 0014  ADD M
+    r.alu_add(m.get(r.get_hl()));
+    // 0015  DAA
+    r.alu_daa();
+    // 0016  INX HL
+    r.set_hl(r.get_hl().wrapping_add(1));
+    // 0017  DCR B
+    r.b = r.alu_dcr(r.b);
+    // 0018  JNZ 0x0014
+    r.pc = if !r.get_flag(Flag::Z) { 0x0014 } else { 0x001b };
+    31
+}
+
+fn b_001b(r: &mut Register, m: &mut dyn Memory) -> u32 {
+    // 001b  STA 0x0300
+    m.set(0x0300, r.a);
+    // 001e  CALL 0x0030
+    r.sp = r.sp.wrapping_sub(2);
+    m.set_word(r.sp, 0x0021);
+    r.pc = 0x0030;
+    36
+}
+
+fn b_0023(r: &mut Register, _: &mut dyn Memory) -> u32 {
+    // 0023  LXI HL, 0x0040
+    r.set_hl(0x0040);
+    // 0026  PCHL
+    r.pc = r.get_hl();
+    15
+}
+
+fn b_0030(r: &mut Register, m: &mut dyn Memory) -> u32 {
+    // 0030  PUSH PSW
+    r.sp = r.sp.wrapping_sub(2);
+    m.set_word(r.sp, r.get_af());
+    // 0031  LXI HL, 0x1234
+    r.set_hl(0x1234);
+    // 0034  XTHL
+    let v = m.get_word(r.sp);
+    m.set_word(r.sp, r.get_hl());
+    r.set_hl(v);
+    // 0035  SHLD 0x0302
+    m.set_word(0x0302, r.get_hl());
+    // 0038  POP PSW
+    r.set_af(m.get_word(r.sp));
+    r.sp = r.sp.wrapping_add(2);
+    // 0039  RET
+    r.pc = m.get_word(r.sp);
+    r.sp = r.sp.wrapping_add(2);
+    81
+}
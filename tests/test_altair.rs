@@ -0,0 +1,95 @@
+use i8080::altair::{Machine, DCDD_CONTROL, DCDD_DATA, DCDD_SELECT, SIO_A_DATA, SIO_A_STATUS};
+use i8080::bdos::Buffer;
+use i8080::diskimg::{self, Disk};
+use i8080::{Device, Memory};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn machine() -> (Machine, Rc<RefCell<Buffer>>) {
+    let con = Rc::new(RefCell::new(Buffer::default()));
+    (Machine::new(con.clone()), con)
+}
+
+#[test]
+fn test_panel() {
+    let (mut m, con) = machine();
+    // MVI A,'H'; OUT 11H; IN 0FFH; STA 0080H; HLT
+    let prog = [0x3e, b'H', 0xd3, 0x11, 0xdb, 0xff, 0x32, 0x80, 0x00, 0x76];
+    m.examine(0x0000);
+    m.deposit(prog[0]);
+    for e in &prog[1..] {
+        m.deposit_next(*e);
+    }
+    assert_eq!(m.leds().address, 0x0009);
+    assert_eq!(m.leds().data, 0x76);
+    assert_eq!(m.examine(0x0001), b'H');
+    assert_eq!(m.examine_next(), 0xd3);
+    m.panel.borrow_mut().switches = 0xa500;
+    m.reset();
+    assert_eq!(m.single_step(), 7);
+    assert_eq!(m.cpu.reg.a, b'H');
+    assert_eq!(m.leds().address, 0x0002);
+    assert!(m.leds().wait);
+    m.go(0x0002);
+    assert_eq!(con.borrow().output, b"H");
+    assert_eq!(m.mem.borrow().get(0x0080), 0xa5);
+    let leds = m.leds();
+    assert!(leds.hlta && leds.wait);
+    assert_eq!(leds.address, 0x000a);
+}
+
+#[test]
+fn test_sio() {
+    let (m, con) = machine();
+    let mut bus = m.bus.borrow_mut();
+    assert_eq!(bus.get(SIO_A_STATUS), 0x02);
+    con.borrow_mut().input.push_back(b'x');
+    assert_eq!(bus.get(SIO_A_STATUS), 0x03);
+    assert_eq!(bus.get(SIO_A_DATA), b'x');
+    bus.set(SIO_A_DATA, b'y' | 0x80);
+    assert_eq!(con.borrow().output, b"y");
+}
+
+// Poll the sector position until the given sector comes under the head.
+fn seek_sector(bus: &mut dyn Device, sector: u8) {
+    loop {
+        let r = bus.get(DCDD_CONTROL);
+        if r & 0x01 == 0 && (r >> 1) & 0x1f == sector {
+            return;
+        }
+    }
+}
+
+#[test]
+fn test_dcdd() {
+    let (mut m, _) = machine();
+    let mut disk = diskimg::Altair::blank();
+    disk.write(2, 5, &[0x42; 128]).unwrap();
+    m.insert(0, disk);
+    let mut bus = m.bus.borrow_mut();
+    assert_eq!(bus.get(DCDD_SELECT), 0xff);
+    bus.set(DCDD_SELECT, 0x00);
+    // Head unloaded, at track 0, head movement allowed.
+    assert_eq!(bus.get(DCDD_SELECT), 0xa5);
+    assert_eq!(bus.get(DCDD_CONTROL), 0xff);
+    bus.set(DCDD_CONTROL, 0x01);
+    bus.set(DCDD_CONTROL, 0x01);
+    bus.set(DCDD_CONTROL, 0x04);
+    assert_eq!(bus.get(DCDD_SELECT), 0x61);
+    seek_sector(&mut *bus, 5);
+    let raw: Vec<u8> = (0..137).map(|_| bus.get(DCDD_DATA)).collect();
+    assert_eq!(raw[0], 0x82);
+    assert_eq!(raw[3..131], [0x42; 128]);
+    // Write a raw sector on track 1.
+    bus.set(DCDD_CONTROL, 0x02);
+    seek_sector(&mut *bus, 7);
+    bus.set(DCDD_CONTROL, 0x80);
+    assert_eq!(bus.get(DCDD_SELECT) & 0x01, 0x00);
+    for i in 0..137 {
+        bus.set(DCDD_DATA, i as u8);
+    }
+    drop(bus);
+    let d = m.dcdd.borrow();
+    let raw = d.drives[0].as_ref().unwrap().read_raw(1, 7).unwrap();
+    assert_eq!(raw, (0..137).map(|e| e as u8).collect::<Vec<u8>>());
+}
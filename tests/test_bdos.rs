@@ -0,0 +1,254 @@
+use i8080::bdos::{self, Bdos, Buffer};
+use i8080::replay::{Event, Journal, Log};
+use i8080::{Cpu, Linear, Memory};
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+fn setup() -> (Rc<RefCell<Linear>>, Cpu, Rc<RefCell<Buffer>>, Bdos) {
+    let mem = Rc::new(RefCell::new(Linear::new()));
+    let cpu = Cpu::power_up(mem.clone());
+    let con = Rc::new(RefCell::new(Buffer::default()));
+    let bdos = Bdos::new(con.clone());
+    bdos.install(&mut *mem.borrow_mut());
+    (mem, cpu, con, bdos)
+}
+
+fn tmpdir(name: &str) -> PathBuf {
+    let p = std::env::temp_dir().join(format!("i8080_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&p);
+    fs::create_dir_all(&p).unwrap();
+    p
+}
+
+fn call(cpu: &mut Cpu, bdos: &mut Bdos, c: u8, de: u16) -> u8 {
+    cpu.reg.c = c;
+    cpu.reg.set_de(de);
+    bdos.call(cpu);
+    cpu.reg.a
+}
+
+#[test]
+fn test_name() {
+    assert_eq!(bdos::to_name("hello.txt"), Some(*b"HELLO   TXT"));
+    assert_eq!(bdos::to_name("makefile"), Some(*b"MAKEFILE   "));
+    assert_eq!(bdos::to_name("toolongname.txt"), None);
+    assert_eq!(bdos::to_name("a.b.c"), None);
+    assert_eq!(bdos::from_name(b"HELLO   TXT"), "HELLO.TXT");
+    assert!(bdos::matches(b"HELLO   ???", b"HELLO   TXT"));
+}
+
+#[test]
+fn test_console() {
+    let (mem, mut cpu, con, mut bdos) = setup();
+    let prog = [
+        0x0e, 0x09, 0x11, 0x12, 0x01, 0xcd, 0x05, 0x00, 0x0e, 0x02, 0x1e, 0x21, 0xcd, 0x05, 0x00, 0xc3, 0x00, 0x00,
+        b'H', b'I', b'$',
+    ];
+    for (i, e) in prog.iter().enumerate() {
+        mem.borrow_mut().set(0x0100 + i as u16, *e);
+    }
+    cpu.reg.pc = 0x0100;
+    bdos.run(&mut cpu);
+    assert_eq!(con.borrow().output, b"HI!");
+    assert_eq!(cpu.reg.pc, 0x0000);
+}
+
+#[test]
+fn test_read_console_buffer() {
+    let (mem, mut cpu, con, mut bdos) = setup();
+    con.borrow_mut().input.extend(b"AX\x08BC\r");
+    mem.borrow_mut().set(0x0200, 0x02);
+    call(&mut cpu, &mut bdos, 0x0a, 0x0200);
+    assert_eq!(mem.borrow().get(0x0201), 0x02);
+    assert_eq!(mem.borrow().get(0x0202), b'A');
+    assert_eq!(mem.borrow().get(0x0203), b'B');
+    con.borrow_mut().input.extend(b"Z");
+    assert_eq!(call(&mut cpu, &mut bdos, 0x0b, 0), 0xff);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x06, 0x00ff), b'Z');
+    assert_eq!(call(&mut cpu, &mut bdos, 0x0c, 0), 0x22);
+}
+
+#[test]
+fn test_command_tail() {
+    let (mem, _, _, bdos) = setup();
+    bdos.command_tail(&mut *mem.borrow_mut(), "b:foo.asm *.com");
+    let m = mem.borrow();
+    assert_eq!(m.get(0x0080), 16);
+    assert_eq!(m.get(0x0081), b' ');
+    assert_eq!(m.get(0x005c), 2);
+    let n: Vec<u8> = (0..11).map(|i| m.get(0x005d + i)).collect();
+    assert_eq!(n, b"FOO     ASM");
+    let n: Vec<u8> = (0..11).map(|i| m.get(0x006d + i)).collect();
+    assert_eq!(n, b"????????COM");
+    drop(m);
+    // Only A to P name a drive.
+    bdos.command_tail(&mut *mem.borrow_mut(), "1:foo.txt q:bar");
+    let m = mem.borrow();
+    assert_eq!(m.get(0x005c), 0);
+    assert_eq!(m.get(0x006c), 0);
+}
+
+#[test]
+fn test_file() {
+    let (mem, mut cpu, _, mut bdos) = setup();
+    let dir = tmpdir("bdos");
+    bdos.mount(0, &dir);
+    bdos.command_tail(&mut *mem.borrow_mut(), "test.dat");
+    // Make the file and write two records.
+    assert_eq!(call(&mut cpu, &mut bdos, 0x16, 0x005c), 0x00);
+    for i in 0..2 {
+        for j in 0..128 {
+            mem.borrow_mut().set(0x0080 + j, i);
+        }
+        assert_eq!(call(&mut cpu, &mut bdos, 0x15, 0x005c), 0x00);
+    }
+    assert_eq!(call(&mut cpu, &mut bdos, 0x10, 0x005c), 0x00);
+    assert_eq!(fs::metadata(dir.join("TEST.DAT")).unwrap().len(), 256);
+    // Read them back.
+    mem.borrow_mut().set(0x005c + 12, 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x0f, 0x005c), 0x00);
+    assert_eq!(mem.borrow().get(0x005c + 15), 2);
+    mem.borrow_mut().set(0x005c + 32, 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x14, 0x005c), 0x00);
+    assert_eq!(mem.borrow().get(0x0080), 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x14, 0x005c), 0x00);
+    assert_eq!(mem.borrow().get(0x0080), 0x01);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x14, 0x005c), 0x01);
+    // Random access and file size.
+    mem.borrow_mut().set_word(0x005c + 33, 0x0000);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x21, 0x005c), 0x00);
+    assert_eq!(mem.borrow().get(0x0080), 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x23, 0x005c), 0x00);
+    assert_eq!(mem.borrow().get(0x005c + 33), 2);
+    // Search, rename and delete.
+    assert_eq!(call(&mut cpu, &mut bdos, 0x11, 0x005c), 0x00);
+    assert_eq!(mem.borrow().get(0x0080 + 15), 2);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x12, 0x005c), 0xff);
+    for (i, c) in b"NEW     DAT".iter().enumerate() {
+        mem.borrow_mut().set(0x005c + 17 + i as u16, *c);
+    }
+    assert_eq!(call(&mut cpu, &mut bdos, 0x17, 0x005c), 0x00);
+    assert!(dir.join("NEW.DAT").exists());
+    for (i, c) in b"NEW     DAT".iter().enumerate() {
+        mem.borrow_mut().set(0x005c + 1 + i as u16, *c);
+    }
+    assert_eq!(call(&mut cpu, &mut bdos, 0x13, 0x005c), 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x0f, 0x005c), 0xff);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_read_only() {
+    let (mem, mut cpu, _, mut bdos) = setup();
+    let dir = tmpdir("read_only");
+    fs::write(dir.join("RO.TXT"), [0x41; 128]).unwrap();
+    bdos.mount(0, &dir);
+    for (i, c) in b"\x00RO      TXT".iter().enumerate() {
+        mem.borrow_mut().set(0x005c + i as u16, *c);
+    }
+    // Set the read only attribute through function 30, then writes and deletes fail.
+    mem.borrow_mut().set(0x005c + 9, b'T' | 0x80);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x1e, 0x005c), 0x00);
+    assert!(fs::metadata(dir.join("RO.TXT")).unwrap().permissions().readonly());
+    assert_eq!(call(&mut cpu, &mut bdos, 0x0f, 0x005c), 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x15, 0x005c), 0x02);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x13, 0x005c), 0xff);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x11, 0x005c), 0x00);
+    assert_eq!(mem.borrow().get(0x0080 + 9), b'T' | 0x80);
+    mem.borrow_mut().set(0x005c + 9, b'T');
+    assert_eq!(call(&mut cpu, &mut bdos, 0x1e, 0x005c), 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x13, 0x005c), 0x00);
+    assert!(!dir.join("RO.TXT").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// An FCB or buffer near the top of memory runs on into address 0000 instead of stopping the host.
+#[test]
+fn test_wrap() {
+    let (mem, mut cpu, con, mut bdos) = setup();
+    let dir = tmpdir("wrap");
+    bdos.mount(0, &dir);
+    let fcb = 0xfff0;
+    for (i, c) in b"\x00WRAP    DAT".iter().enumerate() {
+        mem.borrow_mut().set(fcb + i as u16, *c);
+    }
+    assert_eq!(call(&mut cpu, &mut bdos, 0x16, fcb), 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x15, fcb), 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x10, fcb), 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x0f, fcb), 0x00);
+    // The random record is at 0011 to 0013.
+    mem.borrow_mut().set_word(0x0011, 0x0000);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x21, fcb), 0x00);
+    assert_eq!(call(&mut cpu, &mut bdos, 0x23, fcb), 0x00);
+    assert_eq!(mem.borrow().get(0x0011), 1);
+    // The new name is at 0001.
+    for (i, c) in b"MOVED   DAT".iter().enumerate() {
+        mem.borrow_mut().set(0x0001 + i as u16, *c);
+    }
+    assert_eq!(call(&mut cpu, &mut bdos, 0x17, fcb), 0x00);
+    assert!(dir.join("MOVED.DAT").exists());
+    con.borrow_mut().input.extend(b"AB\r");
+    mem.borrow_mut().set(0xfffe, 0x02);
+    call(&mut cpu, &mut bdos, 0x0a, 0xfffe);
+    assert_eq!(mem.borrow().get(0xffff), 0x02);
+    assert_eq!(mem.borrow().get(0x0000), b'A');
+    assert_eq!(mem.borrow().get(0x0001), b'B');
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// 0100  MVI C,0AH
+// 0102  LXI D,0200H
+// 0105  CALL 0005H
+// 0108  MVI C,0FH
+// 010a  LXI D,005CH
+// 010d  CALL 0005H
+// 0110  MVI C,14H
+// 0112  LXI D,005CH
+// 0115  CALL 0005H
+// 0118  MVI C,09H
+// 011a  LXI D,0080H
+// 011d  CALL 0005H
+// 0120  JMP 0000H
+const READER: [u8; 35] = [
+    0x0e, 0x0a, 0x11, 0x00, 0x02, 0xcd, 0x05, 0x00, 0x0e, 0x0f, 0x11, 0x5c, 0x00, 0xcd, 0x05, 0x00, 0x0e, 0x14, 0x11,
+    0x5c, 0x00, 0xcd, 0x05, 0x00, 0x0e, 0x09, 0x11, 0x80, 0x00, 0xcd, 0x05, 0x00, 0xc3, 0x00, 0x00,
+];
+
+// A recorded run replays without the console input and the files it read, printing what it printed.
+#[test]
+fn test_replay() {
+    let (mem, mut cpu, con, mut bdos) = setup();
+    let dir = tmpdir("replay");
+    let mut data = b"HELLO$".to_vec();
+    data.resize(128, 0x1a);
+    fs::write(dir.join("DATA.TXT"), data).unwrap();
+    bdos.mount(0, &dir);
+    bdos.command_tail(&mut *mem.borrow_mut(), "data.txt");
+    for (i, e) in READER.iter().enumerate() {
+        mem.borrow_mut().set(0x0100 + i as u16, *e);
+    }
+    mem.borrow_mut().set(0x0200, 0x08);
+    cpu.reg.pc = 0x0100;
+    con.borrow_mut().input.extend(b"AB\r");
+    cpu.journal = Some(Journal::record(&cpu, 100));
+    bdos.run(&mut cpu);
+    let log = cpu.journal.take().unwrap().finish(cpu.cycles);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(con.borrow().output, b"AB\rHELLO");
+    assert!(log.events.iter().any(|e| matches!(e, Event::Call { .. })));
+    assert!(log.events.iter().any(|e| matches!(e, Event::Write { addr: 0x0080, .. })));
+    let mut saved = vec![];
+    log.save(&mut saved).unwrap();
+
+    let (mem, mut again, con, mut bdos) = setup();
+    again.journal = Some(Journal::replay(&mut again, Log::load(&mut &saved[..]).unwrap(), true));
+    bdos.run(&mut again);
+    assert_eq!(again.journal.as_ref().unwrap().desync, None);
+    assert_eq!(con.borrow().output, b"HELLO");
+    assert_eq!(again.cycles, cpu.cycles);
+    assert_eq!(again.reg, cpu.reg);
+    assert_eq!(mem.borrow().get(0x0202), b'A');
+    assert_eq!(mem.borrow().get(0x0080), b'H');
+}
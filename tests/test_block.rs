@@ -0,0 +1,112 @@
+use i8080::block::{self, Block, Cache};
+use i8080::{Cpu, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn cpu(code: &[u8]) -> Cpu {
+    let mut mem = Linear::new();
+    mem.data[..code.len()].copy_from_slice(code);
+    Cpu::power_up(Rc::new(RefCell::new(mem)))
+}
+
+#[test]
+fn test_decode() {
+    assert!(block::ends_block(0xc3));
+    assert!(block::ends_block(0xda));
+    assert!(block::ends_block(0xf8));
+    assert!(block::ends_block(0xff));
+    assert!(block::ends_block(0x76));
+    assert!(!block::ends_block(0xc5));
+    assert!(!block::ends_block(0xc6));
+    // MVI A,1; LXI H,1234H; undocumented JMP 0000H
+    let c = cpu(&[0x3e, 0x01, 0x21, 0x34, 0x12, 0xcb, 0x00, 0x00, 0x00]);
+    let b = Block::decode(&*c.mem.borrow(), 0x0000);
+    assert_eq!(b.size, 8);
+    assert_eq!(b.ops.len(), 3);
+    assert_eq!((b.ops[1].code, b.ops[1].imm), (0x21, 0x1234));
+    assert_eq!(b.ops[2].code, 0xc3);
+    assert!(b.contains(0x0007));
+    assert!(!b.contains(0x0008));
+}
+
+// Code written into the block running shows at once.
+#[test]
+fn test_self_modifying() {
+    // 0000  MVI A,3CH
+    // 0002  STA 0006H
+    // 0005  NOP
+    // 0006  NOP, becomes INR A
+    // 0007  HLT
+    let code = [0x3e, 0x3c, 0x32, 0x06, 0x00, 0x00, 0x00, 0x76];
+    let mut c = cpu(&code);
+    c.cache = Some(Cache::new());
+    assert_eq!(c.run_block(), 20);
+    assert_eq!(c.reg.pc, 0x0005);
+    c.run_until(u64::MAX);
+    assert_eq!(c.reg.a, 0x3d);
+    let cache = c.cache.as_ref().unwrap();
+    assert_eq!(cache.stats.invalidations, 1);
+    assert_eq!(cache.stats.misses, 2);
+
+    let mut c = cpu(&code);
+    while !c.halted {
+        c.next();
+    }
+    assert_eq!(c.reg.a, 0x3d);
+}
+
+// A loop runs from the cache, with host writes reported.
+#[test]
+fn test_cache() {
+    // 0000  MVI B,10H
+    // 0002  DCR B
+    // 0003  JNZ 0002H
+    // 0006  HLT
+    let mut c = cpu(&[0x06, 0x10, 0x05, 0xc2, 0x02, 0x00, 0x76]);
+    c.cache = Some(Cache::new());
+    c.run_until(u64::MAX);
+    assert_eq!(c.reg.b, 0);
+    let stats = c.cache.as_ref().unwrap().stats;
+    assert_eq!((stats.hits, stats.misses), (14, 3));
+
+    c.mem.borrow_mut().set(0x0006, 0x3c);
+    c.mem.borrow_mut().set(0x0007, 0x76);
+    c.invalidate(0x0006, 2);
+    c.halted = false;
+    c.reg.pc = 0x0006;
+    c.run_until(u64::MAX);
+    assert_eq!(c.reg.a, 0x01);
+}
+
+// Random code runs to the same state with the cache as one instruction at a time.
+#[test]
+fn test_random() {
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut rand = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    for _ in 0..200 {
+        let mut mem = Linear::new();
+        for e in mem.data.iter_mut() {
+            *e = rand() as u8;
+        }
+        let a = Rc::new(RefCell::new(Linear { data: mem.data.clone() }));
+        let b = Rc::new(RefCell::new(mem));
+        let mut x = Cpu::power_up(a.clone());
+        let mut y = Cpu::power_up(b.clone());
+        x.reg.sp = rand() as u16;
+        y.reg.sp = x.reg.sp;
+        y.cache = Some(Cache::new());
+        let until = 20_000 + rand() % 20_000;
+        while x.cycles < until && !x.halted {
+            x.next();
+        }
+        y.run_until(until);
+        assert_eq!(x.reg, y.reg);
+        assert_eq!((x.cycles, x.halted, x.inte), (y.cycles, y.halted, y.inte));
+        assert!(a.borrow().data == b.borrow().data);
+    }
+}
@@ -0,0 +1,124 @@
+use i8080::bdos::Buffer;
+use i8080::cpm::Machine;
+use i8080::diskimg::{Geometry, Raw};
+use i8080::replay::{Event, Journal};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Stand-in for the CCP: print "OK" and copy logical sector 1 of track 2 to logical sector 0 through the BIOS.
+const SYSTEM: &[u8] = &[
+    0x0e, 0x4f, 0xcd, 0x0c, 0xfa, // MVI C,'O'; CALL CONOUT
+    0x0e, 0x4b, 0xcd, 0x0c, 0xfa, // MVI C,'K'; CALL CONOUT
+    0x0e, 0x00, 0xcd, 0x1b, 0xfa, // MVI C,0; CALL SELDSK
+    0x5e, 0x23, 0x56, 0xeb, 0x22, 0x00, 0x91, // MOV E,M; INX H; MOV D,M; XCHG; SHLD XLT
+    0x01, 0x02, 0x00, 0xcd, 0x1e, 0xfa, // LXI B,2; CALL SETTRK
+    0x2a, 0x00, 0x91, 0xeb, 0x01, 0x01, 0x00, 0xcd, 0x30, 0xfa, // LHLD XLT; XCHG; LXI B,1; CALL SECTRAN
+    0x44, 0x4d, 0xcd, 0x21, 0xfa, // MOV B,H; MOV C,L; CALL SETSEC
+    0x01, 0x00, 0x80, 0xcd, 0x24, 0xfa, // LXI B,8000H; CALL SETDMA
+    0xcd, 0x27, 0xfa, 0x32, 0x00, 0x90, // CALL READ; STA 9000H
+    0x2a, 0x00, 0x91, 0xeb, 0x01, 0x00, 0x00, 0xcd, 0x30, 0xfa, // LHLD XLT; XCHG; LXI B,0; CALL SECTRAN
+    0x44, 0x4d, 0xcd, 0x21, 0xfa, // MOV B,H; MOV C,L; CALL SETSEC
+    0xcd, 0x2a, 0xfa, 0x32, 0x01, 0x90, // CALL WRITE; STA 9001H
+    0x76, 0x00, 0x00, 0x00, // HLT
+];
+
+fn machine() -> (Machine, Rc<RefCell<Buffer>>) {
+    let con = Rc::new(RefCell::new(Buffer::default()));
+    let mut m = Machine::new(con.clone());
+    let mut disk = Raw::blank(Geometry::ibm_3740());
+    disk.data[128..128 + SYSTEM.len()].copy_from_slice(SYSTEM);
+    let a = (2 * 26 + 6) * 128;
+    disk.data[a..a + 128].fill(0x55);
+    m.insert(0, Box::new(disk));
+    (m, con)
+}
+
+#[test]
+fn test_boot() {
+    let (mut m, con) = machine();
+    m.boot().unwrap();
+    assert_eq!(m.cpu.reg.pc, 0xe400);
+    m.run();
+    assert_eq!(con.borrow().output, b"OK");
+    assert_eq!(m.mem.borrow().data[0x8000], 0x55);
+    assert_eq!(m.mem.borrow().data[0x9000], 0x00);
+    assert_eq!(m.mem.borrow().data[0x9001], 0x00);
+    let mut buf = [0; 128];
+    m.fdc.borrow_mut().drives[0].as_mut().unwrap().read(2, 0, &mut buf).unwrap();
+    assert_eq!(buf, [0x55; 128]);
+}
+
+#[test]
+fn test_wboot() {
+    let (mut m, con) = machine();
+    m.boot().unwrap();
+    m.run();
+    m.mem.borrow_mut().data[0xe400] = 0x00;
+    m.cpu.halted = false;
+    m.cpu.reg.pc = 0x0000;
+    m.step();
+    assert_eq!(m.cpu.reg.pc, 0xfa03);
+    m.step();
+    assert_eq!(m.cpu.reg.pc, 0xe402);
+    assert_eq!(m.mem.borrow().data[0xe400], 0x0e);
+    m.run();
+    assert_eq!(con.borrow().output, b"OKOK");
+}
+
+#[test]
+fn test_no_disk() {
+    let con = Rc::new(RefCell::new(Buffer::default()));
+    let mut m = Machine::new(con);
+    assert!(m.boot().is_err());
+}
+
+// The tables of sixteen 8" drives do not fit above the BIOS.
+#[test]
+fn test_too_many_drives() {
+    let (mut m, _) = machine();
+    for i in 1..16 {
+        m.insert(i, Box::new(Raw::blank(Geometry::ibm_3740())));
+    }
+    let e = m.boot().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    // Four do.
+    let (mut m, _) = machine();
+    for i in 1..4 {
+        m.insert(i, Box::new(Raw::blank(Geometry::ibm_3740())));
+    }
+    m.boot().unwrap();
+}
+
+// A CCP placed so high the BIOS would pass the top of memory fails to boot instead of overflowing.
+#[test]
+fn test_ccp_too_high() {
+    let (mut m, _) = machine();
+    m.ccp = 0xf000;
+    assert_eq!(m.boot().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(m.wboot().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    m.cpu.reg.pc = m.bios();
+    m.step();
+    assert!(m.cpu.halted);
+}
+
+// A run recorded from the cold boot replays with a blank disk, the sectors read coming from the log.
+#[test]
+fn test_replay() {
+    let (mut m, _) = machine();
+    m.cpu.reg.pc = m.bios();
+    m.cpu.journal = Some(Journal::record(&m.cpu, 1000));
+    m.run();
+    let log = m.cpu.journal.take().unwrap().finish(m.cpu.cycles);
+    assert!(log.events.iter().any(|e| matches!(e, Event::Write { addr: 0x8000, .. })));
+
+    let con = Rc::new(RefCell::new(Buffer::default()));
+    let mut r = Machine::new(con.clone());
+    r.insert(0, Box::new(Raw::blank(Geometry::ibm_3740())));
+    r.cpu.journal = Some(Journal::replay(&mut r.cpu, log, true));
+    r.run();
+    assert_eq!(r.cpu.journal.as_ref().unwrap().desync, None);
+    assert_eq!(con.borrow().output, b"OK");
+    assert_eq!(r.cpu.cycles, m.cpu.cycles);
+    assert_eq!(r.mem.borrow().data[0x8000], 0x55);
+    assert_eq!(r.mem.borrow().data[0xe400..0xe400 + SYSTEM.len()], *SYSTEM);
+}
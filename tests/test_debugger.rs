@@ -0,0 +1,95 @@
+use i8080::debugger::{Debugger, Stop};
+use i8080::history::History;
+use i8080::symbol::Symbols;
+use i8080::{Cpu, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 0000  LXI SP,0100H
+// 0003  CALL PRINT
+// 0006  HLT
+// 0010  PRINT: MVI A,2AH
+// 0012  RET
+fn program() -> (Cpu, Symbols) {
+    let mut mem = Linear::new();
+    mem.data[..7].copy_from_slice(&[0x31, 0x00, 0x01, 0xcd, 0x10, 0x00, 0x76]);
+    mem.data[0x10..0x13].copy_from_slice(&[0x3e, 0x2a, 0xc9]);
+    let mut s = Symbols::new();
+    s.insert("MAIN", 0x0000);
+    s.insert("PRINT", 0x0010);
+    (Cpu::power_up(Rc::new(RefCell::new(mem))), s)
+}
+
+#[test]
+fn test_debugger() {
+    let (cpu, s) = program();
+    let mut d = Debugger::new(cpu);
+    d.symbols = s;
+    assert_eq!(d.command("b print+2"), "breakpoint at 0012 PRINT+0x2");
+    assert_eq!(d.command("b"), "0012 PRINT+0x2");
+    assert_eq!(d.command("s"), "0003 MAIN+0x3  CALL PRINT");
+    assert_eq!(d.command("c"), "breakpoint at 0012 PRINT+0x2  RET");
+    assert_eq!(d.cpu.reg.a, 0x2a);
+    assert_eq!(
+        d.command("l main 3"),
+        "MAIN:\n  0000  31 00 01  LXI SP, 0x0100\n  0003  cd 10 00  CALL PRINT\n  0006  76        HLT"
+    );
+    assert_eq!(d.command("x print 3"), format!("0010  {:<47}  >*.", "3e 2a c9"));
+    assert_eq!(d.command("i 0x0011"), "0011 PRINT+0x1");
+    assert!(d.command("r").starts_with("A=2a F=02 [-----] BC=0000"));
+    assert_eq!(d.command("sym pr"), "0010 PRINT");
+    assert_eq!(d.command("b nowhere"), "error: unknown address nowhere");
+    assert_eq!(d.command("d print+2"), "");
+    assert_eq!(d.command("frob"), "error: unknown command frob");
+    assert_eq!(d.command("c"), "halted at 0007 MAIN+0x7  NOP");
+    assert_eq!(d.step(), Stop::Halted);
+    assert_eq!(d.command("g main"), "0000 MAIN  LXI SP, 0x0100");
+    assert!(!d.cpu.halted);
+}
+
+#[test]
+fn test_source() {
+    let (cpu, s) = program();
+    let mut d = Debugger::new(cpu);
+    d.symbols = s;
+    // A listing of the main program only, PRINT has no source.
+    d.sources.add(
+        "main.prn",
+        " 0000 310001    start:\tlxi\tsp,100h\n 0003 CD1000    \tcall\tprint\n                ; done\n 0006 76        \thlt\n",
+    );
+    assert_eq!(d.location(), "0000 MAIN  LXI SP, 0x0100\nMAIN:1  start:\tlxi\tsp,100h");
+    assert_eq!(d.command("b main:3"), "breakpoint at 0006 MAIN+0x6 MAIN:4");
+    assert_eq!(d.command("n"), "0003 MAIN+0x3  CALL PRINT\nMAIN:2  call\tprint");
+    assert_eq!(d.command("n"), "0006 MAIN+0x6  HLT\nMAIN:4  hlt");
+    assert_eq!(d.cpu.reg.a, 0x2a);
+    assert_eq!(d.command("src"), "      1  start:\tlxi\tsp,100h\n      2  call\tprint\n      3  ; done\n>     4  hlt");
+    assert_eq!(d.command("src main:2 1"), "      2  call\tprint");
+    assert_eq!(d.command("i 3"), "0003 MAIN+0x3 MAIN:2 call\tprint");
+    assert_eq!(d.command("i print"), "0010 PRINT");
+    assert_eq!(d.command("b other:1"), "error: unknown line other:1");
+    assert_eq!(d.command("b main:5"), "error: no code at or after main:5");
+    assert_eq!(d.command("g main:1"), "0000 MAIN  LXI SP, 0x0100\nMAIN:1  start:\tlxi\tsp,100h");
+    assert_eq!(d.command("c"), "breakpoint at 0006 MAIN+0x6  HLT\nMAIN:4  hlt");
+}
+
+#[test]
+fn test_back() {
+    let (cpu, s) = program();
+    let mut d = Debugger::new(cpu);
+    d.symbols = s;
+    assert_eq!(d.command("bs"), "error: no history kept");
+    d.cpu.history = Some(History::new(1 << 20));
+    assert_eq!(d.command("bs"), "start of history at 0000 MAIN  LXI SP, 0x0100");
+    assert_eq!(d.command("c"), "halted at 0007 MAIN+0x7  NOP");
+    assert_eq!(d.command("bs"), "0006 MAIN+0x6  HLT");
+    assert_eq!(d.command("bw 0x00fe"), "0003 MAIN+0x3  CALL PRINT");
+    assert_eq!(d.cpu.mem.borrow().get(0x00fe), 0x00);
+    assert_eq!(d.command("b print"), "breakpoint at 0010 PRINT");
+    assert_eq!(d.command("c"), "breakpoint at 0010 PRINT  MVI A, 0x2a");
+    assert_eq!(d.command("s 2"), "0006 MAIN+0x6  HLT");
+    assert_eq!(d.cpu.reg.a, 0x2a);
+    assert_eq!(d.command("bc"), "breakpoint at 0010 PRINT  MVI A, 0x2a");
+    assert_eq!(d.cpu.reg.a, 0x00);
+    assert_eq!(d.command("bg main"), "0000 MAIN  LXI SP, 0x0100");
+    assert_eq!(d.command("bs 3"), "start of history at 0000 MAIN  LXI SP, 0x0100");
+}
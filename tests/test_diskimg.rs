@@ -0,0 +1,89 @@
+use i8080::diskimg::{self, Altair, Cpm, Disk, Format, Geometry, Imd, Raw};
+use std::path::Path;
+
+fn data(n: usize) -> Vec<u8> {
+    (0..n).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+fn roundtrip(disk: &mut dyn Disk) {
+    let mut cpm = Cpm::new(disk);
+    let big = data(40 * 1024 + 5);
+    cpm.insert(0, b"BIG     DAT", &big).unwrap();
+    cpm.insert(3, b"SMALL   TXT", b"hello").unwrap();
+    cpm.insert(0, b"EMPTY      ", b"").unwrap();
+    let list = cpm.list().unwrap();
+    assert_eq!(list.len(), 3);
+    assert_eq!(&list[0].name, b"BIG     DAT");
+    assert_eq!(list[0].records, 321);
+    assert_eq!(&list[1].name, b"EMPTY      ");
+    assert_eq!(list[1].records, 0);
+    assert_eq!(list[2].user, 3);
+    let r = cpm.extract(0, b"BIG     DAT").unwrap();
+    assert_eq!(r.len(), 321 * 128);
+    assert_eq!(r[..big.len()], big[..]);
+    assert!(r[big.len()..].iter().all(|e| *e == 0x1a));
+    assert_eq!(&cpm.extract(3, b"SMALL   TXT").unwrap()[..6], b"hello\x1a");
+    // Replacing a file frees its blocks.
+    cpm.insert(0, b"BIG     DAT", b"x").unwrap();
+    assert_eq!(cpm.find(0, b"BIG     DAT").unwrap().unwrap().records, 1);
+    assert!(cpm.erase(3, b"SMALL   TXT").unwrap());
+    assert_eq!(cpm.list().unwrap().len(), 2);
+}
+
+#[test]
+fn test_raw() {
+    roundtrip(&mut Raw::blank(Geometry::ibm_3740()));
+}
+
+#[test]
+fn test_hdsk() {
+    roundtrip(&mut Raw::blank(Geometry::simh_hdsk()));
+}
+
+#[test]
+fn test_altair() {
+    let mut d = Altair::blank();
+    roundtrip(&mut d);
+    d.write(10, 3, &[0x11; 128]).unwrap();
+    let a = (10 * 32 + 3) * 137;
+    assert_eq!(d.data[a], 0x8a);
+    assert_eq!(d.data[a + 1], 3 * 17 % 32);
+    assert_eq!(d.data[a + 4], 0x80);
+    assert_eq!(d.data[a + 7..a + 135], [0x11; 128]);
+    assert_eq!(d.data[a + 135], 0xff);
+    d.write(1, 0, &[0x01; 128]).unwrap();
+    let a = 32 * 137;
+    assert_eq!(d.data[a..a + 4], [0x81, 0x00, 0x01, 0x01]);
+    assert_eq!(d.data[a + 131..a + 133], [0xff, 0x80]);
+}
+
+#[test]
+fn test_imd() {
+    let mut d = Imd::blank(Geometry::ibm_3740());
+    roundtrip(&mut d);
+    let e = d.encode();
+    let d2 = Imd::decode(&e, Geometry::ibm_3740()).unwrap();
+    assert_eq!(d2.tracks, d.tracks);
+    // Formatted sectors compress to two bytes.
+    let blank = Imd::blank(Geometry::ibm_3740()).encode();
+    assert_eq!(blank.len(), 18 + 77 * (5 + 26 + 26 * 2));
+    assert_eq!(&blank[18..23], &[0, 0, 0, 26, 0]);
+    assert!(Imd::decode(b"IMD 1.18\x1a\x00\x00", Geometry::ibm_3740()).is_err());
+}
+
+#[test]
+fn test_detect() {
+    assert_eq!(Format::detect(Path::new("a.IMD"), 0), Format::Imd);
+    assert_eq!(Format::detect(Path::new("a.dsk"), 77 * 32 * 137), Format::Altair);
+    assert_eq!(Format::detect(Path::new("a.dsk"), 8 << 20), Format::Hdsk);
+    assert_eq!(Format::detect(Path::new("a.img"), 256256), Format::Raw);
+    let p = std::env::temp_dir().join(format!("i8080_diskimg_{}.imd", std::process::id()));
+    diskimg::create(&p, Format::Imd).unwrap();
+    {
+        let mut d = diskimg::open(&p, Format::Imd).unwrap();
+        Cpm::new(&mut *d).insert(0, b"A       COM", b"\x76").unwrap();
+    }
+    let mut d = diskimg::open(&p, Format::Imd).unwrap();
+    assert_eq!(Cpm::new(&mut *d).extract(0, b"A       COM").unwrap()[0], 0x76);
+    std::fs::remove_file(&p).unwrap();
+}
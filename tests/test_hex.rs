@@ -0,0 +1,63 @@
+use i8080::{hex, Cpu, Linear, Memory};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn test_decode() {
+    let s = ":0300300002337A1E\n:00000001FF\n";
+    let h = hex::decode(s).unwrap();
+    assert_eq!(h.data, vec![(0x0030, vec![0x02, 0x33, 0x7a])]);
+    assert_eq!(h.start, None);
+    assert_eq!(h.ranges(), vec![0x0030..=0x0032]);
+}
+
+#[test]
+fn test_decode_checksum() {
+    let s = ":0300300002337A1F\n:00000001FF\n";
+    assert!(hex::decode(s).is_err());
+}
+
+#[test]
+fn test_decode_extended() {
+    let s = ":020000040000FA\n:010000007689\n:00000001FF\n";
+    let h = hex::decode(s).unwrap();
+    assert_eq!(h.data, vec![(0x0000, vec![0x76])]);
+    let s = ":020000040001F9\n:00000001FF\n";
+    assert!(hex::decode(s).is_err());
+}
+
+#[test]
+fn test_load() {
+    let mem = Rc::new(RefCell::new(Linear::new()));
+    let mut cpu = Cpu::power_up(mem.clone());
+    let s = ":020100003E0AB5\n:0400000500000100F6\n:00000001FF\n";
+    hex::load(&mut cpu, s).unwrap();
+    assert_eq!(mem.borrow().get(0x0100), 0x3e);
+    assert_eq!(mem.borrow().get(0x0101), 0x0a);
+    assert_eq!(cpu.reg.pc, 0x0100);
+}
+
+#[test]
+fn test_dump() {
+    let mut mem = Linear::new();
+    for i in 0..20 {
+        mem.set(0x0100 + i, i as u8);
+    }
+    let s = hex::dump(&mem, 0x0100..=0x0113, Some(0x0100));
+    let h = hex::decode(&s).unwrap();
+    assert_eq!(h.data, vec![(0x0100, (0..20).collect())]);
+    assert_eq!(h.start, Some(0x0100));
+    assert_eq!(s.lines().count(), 4);
+}
+
+// A dump of the whole memory reads back as one run covering all of it.
+#[test]
+fn test_dump_full() {
+    let mut mem = Linear::new();
+    for (i, e) in mem.data.iter_mut().enumerate() {
+        *e = i as u8;
+    }
+    let h = hex::decode(&hex::dump(&mem, 0x0000..=0xffff, None)).unwrap();
+    assert_eq!(h.ranges(), vec![0x0000..=0xffff]);
+    assert!(h.data[0].1 == mem.data);
+}
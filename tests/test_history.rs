@@ -0,0 +1,118 @@
+use i8080::block::Cache;
+use i8080::history::History;
+use i8080::{Cpu, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 0000  LXI SP,0100H
+// 0003  MVI A,01H
+// 0005  STA 0080H
+// 0008  INR A
+// 0009  CPI 04H
+// 000b  JNZ 0005H
+// 000e  PUSH PSW
+// 000f  EI
+// 0010  HLT
+fn program() -> Cpu {
+    let mut mem = Linear::new();
+    let code = [0x31, 0x00, 0x01, 0x3e, 0x01, 0x32, 0x80, 0x00, 0x3c, 0xfe, 0x04, 0xc2, 0x05, 0x00, 0xf5, 0xfb, 0x76];
+    mem.data[..code.len()].copy_from_slice(&code);
+    mem.data[0x80] = 0xaa;
+    mem.data[0xfe] = 0x55;
+    Cpu::power_up(Rc::new(RefCell::new(mem)))
+}
+
+fn run(cpu: &mut Cpu) {
+    while !cpu.halted {
+        cpu.next();
+    }
+}
+
+#[test]
+fn test_step_back() {
+    let mut cpu = program();
+    cpu.history = Some(History::new(1 << 20));
+    run(&mut cpu);
+    assert_eq!(cpu.history.as_ref().unwrap().len(), 17);
+    assert!(cpu.inte);
+    let mem = cpu.mem.clone();
+    assert_eq!(mem.borrow().get(0x80), 0x03);
+    assert_eq!(mem.borrow().get(0xff), 0x04);
+
+    assert!(cpu.step_back());
+    assert!(!cpu.halted);
+    assert_eq!(cpu.reg.pc, 0x0010);
+    assert!(cpu.step_back());
+    assert!(!cpu.inte);
+    assert!(cpu.step_back());
+    assert_eq!(cpu.reg.pc, 0x000e);
+    assert_eq!(mem.borrow().get_word(0xfe), 0x0055);
+    assert_eq!(cpu.reg.sp, 0x0100);
+
+    assert!(cpu.run_back_to_write(0x80));
+    assert_eq!(cpu.reg.pc, 0x0005);
+    assert_eq!(cpu.reg.a, 0x03);
+    assert_eq!(mem.borrow().get(0x80), 0x02);
+    assert!(cpu.run_back_to(0x0005));
+    assert_eq!(cpu.reg.a, 0x02);
+    assert!(!cpu.run_back_to(0x0010));
+    assert_eq!(cpu.reg.pc, 0x0000);
+    assert_eq!(cpu.cycles, 0);
+    assert_eq!(mem.borrow().get(0x80), 0xaa);
+    assert!(!cpu.step_back());
+
+    // Running forward again gives the same machine.
+    run(&mut cpu);
+    assert_eq!(mem.borrow().get(0x80), 0x03);
+    assert_eq!(cpu.reg.pc, 0x0011);
+}
+
+#[test]
+fn test_budget() {
+    let mut cpu = program();
+    let mut h = History::new(0);
+    h.begin(cpu.reg, false, false, 0);
+    let entry = h.size();
+    cpu.history = Some(History::new(entry * 4));
+    run(&mut cpu);
+    let h = cpu.history.as_ref().unwrap();
+    assert!(h.size() <= entry * 4);
+    assert_eq!(h.len(), 3);
+    assert_eq!(h.last().unwrap().reg.pc, 0x0010);
+    for _ in 0..3 {
+        assert!(cpu.step_back());
+    }
+    assert!(!cpu.step_back());
+    assert_eq!(cpu.reg.pc, 0x000e);
+}
+
+// Code the cache decoded after the program changed it is dropped when going back puts the old bytes.
+//
+// 0000  MVI A,09H
+// 0002  STA 0009H
+// 0005  JMP 0008H
+// 0008  MVI B,01H
+// 000a  HLT
+#[test]
+fn test_step_back_cache() {
+    let mut mem = Linear::new();
+    let code = [0x3e, 0x09, 0x32, 0x09, 0x00, 0xc3, 0x08, 0x00, 0x06, 0x01, 0x76];
+    mem.data[..code.len()].copy_from_slice(&code);
+    let mut cpu = Cpu::power_up(Rc::new(RefCell::new(mem)));
+    cpu.cache = Some(Cache::new());
+    cpu.history = Some(History::new(1 << 20));
+    run(&mut cpu);
+    assert_eq!(cpu.reg.b, 0x09);
+    // Run the changed code again from the cache.
+    let history = cpu.history.take();
+    cpu.halted = false;
+    cpu.reg.pc = 0x0008;
+    cpu.run_block();
+    assert_eq!(cpu.reg.b, 0x09);
+    cpu.history = history;
+    while cpu.step_back() {}
+    cpu.history = None;
+    cpu.reg.pc = 0x0008;
+    cpu.run_block();
+    assert_eq!(cpu.reg.b, 0x01);
+}
@@ -0,0 +1,110 @@
+use i8080::diskimg::Disk;
+use i8080::hostfs::HostDir;
+use std::fs;
+use std::path::PathBuf;
+
+fn temp(name: &str) -> PathBuf {
+    let p = std::env::temp_dir().join(format!("i8080_hostfs_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&p);
+    fs::create_dir_all(&p).unwrap();
+    p
+}
+
+// Records are numbered across the disk: 64 per track, 16 per block.
+fn read(d: &mut HostDir, r: u16) -> [u8; 128] {
+    let mut buf = [0; 128];
+    d.read(r / 64, r % 64, &mut buf).unwrap();
+    buf
+}
+
+fn write(d: &mut HostDir, r: u16, buf: &[u8]) {
+    d.write(r / 64, r % 64, buf).unwrap();
+}
+
+fn entry(user: u8, name: &[u8; 11], rc: u8, blocks: &[u16]) -> [u8; 32] {
+    let mut e = [0; 32];
+    e[0] = user;
+    e[1..12].copy_from_slice(name);
+    e[15] = rc;
+    for (i, b) in blocks.iter().enumerate() {
+        e[16 + 2 * i..18 + 2 * i].copy_from_slice(&b.to_le_bytes());
+    }
+    e
+}
+
+#[test]
+fn test_directory() {
+    let p = temp("directory");
+    fs::write(p.join("hello.txt"), vec![0x41; 200]).unwrap();
+    fs::write(p.join("toolongname.txt"), b"x").unwrap();
+    fs::create_dir_all(p.join("3")).unwrap();
+    fs::write(p.join("3").join("RO.COM"), [0x76]).unwrap();
+    let mut perm = fs::metadata(p.join("3").join("RO.COM")).unwrap().permissions();
+    perm.set_readonly(true);
+    fs::set_permissions(p.join("3").join("RO.COM"), perm).unwrap();
+    let mut d = HostDir::new(&p).unwrap();
+    let dir = read(&mut d, 0);
+    assert_eq!(dir[0], 0);
+    assert_eq!(&dir[1..12], b"HELLO   TXT");
+    assert_eq!(dir[15], 2);
+    assert_eq!(&dir[16..20], &[8, 0, 0, 0]);
+    assert_eq!(dir[32], 3);
+    assert_eq!(&dir[33..41], b"RO      ");
+    assert_eq!(dir[41], b'C' | 0x80);
+    assert_eq!(dir[47], 1);
+    assert_eq!(&dir[48..50], &[9, 0]);
+    assert_eq!(dir[64], 0xe5);
+    let data = read(&mut d, 8 * 16 + 1);
+    assert_eq!(data[..72], [0x41; 72]);
+    assert_eq!(data[72..], [0x1a; 56]);
+    assert!(d.write(0, 9 * 16, &[0; 128]).is_err());
+    fs::remove_dir_all(p.join("3")).unwrap();
+    fs::remove_dir_all(&p).unwrap();
+}
+
+#[test]
+fn test_write() {
+    let p = temp("write");
+    let mut d = HostDir::new(&p).unwrap();
+    // Data first, then the directory entry claiming its block, as the BDOS does on close.
+    write(&mut d, 20 * 16, &[0x55; 128]);
+    write(&mut d, 20 * 16 + 1, &[0x66; 128]);
+    let mut dir = read(&mut d, 0);
+    dir[..32].copy_from_slice(&entry(0, b"NEW     DAT", 2, &[20]));
+    write(&mut d, 0, &dir);
+    let data = fs::read(p.join("NEW.DAT")).unwrap();
+    assert_eq!(data.len(), 256);
+    assert_eq!(data[..128], [0x55; 128]);
+    assert_eq!(data[128..], [0x66; 128]);
+    // Blocks owned by a file are written through.
+    write(&mut d, 20 * 16 + 1, &[0x77; 128]);
+    assert_eq!(fs::read(p.join("NEW.DAT")).unwrap()[128..], [0x77; 128]);
+    // Rename into user 2.
+    dir[..32].copy_from_slice(&entry(2, b"OLD     DAT", 2, &[20]));
+    write(&mut d, 0, &dir);
+    assert!(!p.join("NEW.DAT").exists());
+    assert_eq!(fs::read(p.join("2").join("OLD.DAT")).unwrap().len(), 256);
+    // Truncate, then delete.
+    dir[..32].copy_from_slice(&entry(2, b"OLD     DAT", 1, &[20]));
+    write(&mut d, 0, &dir);
+    assert_eq!(fs::read(p.join("2").join("OLD.DAT")).unwrap().len(), 128);
+    dir[0] = 0xe5;
+    write(&mut d, 0, &dir);
+    assert!(!p.join("2").join("OLD.DAT").exists());
+    fs::remove_dir_all(&p).unwrap();
+}
+
+#[test]
+fn test_refresh() {
+    let p = temp("refresh");
+    fs::write(p.join("A.TXT"), b"a").unwrap();
+    let mut d = HostDir::new(&p).unwrap();
+    fs::write(p.join("B.TXT"), vec![0x42; 3000]).unwrap();
+    let dir = read(&mut d, 0);
+    assert_eq!(&dir[1..12], b"A       TXT");
+    assert_eq!(&dir[16..18], &[8, 0]);
+    assert_eq!(&dir[33..44], b"B       TXT");
+    assert_eq!(dir[47], 24);
+    assert_eq!(&dir[48..52], &[9, 0, 10, 0]);
+    fs::remove_dir_all(&p).unwrap();
+}
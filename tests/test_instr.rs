@@ -91,11 +91,11 @@ fn test_add_1() {
     mem.borrow_mut().set(0x0000, 0x82);
     cpu.next();
     assert_eq!(cpu.reg.a, 0x9a);
-    assert_eq!(cpu.reg.get_flag(Flag::S), true);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::A), true);
-    assert_eq!(cpu.reg.get_flag(Flag::P), true);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(cpu.reg.get_flag(Flag::S));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(cpu.reg.get_flag(Flag::A));
+    assert!(cpu.reg.get_flag(Flag::P));
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -117,11 +117,11 @@ fn test_adc_1() {
     mem.borrow_mut().set(0x0000, 0x89);
     cpu.next();
     assert_eq!(cpu.reg.a, 0x7f);
-    assert_eq!(cpu.reg.get_flag(Flag::S), false);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::A), false);
-    assert_eq!(cpu.reg.get_flag(Flag::P), false);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::S));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(!cpu.reg.get_flag(Flag::A));
+    assert!(!cpu.reg.get_flag(Flag::P));
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -134,11 +134,11 @@ fn test_adc_2() {
     mem.borrow_mut().set(0x0000, 0x89);
     cpu.next();
     assert_eq!(cpu.reg.a, 0x80);
-    assert_eq!(cpu.reg.get_flag(Flag::S), true);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::A), true);
-    assert_eq!(cpu.reg.get_flag(Flag::P), false);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(cpu.reg.get_flag(Flag::S));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(cpu.reg.get_flag(Flag::A));
+    assert!(!cpu.reg.get_flag(Flag::P));
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -161,11 +161,11 @@ fn test_sub() {
     mem.borrow_mut().set(0x0000, 0x97);
     cpu.next();
     assert_eq!(cpu.reg.a, 0x00);
-    assert_eq!(cpu.reg.get_flag(Flag::S), false);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), true);
-    assert_eq!(cpu.reg.get_flag(Flag::A), true);
-    assert_eq!(cpu.reg.get_flag(Flag::P), true);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::S));
+    assert!(cpu.reg.get_flag(Flag::Z));
+    assert!(cpu.reg.get_flag(Flag::A));
+    assert!(cpu.reg.get_flag(Flag::P));
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -178,11 +178,11 @@ fn test_sbb() {
     mem.borrow_mut().set(0x0000, 0x9d);
     cpu.next();
     assert_eq!(cpu.reg.a, 0x01);
-    assert_eq!(cpu.reg.get_flag(Flag::S), false);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::A), true);
-    assert_eq!(cpu.reg.get_flag(Flag::P), false);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::S));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(cpu.reg.get_flag(Flag::A));
+    assert!(!cpu.reg.get_flag(Flag::P));
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -249,8 +249,8 @@ fn test_cmp_1() {
     cpu.next();
     assert_eq!(cpu.reg.a, 0x0a);
     assert_eq!(cpu.reg.e, 0x05);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -263,8 +263,8 @@ fn test_cmp_2() {
     cpu.next();
     assert_eq!(cpu.reg.a, 0x02);
     assert_eq!(cpu.reg.e, 0x05);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::C), true);
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -277,8 +277,8 @@ fn test_cmp_3() {
     cpu.next();
     assert_eq!(cpu.reg.a, 0xe5);
     assert_eq!(cpu.reg.e, 0x05);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -289,7 +289,7 @@ fn test_rlc() {
     mem.borrow_mut().set(0x0000, 0x07);
     cpu.next();
     assert_eq!(cpu.reg.a, 0xe5);
-    assert_eq!(cpu.reg.get_flag(Flag::C), true);
+    assert!(cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -300,7 +300,7 @@ fn test_rrc() {
     mem.borrow_mut().set(0x0000, 0x0f);
     cpu.next();
     assert_eq!(cpu.reg.a, 0x79);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -311,7 +311,7 @@ fn test_ral() {
     mem.borrow_mut().set(0x0000, 0x17);
     cpu.next();
     assert_eq!(cpu.reg.a, 0x6a);
-    assert_eq!(cpu.reg.get_flag(Flag::C), true);
+    assert!(cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -323,7 +323,7 @@ fn test_rar() {
     mem.borrow_mut().set(0x0000, 0x1f);
     cpu.next();
     assert_eq!(cpu.reg.a, 0xb5);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -381,11 +381,11 @@ fn test_stack_pop_2() {
     cpu.next();
     assert_eq!(cpu.reg.a, 0xff);
     assert_eq!(cpu.reg.f, 0xc3);
-    assert_eq!(cpu.reg.get_flag(Flag::S), true);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), true);
-    assert_eq!(cpu.reg.get_flag(Flag::A), false);
-    assert_eq!(cpu.reg.get_flag(Flag::P), false);
-    assert_eq!(cpu.reg.get_flag(Flag::C), true);
+    assert!(cpu.reg.get_flag(Flag::S));
+    assert!(cpu.reg.get_flag(Flag::Z));
+    assert!(!cpu.reg.get_flag(Flag::A));
+    assert!(!cpu.reg.get_flag(Flag::P));
+    assert!(cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -400,7 +400,7 @@ fn test_dad_1() {
     cpu.next();
     assert_eq!(cpu.reg.h, 0xd5);
     assert_eq!(cpu.reg.l, 0x1a);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -524,11 +524,11 @@ fn test_adi() {
     cpu.next();
     cpu.next();
     assert_eq!(cpu.reg.a, 0x14);
-    assert_eq!(cpu.reg.get_flag(Flag::S), false);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::A), true);
-    assert_eq!(cpu.reg.get_flag(Flag::P), true);
-    assert_eq!(cpu.reg.get_flag(Flag::C), true);
+    assert!(!cpu.reg.get_flag(Flag::S));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(cpu.reg.get_flag(Flag::A));
+    assert!(cpu.reg.get_flag(Flag::P));
+    assert!(cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -558,11 +558,11 @@ fn test_sui() {
     cpu.next();
     cpu.next();
     assert_eq!(cpu.reg.a, 0xff);
-    assert_eq!(cpu.reg.get_flag(Flag::S), true);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::A), false);
-    assert_eq!(cpu.reg.get_flag(Flag::P), true);
-    assert_eq!(cpu.reg.get_flag(Flag::C), true);
+    assert!(cpu.reg.get_flag(Flag::S));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(!cpu.reg.get_flag(Flag::A));
+    assert!(cpu.reg.get_flag(Flag::P));
+    assert!(cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -573,11 +573,11 @@ fn test_sbi_1() {
     mem.borrow_mut().set(0x0001, 0x01);
     cpu.next();
     assert_eq!(cpu.reg.a, 0xff);
-    assert_eq!(cpu.reg.get_flag(Flag::S), true);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::A), false);
-    assert_eq!(cpu.reg.get_flag(Flag::P), true);
-    assert_eq!(cpu.reg.get_flag(Flag::C), true);
+    assert!(cpu.reg.get_flag(Flag::S));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(!cpu.reg.get_flag(Flag::A));
+    assert!(cpu.reg.get_flag(Flag::P));
+    assert!(cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -589,11 +589,11 @@ fn test_sbi_2() {
     mem.borrow_mut().set(0x0001, 0x01);
     cpu.next();
     assert_eq!(cpu.reg.a, 0xfe);
-    assert_eq!(cpu.reg.get_flag(Flag::S), true);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::A), false);
-    assert_eq!(cpu.reg.get_flag(Flag::P), false);
-    assert_eq!(cpu.reg.get_flag(Flag::C), true);
+    assert!(cpu.reg.get_flag(Flag::S));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(!cpu.reg.get_flag(Flag::A));
+    assert!(!cpu.reg.get_flag(Flag::P));
+    assert!(cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -618,7 +618,7 @@ fn test_xri() {
     mem.borrow_mut().set(0x0001, 0x81);
     cpu.next();
     assert_eq!(cpu.reg.a, 0xba);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -632,7 +632,7 @@ fn test_ori() {
     cpu.next();
     cpu.next();
     assert_eq!(cpu.reg.a, 0xbf);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -646,8 +646,8 @@ fn test_cpi() {
     cpu.next();
     cpu.next();
     assert_eq!(cpu.reg.a, 0x4a);
-    assert_eq!(cpu.reg.get_flag(Flag::Z), false);
-    assert_eq!(cpu.reg.get_flag(Flag::C), false);
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(!cpu.reg.get_flag(Flag::C));
 }
 
 #[test]
@@ -0,0 +1,74 @@
+use i8080::invaders::{Button, Dips, Event, Invaders, Sound};
+use i8080::{Device, Memory};
+
+// Count the interrupts in RAM, draw a byte at mid screen and write the vblank count to the sound port.
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x2000];
+    let mut put = |a: usize, code: &[u8]| rom[a..a + code.len()].copy_from_slice(code);
+    put(0x00, &[0x31, 0x00, 0x24, 0xfb, 0xc3, 0x04, 0x00]);
+    put(0x08, &[0xc3, 0x20, 0x00]);
+    put(0x10, &[0xc3, 0x40, 0x00]);
+    put(0x20, &[0xf5, 0x3a, 0x01, 0x20, 0x3c, 0x32, 0x01, 0x20, 0x3e, 0xff, 0x32, 0x00, 0x24, 0xf1, 0xfb, 0xc9]);
+    put(0x40, &[0xf5, 0x3a, 0x02, 0x20, 0x3c, 0x32, 0x02, 0x20, 0xd3, 0x03, 0xf1, 0xfb, 0xc9]);
+    rom
+}
+
+#[test]
+fn test_frame() {
+    let mut m = Invaders::new(&rom());
+    // The vertical blank handler of the last frame runs at the start of the next one.
+    for _ in 0..3 {
+        m.run_frame();
+    }
+    assert_eq!(m.frame, 3);
+    assert_eq!(m.cpu.reg.pc, 0x0010);
+    assert_eq!(m.mem.borrow().get(0x2001), 3);
+    assert_eq!(m.mem.borrow().get(0x2002), 2);
+    assert!((0..8).all(|x| m.pixel(x, 0)));
+    assert!(!m.pixel(8, 0));
+    assert_eq!(m.framebuffer().len(), 7168);
+    assert_eq!(m.framebuffer()[0], 0xff);
+    assert_eq!(
+        m.events(),
+        vec![
+            Event { sound: Sound::Ufo, on: true },
+            Event { sound: Sound::Ufo, on: false },
+            Event { sound: Sound::Shot, on: true },
+        ]
+    );
+    assert!(m.events().is_empty());
+}
+
+#[test]
+fn test_rom() {
+    let m = Invaders::new(&rom());
+    m.mem.borrow_mut().set(0x0000, 0x00);
+    assert_eq!(m.mem.borrow().get(0x0000), 0x31);
+    m.mem.borrow_mut().set(0x6001, 0x12);
+    assert_eq!(m.mem.borrow().get(0x2001), 0x12);
+}
+
+#[test]
+fn test_io() {
+    let mut m = Invaders::new(&rom());
+    let io = m.io.clone();
+    assert_eq!(io.borrow_mut().get(1), 0x08);
+    m.press(Button::Coin);
+    m.press(Button::Left2);
+    assert_eq!(io.borrow_mut().get(1), 0x09);
+    assert_eq!(io.borrow_mut().get(2), 0x20);
+    m.release(Button::Coin);
+    assert_eq!(io.borrow_mut().get(1), 0x08);
+    m.set_dips(Dips { lives: 5, bonus_at_1000: true, coin_info: false });
+    assert_eq!(io.borrow_mut().get(2), 0xaa);
+    let mut io = io.borrow_mut();
+    io.set(4, 0xab);
+    io.set(4, 0xcd);
+    io.set(2, 0x00);
+    assert_eq!(io.get(3), 0xcd);
+    io.set(2, 0x04);
+    assert_eq!(io.get(3), 0xda);
+    io.set(5, 0x11);
+    assert_eq!(io.events.len(), 2);
+    assert_eq!(io.events[1], Event { sound: Sound::UfoHit, on: true });
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "jit")]
+use i8080::block::Cache;
+use i8080::jit::Jit;
+use i8080::{Cpu, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn cpu(code: &[u8]) -> Cpu {
+    let mut mem = Linear::new();
+    mem.data[..code.len()].copy_from_slice(code);
+    Cpu::power_up(Rc::new(RefCell::new(mem)))
+}
+
+fn jit(c: &mut Cpu, lockstep: bool) {
+    let mut jit = Jit::new().unwrap();
+    jit.threshold = 1;
+    jit.lockstep = lockstep;
+    c.cache = Some(Cache::new());
+    c.jit = Some(jit);
+}
+
+// The ALU instructions with every operand, A from 00 to ff by 11 and either carry, checked in lockstep.
+#[test]
+fn test_alu() {
+    // 0000  LXI SP,0000H
+    // 0003  MVI C,00H
+    // 0005  MVI B,00H
+    // 0007  MOV A,C
+    // 0008  STC
+    // 0009  op B
+    // 000a  PUSH PSW
+    // 000b  POP H
+    // 000c  MOV A,C
+    // 000d  STC
+    // 000e  CMC
+    // 000f  op B
+    // 0010  PUSH PSW
+    // 0011  POP D
+    // 0012  INR B
+    // 0013  JNZ 0007H
+    // 0016  MOV A,C
+    // 0017  ADI 11H
+    // 0019  MOV C,A
+    // 001a  JNC 0005H
+    // 001d  HLT
+    for op in [0x80, 0x88, 0x90, 0x98, 0xa0, 0xa8, 0xb0, 0xb8, 0x3c, 0x3d, 0x07, 0x0f, 0x17, 0x1f, 0x2f] {
+        let code = [
+            0x31, 0x00, 0x00, 0x0e, 0x00, 0x06, 0x00, 0x79, 0x37, op, 0xf5, 0xe1, 0x79, 0x37, 0x3f, op, 0xf5, 0xd1,
+            0x04, 0xc2, 0x07, 0x00, 0x79, 0xc6, 0x11, 0x4f, 0xd2, 0x05, 0x00, 0x76,
+        ];
+        let mut c = cpu(&code);
+        jit(&mut c, true);
+        c.run_until(u64::MAX);
+        let jit = c.jit.as_ref().unwrap();
+        assert_eq!(jit.divergence, None, "op {:02x}", op);
+        assert!(jit.stats.runs > 0x1000);
+    }
+}
+
+// Code writing into its own block is compiled again, and ends as the interpreter does.
+#[test]
+fn test_self_modifying() {
+    // 0000  LXI SP,0000H
+    // 0003  MVI B,40H
+    // 0005  MVI A,00H, its operand counting up
+    // 0007  ADD D
+    // 0008  MOV D,A
+    // 0009  LXI H,0006H
+    // 000c  INR M
+    // 000d  DCR B
+    // 000e  JNZ 0005H
+    // 0011  HLT
+    let code =
+        [0x31, 0x00, 0x00, 0x06, 0x40, 0x3e, 0x00, 0x82, 0x57, 0x21, 0x06, 0x00, 0x34, 0x05, 0xc2, 0x05, 0x00, 0x76];
+    let mut c = cpu(&code);
+    jit(&mut c, true);
+    c.run_until(u64::MAX);
+    assert_eq!(c.reg.d, 0xe0);
+    let jit = c.jit.as_ref().unwrap();
+    assert_eq!(jit.divergence, None);
+    // The block at 0005 eight times, as many as it is compiled, the blocks at 0000 and 000d once.
+    assert_eq!(jit.stats.compiled, 2 + 8);
+
+    let mut d = cpu(&code);
+    while !d.halted {
+        d.next();
+    }
+    assert_eq!(c.reg, d.reg);
+    assert_eq!(c.cycles, d.cycles);
+}
+
+// Random code runs to the same state compiled as one instruction at a time.
+#[test]
+fn test_random() {
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut rand = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let mut runs = 0;
+    for _ in 0..25 {
+        let mut mem = Linear::new();
+        for e in mem.data.iter_mut() {
+            *e = rand() as u8;
+        }
+        let a = Rc::new(RefCell::new(Linear { data: mem.data.clone() }));
+        let b = Rc::new(RefCell::new(mem));
+        let mut x = Cpu::power_up(a.clone());
+        let mut y = Cpu::power_up(b.clone());
+        x.reg.sp = rand() as u16;
+        y.reg.sp = x.reg.sp;
+        jit(&mut y, false);
+        let until = 20_000 + rand() % 20_000;
+        while x.cycles < until && !x.halted {
+            x.next();
+        }
+        y.run_until(until);
+        assert_eq!(x.reg, y.reg);
+        assert_eq!((x.cycles, x.halted, x.inte), (y.cycles, y.halted, y.inte));
+        assert!(a.borrow().data == b.borrow().data);
+        runs += y.jit.as_ref().unwrap().stats.runs;
+    }
+    assert!(runs > 0);
+}
@@ -0,0 +1,167 @@
+use i8080::loader::{self, Format};
+use i8080::{prn, rel, Linear, Memory};
+
+#[derive(Default)]
+struct Bits {
+    data: Vec<u8>,
+    n: usize,
+}
+
+impl Bits {
+    fn put(&mut self, n: usize, v: u16) -> &mut Self {
+        for i in (0..n).rev() {
+            if self.n & 7 == 0 {
+                self.data.push(0);
+            }
+            if (v >> i) & 1 != 0 {
+                *self.data.last_mut().unwrap() |= 0x80 >> (self.n % 8);
+            }
+            self.n += 1;
+        }
+        self
+    }
+
+    fn byte(&mut self, v: u8) -> &mut Self {
+        self.put(1, 0).put(8, u16::from(v))
+    }
+
+    fn word(&mut self, t: u16, v: u16) -> &mut Self {
+        self.put(1, 1).put(2, t).put(8, v & 0xff).put(8, v >> 8)
+    }
+
+    fn link(&mut self, c: u16, a: Option<(u16, u16)>, b: Option<&str>) -> &mut Self {
+        self.put(1, 1).put(2, 0).put(4, c);
+        if let Some((t, v)) = a {
+            self.put(2, t).put(8, v & 0xff).put(8, v >> 8);
+        }
+        if let Some(s) = b {
+            self.put(3, s.len() as u16);
+            for c in s.bytes() {
+                self.put(8, u16::from(c));
+            }
+        }
+        if c == 14 {
+            self.n = self.n.div_ceil(8) * 8;
+        }
+        self
+    }
+}
+
+#[test]
+fn test_detect() {
+    assert_eq!(Format::detect("A.COM", &[]), Format::Com);
+    assert_eq!(Format::detect("a.hex", &[]), Format::Hex);
+    assert_eq!(Format::detect("a.prn", &[]), Format::Prn);
+    assert_eq!(Format::detect("a.rel", &[]), Format::Rel);
+    assert_eq!(Format::detect("a", b":00000001FF\n"), Format::Hex);
+    assert_eq!(Format::detect("a", &[0xc3, 0x00, 0x01]), Format::Bin);
+}
+
+#[test]
+fn test_com_bin() {
+    let mut mem = Linear::new();
+    let image = loader::load(&mut mem, Format::Com, &[0x3e, 0x01], 0x8000).unwrap();
+    assert_eq!(image.entry, 0x0100);
+    assert_eq!(image.ranges(), vec![0x0100..=0x0101]);
+    assert_eq!(mem.get(0x0101), 0x01);
+    let image = loader::load(&mut mem, Format::Bin, &[0x76], 0x8000).unwrap();
+    assert_eq!(image.entry, 0x8000);
+    assert_eq!(mem.get(0x8000), 0x76);
+    assert!(loader::decode(Format::Bin, &[0x00; 2], 0xffff).is_err());
+}
+
+#[test]
+fn test_prn_packed() {
+    let s = "                ; test\n 0100                   ORG     0100H\n 0100 C30301    START:  JMP     NEXT\n 0103 3E01      NEXT:   MVI     A,1\n 0105 76                HLT\n";
+    let lines = prn::parse(s);
+    assert_eq!(lines[2].addr, Some(0x0100));
+    assert_eq!(lines[2].data, vec![0xc3, 0x03, 0x01]);
+    assert_eq!(lines[2].label(), Some("START"));
+    assert_eq!(lines[4].source, "HLT");
+    let image = loader::decode(Format::Prn, s.as_bytes(), 0).unwrap();
+    assert_eq!(image.data, vec![(0x0100, vec![0xc3, 0x03, 0x01, 0x3e, 0x01, 0x76])]);
+    assert_eq!(image.symbols, vec![("START".to_string(), 0x0100), ("NEXT".to_string(), 0x0103)]);
+    assert_eq!(image.entry, 0x0100);
+}
+
+#[test]
+fn test_prn_spaced() {
+    let s = "  0000'   C3 0003'        START:  JMP NEXT\n  0003'   DB 10           NEXT:   IN 10H\n  0005'   76                      HLT\n";
+    let lines = prn::parse(s);
+    assert_eq!(lines[0].data, vec![0xc3, 0x03, 0x00]);
+    assert_eq!(lines[1].data, vec![0xdb, 0x10]);
+    assert_eq!(lines[1].label(), Some("NEXT"));
+    assert_eq!(lines[2].data, vec![0x76]);
+}
+
+#[test]
+fn test_rel() {
+    // Two modules: MAIN calls PUTC, which is defined in a second module.
+    let mut b = Bits::default();
+    b.link(2, None, Some("MAIN"));
+    b.link(13, Some((1, 0x0004)), None);
+    b.byte(0xcd).byte(0x00).byte(0x00).byte(0x76);
+    b.link(6, Some((1, 0x0001)), Some("PUTC"));
+    b.link(14, Some((1, 0x0000)), None);
+    b.link(2, None, Some("LIB"));
+    b.link(7, Some((1, 0x0000)), Some("PUTC"));
+    b.link(13, Some((1, 0x0003)), None);
+    b.byte(0xd3).byte(0x01).byte(0xc9);
+    b.link(14, Some((0, 0x0000)), None);
+    b.link(15, None, None);
+    let image = rel::decode(&b.data, 0x4000).unwrap();
+    assert_eq!(image.entry, 0x4000);
+    assert_eq!(image.symbols, vec![("PUTC".to_string(), 0x4004)]);
+    assert_eq!(image.data, vec![(0x4000, vec![0xcd, 0x04, 0x40, 0x76, 0xd3, 0x01, 0xc9])]);
+}
+
+// Both modules declare COMMON /X/ and get the same block, placed after the first module.
+#[test]
+fn test_rel_common() {
+    let mut b = Bits::default();
+    for (name, offset, v) in [("A", 0, 0x11), ("B", 1, 0x22)] {
+        b.link(2, None, Some(name));
+        b.link(5, Some((0, 0x0002)), Some("X"));
+        b.link(13, Some((1, 0x0003)), None);
+        b.link(1, None, Some("X"));
+        b.byte(0x32).word(3, offset);
+        b.link(11, Some((3, offset)), None);
+        b.byte(v);
+        b.link(14, Some((0, 0x0000)), None);
+    }
+    b.link(15, None, None);
+    let image = rel::decode(&b.data, 0x0100).unwrap();
+    assert_eq!(image.data, vec![(0x0100, vec![0x32, 0x03, 0x01, 0x11, 0x22, 0x32, 0x04, 0x01])]);
+}
+
+#[test]
+fn test_rel_relocation() {
+    let mut b = Bits::default();
+    b.link(13, Some((1, 0x0003)), None);
+    b.link(10, Some((0, 0x0002)), None);
+    b.byte(0xc3).word(1, 0x0000);
+    b.link(11, Some((2, 0x0000)), None);
+    b.word(2, 0x0001);
+    b.link(14, Some((0, 0x0000)), None);
+    b.link(15, None, None);
+    let image = rel::decode(&b.data, 0x0100).unwrap();
+    assert_eq!(image.data, vec![(0x0100, vec![0xc3, 0x00, 0x01, 0x04, 0x01])]);
+    assert!(rel::decode(&b.data[..3], 0x0100).is_err());
+}
+
+// EQU and ORG lines have an address but no code, and neither starts the program.
+#[test]
+fn test_prn_equ() {
+    let s = " 0005 =         BDOS    EQU     5\n 0100                   ORG     100H\n 0100 0E09      START:  MVI     C,9\n 0102 CD0500            CALL    BDOS\n";
+    let image = loader::decode(Format::Prn, s.as_bytes(), 0).unwrap();
+    assert_eq!(image.data, vec![(0x0100, vec![0x0e, 0x09, 0xcd, 0x05, 0x00])]);
+    assert_eq!(image.entry, 0x0100);
+    assert_eq!(image.ranges(), vec![0x0100..=0x0104]);
+    assert_eq!(image.symbols, vec![("START".to_string(), 0x0100)]);
+}
+
+#[test]
+fn test_ranges_full() {
+    let image = loader::decode(Format::Bin, &vec![0; 0x10000], 0).unwrap();
+    assert_eq!(image.ranges(), vec![0x0000..=0xffff]);
+}
@@ -0,0 +1,112 @@
+use i8080::block::Cache;
+use i8080::lockstep::Lockstep;
+use i8080::{Cpu, Flag, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn pair(data: &[u8]) -> (Cpu, Cpu) {
+    let mut a = Linear::new();
+    let mut b = Linear::new();
+    a.data[..data.len()].copy_from_slice(data);
+    b.data[..data.len()].copy_from_slice(data);
+    (Cpu::power_up(Rc::new(RefCell::new(a))), Cpu::power_up(Rc::new(RefCell::new(b))))
+}
+
+// 0000  LXI SP,0100H
+// 0003  MVI B,08H
+// 0005  LXI H,0200H
+// 0008  MOV A,B
+// 0009  ADI 0FH
+// 000b  MOV M,A
+// 000c  INX H
+// 000d  DCR B
+// 000e  JNZ 0008H
+// 0011  HLT
+const PROGRAM: [u8; 18] =
+    [0x31, 0x00, 0x01, 0x06, 0x08, 0x21, 0x00, 0x02, 0x78, 0xc6, 0x0f, 0x77, 0x23, 0x05, 0xc2, 0x08, 0x00, 0x76];
+
+// Random code runs the same with the block cache as with the interpreter.
+#[test]
+fn test_blocks() {
+    let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+    let mut rand = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    for _ in 0..20 {
+        let data: Vec<u8> = (0..0x10000).map(|_| rand() as u8).collect();
+        let (mut x, mut y) = pair(&data);
+        x.reg.sp = rand() as u16;
+        y.reg.sp = x.reg.sp;
+        y.cache = Some(Cache::new());
+        let mut l = Lockstep::new(x, y, 4);
+        assert!(l.run_until(20_000), "{}", l.divergence.unwrap().report());
+        assert!(l.steps > 0);
+    }
+
+    let (x, mut y) = pair(&PROGRAM);
+    y.cache = Some(Cache::new());
+    let mut l = Lockstep::new(x, y, 4);
+    assert!(l.run_until(u64::MAX));
+    assert!(l.other.halted);
+    assert_eq!(l.steps, 3 + 8 * 6 + 1);
+}
+
+// An engine getting AC wrong after the ADI is stopped there, with the instructions before it.
+#[test]
+fn test_divergence() {
+    let (x, y) = pair(&PROGRAM);
+    let mut l = Lockstep::new(x, y, 3);
+    let ok = l.run(u64::MAX, |cpu| {
+        cpu.run_block();
+        if cpu.reg.pc == 0x000b && cpu.reg.b == 0x03 {
+            cpu.reg.set_flag(Flag::A, !cpu.reg.get_flag(Flag::A));
+        }
+    });
+    assert!(!ok);
+    let d = l.divergence.as_ref().unwrap();
+    // Five times round the loop, then MOV A,B.
+    assert_eq!(d.index, 3 + 5 * 6 + 1);
+    assert_eq!(d.step.pc, 0x0009);
+    assert_eq!(d.before.iter().map(|e| e.pc).collect::<Vec<_>>(), [0x000d, 0x000e, 0x0008]);
+    assert_eq!(d.differences(), ["f expected 16 got 06 (A)"]);
+    let report = d.report();
+    assert!(report.starts_with("  000d  05"));
+    assert!(report.ends_with("diverged at instruction 34: f expected 16 got 06 (A)"));
+}
+
+// A write the other cpu does not make is reported.
+#[test]
+fn test_writes() {
+    let (x, y) = pair(&PROGRAM);
+    let mut l = Lockstep::new(x, y, 0);
+    let ok = l.run(u64::MAX, |cpu| {
+        cpu.run_block();
+        if cpu.reg.pc == 0x000c && cpu.reg.b == 0x08 {
+            cpu.mem.borrow_mut().set(0x0300, 0x01);
+        }
+    });
+    assert!(!ok);
+    let d = l.divergence.as_ref().unwrap();
+    assert_eq!(d.index, 5);
+    assert!(d.before.is_empty());
+    assert_eq!(d.differences(), ["writes expected [0200=17] got [0200=17 0300=01]"]);
+}
+
+// The JIT, compiling every block, runs the loop as one block and ends it in the same state as the interpreter.
+#[cfg(feature = "jit")]
+#[test]
+fn test_jit() {
+    let (x, mut y) = pair(&PROGRAM);
+    let mut jit = i8080::jit::Jit::new().unwrap();
+    jit.threshold = 1;
+    y.cache = Some(Cache::new());
+    y.jit = Some(jit);
+    let mut l = Lockstep::new(x, y, 4);
+    assert!(l.run_until(u64::MAX), "{}", l.divergence.unwrap().report());
+    let stats = l.other.jit.as_ref().unwrap().stats;
+    assert!(stats.runs > 0);
+    assert!(stats.ops > stats.runs);
+}
@@ -0,0 +1,80 @@
+use i8080::midway::{self, Board, Button, Event};
+use i8080::{Device, Memory};
+
+#[test]
+fn test_games() {
+    for g in midway::GAMES {
+        assert_eq!(midway::game(g.name).unwrap().name, g.name);
+        let b = Board::new(g, &vec![0xaa; g.rom_size()]);
+        for r in g.roms {
+            assert_eq!(b.mem.borrow().get(r.addr), 0xaa);
+            assert_eq!(b.mem.borrow().get(r.addr + r.size as u16 - 1), 0xaa);
+        }
+    }
+    assert!(midway::game("pacman").is_none());
+}
+
+#[test]
+fn test_map() {
+    let b = Board::new(&midway::LUNAR_RESCUE, &[0x11; 0x3000]);
+    let mut mem = b.mem.borrow_mut();
+    assert_eq!(mem.get(0x4000), 0x11);
+    assert_eq!(mem.get(0x4fff), 0x11);
+    assert_eq!(mem.get(0x5000), 0x00);
+    mem.set(0x4000, 0x22);
+    assert_eq!(mem.get(0x4000), 0x11);
+    mem.set(0x2400, 0x33);
+    assert_eq!(mem.get(0x6400), 0x33);
+    assert_eq!(mem.get(0xa400), 0x33);
+    assert_eq!(mem.get(0x8000), 0x11);
+}
+
+#[test]
+fn test_gunfight() {
+    let mut b = Board::new(&midway::GUN_FIGHT, &[]);
+    let io = b.io.clone();
+    // Sticks and triggers are active low, the time switches default to 1.
+    assert_eq!(io.borrow_mut().get(0), 0x8f);
+    assert_eq!(io.borrow_mut().get(2), 0x04);
+    b.press(Button::Fire1);
+    b.press(Button::Coin);
+    assert_eq!(io.borrow_mut().get(0), 0x0f);
+    assert_eq!(io.borrow_mut().get(2), 0x44);
+    b.release(Button::Fire1);
+    assert_eq!(io.borrow_mut().get(0), 0x8f);
+    assert!(b.set_dip("coinage", 3));
+    assert!(!b.set_dip("lives", 3));
+    assert_eq!(io.borrow_mut().get(2), 0x47);
+    let mut io = io.borrow_mut();
+    io.set(3, 0x12);
+    io.set(3, 0x34);
+    io.set(2, 0x00);
+    assert_eq!(io.get(3), 0x34);
+    io.set(1, 0x81);
+    assert_eq!(io.events, [Event { port: 1, bit: 0, on: true }, Event { port: 1, bit: 7, on: true }]);
+    io.watchdog = 5;
+    io.set(4, 0x00);
+    assert_eq!(io.watchdog, 0);
+}
+
+#[test]
+fn test_seawolf() {
+    let b = Board::new(&midway::SEA_WOLF, &[]);
+    let mut io = b.io.borrow_mut();
+    io.set(3, 0x01);
+    io.set(3, 0x80);
+    io.set(4, 0x00);
+    assert_eq!(io.get(3), 0x80);
+    assert_eq!(io.get(0), 0x01);
+}
+
+#[test]
+fn test_overlay() {
+    let g = &midway::INVADERS;
+    assert_eq!(g.color(200, 100), [0xff, 0x20, 0x20]);
+    assert_eq!(g.color(20, 100), [0x20, 0xff, 0x20]);
+    assert_eq!(g.color(8, 20), [0x20, 0xff, 0x20]);
+    assert_eq!(g.color(8, 200), [0xff; 3]);
+    assert_eq!(g.color(120, 100), [0xff; 3]);
+    assert_eq!(midway::BOOT_HILL.color(200, 100), [0xff; 3]);
+}
@@ -0,0 +1,59 @@
+use i8080::midway::{self, Board};
+use i8080::pixmap::{self, Image};
+use i8080::Memory;
+
+fn image() -> Image {
+    let mut img = Image::new(3, 2);
+    img.set(0, 0, [0xff, 0x00, 0x00]);
+    img.set(2, 1, [0x01, 0x02, 0x03]);
+    img
+}
+
+#[test]
+fn test_ppm() {
+    let r = image().ppm();
+    assert_eq!(&r[..11], b"P6\n3 2\n255\n");
+    assert_eq!(r.len(), 11 + 18);
+    assert_eq!(&r[11..14], &[0xff, 0x00, 0x00]);
+    assert_eq!(&r[26..29], &[0x01, 0x02, 0x03]);
+}
+
+#[test]
+fn test_png() {
+    let r = image().png().unwrap();
+    assert_eq!(&r[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&r[8..16], b"\x00\x00\x00\x0dIHDR");
+    assert_eq!(&r[16..29], &[0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+    assert_eq!(u32::from_be_bytes(r[29..33].try_into().unwrap()), pixmap::crc32(&r[12..29]));
+    assert_eq!(&r[r.len() - 12..], b"\x00\x00\x00\x00IEND\xae\x42\x60\x82");
+    // A single stored block holding two filtered rows.
+    let n = u32::from_be_bytes(r[33..37].try_into().unwrap()) as usize;
+    assert_eq!(&r[37..41], b"IDAT");
+    let z = &r[41..41 + n];
+    assert_eq!(&z[..3], &[0x78, 0x01, 0x01]);
+    assert_eq!(&z[3..7], &[20, 0, !20, 0xff]);
+    assert_eq!(&z[7..27], &[0, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]);
+    assert_eq!(z.len(), 31);
+    assert_eq!(pixmap::crc32(b"123456789"), 0xcbf43926);
+    assert!(Image::new(0, 2).png().is_err());
+    assert!(Image::new(3, 0).png().is_err());
+}
+
+#[test]
+fn test_screenshot() {
+    let b = Board::new(&midway::INVADERS, &[]);
+    // Leftmost pixel of the first line, and a pixel in the saucer band of line 100.
+    b.mem.borrow_mut().set(0x2400, 0x01);
+    b.mem.borrow_mut().set(0x2400 + 100 * 32 + 25, 0x01);
+    let img = b.screenshot(true);
+    assert_eq!((img.width, img.height), (224, 256));
+    assert_eq!(img.get(0, 255), [0xff; 3]);
+    assert_eq!(img.get(100, 255 - 200), [0xff, 0x20, 0x20]);
+    assert_eq!(img.get(0, 0), [0x00; 3]);
+    assert_eq!(b.screenshot(false).get(100, 55), [0xff; 3]);
+    let b = Board::new(&midway::GUN_FIGHT, &[]);
+    b.mem.borrow_mut().set(0x2400 + 32 + 1, 0x80);
+    let img = b.screenshot(true);
+    assert_eq!((img.width, img.height), (256, 224));
+    assert_eq!(img.get(15, 1), [0xff; 3]);
+}
@@ -0,0 +1,135 @@
+use i8080::profile::{self, Flow, Profile};
+use i8080::trace::Record;
+use i8080::{Cpu, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 0000  LXI SP,0100H
+// 0003  MVI B,03H
+// 0005  CALL 0010H
+// 0008  DCR B
+// 0009  JNZ 0005H
+// 000c  HLT
+// 0010  CALL 0020H      outer
+// 0013  RET
+// 0020  NOP             inner
+// 0021  RZ              not taken
+// 0022  RET
+const PROG: &[(u16, &[u8])] = &[
+    (0x0000, &[0x31, 0x00, 0x01, 0x06, 0x03, 0xcd, 0x10, 0x00, 0x05, 0xc2, 0x05, 0x00, 0x76]),
+    (0x0010, &[0xcd, 0x20, 0x00, 0xc9]),
+    (0x0020, &[0x00, 0xc8, 0xc9]),
+];
+
+fn cpu() -> (Cpu, Rc<RefCell<Linear>>) {
+    let mut mem = Linear::new();
+    for (a, data) in PROG {
+        mem.data[usize::from(*a)..usize::from(*a) + data.len()].copy_from_slice(data);
+    }
+    let mem = Rc::new(RefCell::new(mem));
+    (Cpu::power_up(mem.clone()), mem)
+}
+
+fn run() -> (Profile, Rc<RefCell<Linear>>) {
+    let (mut cpu, mem) = cpu();
+    let p = Rc::new(RefCell::new(Profile::new()));
+    cpu.tracer = Some(Box::new(p.clone()));
+    while !cpu.halted {
+        cpu.next();
+    }
+    cpu.tracer = None;
+    let mut p = Rc::try_unwrap(p).ok().unwrap().into_inner();
+    p.finish();
+    (p, mem)
+}
+
+#[test]
+fn test_flow() {
+    let (mut cpu, _) = cpu();
+    let rec = Rc::new(RefCell::new(Record::default()));
+    cpu.tracer = Some(Box::new(rec.clone()));
+    while !cpu.halted {
+        cpu.next();
+    }
+    let flows: Vec<Flow> = rec.borrow().steps.iter().take(8).map(profile::flow).collect();
+    use Flow::*;
+    assert_eq!(flows, vec![Other, Other, Call, Call, Other, Other, Return, Return]);
+}
+
+#[test]
+fn test_profile() {
+    let (p, _) = run();
+    assert_eq!(p.total.count, 2 + 3 * 8 + 1);
+    assert_eq!(p.addrs[&0x0020].count, 3);
+    assert_eq!(p.addrs[&0x0021].cycles, 3 * 5);
+    let outer = p.routines[&0x0010];
+    let inner = p.routines[&0x0020];
+    assert_eq!(outer.calls, 3);
+    assert_eq!(inner.calls, 3);
+    assert_eq!(inner.instructions, 9);
+    assert_eq!(outer.instructions, 6);
+    assert_eq!(outer.total_cycles, outer.self_cycles + inner.total_cycles);
+    let root = p.routines[&0x0000];
+    assert_eq!(root.total_cycles, p.total.cycles);
+    assert_eq!(root.self_cycles + outer.total_cycles, p.total.cycles);
+    assert!(p.stack().is_empty());
+}
+
+#[test]
+fn test_report() {
+    let (mut p, mem) = run();
+    p.symbols.insert("outer", 0x0010);
+    p.symbols.insert("inner", 0x0020);
+    assert_eq!(p.name(0x0022), "inner+0x2");
+    assert_eq!(p.name(0x0008), "0x0008");
+    let r = p.report(3);
+    let lines: Vec<&str> = r.lines().collect();
+    assert_eq!(lines[0], format!("{} instructions, {} cycles", p.total.count, p.total.cycles));
+    assert!(lines[3].ends_with("0x0000"));
+    assert!(lines[4].ends_with("outer"));
+    assert!(lines[5].ends_with("inner"));
+    let a = p.annotate(&*mem.borrow());
+    assert!(a.contains("inner:\n"));
+    assert!(a.contains("0021  c8        RZ"));
+    assert!(a.contains("  ...\n"));
+}
+
+#[test]
+fn test_folded() {
+    let (mut p, _) = run();
+    p.symbols.insert("outer", 0x0010);
+    p.symbols.insert("inner;x", 0x0020);
+    let total: u64 = p.stacks.values().sum();
+    assert_eq!(total, p.total.cycles);
+    assert_eq!(p.stacks[&vec![0x0000, 0x0010, 0x0020]], p.routines[&0x0020].self_cycles);
+    let folded = p.folded();
+    let lines: Vec<&str> = folded.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines.contains(&format!("0x0000;outer;inner_x {}", p.routines[&0x0020].self_cycles).as_str()));
+
+    // EI; NOP; JMP 0001H, with the interrupt routine INR B; EI; RET at 0x0038.
+    let mut mem = Linear::new();
+    mem.data[..5].copy_from_slice(&[0xfb, 0x00, 0xc3, 0x01, 0x00]);
+    mem.data[0x38..0x3b].copy_from_slice(&[0x04, 0xfb, 0xc9]);
+    let mut cpu = Cpu::power_up(Rc::new(RefCell::new(mem)));
+    cpu.reg.sp = 0x0100;
+    let p = Rc::new(RefCell::new(Profile::new()));
+    cpu.tracer = Some(Box::new(p.clone()));
+    for _ in 0..2 {
+        for _ in 0..5 {
+            cpu.next();
+        }
+        cpu.inte_handle(0x0038);
+        for _ in 0..3 {
+            cpu.next();
+        }
+    }
+    cpu.tracer = None;
+    let mut p = Rc::try_unwrap(p).ok().unwrap().into_inner();
+    assert_eq!(p.stack(), vec![0x0000]);
+    p.finish();
+    assert_eq!(p.routines[&0x0038].calls, 2);
+    let isr: u64 = (0x38..0x3b).map(|a| p.addrs[&a].cycles).sum();
+    assert_eq!(p.stacks[&vec![0x0000, 0x0038]], isr);
+    assert_eq!(p.addrs[&0x0038].count, 2);
+}
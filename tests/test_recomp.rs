@@ -0,0 +1,100 @@
+use i8080::recomp;
+use i8080::{Cpu, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// The module recomp emits for PROGRAM.
+#[path = "recomp/prog.rs"]
+mod prog;
+
+// 0000  LXI SP,0100H
+// 0003  MVI B,10H
+// 0005  LXI H,0200H
+// 0008  MOV M,B
+// 0009  INX H
+// 000a  DCR B
+// 000b  JNZ 0008H
+// 000e  MVI B,10H
+// 0010  LXI H,0200H
+// 0013  XRA A
+// 0014  ADD M
+// 0015  DAA
+// 0016  INX H
+// 0017  DCR B
+// 0018  JNZ 0014H
+// 001b  STA 0300H
+// 001e  CALL 0030H
+// 0021  OUT 01H
+// 0023  LXI H,0040H
+// 0026  PCHL
+//
+// 0030  PUSH PSW
+// 0031  LXI H,1234H
+// 0034  XTHL
+// 0035  SHLD 0302H
+// 0038  POP PSW
+// 0039  RET
+//
+// 0040  LHLD 0302H
+// 0043  XCHG
+// 0044  MOV A,E
+// 0045  RLC
+// 0046  CPI 20H
+// 0048  CC 0050H
+// 004b  HLT
+//
+// 0050  INR A
+// 0051  RET
+fn program() -> Linear {
+    let mut mem = Linear::new();
+    let code: [&[u8]; 4] = [
+        &[
+            0x31, 0x00, 0x01, 0x06, 0x10, 0x21, 0x00, 0x02, 0x70, 0x23, 0x05, 0xc2, 0x08, 0x00, 0x06, 0x10, 0x21, 0x00,
+            0x02, 0xaf, 0x86, 0x27, 0x23, 0x05, 0xc2, 0x14, 0x00, 0x32, 0x00, 0x03, 0xcd, 0x30, 0x00, 0xd3, 0x01, 0x21,
+            0x40, 0x00, 0xe9,
+        ],
+        &[0xf5, 0x21, 0x34, 0x12, 0xe3, 0x22, 0x02, 0x03, 0xf1, 0xc9],
+        &[0x2a, 0x02, 0x03, 0xeb, 0x7b, 0x07, 0xfe, 0x20, 0xdc, 0x50, 0x00, 0x76],
+        &[0x3c, 0xc9],
+    ];
+    for (a, c) in [0x00, 0x30, 0x40, 0x50].into_iter().zip(code) {
+        mem.data[a..a + c.len()].copy_from_slice(c);
+    }
+    mem
+}
+
+// The blocks found from the entry point, and the module written for them.
+#[test]
+fn test_emit() {
+    let blocks = recomp::discover(&program(), 0x0000..0x0060, &[0x0000]);
+    // The code after the PCHL is not found, and OUT starts none.
+    let starts: Vec<u16> = blocks.keys().copied().collect();
+    assert_eq!(starts, [0x0000, 0x0008, 0x000e, 0x0014, 0x001b, 0x0023, 0x0030]);
+    assert_eq!(blocks[&0x001b].size, 6);
+    assert_eq!(recomp::emit(&blocks), include_str!("recomp/prog.rs"));
+}
+
+// The recompiled program, with the interpreter for the rest, ends as the interpreter alone does.
+#[test]
+fn test_run() {
+    let a = Rc::new(RefCell::new(program()));
+    let b = Rc::new(RefCell::new(program()));
+    let mut x = Cpu::power_up(a.clone());
+    let mut y = Cpu::power_up(b.clone());
+    while !x.halted {
+        x.next();
+    }
+    let runs = std::cell::Cell::new(0);
+    let block = |pc| {
+        let f = prog::block(pc);
+        runs.set(runs.get() + usize::from(f.is_some()));
+        f
+    };
+    recomp::run(&mut y, block, u64::MAX);
+    assert!(y.halted);
+    // Each loop runs its block 15 times, after the block it starts in.
+    assert_eq!(runs.get(), 35);
+    assert_eq!(x.reg, y.reg);
+    assert_eq!(x.cycles, y.cycles);
+    assert!(a.borrow().data == b.borrow().data);
+}
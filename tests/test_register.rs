@@ -0,0 +1,41 @@
+use i8080::{Flag, Register};
+
+// The flags of an operation are seen before they are settled into f.
+#[test]
+fn test_pending() {
+    let mut r = Register::power_up();
+    r.a = 0x3a;
+    r.alu_sub(0x3a);
+    assert_eq!(r.f, 0x02);
+    assert!(r.get_flag(Flag::Z));
+    assert!(r.get_flag(Flag::A));
+    assert_eq!(r.flags(), 0x56);
+    assert_eq!(r.get_af(), 0x0056);
+    let mut s = r;
+    s.settle();
+    assert_eq!(s.f, 0x56);
+    assert_eq!(r, s);
+}
+
+// Each operation replaces the flags of the one before, and setting one flag keeps the others.
+#[test]
+fn test_settle() {
+    let mut r = Register::power_up();
+    r.a = 0x0f;
+    r.alu_add(0xf1);
+    assert_eq!((r.a, r.flags()), (0x00, 0x57));
+    r.alu_ora(0x80);
+    assert_eq!((r.a, r.flags()), (0x80, 0x82));
+    r.set_flag(Flag::C, true);
+    assert_eq!(r.f, 0x83);
+    r.alu_adc(0x00);
+    assert_eq!((r.a, r.flags()), (0x81, 0x86));
+    r.alu_ana(0x08);
+    assert_eq!((r.a, r.flags()), (0x00, 0x56));
+    // INR keeps the carry of the pending CMP.
+    r.alu_cmp(0x01);
+    r.b = r.alu_inr(0x7f);
+    assert_eq!((r.a, r.b, r.flags()), (0x00, 0x80, 0x93));
+    r.set_af(0x00ff);
+    assert_eq!(r.flags(), 0xd7);
+}
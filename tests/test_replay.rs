@@ -0,0 +1,134 @@
+use i8080::block::Cache;
+use i8080::replay::{Event, Journal, Log, Snapshot};
+use i8080::{Cpu, Device, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// 0000  LXI SP,0100H
+// 0003  LXI H,0080H
+// 0006  EI
+// 0007  IN 01H
+// 0009  ADD M
+// 000a  MOV M,A
+// 000b  JMP 0006H
+// 0020  INR M
+// 0021  RET
+fn program(dev: Rc<RefCell<dyn Device>>) -> Cpu {
+    let mut mem = Linear::new();
+    let code = [0x31, 0x00, 0x01, 0x21, 0x80, 0x00, 0xfb, 0xdb, 0x01, 0x86, 0x77, 0xc3, 0x06, 0x00];
+    mem.data[..code.len()].copy_from_slice(&code);
+    mem.data[0x20..0x22].copy_from_slice(&[0x34, 0xc9]);
+    let mut cpu = Cpu::power_up(Rc::new(RefCell::new(mem)));
+    cpu.dev = Some(dev);
+    cpu
+}
+
+// Gives a different value at every read.
+#[derive(Default)]
+struct Counter {
+    n: u8,
+}
+
+impl Device for Counter {
+    fn get(&mut self, _: u8) -> u8 {
+        self.n = self.n.wrapping_mul(5).wrapping_add(3);
+        self.n
+    }
+
+    fn set(&mut self, _: u8, _: u8) {}
+}
+
+// Run some instructions with an interrupt every so often, asked at the step given.
+fn run(cpu: &mut Cpu, steps: u64, every: u64) {
+    for i in 0..steps {
+        if i % every == every - 1 {
+            cpu.inte_handle(0x20);
+        }
+        cpu.next();
+    }
+}
+
+fn record() -> (Log, Cpu) {
+    let mut cpu = program(Rc::new(RefCell::new(Counter::default())));
+    // Start from a state other than power up.
+    run(&mut cpu, 100, 7);
+    cpu.journal = Some(Journal::record(&cpu, 500));
+    run(&mut cpu, 2000, 13);
+    let log = cpu.journal.take().unwrap().finish(cpu.cycles);
+    let mut data = vec![];
+    log.save(&mut data).unwrap();
+    (Log::load(&mut &data[..]).unwrap(), cpu)
+}
+
+fn replay(log: Log, verify: bool) -> (Cpu, Journal) {
+    let mut cpu = program(Rc::new(RefCell::new(Counter { n: 0x55 })));
+    cpu.journal = Some(Journal::replay(&mut cpu, log, verify));
+    let mut i = 0;
+    while !cpu.journal.as_ref().unwrap().done(cpu.cycles) {
+        // Interrupts of the front end are left out.
+        if i % 3 == 0 {
+            cpu.inte_handle(0x30);
+        }
+        cpu.next();
+        i += 1;
+    }
+    let j = cpu.journal.take().unwrap();
+    (cpu, j)
+}
+
+#[test]
+fn test_replay() {
+    let (log, want) = record();
+    assert!(log.events.iter().any(|e| matches!(e, Event::Interrupt { addr: 0x20, .. })));
+    assert!(log.events.iter().filter(|e| matches!(e, Event::Hash { .. })).count() > 10);
+    assert_eq!(log.end, want.cycles);
+    let (cpu, j) = replay(log, true);
+    assert_eq!(j.desync, None);
+    assert_eq!(cpu.cycles, want.cycles);
+    assert_eq!(cpu.reg, want.reg);
+    assert_eq!(cpu.inte, want.inte);
+    assert_eq!(cpu.mem.borrow().get(0x80), want.mem.borrow().get(0x80));
+    assert_eq!(cpu.mem.borrow().get_word(0xfe), want.mem.borrow().get_word(0xfe));
+}
+
+#[test]
+fn test_verify() {
+    let (log, _) = record();
+    let i = log.events.iter().position(|e| matches!(e, Event::Input { .. })).unwrap();
+    let mut bad = log.clone();
+    if let Event::Input { value, .. } = &mut bad.events[i] {
+        *value ^= 0x01;
+    }
+    // Without verify the changed input is followed as it is.
+    let (_, j) = replay(bad.clone(), false);
+    assert_eq!(j.desync, None);
+    // With verify it shows at the next hash.
+    let (_, j) = replay(bad, true);
+    let d = j.desync.unwrap();
+    assert!(matches!(log.events[d.index], Event::Hash { .. }));
+    assert!(d.index > i);
+    assert!(log.events[i + 1..d.index].iter().all(|e| !matches!(e, Event::Hash { .. })));
+
+    // A log that does not belong to the program leaves it at the first input.
+    let mut bad = log.clone();
+    bad.snapshot.mem[0x08] = 0x02;
+    let (_, j) = replay(bad, false);
+    assert!(j.desync.unwrap().reason.starts_with("IN 02"));
+}
+
+// Restoring a snapshot drops the blocks the cache decoded from the memory it replaces.
+#[test]
+fn test_restore_cache() {
+    let mut mem = Linear::new();
+    mem.data[..3].copy_from_slice(&[0x3e, 0x01, 0x76]);
+    let mut cpu = Cpu::power_up(Rc::new(RefCell::new(mem)));
+    cpu.cache = Some(Cache::new());
+    let snapshot = Snapshot::take(&cpu);
+    cpu.mem.borrow_mut().set(0x0001, 0x02);
+    cpu.invalidate(0x0001, 1);
+    cpu.run_block();
+    assert_eq!(cpu.reg.a, 0x02);
+    snapshot.restore(&mut cpu);
+    cpu.run_block();
+    assert_eq!(cpu.reg.a, 0x01);
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "singlestep")]
+use i8080::singlestep;
+
+// Hand-checked vectors in the format of the published suites: ALU, DAA, memory, a call, the stack, I/O and EI.
+#[test]
+fn test_sample() {
+    let tests = singlestep::load("tests/singlestep/sample.json").unwrap();
+    assert_eq!(tests.len(), 8);
+    assert_eq!(tests[0].cycles, Some(4));
+    assert_eq!(tests[5].ports, [(0x10, 0x23, false)]);
+    for t in &tests {
+        assert_eq!(t.run(), Vec::<String>::new(), "{}", t.name);
+    }
+}
+
+// A wrong final state is reported field by field.
+#[test]
+fn test_mismatch() {
+    let mut tests = singlestep::load("tests/singlestep/sample.json").unwrap();
+    let t = &mut tests[4];
+    t.expected.reg.f = 0x57;
+    t.expected.ram[1].1 = 0xd5;
+    t.cycles = Some(12);
+    assert_eq!(
+        t.run(),
+        ["f expected 57 got d7 (S)", "ram 2ffe expected d5 got d7", "cycles expected 12 got 11"].map(String::from)
+    );
+    let t = &mut tests[6];
+    t.ports[0].1 = 0x43;
+    assert_eq!(t.run(), ["ports expected [(20, 43)] got [(20, 42)]"]);
+}
+
+#[test]
+fn test_parse() {
+    let tests = singlestep::parse(r#"[{"name": "00", "initial": {"pc": 0}, "final": {}}]"#);
+    assert_eq!(tests.unwrap_err().to_string(), "singlestep: 00: bad a");
+    let tests = singlestep::parse(r#"{"name": "00"}"#);
+    assert_eq!(tests.unwrap_err().to_string(), "singlestep: json: not an array of tests");
+}
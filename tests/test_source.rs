@@ -0,0 +1,64 @@
+use i8080::source::{Loc, Sources};
+
+// CP/M ASM layout: one listing line a source line.
+const ASM: &str = "\
+                ; print a star
+ 0100           \torg\t100h
+ 0100 0E02      start:\tmvi\tc,2
+ 0102 1E2A      \tmvi\te,'*'
+ 0104 CD0500    \tcall\t5
+ 0107 C30000    \tjmp\t0
+";
+
+// M80 layout with a page header and a DB running over two lines.
+const M80: &str = "\
+  0000'   21 000A'              lxi     h,msg
+  0003'   76                    hlt
+
+\x0cMACRO-80 3.44\t09-Dec-81\tPAGE\t1
+
+  0004'   00 00                 dw      0
+  0006'                 ; the message
+  0006'   48 45 4C 4C   msg:    db      'HELLO, WORLD'
+  000A'   4F 2C 20 57
+  000E'   4F 52 4C 44
+  0012'   C9                    ret
+";
+
+#[test]
+fn test_asm() {
+    let mut s = Sources::new();
+    s.add("./res/STAR.PRN", ASM);
+    assert_eq!(s.files[0].name, "STAR");
+    assert_eq!(s.files[0].lines.len(), 6);
+    let loc = s.find(0x0103).unwrap();
+    assert_eq!(loc, Loc { file: 0, line: 4 });
+    assert_eq!(s.describe(loc), "STAR:4");
+    assert_eq!(s.text(loc), "mvi\te,'*'");
+    assert_eq!(s.find(0x010a), None);
+    assert!(s.is_start(0x0104));
+    assert!(!s.is_start(0x0105));
+    assert_eq!(s.parse("star.asm:5"), Some(Loc { file: 0, line: 5 }));
+    assert_eq!(s.parse("5"), Some(Loc { file: 0, line: 5 }));
+    assert_eq!(s.parse("other:5"), None);
+    // The org line has no code, the first line of code after it is taken.
+    assert_eq!(s.address(Loc { file: 0, line: 2 }), Some(0x0100));
+    assert_eq!(s.address(Loc { file: 0, line: 6 }), Some(0x0107));
+    assert_eq!(s.address(Loc { file: 0, line: 7 }), None);
+}
+
+#[test]
+fn test_m80() {
+    let mut s = Sources::new();
+    s.add("star.asm", ASM);
+    s.add("hello.prn", M80);
+    assert_eq!(s.file("HELLO.MAC"), Some(1));
+    assert_eq!(
+        s.files[1].lines,
+        vec!["lxi     h,msg", "hlt", "", "dw      0", "; the message", "msg:    db      'HELLO, WORLD'", "ret"]
+    );
+    assert_eq!(s.find(0x000d), Some(Loc { file: 1, line: 6 }));
+    assert_eq!(s.find(0x0012), Some(Loc { file: 1, line: 7 }));
+    assert_eq!(s.parse("5"), None);
+    assert_eq!(s.address(s.parse("hello:5").unwrap()), Some(0x0006));
+}
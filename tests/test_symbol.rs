@@ -0,0 +1,84 @@
+use i8080::symbol::{self, Symbols};
+use i8080::trace::{self, Record};
+use i8080::{asm, Cpu, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn test_parse() {
+    let s = Symbols::parse_sym("0100 START   0103 PRINT\r\n0120 MSG\r\n\x1a0200 JUNK");
+    assert_eq!(s.len(), 3);
+    assert_eq!(s.get("print"), Some(0x0103));
+    assert_eq!(s.get("JUNK"), None);
+
+    let s = Symbols::parse_text("; symbols\n0100 start\n0x0123 print # the routine\nnot a symbol\n");
+    assert_eq!(s.get("START"), Some(0x0100));
+    assert_eq!(s.name(0x0123), Some("print"));
+    assert_eq!(s.len(), 2);
+
+    let prn = "\
+                                title   test
+  0100'   0E 09         start:  mvi     c,9
+  0102'   CD 0108'              call    print
+  0105'   C3 0000               jmp     0
+  0108'   C9            print:  ret
+
+Symbols:
+0100'   START           0108'   PRINT           0000    BOOT
+";
+    let s = Symbols::parse_prn(prn);
+    assert_eq!(s.get("start"), Some(0x0100));
+    assert_eq!(s.name(0x0108), Some("print"));
+    assert_eq!(s.get("BOOT"), Some(0x0000));
+    assert_eq!(s.len(), 3);
+}
+
+#[test]
+fn test_resolve() {
+    let mut s = Symbols::new();
+    s.insert("PRINT", 0x0123);
+    s.insert("ALIAS", 0x0123);
+    assert_eq!(s.resolve("print"), Some(0x0123));
+    assert_eq!(s.resolve("PRINT+3"), Some(0x0126));
+    assert_eq!(s.resolve("print-0x10"), Some(0x0113));
+    assert_eq!(s.resolve("alias"), Some(0x0123));
+    assert_eq!(s.resolve("1234"), Some(0x1234));
+    assert_eq!(s.resolve("0fah"), Some(0x00fa));
+    assert_eq!(s.resolve("$ff00"), Some(0xff00));
+    assert_eq!(s.resolve("#256"), Some(0x0100));
+    assert_eq!(s.resolve("nowhere"), None);
+    assert_eq!(symbol::number("0x10"), Some(0x10));
+    assert_eq!(s.name(0x0123), Some("PRINT"));
+    assert_eq!(s.label(0x0125), Some("PRINT+0x2".to_string()));
+    assert_eq!(s.describe(0x0010), "0x0010");
+    assert_eq!(asm::disasm_with(&[0xcd, 0x23, 0x01], |w| s.name(w).map(String::from)), "CALL PRINT");
+    assert_eq!(asm::disasm_with(&[0x21, 0x24, 0x01], |w| s.name(w).map(String::from)), "LXI HL, 0x0124");
+}
+
+// 0000  LXI SP,0100H
+// 0003  CALL PRINT
+// 0006  HLT
+// 0010  PRINT: MVI A,2AH
+// 0012  RET
+fn program() -> (Cpu, Symbols) {
+    let mut mem = Linear::new();
+    mem.data[..7].copy_from_slice(&[0x31, 0x00, 0x01, 0xcd, 0x10, 0x00, 0x76]);
+    mem.data[0x10..0x13].copy_from_slice(&[0x3e, 0x2a, 0xc9]);
+    let mut s = Symbols::new();
+    s.insert("MAIN", 0x0000);
+    s.insert("PRINT", 0x0010);
+    (Cpu::power_up(Rc::new(RefCell::new(mem))), s)
+}
+
+#[test]
+fn test_trace() {
+    let (mut cpu, s) = program();
+    let rec = Rc::new(RefCell::new(Record::default()));
+    cpu.tracer = Some(Box::new(rec.clone()));
+    cpu.next();
+    cpu.next();
+    let steps = &rec.borrow().steps;
+    assert_eq!(steps[1].instruction_with(&s), "CALL PRINT");
+    assert!(trace::text_with(&steps[1], &s).starts_with("0003  cd 10 00  CALL PRINT       A=00"));
+    assert!(trace::json_with(&steps[1], &s).contains("\"instr\":\"CALL PRINT\""));
+}
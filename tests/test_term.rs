@@ -0,0 +1,62 @@
+use i8080::pixmap::Image;
+use i8080::term::{self, Key, Keymap, Mode, Screen};
+use i8080::{Linear, Memory};
+
+#[test]
+fn test_braille() {
+    let mut mem = Linear::new();
+    // 16x4 pixels: the top left and bottom right dots of the first cell, the whole last column.
+    mem.set(0x2400, 0x01);
+    mem.set(0x2406, 0x02);
+    for y in 0..4 {
+        mem.set(0x2401 + y * 2, 0x80);
+    }
+    let img = term::bitmap(&mem, 0x2400, 16, 4);
+    assert!(img.get(0, 0) == [0xff; 3] && img.get(15, 3) == [0xff; 3] && img.get(1, 0) == [0; 3]);
+    let s = term::line(&img, Mode::Braille, 0);
+    let chars: Vec<char> = s.chars().filter(|c| ('\u{2800}'..='\u{28ff}').contains(c)).collect();
+    assert_eq!(chars.len(), 8);
+    assert_eq!(chars[0], '\u{2881}');
+    assert_eq!(chars[1..7], ['\u{2800}'; 6]);
+    assert_eq!(chars[7], '\u{28b8}');
+    assert_eq!(term::rows(&img, Mode::Braille), 1);
+}
+
+#[test]
+fn test_half_block() {
+    let mut img = Image::new(2, 3);
+    img.set(0, 0, [0xff, 0x00, 0x00]);
+    img.set(1, 1, [0x00, 0xff, 0x00]);
+    img.set(1, 2, [0x00, 0x00, 0xff]);
+    assert_eq!(term::rows(&img, Mode::HalfBlock), 2);
+    assert_eq!(
+        term::line(&img, Mode::HalfBlock, 0),
+        "\x1b[38;2;255;0;0;48;2;0;0;0m▀\x1b[38;2;0;0;0;48;2;0;255;0m▀\x1b[0m"
+    );
+    // The missing row below the image is black.
+    assert_eq!(
+        term::line(&img, Mode::HalfBlock, 1),
+        "\x1b[38;2;0;0;0;48;2;0;0;0m▀\x1b[38;2;0;0;255;48;2;0;0;0m▀\x1b[0m"
+    );
+    let mut screen = Screen::new(Mode::HalfBlock);
+    let first = screen.draw(&img);
+    assert!(first.starts_with("\x1b[2J") && first.contains("\x1b[1;1H") && first.contains("\x1b[2;1H"));
+    img.set(0, 2, [0xff; 3]);
+    let second = screen.draw(&img);
+    assert!(!second.contains("\x1b[1;1H") && second.contains("\x1b[2;1H"));
+    assert_eq!(screen.draw(&img), "");
+}
+
+#[test]
+fn test_keys() {
+    assert_eq!(
+        term::parse(b"c\x1b[D\x1bOA\r\x1b[1;5C \x1b"),
+        vec![Key::Char(b'c'), Key::Left, Key::Up, Key::Enter, Key::Right, Key::Char(b' '), Key::Esc]
+    );
+    let mut map = Keymap::new(vec![(Key::Left, 1), (Key::Char(b' '), 2)], 2);
+    assert_eq!(map.update(&[Key::Left]), (vec![1], vec![]));
+    assert_eq!(map.update(&[Key::Left, Key::Char(b' ')]), (vec![2], vec![]));
+    assert_eq!(map.update(&[]), (vec![], vec![]));
+    assert_eq!(map.update(&[]), (vec![], vec![1, 2]));
+    assert_eq!(map.update(&[Key::Char(b'x')]), (vec![], vec![]));
+}
@@ -0,0 +1,91 @@
+use i8080::symbol::Symbols;
+use i8080::trace::{self, Access, Binary, Json, Record, Text, Tracer};
+use i8080::{asm, Cpu, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// LXI SP,0100H; MVI A,12H; STA 2400H; CALL 000CH; HLT; PUSH B; POP B; RET
+const PROG: [u8; 15] = [0x31, 0x00, 0x01, 0x3e, 0x12, 0x32, 0x00, 0x24, 0xcd, 0x0c, 0x00, 0x76, 0xc5, 0xc1, 0xc9];
+
+fn cpu() -> Cpu {
+    let mut mem = Linear::new();
+    mem.data[..PROG.len()].copy_from_slice(&PROG);
+    Cpu::power_up(Rc::new(RefCell::new(mem)))
+}
+
+// Run the program to the halt with the tracer and take the tracer back.
+fn run<T: Tracer + 'static>(tracer: T) -> T {
+    let mut cpu = cpu();
+    let t = Rc::new(RefCell::new(tracer));
+    cpu.tracer = Some(Box::new(t.clone()));
+    while !cpu.halted {
+        cpu.next();
+    }
+    cpu.tracer = None;
+    Rc::try_unwrap(t).ok().unwrap().into_inner()
+}
+
+#[test]
+fn test_record() {
+    let steps = run(Record::default()).steps;
+    assert_eq!(steps.len(), 8);
+    assert_eq!(steps[2].pc, 0x0005);
+    assert_eq!(steps[2].bytes(), &[0x32, 0x00, 0x24]);
+    assert_eq!(steps[2].instruction(), "STA 0x2400");
+    assert_eq!(steps[2].accesses, vec![Access { addr: 0x2400, value: 0x12, write: true }]);
+    assert_eq!(steps[1].before.a, 0x00);
+    assert_eq!(steps[1].after.a, 0x12);
+    // The call pushes the return address 0x000b.
+    assert_eq!(
+        steps[3].accesses,
+        vec![Access { addr: 0x00fe, value: 0x0b, write: true }, Access { addr: 0x00ff, value: 0x00, write: true }]
+    );
+    assert_eq!(steps[6].instruction(), "RET");
+    assert!(steps[6].accesses.iter().all(|e| !e.write));
+    assert_eq!(steps[7].instruction(), "HLT");
+    let total: u32 = steps.iter().map(|e| e.cycles).sum();
+    assert_eq!(steps[7].total, u64::from(total));
+
+    let mut cpu = cpu();
+    while !cpu.halted {
+        cpu.next();
+    }
+    assert_eq!(cpu.cycles, u64::from(total));
+}
+
+#[test]
+fn test_text_json() {
+    let text = String::from_utf8(run(Text::new(vec![])).finish().unwrap()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 8);
+    assert_eq!(
+        lines[2],
+        "0005  32 00 24  STA 0x2400       A=12 F=02 B=00 C=00 D=00 E=00 H=00 L=00 SP=0100 13 W 2400=12"
+    );
+    let json = String::from_utf8(run(Json::new(vec![])).finish().unwrap()).unwrap();
+    let first = json.lines().next().unwrap();
+    assert!(first.starts_with("{\"pc\":0,\"bytes\":[49,0,1],\"instr\":\"LXI SP, 0x0100\",\"before\":{\"a\":0,"));
+    assert!(first.ends_with("\"mem\":[],\"cycles\":10,\"total\":10}"));
+    assert_eq!(asm::disasm(&[0x06, 0x7f]), "MVI B, 0x7f");
+    assert_eq!(asm::disasm(&[0xd3]), "OUT 0x00");
+}
+
+#[test]
+fn test_binary() {
+    let data = run(Binary::new(vec![])).finish().unwrap();
+    assert_eq!(&data[..8], trace::MAGIC);
+    let steps = trace::load(&mut &data[..]).unwrap();
+    assert_eq!(steps, run(Record::default()).steps);
+    assert!(trace::load(&mut &data[..data.len() - 1]).is_err());
+    assert!(trace::load(&mut &b"I8080TR\x02"[..]).is_err());
+}
+
+// Symbol names are quoted in the JSON, whatever characters they hold.
+#[test]
+fn test_json_escape() {
+    let steps = run(Record::default()).steps;
+    let mut symbols = Symbols::new();
+    symbols.insert("A\"B\\C\t", 0x000c);
+    let json = trace::json_with(&steps[3], &symbols);
+    assert!(json.contains("\"instr\":\"CALL A\\\"B\\\\C\\u0009\","), "{}", json);
+}
@@ -0,0 +1,118 @@
+use i8080::trace::Record;
+use i8080::tracediff::{Checker, Field, Format, Mismatch};
+use i8080::{Cpu, Linear};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// MVI A,99H; ADI 01H; DAA; MVI B,05H; SUB B; DCR B; JNZ 0007H; HLT
+const PROG: [u8; 13] = [0x3e, 0x99, 0xc6, 0x01, 0x27, 0x06, 0x05, 0x90, 0x05, 0xc2, 0x07, 0x00, 0x76];
+
+fn cpu() -> Cpu {
+    let mut mem = Linear::new();
+    mem.data[..PROG.len()].copy_from_slice(&PROG);
+    Cpu::power_up(Rc::new(RefCell::new(mem)))
+}
+
+// A log of the program in the zazu format, as another emulator would write it.
+fn log() -> Vec<String> {
+    let mut cpu = cpu();
+    let rec = Rc::new(RefCell::new(Record::default()));
+    cpu.tracer = Some(Box::new(rec.clone()));
+    while !cpu.halted {
+        cpu.next();
+    }
+    let steps = &rec.borrow().steps;
+    steps
+        .iter()
+        .map(|s| {
+            let r = &s.before;
+            format!(
+                "PC: {:04X}, AF: {:04X}, BC: {:04X}, DE: {:04X}, HL: {:04X}, SP: {:04X}, CYC: {}\t({:02X})",
+                r.pc,
+                u16::from(r.a) << 8 | u16::from(r.f),
+                r.get_bc(),
+                r.get_de(),
+                r.get_hl(),
+                r.sp,
+                s.total - u64::from(s.cycles),
+                s.opcode[0]
+            )
+        })
+        .collect()
+}
+
+fn check(log: Vec<String>, setup: impl FnOnce(&mut Checker)) -> Checker {
+    let data = log.join("\n").into_bytes();
+    let mut checker = Checker::new(Format::preset("zazu").unwrap(), Box::new(std::io::Cursor::new(data)));
+    setup(&mut checker);
+    let checker = Rc::new(RefCell::new(checker));
+    let mut cpu = cpu();
+    cpu.tracer = Some(Box::new(checker.clone()));
+    while !cpu.halted && !checker.borrow().stopped() {
+        cpu.next();
+    }
+    cpu.tracer = None;
+    Rc::try_unwrap(checker).ok().unwrap().into_inner()
+}
+
+#[test]
+fn test_format() {
+    let f = Format::preset("doctor").unwrap();
+    let r = f.read("A:12 F:93 B:00 C:01 D:02 E:03 H:04 L:05 SP:fffe PC:0100 PCMEM:00,c3,13,02").unwrap();
+    assert_eq!(r.len(), 10);
+    assert_eq!(r[0], (Field::A, 0x12));
+    assert_eq!(r[9], (Field::Pc, 0x0100));
+    assert_eq!(f.read("Hello, world"), None);
+    let f = Format::parse("{*} pc={pc}  cycles {cyc}").unwrap();
+    assert_eq!(f.read("#17 pc=0a2f cycles 1234 JMP").unwrap(), vec![(Field::Pc, 0x0a2f), (Field::Cycles, 1234)]);
+    assert_eq!(f.fields(), vec![Field::Pc, Field::Cycles]);
+    assert!(Format::parse("pc={pc").is_err());
+    assert!(Format::parse("{ix}").is_err());
+    assert!(Format::parse("no fields").is_err());
+}
+
+#[test]
+fn test_match() {
+    let mut log = log();
+    let n = log.len() as u64;
+    // Output of the program and unknown lines are passed over, the flag bits outside the mask are not compared.
+    log.insert(3, "CPU IS OPERATIONAL".to_string());
+    log[0] = log[0].replace("AF: 0002", "AF: 0000");
+    let c = check(log.clone(), |_| {});
+    assert!(c.divergence.is_none());
+    assert_eq!(c.matched, n);
+    assert!(!c.done);
+    // A log cut short ends the comparison.
+    log.truncate(5);
+    let c = check(log, |_| {});
+    assert!(c.done && c.divergence.is_none());
+    assert_eq!(c.matched, 4);
+}
+
+#[test]
+fn test_divergence() {
+    let mut log = log();
+    // The log says DAA left 00 with the carry set where this cpu has something else.
+    log[3] = log[3].replace("AF: 0057", "AF: 0155");
+    log[3] = log[3].replace("CYC: 18", "CYC: 19");
+    let c = check(log.clone(), |c| c.context = 2);
+    let d = c.divergence.unwrap();
+    assert_eq!(d.index, 3);
+    assert_eq!(d.line, 4);
+    assert_eq!(d.step.pc, 0x0005);
+    assert_eq!(
+        d.mismatches,
+        vec![
+            Mismatch { field: Field::Af, expected: 0x0155, actual: 0x0055 },
+            Mismatch { field: Field::Cycles, expected: 19, actual: 18 }
+        ]
+    );
+    assert_eq!(d.before.len(), 2);
+    assert_eq!(d.after.len(), 2);
+    let report = d.report();
+    assert!(report.contains("> 0005  06 05     MVI B, 0x05"));
+    assert!(report.ends_with("diverged at instruction 3, log line 4: af expected 0155 got 0055 cyc expected 19 got 18"));
+
+    let c = check(log, |c| c.ignore = vec![Field::Af, Field::Cycles]);
+    assert!(c.divergence.is_none());
+}